@@ -0,0 +1,33 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use message::http::framing::Framing;
+
+// Primeiro byte escolhe requisição ou resposta e o tamanho dos pedaços, como
+// o proxy recebe do socket. Sobra de um pedaço é da próxima mensagem.
+fuzz_target!(|data: &[u8]| {
+    let Some((&control, data)) = data.split_first() else {
+        return;
+    };
+    let mut framing = match control & 1 {
+        0 => Framing::request(),
+        _ => Framing::response(control & 2 != 0),
+    };
+    let size = (control >> 2) as usize + 1;
+
+    for mut chunk in data.chunks(size) {
+        while !chunk.is_empty() {
+            let Ok(used) = framing.feed(chunk) else {
+                return;
+            };
+            assert!(used <= chunk.len());
+            if !framing.done() && used < chunk.len() {
+                return;
+            }
+            if framing.done() {
+                framing.reset(false);
+            }
+            chunk = &chunk[used..];
+        }
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use message::http::parse;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse::parse_body(data);
+});
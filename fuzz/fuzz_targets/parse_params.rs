@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use message::{http::parse, time};
+
+// Query do /payments-summary, com as datas indo para o parse_iso8601
+fuzz_target!(|data: &[u8]| {
+    let (params, offset) = parse::parse_params(data);
+    assert!(offset <= data.len());
+    for value in params.values() {
+        let _ = time::parse_iso8601(value);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use message::http::{Request, parse};
+
+// O caminho do handler do load balancer: progress enquanto lê, depois o parse
+fuzz_target!(|data: &[u8]| {
+    if parse::progress(data) == parse::Progress::Complete {
+        let _ = parse::header(data, b"x-purge-token");
+    }
+    let _ = Request::from_bytes(data);
+});
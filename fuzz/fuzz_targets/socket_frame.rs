@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use message::socket::Message;
+
+// Do jeito que sai do socket, de qualquer tamanho. O que decodifica também
+// tem que voltar a frame.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = Message::from_bytes(data) {
+        let _ = message.to_bytes();
+    }
+});
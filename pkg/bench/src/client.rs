@@ -0,0 +1,197 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+// Cliente HTTP/1.1 bloqueante com keep-alive, o mínimo que o cenário usa:
+// status e corpo inteiro. Erro de rede derruba a conexão e a próxima
+// requisição reconecta, como o Httpx do k6.
+pub struct Client {
+    addr: String,
+    host: String,
+    stream: Option<TcpStream>,
+    timeout: Duration,
+    buffer: Vec<u8>,
+}
+
+impl Client {
+    pub fn new(url: &str, timeout: Duration) -> Client {
+        let addr = url
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+            .to_string();
+        let host = addr.split(':').next().unwrap_or_default().to_string();
+        Client {
+            addr,
+            host,
+            stream: None,
+            timeout,
+            buffer: Vec::with_capacity(1024),
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> std::io::Result<(u16, String)> {
+        let result = self.exchange(method, path, token, body);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    fn connect(&mut self) -> std::io::Result<&mut TcpStream> {
+        // o servidor pode ter fechado a conexão parada, o cliente do k6
+        // percebe antes de reusar e abre outra
+        if self.stream.as_ref().is_some_and(closed) {
+            self.stream = None;
+        }
+        if self.stream.is_none() {
+            let addr = self
+                .addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no address"))?;
+            let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            self.stream = Some(stream);
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    fn exchange(
+        &mut self,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> std::io::Result<(u16, String)> {
+        let mut request = Vec::with_capacity(256 + body.len());
+        write!(
+            request,
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n",
+            self.host
+        )?;
+        if let Some(token) = token {
+            write!(request, "X-Rinha-Token: {token}\r\n")?;
+        }
+        write!(request, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+
+        let stream = self.connect()?;
+        stream.write_all(&request)?;
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let response = read_response(self.stream.as_mut().unwrap(), &mut buffer);
+        self.buffer = buffer;
+        response
+    }
+}
+
+fn closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = match stream.peek(&mut [0u8; 1]) {
+        Ok(_) => true, // nada pendente é esperado, sobra de resposta também invalida
+        Err(e) => e.kind() != ErrorKind::WouldBlock,
+    };
+    closed || stream.set_nonblocking(false).is_err()
+}
+
+fn read_response(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> std::io::Result<(u16, String)> {
+    buffer.clear();
+    let mut chunk = [0u8; 1024];
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "server closed the connection",
+            ));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| std::io::Error::other("invalid response"))?;
+    let length = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().ok())?
+        })
+        .unwrap_or(0);
+
+    while buffer.len() < head_end + length {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "server closed the connection",
+            ));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+
+    let body = String::from_utf8_lossy(&buffer[head_end..head_end + length]).into_owned();
+    Ok((status, body))
+}
+
+// Número de um campo do JSON, dentro do objeto `object` quando dado:
+// {"default":{"totalRequests":1,...}}. Suficiente para os summaries.
+pub fn number(body: &str, object: Option<&str>, key: &str) -> Option<f64> {
+    let scope = match object {
+        Some(object) => {
+            let at = body.find(&format!("\"{object}\""))?;
+            let start = at + body[at..].find('{')?;
+            let end = start + body[start..].find('}')?;
+            &body[start..=end]
+        }
+        None => body,
+    };
+    let at = scope.find(&format!("\"{key}\""))? + key.len() + 2;
+    let value = scope[at..].trim_start().strip_prefix(':')?.trim_start();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | 'e' | 'E' | '+')))
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        let backend = r#"{"default":{"totalRequests":50,"totalAmount":995.00},"fallback":{"totalRequests":2,"totalAmount":39.8}}"#;
+        assert_eq!(
+            number(backend, Some("default"), "totalRequests"),
+            Some(50.0)
+        );
+        assert_eq!(number(backend, Some("fallback"), "totalAmount"), Some(39.8));
+        assert_eq!(number(backend, Some("other"), "totalAmount"), None);
+
+        let processor = r#"{"totalRequests": 2, "totalAmount": 39.80, "feePerTransaction": 0.15}"#;
+        assert_eq!(number(processor, None, "feePerTransaction"), Some(0.15));
+        assert_eq!(number(processor, None, "totalFee"), None);
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use message::time;
+
+use score::{Inputs, Totals, percentile};
+
+use crate::client::{Client, number};
+
+pub mod client;
+
+// O cenário do resources/rinha.js sem k6: VUs em rampa mandando pagamentos,
+// os estágios de delay/falha aplicados nos processadores pelo /admin, a
+// checagem de consistência a cada 10s e no fim o partial-results.json com
+// as contas do handleSummary. Uma thread por VU, como os VUs do k6 que
+// esperam a resposta antes do sleep(1).
+
+const INITIAL_TOKEN: &str = "123";
+const SCENARIO: Duration = Duration::from_secs(60);
+const CONSISTENCY_INTERVAL: Duration = Duration::from_secs(10);
+const AMOUNT: &str = "19.9"; // Big(19.90).toNumber()
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub backend: String,
+    pub default: String,
+    pub fallback: String,
+    pub max_requests: u64,
+    // 60s no rinha.js, com menos os estágios e a checagem encolhem junto
+    pub duration: Duration,
+    pub token: String, // trocado no setup, igual ao TOKEN do run-tests.sh
+    pub participant: String,
+    pub timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            backend: "http://localhost:9999".to_string(),
+            default: "http://localhost:8001".to_string(),
+            fallback: "http://localhost:8002".to_string(),
+            max_requests: 550,
+            duration: SCENARIO,
+            token: INITIAL_TOKEN.to_string(),
+            participant: "anonymous".to_string(),
+            timeout: Duration::from_millis(1500),
+        }
+    }
+}
+
+impl Options {
+    // Momento do cenário de 60s levado para a duração escolhida
+    fn scaled(&self, at: Duration) -> Duration {
+        at.mul_f64(self.duration.as_secs_f64() / SCENARIO.as_secs_f64())
+    }
+}
+
+// stage_00..stage_05: (início, delay e falha do default, delay e falha do fallback)
+const STAGES: [(u64, u64, bool, u64, bool); 6] = [
+    (1, 0, false, 0, false),
+    (10, 100, false, 0, false),
+    (20, 100, true, 0, false),
+    (30, 2000, true, 1000, true),
+    (40, 20, false, 20, false),
+    (50, 0, false, 5000, false),
+];
+
+#[derive(Default)]
+struct Tally {
+    success: u64,
+    failure: u64,
+    durations: Vec<f64>, // ms das respostas esperadas
+}
+
+pub fn run(options: &Options) -> std::io::Result<Inputs> {
+    setup(options)?;
+
+    let start = Instant::now();
+    let end = start + options.duration;
+    let inconsistencies = Arc::new(AtomicU64::new(0));
+
+    let stages = {
+        let options = options.clone();
+        std::thread::Builder::new()
+            .name("bench-stages".to_string())
+            .spawn(move || {
+                for (at, default_delay, default_failure, fallback_delay, fallback_failure) in STAGES
+                {
+                    sleep_until(start + options.scaled(Duration::from_secs(at)));
+                    if Instant::now() >= end {
+                        break;
+                    }
+                    logger::info!(
+                        "Stage at {at}s";
+                        default = format!("{default_delay}ms failure={default_failure}"),
+                        fallback = format!("{fallback_delay}ms failure={fallback_failure}")
+                    );
+                    stage(
+                        &options.default,
+                        &options.token,
+                        default_delay,
+                        default_failure,
+                    );
+                    stage(
+                        &options.fallback,
+                        &options.token,
+                        fallback_delay,
+                        fallback_failure,
+                    );
+                }
+            })?
+    };
+
+    let consistency = {
+        let options = options.clone();
+        let inconsistencies = inconsistencies.clone();
+        std::thread::Builder::new()
+            .name("bench-consistency".to_string())
+            .spawn(move || {
+                let mut next = start;
+                while next < end {
+                    sleep_until(next);
+                    let found = check_consistency(&options);
+                    if found > 0 {
+                        logger::warn!("{found} inconsistências encontradas.");
+                    }
+                    inconsistencies.fetch_add(found, Ordering::Relaxed);
+                    next += options.scaled(CONSISTENCY_INTERVAL);
+                }
+            })?
+    };
+
+    // ramping-vus de 1 até max_requests: o VU n entra quando a rampa passa por n
+    let vus = options.max_requests.max(1);
+    let mut threads = Vec::with_capacity(vus as usize);
+    for vu in 0..vus {
+        let options = options.clone();
+        let starts = start + options.duration.mul_f64(vu as f64 / vus as f64);
+        threads.push(
+            std::thread::Builder::new()
+                .name(format!("bench-vu-{vu}"))
+                .stack_size(128 * 1024)
+                .spawn(move || virtual_user(&options, vu, starts, end))?,
+        );
+    }
+
+    let mut tally = Tally::default();
+    for thread in threads {
+        if let Ok(vu) = thread.join() {
+            tally.success += vu.success;
+            tally.failure += vu.failure;
+            tally.durations.extend(vu.durations);
+        }
+    }
+    let _ = stages.join();
+    let _ = consistency.join();
+
+    tally.durations.sort_by(f64::total_cmp);
+    let mut inputs = teardown(options);
+    inputs.success = tally.success;
+    inputs.failure = tally.failure;
+    inputs.p99_ms = percentile(&tally.durations, 0.99);
+    inputs.inconsistencies = inconsistencies.load(Ordering::Relaxed);
+    Ok(inputs)
+}
+
+fn virtual_user(options: &Options, vu: u64, starts: Instant, end: Instant) -> Tally {
+    let mut tally = Tally::default();
+    let mut client = Client::new(&options.backend, options.timeout);
+    let mut seed = time::now_millis() ^ (vu + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+    sleep_until(starts);
+    while Instant::now() < end {
+        let body = format!(
+            r#"{{"correlationId":"{}","amount":{AMOUNT}}}"#,
+            uuid(&mut seed)
+        );
+        let sent = Instant::now();
+        match client.request("POST", "/payments", None, &body) {
+            Ok((status, _)) => {
+                if matches!(status, 200 | 201 | 202 | 204) {
+                    tally.success += 1;
+                } else {
+                    tally.failure += 1;
+                }
+                if (200..400).contains(&status) {
+                    tally.durations.push(sent.elapsed().as_secs_f64() * 1000.0);
+                }
+            }
+            Err(_) => tally.failure += 1,
+        }
+        sleep_until((Instant::now() + Duration::from_secs(1)).min(end));
+    }
+    tally
+}
+
+fn setup(options: &Options) -> std::io::Result<()> {
+    for url in [&options.default, &options.fallback] {
+        let mut client = Client::new(url, options.timeout);
+        let body = format!(r#"{{"token":"{}"}}"#, options.token);
+        expect(
+            &mut client,
+            "PUT",
+            "/admin/configurations/token",
+            INITIAL_TOKEN,
+            &body,
+            204,
+        )?;
+        expect(
+            &mut client,
+            "POST",
+            "/admin/purge-payments",
+            &options.token,
+            "",
+            200,
+        )?;
+    }
+
+    // backend sem /purge-payments não é problema
+    let mut backend = Client::new(&options.backend, options.timeout);
+    if let Err(e) = backend.request("POST", "/purge-payments", None, "") {
+        logger::info!("Backend purge failed: {e}");
+    }
+    Ok(())
+}
+
+fn expect(
+    client: &mut Client,
+    method: &str,
+    path: &str,
+    token: &str,
+    body: &str,
+    status: u16,
+) -> std::io::Result<()> {
+    match client.request(method, path, Some(token), body)? {
+        (got, _) if got == status => Ok(()),
+        (got, _) => Err(std::io::Error::other(format!(
+            "{method} {path} on {} returned {got}",
+            client.addr()
+        ))),
+    }
+}
+
+fn stage(url: &str, token: &str, delay: u64, failure: bool) {
+    let mut client = Client::new(url, Duration::from_millis(1500));
+    let delay = format!(r#"{{"delay":{delay}}}"#);
+    let failure = format!(r#"{{"failure":{failure}}}"#);
+    for (path, body) in [
+        ("/admin/configurations/delay", delay),
+        ("/admin/configurations/failure", failure),
+    ] {
+        if let Err(e) = expect(&mut client, "PUT", path, token, &body, 200) {
+            logger::warn!("Unable to apply stage: {e}");
+        }
+    }
+}
+
+fn check_consistency(options: &Options) -> u64 {
+    let now = time::now_millis();
+    let window = window(now - 15_000, now - 1_500);
+    let default = processor_summary(options, &options.default, &window);
+    let fallback = processor_summary(options, &options.fallback, &window);
+    let (backend_default, backend_fallback) = backend_summary(options, &window);
+
+    let difference = (backend_default.requests as i64 - default.requests as i64)
+        + (backend_fallback.requests as i64 - fallback.requests as i64);
+    difference.unsigned_abs()
+}
+
+fn teardown(options: &Options) -> Inputs {
+    let now = time::now_millis();
+    let window = window(now - 70_000, now);
+    let default_fee = processor_fee(options, &options.default, &window);
+    let fallback_fee = processor_fee(options, &options.fallback, &window);
+    let (default, fallback) = backend_summary(options, &window);
+
+    Inputs {
+        participant: options.participant.clone(),
+        max_requests: options.max_requests,
+        default,
+        fallback,
+        default_fee,
+        fallback_fee,
+        ..Inputs::default()
+    }
+}
+
+// ?from=...&to=... com as datas em ISO, do jeito que o k6 manda
+pub fn window(from: u64, to: u64) -> String {
+    let mut from_iso = [0u8; time::ISO_LEN];
+    let mut to_iso = [0u8; time::ISO_LEN];
+    time::format_iso8601(from, &mut from_iso);
+    time::format_iso8601(to, &mut to_iso);
+    format!(
+        "from={}&to={}",
+        String::from_utf8_lossy(&from_iso),
+        String::from_utf8_lossy(&to_iso)
+    )
+}
+
+// Sem resposta conta como zero, como o requests.js
+fn processor_body(options: &Options, url: &str, window: &str) -> String {
+    let mut client = Client::new(url, options.timeout);
+    let path = format!("/admin/payments-summary?{window}");
+    match client.request("GET", &path, Some(&options.token), "") {
+        Ok((200, body)) => body,
+        Ok((status, _)) => {
+            logger::error!("Processor summary failed with HTTP {status}"; processor = url);
+            String::new()
+        }
+        Err(e) => {
+            logger::error!("Processor summary failed: {e}"; processor = url);
+            String::new()
+        }
+    }
+}
+
+fn processor_summary(options: &Options, url: &str, window: &str) -> Totals {
+    let body = processor_body(options, url, window);
+    Totals {
+        requests: number(&body, None, "totalRequests").unwrap_or(0.0) as u64,
+        amount: number(&body, None, "totalAmount").unwrap_or(0.0),
+    }
+}
+
+fn processor_fee(options: &Options, url: &str, window: &str) -> f64 {
+    let body = processor_body(options, url, window);
+    number(&body, None, "feePerTransaction").unwrap_or(0.0)
+}
+
+fn backend_summary(options: &Options, window: &str) -> (Totals, Totals) {
+    let mut client = Client::new(&options.backend, options.timeout);
+    let body = match client.request("GET", &format!("/payments-summary?{window}"), None, "") {
+        Ok((200, body)) => body,
+        Ok((status, _)) => {
+            logger::error!("Backend summary failed with HTTP {status}");
+            String::new()
+        }
+        Err(e) => {
+            logger::error!("Backend summary failed: {e}");
+            String::new()
+        }
+    };
+    let totals = |processor: &str| Totals {
+        requests: number(&body, Some(processor), "totalRequests").unwrap_or(0.0) as u64,
+        amount: number(&body, Some(processor), "totalAmount").unwrap_or(0.0),
+    };
+    (totals("default"), totals("fallback"))
+}
+
+fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now {
+        std::thread::sleep(deadline - now);
+    }
+}
+
+// UUID v4 de um xorshift por VU, não precisa ser criptográfico
+pub fn uuid(state: &mut u64) -> String {
+    let mut bytes = [0u8; 16];
+    for half in bytes.chunks_mut(8) {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        half.copy_from_slice(&state.to_be_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uuid() {
+        let mut seed = 42;
+        let first = uuid(&mut seed);
+        let second = uuid(&mut seed);
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 36);
+        assert_eq!(&first[14..15], "4");
+        assert!(matches!(&first[19..20], "8" | "9" | "a" | "b"));
+    }
+
+    // Cenário encolhido contra o modo standalone e os dois mocks, com os
+    // estágios de falha no meio: o que importa é não sobrar pagamento a mais
+    #[test]
+    fn test_run_against_mocks() {
+        logger::init("warn");
+        let default =
+            processor_mock::spawn("127.0.0.1:0", processor_mock::Options::default_processor())
+                .unwrap();
+        let fallback =
+            processor_mock::spawn("127.0.0.1:0", processor_mock::Options::fallback_processor())
+                .unwrap();
+        let default_url = format!("http://{}", default.addr);
+        let fallback_url = format!("http://{}", fallback.addr);
+        let vars = [
+            ("MODE", "standalone"),
+            ("PROCESSOR_DEFAULT_URL", default_url.as_str()),
+            ("PROCESSOR_FALLBACK_URL", fallback_url.as_str()),
+            ("DRAIN_TIMEOUT_MS", "1000"),
+        ];
+        let config = config::Config::from_pairs(&vars).unwrap();
+        let backend = standalone::spawn(config, "127.0.0.1:0").unwrap();
+
+        let options = Options {
+            backend: format!("http://{}", backend.addr),
+            default: default_url,
+            fallback: fallback_url,
+            max_requests: 5,
+            duration: Duration::from_secs(3),
+            token: "bench".to_string(),
+            ..Options::default()
+        };
+        let inputs = run(&options).unwrap();
+
+        assert!(inputs.success >= 5, "{inputs:?}");
+        assert_eq!(inputs.failure, 0, "{inputs:?}");
+        assert!(inputs.p99_ms > 0.0);
+        assert_eq!(inputs.default_fee, 0.05);
+        assert_eq!(inputs.fallback_fee, 0.15);
+        assert!(inputs.score().unwrap().lag >= 0, "{inputs:?}");
+        // o processador registra antes de responder, então o backend pode
+        // estar atrás mas nunca na frente
+        let processed = default.processor.summary(None, None).requests
+            + fallback.processor.summary(None, None).requests;
+        assert!(inputs.default.requests + inputs.fallback.requests <= processed);
+
+        backend.stop();
+        default.stop();
+        fallback.stop();
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use bench::Options;
+
+const USAGE: &str = "usage: bench [OPTIONS]
+
+Runs the resources/rinha.js scenario and writes partial-results.json.
+
+  --backend URL        Backend under test (default http://localhost:9999)
+  --default URL        Default processor (default http://localhost:8001)
+  --fallback URL       Fallback processor (default http://localhost:8002)
+  --max-requests N     Virtual users at the end of the ramp, MAX_REQUESTS (default 550)
+  --duration SECS      Scenario length, stages scale with it (default 60)
+  --token TOKEN        Processor admin token set during setup, TOKEN (default 123)
+  --participant NAME   PARTICIPANT, also picks ../participantes/NAME/partial-results.json
+  --output PATH        Where to write the results (default ./partial-results.json)
+  --mock               Start mock processors on the default and fallback addresses
+";
+
+fn main() {
+    let env = |name: &str| std::env::var(name).ok();
+    let mut options = Options::default();
+    let mut output = None;
+    let mut mock = false;
+    if let Some(max) = env("MAX_REQUESTS") {
+        options.max_requests = number(&max, "MAX_REQUESTS");
+    }
+    if let Some(token) = env("TOKEN") {
+        options.token = token;
+    }
+    let mut participant = env("PARTICIPANT");
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                eprintln!("error: {arg} needs a value\n\n{USAGE}");
+                std::process::exit(2);
+            })
+        };
+        match arg.as_str() {
+            "--backend" => options.backend = value(),
+            "--default" => options.default = value(),
+            "--fallback" => options.fallback = value(),
+            "--max-requests" => options.max_requests = number(&value(), "--max-requests"),
+            "--duration" => {
+                options.duration = Duration::from_secs(number(&value(), "--duration").max(1))
+            }
+            "--token" => options.token = value(),
+            "--participant" => participant = Some(value()),
+            "--output" => output = Some(value()),
+            "--mock" => mock = true,
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return;
+            }
+            other => {
+                eprintln!("error: unknown argument {other}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // mesmo destino do handleSummary
+    let output = output.unwrap_or_else(|| match &participant {
+        Some(name) => format!("../participantes/{name}/partial-results.json"),
+        None => "./partial-results.json".to_string(),
+    });
+    if let Some(name) = participant {
+        options.participant = name;
+    }
+
+    logger::init("info");
+    let mocks = match mock {
+        true => [
+            (
+                &options.default,
+                processor_mock::Options::default_processor(),
+            ),
+            (
+                &options.fallback,
+                processor_mock::Options::fallback_processor(),
+            ),
+        ]
+        .into_iter()
+        .map(|(url, mock)| {
+            let addr = url.trim_start_matches("http://").trim_end_matches('/');
+            processor_mock::spawn(addr, mock).unwrap_or_else(|e| {
+                eprintln!("error: unable to start the mock processor on {addr}: {e}");
+                std::process::exit(1);
+            })
+        })
+        .collect(),
+        false => Vec::new(),
+    };
+
+    let inputs = match bench::run(&options) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+    for mock in mocks {
+        mock.stop();
+    }
+
+    let (Some(score), Some(results)) = (inputs.score(), inputs.partial_results()) else {
+        eprintln!("error: totals out of range: {inputs:?}");
+        std::process::exit(1);
+    };
+    println!(
+        "requests: {} ok, {} failed\np99: {}ms (bonus {}%)\ninconsistencies: {}\nlag: {}\ntotal_liquido: {}",
+        inputs.success,
+        inputs.failure,
+        score::js_number(score.p99),
+        score::js_number(score.bonus * 100.0),
+        inputs.inconsistencies,
+        score.lag,
+        score::js_number(score.liquid)
+    );
+    if let Err(e) = std::fs::write(&output, results) {
+        eprintln!("error: unable to write {output}: {e}");
+        std::process::exit(1);
+    }
+    logger::flush();
+}
+
+fn number(value: &str, name: &str) -> u64 {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("error: {name} must be a number, got {value}");
+        std::process::exit(2);
+    })
+}
@@ -0,0 +1,611 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+mod toml;
+
+// Tudo que o binário lê do ambiente passa por aqui. Ordem: padrão, arquivo
+// TOML apontado por RINHA_CONFIG e por último as variáveis de ambiente.
+// Todos os erros são juntados e devolvidos de uma vez.
+
+pub const FILE_ENV: &str = "RINHA_CONFIG";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    LoadBalancer,
+    Worker,
+    Standalone,
+    Proxy, // só HTTP para upstreams, no lugar do nginx
+}
+
+impl Mode {
+    pub fn parse(value: &str) -> Option<Mode> {
+        match value {
+            "lb" | "api" | "load-balance" => Some(Mode::LoadBalancer),
+            "worker" => Some(Mode::Worker),
+            "standalone" => Some(Mode::Standalone),
+            "proxy" => Some(Mode::Proxy),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::LoadBalancer => "lb",
+            Mode::Worker => "worker",
+            Mode::Standalone => "standalone",
+            Mode::Proxy => "proxy",
+        }
+    }
+}
+
+// Compartilhado por todos os processos
+#[derive(Debug, Clone)]
+pub struct Common {
+    pub mode: Mode,
+    pub socket_dir: String,
+    pub log: String,
+    pub drain_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadBalancer {
+    pub port: u16,
+    pub max_slots: usize,
+    pub buffer_size: usize,
+    pub accept_per_iter: usize,
+    pub threads: usize,     // event loops com SO_REUSEPORT, cada um com seus links
+    pub max_workers: usize, // sockets abertos por renew
+    pub renew_after: usize, // falhas seguidas antes de renovar os sockets
+    pub reply_timeout: Duration,
+    pub header_timeout: Duration, // do accept (ou do fim da anterior) até os headers completos
+    pub body_timeout: Duration,
+    pub idle_timeout: Duration, // keep-alive parado entre requisições, só no proxy: o modo lb fecha depois da resposta
+    pub write_timeout: Duration,
+    pub purge_token: Option<String>,
+    pub workers: Vec<String>, // endereços fixos, vazio descobre os *.sock em socket_dir
+    pub ring: bool,           // pagamentos por ring em memória compartilhada com workers UNIX
+    pub rate_limit: u32,      // requisições por segundo por IP, 0 desliga
+    pub rate_burst: u32,
+    pub max_conns_per_ip: usize,   // 0 desliga
+    pub limiter_entries: usize,    // IPs lembrados pelo limitador, tamanho fixo
+    pub shed: bool,                // com os slots cheios responde 503 em vez de esperar no backlog
+    pub upstreams: Vec<String>,    // servidores HTTP do modo proxy
+    pub upstream_keepalive: usize, // conexões ociosas guardadas por upstream
+    pub upstream_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Worker {
+    pub host: String,
+    pub listen: Option<String>, // host:porta para TCP, sem ele UNIX socket em socket_dir
+    pub max_slots: usize,
+    pub buffer_size: usize, // múltiplo do frame de 54 bytes
+    pub accept_per_iter: usize,
+    pub threads: usize,
+    pub processor_timeout: Duration,
+    pub retry_interval: Duration,
+    pub default_url: String,
+    pub fallback_url: String,
+    pub credit_window: u64, // pagamentos na fila antes de segurar o load balancer, 0 desliga
+}
+
+#[derive(Debug, Clone)]
+pub struct Standalone {
+    pub workers: usize, // threads de worker, cada uma com "{host}-{n}"
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub common: Common,
+    pub lb: LoadBalancer,
+    pub worker: Worker,
+    pub standalone: Standalone,
+}
+
+#[derive(Debug)]
+pub struct Errors(pub Vec<String>);
+
+impl Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+struct Loader<'a> {
+    file: Vec<(String, String)>,
+    used: Vec<bool>,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    errors: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn raw(&mut self, key: &str, env: &str) -> Option<String> {
+        let from_file = self.file.iter().rposition(|(k, _)| k == key);
+        if let Some(index) = from_file {
+            self.used[index] = true;
+        }
+
+        (self.env)(env).or_else(|| from_file.map(|index| self.file[index].1.clone()))
+    }
+
+    fn string(&mut self, key: &str, env: &str, default: &str) -> String {
+        self.raw(key, env).unwrap_or_else(|| default.to_string())
+    }
+
+    fn optional(&mut self, key: &str, env: &str) -> Option<String> {
+        self.raw(key, env).filter(|value| !value.is_empty())
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &str, env: &str, default: T) -> T {
+        match self.raw(key, env) {
+            None => default,
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                self.errors
+                    .push(format!("{key} ({env}): invalid value '{value}'"));
+                default
+            }),
+        }
+    }
+
+    fn millis(&mut self, key: &str, env: &str, default: u64) -> Duration {
+        let ms = self.parse(key, env, default);
+        self.check(ms > 0, key, env, "must be greater than zero");
+        Duration::from_millis(ms)
+    }
+
+    fn at_least(&mut self, key: &str, env: &str, default: usize, min: usize) -> usize {
+        let value = self.parse(key, env, default);
+        self.check(value >= min, key, env, &format!("must be at least {min}"));
+        value
+    }
+
+    fn addresses(&mut self, key: &str, env: &str) -> Vec<String> {
+        let list = self.string(key, env, "");
+        let addresses: Vec<String> = list
+            .split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::to_string)
+            .collect();
+        for address in &addresses {
+            self.check(
+                valid_address(address),
+                key,
+                env,
+                &format!("'{address}' must look like host:port or unix:/path"),
+            );
+        }
+        addresses
+    }
+
+    fn url(&mut self, key: &str, env: &str, default: &str) -> String {
+        let url = self.string(key, env, default);
+        let valid = url
+            .strip_prefix("http://")
+            .is_some_and(|rest| !rest.is_empty() && !rest.contains('/'));
+        self.check(valid, key, env, "must look like http://host:port");
+        url
+    }
+
+    fn check(&mut self, ok: bool, key: &str, env: &str, message: &str) {
+        if !ok {
+            self.errors.push(format!("{key} ({env}): {message}"));
+        }
+    }
+}
+
+impl Config {
+    // env normalmente é std::env::var, a CLI passa as flags por cima
+    pub fn load(env: &dyn Fn(&str) -> Option<String>) -> Result<Config, Errors> {
+        let file = match env(FILE_ENV) {
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(text) => Some(text),
+                Err(e) => {
+                    return Err(Errors(vec![format!(
+                        "{FILE_ENV}: unable to read {path}: {e}"
+                    )]));
+                }
+            },
+            None => None,
+        };
+
+        Config::from_sources(file.as_deref(), env)
+    }
+
+    // Só variáveis, sem arquivo: testes, harness e simulação montam a config
+    // assim. Com a mesma chave repetida vale a primeira.
+    pub fn from_pairs(vars: &[(&str, &str)]) -> Result<Config, Errors> {
+        let env = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        Config::from_sources(None, &env)
+    }
+
+    pub fn from_sources(
+        file: Option<&str>,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Config, Errors> {
+        let mut errors = Vec::new();
+        let values = file
+            .map(|text| toml::parse(text, &mut errors))
+            .unwrap_or_default();
+        let mut errors: Vec<String> = errors
+            .into_iter()
+            .map(|error| format!("config file {error}"))
+            .collect();
+
+        let mut loader = Loader {
+            used: vec![false; values.len()],
+            file: values,
+            env,
+            errors: Vec::new(),
+        };
+
+        let mode = loader.string("mode", "MODE", "lb");
+        let mode = Mode::parse(&mode).unwrap_or_else(|| {
+            loader.errors.push(format!(
+                "mode (MODE): unknown mode '{mode}', expected lb, worker, standalone or proxy"
+            ));
+            Mode::LoadBalancer
+        });
+
+        // Lidos antes porque com endereços fixos a pasta compartilhada é opcional
+        let lb_workers = loader.addresses("lb.workers", "LB_WORKERS");
+        let mut listen = loader.addresses("worker.listen", "WORKER_LISTEN");
+        loader.check(
+            listen.len() <= 1,
+            "worker.listen",
+            "WORKER_LISTEN",
+            "takes a single address",
+        );
+        let listen = listen.pop();
+
+        // No standalone tudo fica em memória, a pasta só guarda pagamentos pendentes
+        let socket_dir = loader.string("socket_dir", "SOCKET_DIR", "");
+        let needs_dir = match mode {
+            Mode::LoadBalancer => lb_workers.is_empty(),
+            Mode::Worker => listen.is_none(),
+            Mode::Standalone | Mode::Proxy => false,
+        };
+        loader.check(
+            !socket_dir.is_empty() || !needs_dir,
+            "socket_dir",
+            "SOCKET_DIR",
+            "is required",
+        );
+
+        let log = loader.string("log", "RINHA_LOG", "info");
+        for directive in log.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let level = directive.rsplit('=').next().unwrap_or_default();
+            loader.check(
+                logger::Level::parse(level).is_some(),
+                "log",
+                "RINHA_LOG",
+                &format!("invalid level in '{directive}'"),
+            );
+        }
+
+        let common = Common {
+            mode,
+            socket_dir,
+            log,
+            drain_timeout: loader.millis("drain_timeout_ms", "DRAIN_TIMEOUT_MS", 5_000),
+        };
+
+        let port = loader.parse("lb.port", "PORT", 9999u16);
+        loader.check(port > 0, "lb.port", "PORT", "must be greater than zero");
+        let lb = LoadBalancer {
+            port,
+            max_slots: loader.at_least("lb.max_slots", "LB_MAX_SLOTS", 50, 1),
+            buffer_size: loader.at_least("lb.buffer_size", "LB_BUFFER_SIZE", 350, 128),
+            accept_per_iter: loader.at_least("lb.accept_per_iter", "LB_ACCEPT_PER_ITER", 10, 1),
+            threads: loader.at_least("lb.threads", "LB_THREADS", 1, 1),
+            max_workers: loader.at_least("lb.max_workers", "LB_MAX_WORKERS", 10, 1),
+            renew_after: loader.at_least("lb.renew_after", "LB_RENEW_AFTER", 10, 1),
+            reply_timeout: loader.millis("lb.reply_timeout_ms", "LB_REPLY_TIMEOUT_MS", 2_000),
+            header_timeout: loader.millis("lb.header_timeout_ms", "LB_HEADER_TIMEOUT_MS", 5_000),
+            body_timeout: loader.millis("lb.body_timeout_ms", "LB_BODY_TIMEOUT_MS", 5_000),
+            idle_timeout: loader.millis("lb.idle_timeout_ms", "LB_IDLE_TIMEOUT_MS", 30_000),
+            write_timeout: loader.millis("lb.write_timeout_ms", "LB_WRITE_TIMEOUT_MS", 5_000),
+            purge_token: loader.optional("lb.purge_token", "PURGE_TOKEN"),
+            workers: lb_workers,
+            ring: loader.parse("lb.ring", "LB_RING", false),
+            rate_limit: loader.parse("lb.rate_limit", "LB_RATE_LIMIT", 0),
+            rate_burst: loader.parse("lb.rate_burst", "LB_RATE_BURST", 50),
+            max_conns_per_ip: loader.parse("lb.max_conns_per_ip", "LB_MAX_CONNS_PER_IP", 0),
+            limiter_entries: loader.at_least("lb.limiter_entries", "LB_LIMITER_ENTRIES", 4096, 4),
+            shed: loader.parse("lb.shed", "LB_SHED", false),
+            upstreams: loader.addresses("lb.upstreams", "LB_UPSTREAMS"),
+            upstream_keepalive: loader.parse("lb.upstream_keepalive", "LB_UPSTREAM_KEEPALIVE", 16),
+            upstream_timeout: loader.millis(
+                "lb.upstream_timeout_ms",
+                "LB_UPSTREAM_TIMEOUT_MS",
+                2_000,
+            ),
+        };
+        loader.check(
+            lb.rate_limit == 0 || lb.rate_burst > 0,
+            "lb.rate_burst",
+            "LB_RATE_BURST",
+            "must be greater than zero with rate_limit set",
+        );
+        loader.check(
+            mode != Mode::Proxy || !lb.upstreams.is_empty(),
+            "lb.upstreams",
+            "LB_UPSTREAMS",
+            "is required in proxy mode",
+        );
+
+        let host = loader.string("worker.host", "HOST", "worker");
+        loader.check(
+            !host.is_empty() && !host.contains('/'),
+            "worker.host",
+            "HOST",
+            "must be a plain name, it becomes the socket file name",
+        );
+        let buffer_size = loader.at_least(
+            "worker.buffer_size",
+            "WORKER_BUFFER_SIZE",
+            540,
+            message::socket::Message::SIZE,
+        );
+        loader.check(
+            buffer_size.is_multiple_of(message::socket::Message::SIZE),
+            "worker.buffer_size",
+            "WORKER_BUFFER_SIZE",
+            &format!("must be a multiple of {}", message::socket::Message::SIZE),
+        );
+        let worker = Worker {
+            host,
+            listen,
+            max_slots: loader.at_least("worker.max_slots", "WORKER_MAX_SLOTS", 5, 1),
+            buffer_size,
+            accept_per_iter: loader.at_least(
+                "worker.accept_per_iter",
+                "WORKER_ACCEPT_PER_ITER",
+                10,
+                1,
+            ),
+            threads: loader.at_least("worker.threads", "WORKER_THREADS", 4, 1),
+            processor_timeout: loader.millis(
+                "worker.processor_timeout_ms",
+                "PROCESSOR_TIMEOUT_MS",
+                1_000,
+            ),
+            retry_interval: loader.millis(
+                "worker.retry_interval_ms",
+                "PROCESSOR_RETRY_INTERVAL_MS",
+                50,
+            ),
+            default_url: loader.url(
+                "worker.default_url",
+                "PROCESSOR_DEFAULT_URL",
+                "http://payment-processor-default:8080",
+            ),
+            fallback_url: loader.url(
+                "worker.fallback_url",
+                "PROCESSOR_FALLBACK_URL",
+                "http://payment-processor-fallback:8080",
+            ),
+            credit_window: loader.parse("worker.credit_window", "WORKER_CREDIT_WINDOW", 0),
+        };
+
+        let standalone = Standalone {
+            workers: loader.at_least("standalone.workers", "STANDALONE_WORKERS", 2, 1),
+        };
+
+        // Chave desconhecida no arquivo quase sempre é erro de digitação
+        for (index, (key, _)) in loader.file.iter().enumerate() {
+            if !loader.used[index] {
+                loader
+                    .errors
+                    .push(format!("config file: unknown key '{key}'"));
+            }
+        }
+
+        errors.append(&mut loader.errors);
+        if !errors.is_empty() {
+            return Err(Errors(errors));
+        }
+
+        Ok(Config {
+            common,
+            lb,
+            worker,
+            standalone,
+        })
+    }
+}
+
+// unix:/caminho ou host:porta, o nome é resolvido só na hora de conectar
+fn valid_address(address: &str) -> bool {
+    if let Some(path) = address.strip_prefix("unix:") {
+        return !path.is_empty();
+    }
+
+    address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0))
+}
+
+// No mesmo formato do arquivo, com segredos escondidos
+impl Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Config {
+            common,
+            lb,
+            worker,
+            standalone,
+        } = self;
+        writeln!(f, "mode = \"{}\"", common.mode.name())?;
+        writeln!(f, "socket_dir = \"{}\"", common.socket_dir)?;
+        writeln!(f, "log = \"{}\"", common.log)?;
+        writeln!(f, "drain_timeout_ms = {}", common.drain_timeout.as_millis())?;
+
+        writeln!(f, "[lb]")?;
+        writeln!(f, "port = {}", lb.port)?;
+        writeln!(f, "max_slots = {}", lb.max_slots)?;
+        writeln!(f, "buffer_size = {}", lb.buffer_size)?;
+        writeln!(f, "accept_per_iter = {}", lb.accept_per_iter)?;
+        writeln!(f, "threads = {}", lb.threads)?;
+        writeln!(f, "max_workers = {}", lb.max_workers)?;
+        writeln!(f, "renew_after = {}", lb.renew_after)?;
+        writeln!(f, "reply_timeout_ms = {}", lb.reply_timeout.as_millis())?;
+        writeln!(f, "header_timeout_ms = {}", lb.header_timeout.as_millis())?;
+        writeln!(f, "body_timeout_ms = {}", lb.body_timeout.as_millis())?;
+        writeln!(f, "idle_timeout_ms = {}", lb.idle_timeout.as_millis())?;
+        writeln!(f, "write_timeout_ms = {}", lb.write_timeout.as_millis())?;
+        match lb.purge_token {
+            Some(_) => writeln!(f, "purge_token = \"<redacted>\"")?,
+            None => writeln!(f, "# purge_token not set, purge is open")?,
+        }
+        writeln!(f, "workers = \"{}\"", lb.workers.join(","))?;
+        writeln!(f, "ring = {}", lb.ring)?;
+        writeln!(f, "rate_limit = {}", lb.rate_limit)?;
+        writeln!(f, "rate_burst = {}", lb.rate_burst)?;
+        writeln!(f, "max_conns_per_ip = {}", lb.max_conns_per_ip)?;
+        writeln!(f, "limiter_entries = {}", lb.limiter_entries)?;
+        writeln!(f, "shed = {}", lb.shed)?;
+        writeln!(f, "upstreams = \"{}\"", lb.upstreams.join(","))?;
+        writeln!(f, "upstream_keepalive = {}", lb.upstream_keepalive)?;
+        writeln!(
+            f,
+            "upstream_timeout_ms = {}",
+            lb.upstream_timeout.as_millis()
+        )?;
+
+        writeln!(f, "[worker]")?;
+        writeln!(f, "host = \"{}\"", worker.host)?;
+        match &worker.listen {
+            Some(listen) => writeln!(f, "listen = \"{listen}\"")?,
+            None => writeln!(f, "# listen not set, UNIX socket in socket_dir")?,
+        }
+        writeln!(f, "max_slots = {}", worker.max_slots)?;
+        writeln!(f, "buffer_size = {}", worker.buffer_size)?;
+        writeln!(f, "accept_per_iter = {}", worker.accept_per_iter)?;
+        writeln!(f, "threads = {}", worker.threads)?;
+        writeln!(
+            f,
+            "processor_timeout_ms = {}",
+            worker.processor_timeout.as_millis()
+        )?;
+        writeln!(
+            f,
+            "retry_interval_ms = {}",
+            worker.retry_interval.as_millis()
+        )?;
+        writeln!(f, "default_url = \"{}\"", worker.default_url)?;
+        writeln!(f, "fallback_url = \"{}\"", worker.fallback_url)?;
+        writeln!(f, "credit_window = {}", worker.credit_window)?;
+
+        writeln!(f, "[standalone]")?;
+        write!(f, "workers = {}", standalone.workers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn test_defaults_and_precedence() {
+        let file = "socket_dir = \"/from/file\"\n[lb]\nport = 8080\nmax_slots = 64\nring = true\n[worker]\nthreads = 8\n";
+        let vars = [
+            ("PORT", "9999"),
+            ("HOST", "api1"),
+            ("PURGE_TOKEN", "secret"),
+        ];
+        let config = Config::from_sources(Some(file), &env(&vars)).unwrap();
+
+        assert_eq!(config.common.mode, Mode::LoadBalancer);
+        assert_eq!(config.common.socket_dir, "/from/file");
+        assert_eq!(config.common.drain_timeout, Duration::from_secs(5));
+        assert_eq!(config.lb.port, 9999); // env ganha do arquivo
+        assert_eq!(config.lb.max_slots, 64);
+        assert_eq!(config.lb.buffer_size, 350);
+        assert!(config.lb.ring);
+        assert_eq!(config.lb.rate_limit, 0); // limitador desligado por padrão
+        assert_eq!(config.lb.limiter_entries, 4096);
+        assert!(!config.lb.shed);
+        assert_eq!(config.worker.host, "api1");
+        assert_eq!(config.worker.threads, 8);
+        assert_eq!(config.worker.buffer_size, 540);
+        assert_eq!(config.worker.credit_window, 0);
+
+        let printed = config.to_string();
+        assert!(printed.contains("purge_token = \"<redacted>\""));
+        assert!(!printed.contains("secret"));
+
+        // a saída é um arquivo válido com a mesma configuração
+        let reloaded =
+            Config::from_sources(Some(&printed.replace("<redacted>", "x")), &env(&[])).unwrap();
+        assert_eq!(reloaded.lb.port, 9999);
+        assert_eq!(reloaded.worker.host, "api1");
+
+        // standalone não precisa de pasta de sockets
+        let standalone = Config::from_pairs(&[("MODE", "standalone")]).unwrap();
+        assert_eq!(standalone.common.mode, Mode::Standalone);
+        assert_eq!(standalone.standalone.workers, 2);
+
+        // com endereços TCP também não
+        let vars = [("LB_WORKERS", "api1:7000, unix:/tmp/api2.sock")];
+        let tcp = Config::from_pairs(&vars).unwrap();
+        assert_eq!(tcp.lb.workers, vec!["api1:7000", "unix:/tmp/api2.sock"]);
+        let vars = [("MODE", "worker"), ("WORKER_LISTEN", "0.0.0.0:7000")];
+        let tcp = Config::from_pairs(&vars).unwrap();
+        assert_eq!(tcp.worker.listen.as_deref(), Some("0.0.0.0:7000"));
+        assert!(Config::from_pairs(&[("MODE", "worker")]).is_err());
+
+        // proxy só precisa dos upstreams
+        let vars = [("MODE", "proxy"), ("LB_UPSTREAMS", "api1:8080,api2:8080")];
+        let proxy = Config::from_pairs(&vars).unwrap();
+        assert_eq!(proxy.common.mode, Mode::Proxy);
+        assert_eq!(proxy.lb.upstreams, vec!["api1:8080", "api2:8080"]);
+        assert_eq!(proxy.lb.upstream_keepalive, 16);
+        let reloaded = Config::from_sources(Some(&proxy.to_string()), &env(&[])).unwrap();
+        assert_eq!(reloaded.lb.upstreams, proxy.lb.upstreams);
+        assert!(Config::from_pairs(&[("MODE", "proxy")]).is_err());
+    }
+
+    #[test]
+    fn test_every_error_reported() {
+        let file = "[lb]\nport = 0\nprot = 1\n[worker]\nbuffer_size = 100\n";
+        let vars = [
+            ("MODE", "gateway"),
+            ("WORKER_THREADS", "many"),
+            ("PROCESSOR_DEFAULT_URL", "https://x"),
+            ("RINHA_LOG", "info,worker=loud"),
+            ("WORKER_LISTEN", "api2, 0.0.0.0:7001"),
+        ];
+        let Errors(errors) = Config::from_sources(Some(file), &env(&vars)).unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "mode (MODE): unknown mode 'gateway', expected lb, worker, standalone or proxy",
+                "worker.listen (WORKER_LISTEN): 'api2' must look like host:port or unix:/path",
+                "worker.listen (WORKER_LISTEN): takes a single address",
+                "socket_dir (SOCKET_DIR): is required",
+                "log (RINHA_LOG): invalid level in 'worker=loud'",
+                "lb.port (PORT): must be greater than zero",
+                "worker.buffer_size (WORKER_BUFFER_SIZE): must be a multiple of 54",
+                "worker.threads (WORKER_THREADS): invalid value 'many'",
+                "worker.default_url (PROCESSOR_DEFAULT_URL): must look like http://host:port",
+                "config file: unknown key 'lb.prot'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// Só o pedaço de TOML que a configuração usa: [seção], chave = valor com
+// string, inteiro ou bool, e comentários com #. Chaves voltam como "secao.chave".
+
+pub fn parse(text: &str, errors: &mut Vec<String>) -> Vec<(String, String)> {
+    let mut values = Vec::new();
+    let mut section = String::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            match name.strip_suffix(']') {
+                Some(name) if is_key(name.trim()) => section = name.trim().to_string(),
+                _ => errors.push(format!("line {}: invalid section '{line}'", number + 1)),
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(format!("line {}: expected 'key = value'", number + 1));
+            continue;
+        };
+
+        let key = key.trim();
+        if !is_key(key) {
+            errors.push(format!("line {}: invalid key '{key}'", number + 1));
+            continue;
+        }
+
+        let Some(value) = parse_value(value.trim()) else {
+            errors.push(format!("line {}: invalid value for '{key}'", number + 1));
+            continue;
+        };
+
+        let key = if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{section}.{key}")
+        };
+        values.push((key, value));
+    }
+
+    values
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.')
+}
+
+// # dentro de string não é comentário
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let inner = quoted.strip_suffix('"')?;
+        if inner.contains('"') {
+            return None;
+        }
+        return Some(inner.replace("\\\\", "\\"));
+    }
+
+    if value == "true" || value == "false" {
+        return Some(value.to_string());
+    }
+
+    let digits = value.replace('_', "");
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(digits);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+# comentário
+socket_dir = "/var/run/sockets" # fim de linha
+drain_timeout_ms = 5_000
+
+[lb]
+port = 9999
+purge_token = "a#b"
+
+[worker]
+host = "api1"
+"#;
+        let mut errors = Vec::new();
+        let values = parse(text, &mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            values,
+            vec![
+                ("socket_dir".to_string(), "/var/run/sockets".to_string()),
+                ("drain_timeout_ms".to_string(), "5000".to_string()),
+                ("lb.port".to_string(), "9999".to_string()),
+                ("lb.purge_token".to_string(), "a#b".to_string()),
+                ("worker.host".to_string(), "api1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut errors = Vec::new();
+        let values = parse("[lb\nport\nport = nope\n= 1\nok = 1", &mut errors);
+        assert_eq!(values, vec![("ok".to_string(), "1".to_string())]);
+        assert_eq!(
+            errors,
+            vec![
+                "line 1: invalid section '[lb'",
+                "line 2: expected 'key = value'",
+                "line 3: invalid value for 'port'",
+                "line 4: invalid key ''",
+            ]
+        );
+    }
+}
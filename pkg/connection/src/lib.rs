@@ -1,6 +1,46 @@
 use std::io::{Read, Write};
 
-use message::socket::Message;
+use message::{
+    http::parse::{self, Progress},
+    socket::Message,
+};
+
+pub mod memory;
+pub mod metrics;
+pub mod reactor;
+pub mod ring;
+pub mod shutdown;
+pub mod tokens;
+pub mod transport;
+#[cfg(feature = "io-uring")]
+pub mod uring;
+
+// Leituras por chamada de read_messages: o epoll é edge-triggered, então lê
+// até o WouldBlock, mas sem deixar um link ocupado segurar o loop inteiro
+pub const READ_BUDGET: usize = 16;
+
+// Frames esperando o load balancer ler, acima disso o link é dado como parado
+pub const OUTBOUND_LIMIT: usize = 64 * 1024;
+
+// TCP (e às vezes o UNIX socket) corta frames no meio, o resto vem na próxima
+// leitura: o começo que sobrou vai para o início do buffer e o retorno diz
+// quantos bytes dele já estão lá
+pub fn parse_frames(buffer: &mut [u8], filled: usize) -> (Vec<Message>, usize) {
+    let msg_count = filled / Message::SIZE;
+    let mut messages: Vec<Message> = Vec::with_capacity(msg_count);
+    for i in 0..msg_count {
+        match Message::from_bytes(&buffer[i * Message::SIZE..(i + 1) * Message::SIZE]) {
+            Ok(message) => messages.push(message),
+            Err(e) => {
+                logger::warn!("Failed to parse message: {e}");
+                break;
+            }
+        }
+    }
+    buffer.copy_within(msg_count * Message::SIZE..filled, 0);
+
+    (messages, filled % Message::SIZE)
+}
 
 #[derive(PartialEq, Clone)]
 pub enum Status {
@@ -23,40 +63,80 @@ impl std::fmt::Debug for Status {
     }
 }
 
-pub struct Connection<const BUFFER_SIZE: usize, T> {
+pub struct Connection<T> {
     pub stream: Option<T>,
-    pub in_buffer: [u8; BUFFER_SIZE], // buffer for reading
-    pub out_buffer: Vec<u8>,          // buffer for writing
+    pub in_buffer: Box<[u8]>, // buffer for reading, tamanho vem da config
+    pub out_buffer: Vec<u8>,  // buffer for writing
     pub status: Status,
     written: usize, // bytes written
     round_trip: usize,
+    partial: usize, // começo de frame que chegou sem o resto, fica no início do in_buffer
+    filled: usize,  // bytes da requisição HTTP lidos até agora
+    backlog: bool,  // read_messages parou antes do WouldBlock
 }
 
 // O design dessa coisa teria ficado melhor se ele tivesse levado em conta apenas um modelo de request/response
 // o problema é deixar isso performatico
-impl<const BUFFER_SIZE: usize, T: Read + Write> Connection<BUFFER_SIZE, T>
+impl<T: Read + Write> Connection<T>
 where
     T: std::io::Read + std::io::Write,
 {
-    pub fn new(stream: Option<T>) -> Self {
+    pub fn new(stream: Option<T>, buffer_size: usize) -> Self {
         Connection {
             stream,
-            in_buffer: [0; BUFFER_SIZE],
-            out_buffer: Vec::with_capacity(BUFFER_SIZE),
+            in_buffer: vec![0; buffer_size].into_boxed_slice(),
+            out_buffer: Vec::with_capacity(buffer_size),
             written: 0,
             status: Status::Empty,
             round_trip: 0,
+            partial: 0,
+            filled: 0,
+            backlog: false,
         }
     }
 
+    pub fn open(&mut self, stream: T) {
+        self.stream = Some(stream);
+        self.status = Status::Readable;
+        metrics::ACTIVE.inc();
+    }
+
     pub fn reset(&mut self) {
+        if self.stream.is_some() {
+            metrics::ACTIVE.dec();
+        }
         self.written = 0;
         self.status = Status::Empty;
         self.round_trip = 0;
+        self.partial = 0;
+        self.filled = 0;
+        self.backlog = false;
         self.out_buffer.clear();
         self.stream = None;
     }
 
+    // O que chegou da requisição HTTP até agora
+    pub fn request(&self) -> &[u8] {
+        &self.in_buffer[..self.filled]
+    }
+
+    // Requisição e out_buffer ao mesmo tempo, para a resposta ser montada direto
+    pub fn request_and_out(&mut self) -> (&[u8], &mut Vec<u8>) {
+        (&self.in_buffer[..self.filled], &mut self.out_buffer)
+    }
+
+    pub fn progress(&self) -> Progress {
+        parse::progress(self.request())
+    }
+
+    // Sem evento novo o que ficou no socket só sai se quem chamou voltar aqui
+    pub fn backlog(&self) -> bool {
+        self.backlog
+    }
+
+    // Lê até o WouldBlock ou até READ_BUDGET leituras. Com o orçamento
+    // estourado (ou EOF depois de mensagens) backlog() fica true e a
+    // conexão precisa de outra passada.
     pub fn read_messages(&mut self) -> std::io::Result<Vec<Message>> {
         if self.stream.is_none() || self.status == Status::Close {
             return Err(std::io::Error::other(
@@ -65,43 +145,91 @@ where
         }
 
         let streamref = self.stream.as_mut().unwrap();
-        let n = streamref.read(&mut self.in_buffer)?;
-        if n == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Connection closed",
-            ));
-        }
-
-        let msg_count = n / Message::SIZE;
-        let mut messages: Vec<Message> = Vec::with_capacity(msg_count);
-        for i in 0..msg_count {
-            match Message::from_bytes(&self.in_buffer[i * Message::SIZE..(i + 1) * Message::SIZE]) {
-                Ok(message) => messages.push(message),
+        let mut messages = Vec::new();
+        let mut read = 0;
+        self.backlog = true;
+        for _ in 0..READ_BUDGET {
+            match streamref.read(&mut self.in_buffer[self.partial..]) {
+                Ok(0) if read == 0 => {
+                    self.backlog = false;
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Connection closed",
+                    ));
+                }
+                // entrega o que já leu, o EOF (ou o erro) vem na próxima passada
+                Ok(0) => break,
+                Ok(n) => {
+                    metrics::BYTES_READ.add(n as u64);
+                    read += n;
+                    let (parsed, partial) = parse_frames(&mut self.in_buffer, self.partial + n);
+                    self.partial = partial;
+                    messages.extend(parsed);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) if read == 0 => {
+                    self.backlog = false;
+                    return Err(e);
+                }
                 Err(e) => {
-                    eprintln!("Failed to parse message: {e}");
+                    self.backlog = e.kind() != std::io::ErrorKind::WouldBlock;
                     break;
                 }
             }
         }
-
         Ok(messages)
     }
 
-    pub fn write_messsage(&mut self, message: &Message) -> std::io::Result<()> {
+    // Entra na fila de saída e vai o quanto o socket aceitar agora, o resto
+    // sai nos próximos flush_messages (escrita parcial não desalinha nada)
+    pub fn queue_message(&mut self, message: &Message) -> std::io::Result<()> {
         if self.stream.is_none() || self.status == Status::Close {
             return Err(std::io::Error::other("Cannot write to a closed connection"));
         }
+        if self.out_buffer.len() - self.written + Message::SIZE > OUTBOUND_LIMIT {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                "outbound queue is full, peer is not reading",
+            ));
+        }
 
-        let streamref = self.stream.as_mut().unwrap();
         self.out_buffer.extend_from_slice(&message.to_bytes());
-        self.status = Status::Writable;
-        streamref.write_all(&self.out_buffer)
+        self.flush_messages()
+    }
+
+    pub fn flush_messages(&mut self) -> std::io::Result<()> {
+        let Some(streamref) = self.stream.as_mut() else {
+            return Ok(());
+        };
+        while self.written < self.out_buffer.len() {
+            match streamref.write(&self.out_buffer[self.written..]) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    metrics::BYTES_WRITTEN.add(n as u64);
+                    self.written += n;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        self.out_buffer.clear();
+        self.written = 0;
+        Ok(())
+    }
+
+    pub fn queued(&self) -> usize {
+        self.out_buffer.len() - self.written
     }
 
     pub fn http_handle(&mut self, event: &mio::event::Event) -> std::io::Result<&Status> {
+        self.http_ready(event.is_readable())
+    }
+
+    // O mesmo sem o evento do mio, para quem decide a prontidão (simulador)
+    pub fn http_ready(&mut self, readable: bool) -> std::io::Result<&Status> {
         if self.stream.is_none() || self.status == Status::Empty || self.status == Status::Close {
-            println!("{:?}", self.status);
+            logger::debug!("Unexpected status: {:?}", self.status);
             return Err(std::io::Error::other(
                 "Connot handle a closed, empty or non stream connections".to_string(),
             ));
@@ -109,16 +237,26 @@ where
 
         self.round_trip += 1;
         let streamref = self.stream.as_mut().unwrap();
-        if self.status == Status::Readable
-            && event.is_readable()
-            && let Ok(n) = streamref.read(&mut self.in_buffer)
-        {
+        // Edge-triggered: lê até o WouldBlock, o buffer do slot limita a conta
+        while self.status == Status::Readable && readable {
+            let n = match streamref.read(&mut self.in_buffer[self.filled..]) {
+                Ok(0) => return Ok(&Status::Close), // Connection closed
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(&self.status),
+                Err(e) => return Err(e),
+            };
             // println!("{} Reading.. {n}", self.round_trip);
-            if n == 0 {
-                return Ok(&Status::Close); // Connection closed
+            metrics::BYTES_READ.add(n as u64);
+            self.filled += n;
+
+            // Requisição partida espera o resto, com o buffer cheio vai como está
+            let request = &self.in_buffer[..self.filled];
+            if self.filled == self.in_buffer.len() || parse::progress(request) == Progress::Complete
+            {
+                self.status = Status::Writable;
+                return Ok(&self.status);
             }
-            self.status = Status::Writable;
-            return Ok(&self.status);
         }
 
         if self.status == Status::Writable && self.out_buffer.is_empty() {
@@ -129,13 +267,18 @@ where
             return Ok(&Status::Done(true)); // Nothing to send back
         }
 
-        if self.status == Status::Writable {
-            self.written +=
-                streamref.write(&self.out_buffer[self.written..self.out_buffer.len()])?;
+        while self.status == Status::Writable {
+            let n = match streamref.write(&self.out_buffer[self.written..self.out_buffer.len()]) {
+                Ok(0) => return Ok(&Status::Close), // Connection closed
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                // o resto sai no próximo evento de escrita
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(&self.status),
+                Err(e) => return Err(e),
+            };
+            metrics::BYTES_WRITTEN.add(n as u64);
+            self.written += n;
             // println!("{} Writing response.. {}", self.round_trip, self.written);
-            if self.written == 0 {
-                return Ok(&Status::Close); // Connection closed
-            }
 
             if self.written >= self.out_buffer.len() {
                 // println!(
@@ -0,0 +1,28 @@
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use message::socket::Message;
+
+// Link em memória entre load-balance e worker no mesmo processo. Trafega os
+// mesmos frames de 54 bytes do socket, assim o protocolo é um só.
+
+pub type Frame = [u8; Message::SIZE];
+
+pub struct Endpoint {
+    pub tx: Sender<Frame>,
+    pub rx: Receiver<Frame>,
+}
+
+pub fn pair() -> (Endpoint, Endpoint) {
+    let (left_tx, right_rx) = channel();
+    let (right_tx, left_rx) = channel();
+    (
+        Endpoint {
+            tx: left_tx,
+            rx: left_rx,
+        },
+        Endpoint {
+            tx: right_tx,
+            rx: right_rx,
+        },
+    )
+}
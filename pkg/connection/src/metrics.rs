@@ -0,0 +1,22 @@
+use metrics::{Counter, Gauge, Metric};
+
+pub static ACTIVE: Gauge = Gauge::new(
+    "rinha_connections_active",
+    "Connections currently attached to a slot",
+    &[],
+);
+pub static BYTES_READ: Counter = Counter::new(
+    "rinha_connection_read_bytes_total",
+    "Bytes read from connections",
+    &[],
+);
+pub static BYTES_WRITTEN: Counter = Counter::new(
+    "rinha_connection_written_bytes_total",
+    "Bytes written to connections",
+    &[],
+);
+
+pub fn register() {
+    let all: [&'static dyn Metric; 3] = [&ACTIVE, &BYTES_READ, &BYTES_WRITTEN];
+    metrics::register(&all);
+}
@@ -0,0 +1,77 @@
+use std::{
+    io::{Read, Write},
+    net::IpAddr,
+    os::fd::RawFd,
+};
+
+pub use mio::Interest;
+
+// O que os event loops pedem ao epoll: conexão nova do listener e o
+// interesse de cada stream. Em produção é o mio; o simulador implementa o
+// mesmo sobre a rede dele e decide sozinho quando cada token fica pronto.
+// O loop só vê a prontidão numa lista de Ready, venha de onde vier.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ready {
+    pub token: usize,
+    pub readable: bool,
+}
+
+impl From<&mio::event::Event> for Ready {
+    fn from(event: &mio::event::Event) -> Ready {
+        Ready {
+            token: event.token().0,
+            readable: event.is_readable(),
+        }
+    }
+}
+
+pub trait Reactor {
+    type Stream: Read + Write;
+
+    // WouldBlock com a fila do listener vazia. O IP só existe no TCP.
+    fn accept(&mut self) -> std::io::Result<(Self::Stream, Option<IpAddr>)>;
+
+    fn register(
+        &mut self,
+        stream: &mut Self::Stream,
+        token: usize,
+        interest: Interest,
+    ) -> std::io::Result<()>;
+
+    fn reregister(
+        &mut self,
+        stream: &mut Self::Stream,
+        token: usize,
+        interest: Interest,
+    ) -> std::io::Result<()>;
+
+    fn deregister(&mut self, stream: &mut Self::Stream) -> std::io::Result<()>;
+
+    // Drain: o listener fecha e o accept não é mais chamado
+    fn close(&mut self);
+
+    // Resposta pronta e close, sem a conexão ocupar slot
+    fn refuse(&mut self, stream: Self::Stream, response: &[u8]);
+}
+
+// O refuse de um socket de verdade. Lê antes o que o cliente já mandou:
+// close com dado não lido vira RST e o cliente perde a resposta.
+pub fn refuse(fd: RawFd, response: &[u8]) {
+    let mut discard = [0u8; 1024];
+    unsafe {
+        libc::recv(
+            fd,
+            discard.as_mut_ptr() as *mut libc::c_void,
+            discard.len(),
+            libc::MSG_DONTWAIT,
+        );
+        libc::send(
+            fd,
+            response.as_ptr() as *const libc::c_void,
+            response.len(),
+            libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+        );
+        libc::shutdown(fd, libc::SHUT_WR);
+    }
+}
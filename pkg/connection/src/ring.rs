@@ -0,0 +1,289 @@
+use std::{
+    fs::OpenOptions,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering, fence},
+    time::Duration,
+};
+
+use message::socket::Message;
+
+// Fila SPSC num arquivo mapeado (em /dev/shm na prática), do load balancer
+// para um worker. Carrega os mesmos frames de 54 bytes do socket, sem syscall
+// por mensagem: o produtor só faz futex_wake quando o consumidor está parado.
+// Head e tail ficam no arquivo, um consumidor novo continua de onde o outro parou.
+
+pub type Frame = [u8; Message::SIZE];
+
+const MAGIC: u64 = 0x5249_4E48_4152_4E47; // "RINHARNG"
+const SLOT: usize = 64; // frame + padding, um por linha de cache
+const AWAKE: u32 = 0;
+const PARKED: u32 = 1;
+
+#[repr(C, align(64))]
+struct Header {
+    magic: AtomicU64,
+    capacity: AtomicU64,
+}
+
+#[repr(C, align(64))]
+struct Producer {
+    tail: AtomicU64, // próximo frame a escrever
+}
+
+#[repr(C, align(64))]
+struct Consumer {
+    head: AtomicU64, // próximo frame a ler
+    bell: AtomicU32, // futex, PARKED enquanto o consumidor dorme
+}
+
+const SLOTS_AT: usize = 3 * 64;
+
+pub struct Ring {
+    ptr: *mut u8,
+    len: usize,
+    capacity: u64,
+    owned: Option<PathBuf>, // o produtor apaga o arquivo quando larga o ring
+}
+
+unsafe impl Send for Ring {}
+
+impl Ring {
+    // Lado do produtor, capacity é potência de 2
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> std::io::Result<Ring> {
+        assert!(
+            capacity.is_power_of_two(),
+            "ring capacity must be a power of two"
+        );
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let len = SLOTS_AT + capacity * SLOT;
+        file.set_len(len as u64)?;
+
+        let mut ring = Ring::map(&file, len)?;
+        ring.owned = Some(path.to_path_buf());
+        ring.capacity = capacity as u64;
+        ring.header()
+            .capacity
+            .store(capacity as u64, Ordering::Relaxed);
+        ring.header().magic.store(MAGIC, Ordering::Release);
+        Ok(ring)
+    }
+
+    // Lado do consumidor
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Ring> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < SLOTS_AT {
+            return Err(std::io::Error::other("ring file is too small"));
+        }
+
+        let mut ring = Ring::map(&file, len)?;
+        let capacity = ring.header().capacity.load(Ordering::Relaxed);
+        if ring.header().magic.load(Ordering::Acquire) != MAGIC
+            || !capacity.is_power_of_two()
+            || SLOTS_AT + capacity as usize * SLOT != len
+        {
+            return Err(std::io::Error::other("invalid ring file"));
+        }
+        ring.capacity = capacity;
+        Ok(ring)
+    }
+
+    fn map(file: &std::fs::File, len: usize) -> std::io::Result<Ring> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Ring {
+            ptr: ptr as *mut u8,
+            len,
+            capacity: 0,
+            owned: None,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.ptr as *const Header) }
+    }
+
+    fn producer(&self) -> &Producer {
+        unsafe { &*(self.ptr.add(64) as *const Producer) }
+    }
+
+    fn consumer(&self) -> &Consumer {
+        unsafe { &*(self.ptr.add(128) as *const Consumer) }
+    }
+
+    fn slot(&self, index: u64) -> *mut u8 {
+        unsafe {
+            self.ptr
+                .add(SLOTS_AT + (index & (self.capacity - 1)) as usize * SLOT)
+        }
+    }
+
+    // false com o ring cheio
+    pub fn push(&self, frame: &Frame) -> bool {
+        let tail = self.producer().tail.load(Ordering::Relaxed);
+        if tail - self.consumer().head.load(Ordering::Acquire) == self.capacity {
+            return false;
+        }
+
+        unsafe { std::ptr::copy_nonoverlapping(frame.as_ptr(), self.slot(tail), frame.len()) };
+        self.producer().tail.store(tail + 1, Ordering::Release);
+
+        // Par do store do bell no park: um dos dois sempre vê o outro
+        fence(Ordering::SeqCst);
+        let bell = &self.consumer().bell;
+        if bell.load(Ordering::Relaxed) == PARKED {
+            bell.store(AWAKE, Ordering::Relaxed);
+            futex(bell, libc::FUTEX_WAKE, 1, None);
+        }
+        true
+    }
+
+    // Entrega tudo que já chegou e só então avança o head, assim is_empty
+    // do produtor garante que os frames já foram tratados
+    pub fn consume(&self, mut handle: impl FnMut(&Frame)) -> usize {
+        let head = self.consumer().head.load(Ordering::Relaxed);
+        let tail = self.producer().tail.load(Ordering::Acquire);
+
+        let mut frame = [0u8; Message::SIZE];
+        for index in head..tail {
+            unsafe {
+                std::ptr::copy_nonoverlapping(self.slot(index), frame.as_mut_ptr(), frame.len())
+            };
+            handle(&frame);
+        }
+
+        if tail != head {
+            self.consumer().head.store(tail, Ordering::Release);
+        }
+        (tail - head) as usize
+    }
+
+    // Dorme até o produtor tocar o bell ou o timeout, para o consumidor olhar
+    // o shutdown de tempos em tempos
+    pub fn wait(&self, timeout: Duration) {
+        let bell = &self.consumer().bell;
+        bell.store(PARKED, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        if self.is_empty() {
+            futex(bell, libc::FUTEX_WAIT, PARKED, Some(timeout));
+        }
+        bell.store(AWAKE, Ordering::Relaxed);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.producer().tail.load(Ordering::Acquire) == self.consumer().head.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+        if let Some(path) = &self.owned {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Futex compartilhado (sem FUTEX_PRIVATE_FLAG), funciona entre processos no mesmo arquivo
+fn futex(word: &AtomicU32, op: libc::c_int, value: u32, timeout: Option<Duration>) {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            op,
+            value,
+            timeout
+                .as_ref()
+                .map_or(std::ptr::null(), |t| t as *const libc::timespec),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn frame(n: u64) -> Frame {
+        Message::Summary(n, n).to_bytes()
+    }
+
+    fn number(frame: &Frame) -> u64 {
+        match Message::from_bytes(frame).unwrap() {
+            Message::Summary(n, _) => n,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_wraparound_and_restart() {
+        let path = std::env::temp_dir().join(format!("ring-{}.ring", std::process::id()));
+        let producer = Ring::create(&path, 8).unwrap();
+        let mut received = Vec::new();
+
+        // várias voltas com o ring enchendo
+        let consumer = Ring::open(&path).unwrap();
+        for n in 0..20 {
+            while !producer.push(&frame(n)) {
+                consumer.consume(|f| received.push(number(f)));
+            }
+        }
+
+        // consumidor reinicia no meio, o próximo continua do head gravado
+        consumer.consume(|f| received.push(number(f)));
+        drop(consumer);
+        for n in 20..25 {
+            assert!(producer.push(&frame(n)));
+        }
+        let consumer = Ring::open(&path).unwrap();
+        consumer.consume(|f| received.push(number(f)));
+        assert_eq!(received, (0..25).collect::<Vec<_>>());
+        assert!(producer.is_empty());
+
+        // consumidor parado no futex acorda com o push de outra thread
+        let waiter = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut got = Vec::new();
+            while got.is_empty() {
+                consumer.wait(Duration::from_secs(5));
+                consumer.consume(|f| got.push(number(f)));
+            }
+            (got, started.elapsed())
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(producer.push(&frame(7)));
+        let (got, elapsed) = waiter.join().unwrap();
+        assert_eq!(got, vec![7]);
+        assert!(elapsed < Duration::from_secs(5));
+
+        drop(producer);
+        assert!(!path.exists());
+        assert!(Ring::open(&path).is_err());
+    }
+}
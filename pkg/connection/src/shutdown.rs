@@ -0,0 +1,266 @@
+use std::{
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        Arc, Mutex, Once,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+    },
+};
+
+use mio::{Registry, Token, Waker};
+
+// SIGTERM/SIGINT viram um byte num pipe (a única coisa segura de fazer dentro
+// do handler), uma thread lê o pipe e acorda o Waker de cada event loop.
+// Um segundo sinal durante o drain encerra na hora.
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+static WAKERS: Mutex<Vec<Arc<Waker>>> = Mutex::new(Vec::new());
+static EVENTFDS: Mutex<Vec<OwnedFd>> = Mutex::new(Vec::new());
+static INSTALL: Once = Once::new();
+
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = PIPE_WRITE.load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+
+    unsafe {
+        let errno = *libc::__errno_location();
+        libc::write(fd, [1u8].as_ptr() as *const libc::c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+pub fn install() {
+    INSTALL.call_once(|| {
+        let mut fds = [0 as libc::c_int; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            logger::error!(
+                "Unable to create signal pipe: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        PIPE_WRITE.store(fds[1], Ordering::Relaxed);
+
+        for signal in [libc::SIGTERM, libc::SIGINT] {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+
+        let read_fd = fds[0];
+        std::thread::Builder::new()
+            .name("signals".to_string())
+            .spawn(move || {
+                let mut byte = [0u8; 1];
+                loop {
+                    let n =
+                        unsafe { libc::read(read_fd, byte.as_mut_ptr() as *mut libc::c_void, 1) };
+                    if n < 0
+                        && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+                    {
+                        continue;
+                    }
+                    if n <= 0 {
+                        break;
+                    }
+
+                    if requested() {
+                        logger::warn!("Second signal received, exiting now");
+                        logger::flush();
+                        std::process::exit(130);
+                    }
+                    logger::info!("Signal received, draining");
+                    request();
+                }
+            })
+            .expect("unable to spawn signal thread");
+    });
+}
+
+// O loop recebe um evento com esse token quando o shutdown for pedido
+pub fn watch(registry: &Registry, token: Token) -> std::io::Result<()> {
+    watched(registry, token).map(|_| ())
+}
+
+fn watched(registry: &Registry, token: Token) -> std::io::Result<Arc<Waker>> {
+    let waker = Arc::new(Waker::new(registry, token)?);
+    let mut wakers = WAKERS.lock().expect("shutdown wakers poisoned");
+    if requested() {
+        waker.wake()?;
+    }
+    wakers.push(waker.clone());
+    Ok(waker)
+}
+
+// Mesma coisa para loops fora do mio (io_uring): o eventfd fica legível no
+// shutdown e vive até o fim do processo, igual aos wakers
+pub fn eventfd() -> std::io::Result<RawFd> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let raw = fd.as_raw_fd();
+
+    let mut eventfds = EVENTFDS.lock().expect("shutdown eventfds poisoned");
+    if requested() {
+        ring(raw);
+    }
+    eventfds.push(fd);
+    Ok(raw)
+}
+
+fn ring(fd: RawFd) {
+    let one = 1u64.to_ne_bytes();
+    if unsafe { libc::write(fd, one.as_ptr() as *const libc::c_void, one.len()) } < 0 {
+        logger::warn!(
+            "Unable to wake event loop: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+pub fn request() {
+    let wakers = WAKERS.lock().expect("shutdown wakers poisoned");
+    let eventfds = EVENTFDS.lock().expect("shutdown eventfds poisoned");
+    REQUESTED.store(true, Ordering::Release);
+    for waker in wakers.iter() {
+        if let Err(e) = waker.wake() {
+            logger::warn!("Unable to wake event loop: {e}");
+        }
+    }
+    for fd in eventfds.iter() {
+        ring(fd.as_raw_fd());
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Acquire)
+}
+
+// Shutdown de uma parte do processo só, um worker no harness de testes. O
+// loop que observa um Scope acorda no pedido dele e também no global, então
+// o Scope de quem nunca pede nada se comporta igual ao watch/eventfd.
+#[derive(Default)]
+pub struct Scope {
+    requested: AtomicBool,
+    wakers: Mutex<Vec<Arc<Waker>>>,
+    eventfds: Mutex<Vec<RawFd>>, // os fds são do EVENTFDS global
+}
+
+impl Scope {
+    pub fn watch(&self, registry: &Registry, token: Token) -> std::io::Result<()> {
+        let waker = watched(registry, token)?;
+        let mut wakers = self.wakers.lock().expect("scope wakers poisoned");
+        if self.requested() {
+            waker.wake()?;
+        }
+        wakers.push(waker);
+        Ok(())
+    }
+
+    pub fn eventfd(&self) -> std::io::Result<RawFd> {
+        let fd = eventfd()?;
+        let mut eventfds = self.eventfds.lock().expect("scope eventfds poisoned");
+        if self.requested() {
+            ring(fd);
+        }
+        eventfds.push(fd);
+        Ok(fd)
+    }
+
+    pub fn request(&self) {
+        let wakers = self.wakers.lock().expect("scope wakers poisoned");
+        let eventfds = self.eventfds.lock().expect("scope eventfds poisoned");
+        self.requested.store(true, Ordering::Release);
+        for waker in wakers.iter() {
+            if let Err(e) = waker.wake() {
+                logger::warn!("Unable to wake event loop: {e}");
+            }
+        }
+        eventfds.iter().for_each(|fd| ring(*fd));
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire) || requested()
+    }
+}
+
+// Para testes que sobem servidor depois de outro ter pedido shutdown no mesmo
+// processo. Wakers e eventfds antigos ficam: loop que ainda roda continua
+// acordando no próximo pedido.
+pub fn reset() {
+    let _wakers = WAKERS.lock().expect("shutdown wakers poisoned");
+    REQUESTED.store(false, Ordering::Release);
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_request_wakes_loops() {
+        const SHUTDOWN: Token = Token(usize::MAX);
+        let mut before = mio::Poll::new().unwrap();
+        watch(before.registry(), SHUTDOWN).unwrap();
+        let fd = eventfd().unwrap();
+
+        request();
+        assert!(requested());
+        let mut count = [0u8; 8];
+        let n = unsafe { libc::read(fd, count.as_mut_ptr() as *mut libc::c_void, 8) };
+        assert_eq!((n, u64::from_ne_bytes(count)), (8, 1));
+
+        let mut events = mio::Events::with_capacity(4);
+        before
+            .poll(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(events.iter().any(|event| event.token() == SHUTDOWN));
+
+        // quem se registra depois do pedido também é acordado
+        let mut after = mio::Poll::new().unwrap();
+        watch(after.registry(), SHUTDOWN).unwrap();
+        after
+            .poll(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(events.iter().any(|event| event.token() == SHUTDOWN));
+    }
+
+    #[test]
+    fn test_scope_wakes_only_its_loop() {
+        const SHUTDOWN: Token = Token(usize::MAX);
+        let scope = Scope::default();
+        let mut mine = mio::Poll::new().unwrap();
+        scope.watch(mine.registry(), SHUTDOWN).unwrap();
+        let fd = scope.eventfd().unwrap();
+        let mut other = mio::Poll::new().unwrap();
+        watch(other.registry(), SHUTDOWN).unwrap();
+
+        scope.request();
+        assert!(scope.requested());
+        // mais de 1 se o global também tocou
+        let mut count = [0u8; 8];
+        let n = unsafe { libc::read(fd, count.as_mut_ptr() as *mut libc::c_void, 8) };
+        assert!(n == 8 && u64::from_ne_bytes(count) >= 1);
+
+        let mut events = mio::Events::with_capacity(4);
+        mine.poll(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(events.iter().any(|event| event.token() == SHUTDOWN));
+        // o global pode ter sido pedido pelo outro teste, só vale sem ele
+        if !requested() {
+            other
+                .poll(&mut events, Some(Duration::from_millis(50)))
+                .unwrap();
+            assert!(events.is_empty());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Token do mio para cada conexão: cresce sempre e token % slots é o slot. O
+// slot lembra o token de quem está nele, então evento atrasado (ou requeue)
+// de uma conexão que já fechou não cai na que reusou o slot.
+
+pub struct Tokens {
+    current: Vec<Option<usize>>,
+    next: usize, // 0 é do listener
+    open: usize,
+}
+
+impl Tokens {
+    pub fn new(max_slots: usize) -> Tokens {
+        Tokens {
+            current: vec![None; max_slots],
+            next: 1,
+            open: 0,
+        }
+    }
+
+    // (token, slot) da conexão nova, None com todos os slots ocupados
+    pub fn open(&mut self) -> Option<(usize, usize)> {
+        if self.open == self.current.len() {
+            return None;
+        }
+        // pula os slots que ainda estão ocupados
+        while self.current[self.next % self.current.len()].is_some() {
+            self.next += 1;
+        }
+        let (token, slot) = (self.next, self.next % self.current.len());
+        self.current[slot] = Some(token);
+        self.next += 1;
+        self.open += 1;
+        Some((token, slot))
+    }
+
+    // Só o token de quem ainda está no slot
+    pub fn slot(&self, token: usize) -> Option<usize> {
+        let slot = token % self.current.len();
+        (self.current[slot] == Some(token)).then_some(slot)
+    }
+
+    pub fn token(&self, slot: usize) -> Option<usize> {
+        self.current[slot]
+    }
+
+    pub fn close(&mut self, slot: usize) {
+        if self.current[slot].take().is_some() {
+            self.open -= 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.open
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.open == self.current.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stale_token() {
+        let mut tokens = Tokens::new(2);
+        let (first, slot) = tokens.open().unwrap();
+        let (second, _) = tokens.open().unwrap();
+        assert!(tokens.open().is_none());
+
+        // o slot do primeiro volta com outro token
+        tokens.close(slot);
+        let (third, reused) = tokens.open().unwrap();
+        assert_eq!(reused, slot);
+        assert_ne!(third, first);
+        assert_eq!(tokens.slot(first), None);
+        assert_eq!(tokens.slot(third), Some(slot));
+        assert_eq!(tokens.slot(second), Some(1 - slot));
+        assert_eq!(tokens.len(), 2);
+
+        // pula o slot ocupado em vez de sobrescrever
+        tokens.close(1 - slot);
+        let (_, free) = tokens.open().unwrap();
+        assert_eq!(free, 1 - slot);
+    }
+}
@@ -0,0 +1,473 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    os::fd::{AsRawFd, RawFd},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use message::socket::Message;
+use mio::{
+    Interest, Registry, Token,
+    event::Source,
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+
+// Transporte entre load-balance e worker: UNIX socket numa pasta compartilhada
+// ou TCP entre máquinas. Os dois carregam os mesmos frames de 54 bytes.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Unix(PathBuf),
+    Tcp(String), // host:porta, resolvido a cada conexão
+}
+
+impl Address {
+    pub fn parse(address: &str) -> Address {
+        match address.strip_prefix("unix:") {
+            Some(path) => Address::Unix(PathBuf::from(path)),
+            None => Address::Tcp(address.to_string()),
+        }
+    }
+}
+
+// Endereço já resolvido: conectar nele não passa pelo DNS
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl Address {
+    // getaddrinfo bloqueia, quem conecta de dentro de um event loop resolve
+    // uma vez antes
+    pub fn resolve(&self) -> std::io::Result<Target> {
+        match self {
+            Address::Unix(path) => Ok(Target::Unix(path.clone())),
+            Address::Tcp(address) => resolve(address).map(Target::Tcp),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Tcp(address) => write!(f, "{address}"),
+        }
+    }
+}
+
+pub enum Listener {
+    Unix(UnixListener, PathBuf),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    pub fn bind(address: &Address) -> std::io::Result<Listener> {
+        match address {
+            Address::Unix(path) => {
+                // socket de uma execução anterior
+                let _ = std::fs::remove_file(path);
+                Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+            Address::Tcp(address) => {
+                let addr = resolve(address)?;
+                Ok(Listener::Tcp(TcpListener::bind(addr)?))
+            }
+        }
+    }
+
+    pub fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Unix(listener, _) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+        }
+    }
+
+    // Sem o arquivo ou a porta o load balancer não reconecta
+    pub fn close(self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Para loops que aceitam fora do mio (io_uring)
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Source for Listener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            Listener::Unix(listener, _) => listener.register(registry, token, interests),
+            Listener::Tcp(listener) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            Listener::Unix(listener, _) => listener.reregister(registry, token, interests),
+            Listener::Tcp(listener) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        match self {
+            Listener::Unix(listener, _) => listener.deregister(registry),
+            Listener::Tcp(listener) => listener.deregister(registry),
+        }
+    }
+}
+
+pub enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    // Conecta bloqueando e só depois vira não bloqueante, assim o primeiro
+    // frame não esbarra num connect em andamento
+    pub fn connect(address: &Address, timeout: Duration) -> std::io::Result<Stream> {
+        match address {
+            Address::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_nonblocking(true)?;
+                Ok(Stream::Unix(UnixStream::from_std(stream)))
+            }
+            Address::Tcp(address) => {
+                let stream = std::net::TcpStream::connect_timeout(&resolve(address)?, timeout)?;
+                stream.set_nodelay(true)?;
+                stream.set_nonblocking(true)?;
+                Ok(Stream::Tcp(TcpStream::from_std(stream)))
+            }
+        }
+    }
+
+    // Sem bloquear quem está num event loop: o handshake termina depois, quem
+    // chama espera o WRITABLE e confere com connected()
+    pub fn connect_nonblocking(target: &Target) -> std::io::Result<Stream> {
+        match target {
+            Target::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+            Target::Tcp(address) => {
+                let stream = TcpStream::connect(*address)?;
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+        }
+    }
+
+    // Ok(false) enquanto o connect não terminou, a falha dele (SO_ERROR) vem como erro
+    pub fn connected(&self) -> std::io::Result<bool> {
+        let (error, peer) = match self {
+            Stream::Unix(stream) => (stream.take_error()?, stream.peer_addr().map(drop)),
+            Stream::Tcp(stream) => (stream.take_error()?, stream.peer_addr().map(drop)),
+        };
+        if let Some(e) = error {
+            return Err(e);
+        }
+        match peer {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotConnected => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Um frame inteiro ou nada: Ok(false) quando nenhum byte saiu até o
+    // deadline. Se sair só uma parte o stream fica desalinhado e vira erro.
+    pub fn write_frame(
+        &mut self,
+        frame: &[u8; Message::SIZE],
+        deadline: Instant,
+    ) -> std::io::Result<bool> {
+        let mut written = 0;
+        while written < frame.len() {
+            match self.write(&frame[written..]) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        if written == 0 {
+                            return Ok(false);
+                        }
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            "frame partially written",
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_micros(100));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn read_frame(&mut self, deadline: Instant) -> std::io::Result<Message> {
+        let mut frame = [0u8; Message::SIZE];
+        let mut read = 0;
+
+        while read < frame.len() {
+            match self.read(&mut frame[read..]) {
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "peer closed the connection",
+                    ));
+                }
+                Ok(n) => read += n,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            "reply timed out",
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Message::from_bytes(&frame).map_err(std::io::Error::other)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.register(registry, token, interests),
+            Stream::Tcp(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.reregister(registry, token, interests),
+            Stream::Tcp(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.deregister(registry),
+            Stream::Tcp(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+fn resolve(address: &str) -> std::io::Result<std::net::SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no address found for {address}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Connection, OUTBOUND_LIMIT};
+
+    // Mesmo protocolo nos dois transportes, inclusive com frame quebrado no meio
+    fn round_trip(address: Address) {
+        let listener = Listener::bind(&address).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => Address::Tcp(listener.local_addr().unwrap().to_string()),
+            Listener::Unix(..) => address,
+        };
+
+        let mut client = Stream::connect(&address, Duration::from_secs(1)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let server = loop {
+            match listener.accept() {
+                Ok(stream) => break stream,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline);
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => panic!("{e}"),
+            }
+        };
+
+        let frame = Message::Summary(1, 2).to_bytes();
+        client.write_all(&frame[..20]).unwrap();
+        let mut conn = Connection::new(None, Message::SIZE * 4);
+        conn.open(server);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(conn.read_messages().unwrap().is_empty());
+
+        client.write_all(&frame[20..]).unwrap();
+        assert!(
+            client
+                .write_frame(&Message::Purge.to_bytes(), deadline)
+                .unwrap()
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        let messages = conn.read_messages().unwrap();
+        assert!(matches!(
+            messages[..],
+            [Message::Summary(1, 2), Message::Purge]
+        ));
+
+        conn.queue_message(&Message::Ack).unwrap();
+        assert!(matches!(client.read_frame(deadline).unwrap(), Message::Ack));
+        listener.close();
+    }
+
+    #[test]
+    fn test_unix_and_tcp() {
+        let path = std::env::temp_dir().join(format!("transport-{}.sock", std::process::id()));
+        round_trip(Address::Unix(path.clone()));
+        assert!(!path.exists());
+        round_trip(Address::parse("127.0.0.1:0"));
+    }
+
+    // Mais frames que o orçamento de uma passada: sai em várias, sem perder
+    // nada e sem depender de evento novo
+    #[test]
+    fn test_read_budget() {
+        let (writer, reader) = std::os::unix::net::UnixStream::pair().unwrap();
+        reader.set_nonblocking(true).unwrap();
+        let mut conn = Connection::new(None, Message::SIZE * 4);
+        conn.open(UnixStream::from_std(reader));
+
+        let total = crate::READ_BUDGET * 4 * 3 + 2;
+        let mut frames = Vec::new();
+        for n in 0..total {
+            frames.extend_from_slice(&Message::Summary(n as u64, 0).to_bytes());
+        }
+        (&writer).write_all(&frames).unwrap();
+
+        let mut received = Vec::new();
+        let mut passes = 0;
+        loop {
+            passes += 1;
+            received.extend(conn.read_messages().unwrap());
+            if !conn.backlog() {
+                break;
+            }
+        }
+        assert_eq!(passes, 4);
+        assert_eq!(received.len(), total);
+        assert!(
+            received.iter().enumerate().all(
+                |(n, message)| matches!(message, Message::Summary(from, 0) if *from == n as u64)
+            )
+        );
+
+        // EOF logo depois dos dados ainda entrega os dados primeiro
+        (&writer).write_all(&frames[..Message::SIZE]).unwrap();
+        drop(writer);
+        assert_eq!(conn.read_messages().unwrap().len(), 1);
+        assert!(conn.backlog());
+        let eof = conn.read_messages().unwrap_err();
+        assert_eq!(eof.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    // Socket cheio no meio de um frame: o resto fica na fila e sai inteiro
+    // quando o outro lado volta a ler
+    #[test]
+    fn test_outbound_queue() {
+        let (reader, writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        writer.set_nonblocking(true).unwrap();
+        let mut conn = Connection::new(None, Message::SIZE * 4);
+        conn.open(UnixStream::from_std(writer));
+
+        let mut sent = 0;
+        while conn.queued() == 0 {
+            conn.queue_message(&Message::Credit(sent)).unwrap();
+            sent += 1;
+        }
+        // mais alguns atrás do que ficou pela metade
+        for _ in 0..3 {
+            conn.queue_message(&Message::Credit(sent)).unwrap();
+            sent += 1;
+        }
+
+        let mut reader = Stream::Unix(UnixStream::from_std(reader));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        for expected in 0..sent {
+            if expected % 64 == 0 {
+                conn.flush_messages().unwrap();
+            }
+            match reader.read_frame(deadline) {
+                Ok(Message::Credit(n)) => assert_eq!(n, expected),
+                other => panic!("frame {expected}: {other:?}"),
+            }
+        }
+        conn.flush_messages().unwrap();
+        assert_eq!(conn.queued(), 0);
+
+        // quem não lê nunca não faz a fila crescer sem limite
+        let mut full = Ok(());
+        for _ in 0..OUTBOUND_LIMIT {
+            full = conn.queue_message(&Message::Ack);
+            if full.is_err() {
+                break;
+            }
+        }
+        assert_eq!(full.unwrap_err().kind(), std::io::ErrorKind::StorageFull);
+    }
+}
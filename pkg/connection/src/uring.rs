@@ -0,0 +1,305 @@
+use std::{os::fd::RawFd, time::Duration};
+
+use io_uring::{
+    IoUring, cqueue, opcode, squeue,
+    types::{CancelBuilder, Fd, SubmitArgs, Timespec},
+};
+
+// Event loop em io_uring para quem quiser trocar o epoll do mio: um buffer
+// registrado por slot (ReadFixed/WriteFixed sem copiar páginas a cada
+// operação), accept multishot e tudo que o loop empilha numa iteração sai num
+// único io_uring_enter. O user_data de cada operação é slot << 8 | op.
+
+pub const ACCEPT: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITE: u8 = 3;
+pub const CLOSE: u8 = 4;
+pub const SHUTDOWN: u8 = 5;
+pub const CANCEL: u8 = 6;
+pub const WAKE: u8 = 7;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Completion {
+    pub op: u8,
+    pub slot: usize,
+    pub result: i32,
+    pub more: bool, // multishot continua armado
+}
+
+pub struct Reactor {
+    ring: IoUring,
+    buffers: Box<[u8]>, // slots * buffer_size, registrado como um bloco só
+    buffer_size: usize,
+    multishot: bool,
+    inflight: usize,
+    overflow: Vec<squeue::Entry>, // o que não coube na fila de submissão
+}
+
+impl Reactor {
+    // Erro aqui quer dizer kernel sem io_uring (ou bloqueado por seccomp/limite
+    // de memlock), quem chama volta para o mio
+    pub fn new(slots: usize, buffer_size: usize) -> std::io::Result<Reactor> {
+        let entries = (slots * 2 + 16).next_power_of_two().clamp(64, 4096) as u32;
+        let ring = IoUring::new(entries)?;
+        let mut buffers = vec![0u8; slots * buffer_size].into_boxed_slice();
+
+        let iovec = libc::iovec {
+            iov_base: buffers.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffers.len(),
+        };
+        unsafe { ring.submitter().register_buffers(&[iovec])? };
+
+        Ok(Reactor {
+            ring,
+            buffers,
+            buffer_size,
+            multishot: true,
+            inflight: 0,
+            overflow: Vec::new(),
+        })
+    }
+
+    pub fn buffer(&mut self, slot: usize) -> &mut [u8] {
+        &mut self.buffers[slot * self.buffer_size..(slot + 1) * self.buffer_size]
+    }
+
+    pub fn accept(&mut self, listener: RawFd) {
+        let entry = match self.multishot {
+            true => opcode::AcceptMulti::new(Fd(listener))
+                .flags(libc::SOCK_CLOEXEC)
+                .build(),
+            false => opcode::Accept::new(Fd(listener), std::ptr::null_mut(), std::ptr::null_mut())
+                .flags(libc::SOCK_CLOEXEC)
+                .build(),
+        };
+        self.push(entry, ACCEPT, 0);
+    }
+
+    // Lê no buffer do slot a partir de offset (o pedaço de frame que sobrou)
+    pub fn read(&mut self, slot: usize, fd: RawFd, offset: usize) {
+        let buffer = &mut self.buffer(slot)[offset..];
+        let entry = opcode::ReadFixed::new(Fd(fd), buffer.as_mut_ptr(), buffer.len() as u32, 0)
+            .offset(u64::MAX) // posição atual, sockets não têm offset
+            .build();
+        self.push(entry, READ, slot);
+    }
+
+    pub fn write(&mut self, slot: usize, fd: RawFd, range: std::ops::Range<usize>) {
+        let buffer = &self.buffer(slot)[range];
+        let entry = opcode::WriteFixed::new(Fd(fd), buffer.as_ptr(), buffer.len() as u32, 0)
+            .offset(u64::MAX)
+            .build();
+        self.push(entry, WRITE, slot);
+    }
+
+    /// Envia bytes fora dos buffers registrados (resposta maior que o slot).
+    ///
+    /// # Safety
+    ///
+    /// `data` precisa continuar vivo e sem mudar até a conclusão do WRITE do slot.
+    pub unsafe fn send(&mut self, slot: usize, fd: RawFd, data: &[u8]) {
+        let entry = opcode::Send::new(Fd(fd), data.as_ptr(), data.len() as u32)
+            .flags(libc::MSG_NOSIGNAL)
+            .build();
+        self.push(entry, WRITE, slot);
+    }
+
+    pub fn close(&mut self, slot: usize, fd: RawFd) {
+        self.push(opcode::Close::new(Fd(fd)).build(), CLOSE, slot);
+    }
+
+    // Fica pronto quando o eventfd do shutdown for escrito
+    pub fn watch(&mut self, eventfd: RawFd) {
+        let entry = opcode::PollAdd::new(Fd(eventfd), libc::POLLIN as u32).build();
+        self.push(entry, SHUTDOWN, 0);
+    }
+
+    // O mesmo para um eventfd de aviso qualquer, conclui como WAKE e precisa
+    // ser armado de novo depois de cada conclusão
+    pub fn notify(&mut self, eventfd: RawFd) {
+        let entry = opcode::PollAdd::new(Fd(eventfd), libc::POLLIN as u32).build();
+        self.push(entry, WAKE, 0);
+    }
+
+    pub fn cancel_accept(&mut self) {
+        self.cancel(0, ACCEPT);
+    }
+
+    // A operação cancelada ainda conclui (com -ECANCELED ou o resultado, se
+    // ganhou a corrida), só depois disso o slot pode ser reaproveitado
+    pub fn cancel(&mut self, slot: usize, op: u8) {
+        let entry = opcode::AsyncCancel::new(user_data(op, slot)).build();
+        self.push(entry, CANCEL, slot);
+    }
+
+    fn push(&mut self, entry: squeue::Entry, op: u8, slot: usize) {
+        let entry = entry.user_data(user_data(op, slot));
+        self.inflight += 1;
+        if !self.overflow.is_empty() || unsafe { self.ring.submission().push(&entry).is_err() } {
+            self.overflow.push(entry);
+        }
+    }
+
+    // Submete o que foi empilhado e espera pelo menos uma conclusão
+    pub fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+        completions: &mut Vec<Completion>,
+    ) -> std::io::Result<()> {
+        while !self.overflow.is_empty() {
+            let mut pending = std::mem::take(&mut self.overflow).into_iter();
+            for entry in pending.by_ref() {
+                if unsafe { self.ring.submission().push(&entry).is_err() } {
+                    self.overflow.push(entry);
+                    break;
+                }
+            }
+            self.overflow.extend(pending);
+            if !self.overflow.is_empty() {
+                self.ring.submit()?;
+            }
+        }
+
+        let submitted = match timeout {
+            Some(timeout) => {
+                let timespec = Timespec::from(timeout);
+                let args = SubmitArgs::new().timespec(&timespec);
+                self.ring.submitter().submit_with_args(1, &args)
+            }
+            None => self.ring.submit_and_wait(1),
+        };
+        match submitted {
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::ETIME | libc::EINTR | libc::EBUSY)
+                ) => {}
+            Err(e) => return Err(e),
+        }
+
+        completions.clear();
+        for cqe in self.ring.completion() {
+            let completion = Completion {
+                op: (cqe.user_data() & 0xff) as u8,
+                slot: (cqe.user_data() >> 8) as usize,
+                result: cqe.result(),
+                more: cqueue::more(cqe.flags()),
+            };
+            if !completion.more {
+                self.inflight -= 1;
+            }
+
+            // Kernel antes do 5.19: accept simples, rearmado a cada conexão
+            if completion.op == ACCEPT && completion.result == -libc::EINVAL && self.multishot {
+                logger::warn!("Multishot accept not supported, using single accepts");
+                self.multishot = false;
+            }
+            completions.push(completion);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Reactor {
+    // O kernel escreve nos buffers enquanto houver leitura pendente, então
+    // cancela tudo e espera antes de liberar a memória
+    fn drop(&mut self) {
+        if self.inflight == 0 {
+            return;
+        }
+
+        let entry = opcode::AsyncCancel2::new(CancelBuilder::any()).build();
+        self.push(entry, CANCEL, 0);
+        let mut completions = Vec::new();
+        for _ in 0..10 {
+            if self.inflight == 0
+                || self
+                    .wait(Some(Duration::from_millis(10)), &mut completions)
+                    .is_err()
+            {
+                break;
+            }
+        }
+
+        if self.inflight > 0 {
+            logger::warn!("io_uring operations still in flight, leaking buffers"; inflight = self.inflight);
+            std::mem::forget(std::mem::take(&mut self.buffers));
+        }
+    }
+}
+
+fn user_data(op: u8, slot: usize) -> u64 {
+    (slot as u64) << 8 | op as u64
+}
+
+// O io_uring devolve EAGAIN em fd O_NONBLOCK em vez de esperar, então os
+// sockets do loop precisam ser bloqueantes
+pub fn blocking(fd: RawFd) -> std::io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        os::fd::AsRawFd,
+    };
+
+    use super::*;
+
+    // accept, leitura no buffer registrado, escrita de volta e close
+    #[test]
+    fn test_echo() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut reactor = Reactor::new(2, 64).unwrap();
+        reactor.accept(listener.as_raw_fd());
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"ping").unwrap();
+
+        let mut completions = Vec::new();
+        let mut accepted = -1;
+        let mut closed = false;
+        while !closed {
+            reactor
+                .wait(Some(Duration::from_secs(1)), &mut completions)
+                .unwrap();
+            assert!(!completions.is_empty(), "no completion before timeout");
+            for completion in completions.iter() {
+                match completion.op {
+                    ACCEPT => {
+                        assert!(completion.result >= 0, "{completion:?}");
+                        accepted = completion.result;
+                        reactor.read(1, accepted, 0);
+                    }
+                    READ => {
+                        assert_eq!(completion.result, 4);
+                        assert_eq!(&reactor.buffer(1)[..4], b"ping");
+                        reactor.buffer(1)[..4].copy_from_slice(b"pong");
+                        reactor.write(1, accepted, 0..4);
+                    }
+                    WRITE => {
+                        assert_eq!(completion.result, 4);
+                        reactor.close(1, accepted);
+                    }
+                    CLOSE => closed = true,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"pong");
+
+        // o accept multishot continua pendente, o drop cancela antes de soltar os buffers
+        reactor.cancel_accept();
+        drop(reactor);
+    }
+}
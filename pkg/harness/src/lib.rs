@@ -0,0 +1,453 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use bench::client::{Client, number};
+use config::Config;
+use connection::shutdown;
+use ledger::{Processor, Summary, Totals};
+
+// Load balancer, N workers numa pasta de sockets temporária e os dois
+// processadores falsos, tudo no mesmo processo e com o mesmo código do modo
+// separado. O teste manda tráfego HTTP de verdade, injeta falhas no meio e
+// no fim confere o que a rinha confere.
+
+// O shutdown é do processo inteiro, então um cluster por vez
+static CLUSTERS: Mutex<()> = Mutex::new(());
+static DIRS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub workers: usize,
+    // por cima das variáveis do harness: LB_RING, WORKER_CREDIT_WINDOW...
+    pub vars: Vec<(String, String)>,
+    pub timeout: Duration, // das requisições HTTP do teste
+    pub pace: Duration,    // entre os pagamentos de cada cliente do load
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            workers: 2,
+            vars: Vec::new(),
+            timeout: Duration::from_secs(2),
+            pace: Duration::from_millis(2),
+        }
+    }
+}
+
+// Pagamento que o backend respondeu com 2xx
+#[derive(Debug, Clone)]
+pub struct Accepted {
+    pub correlation_id: String,
+    pub amount: u64, // centavos
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    Missing(String),          // 2xx que nenhum processador recebeu
+    Duplicate(String),        // 2xx que os dois processadores receberam
+    Amount(String, u64, u64), // (correlationId, mandado, no processador)
+    Window {
+        window: Option<(u64, u64)>,
+        backend: Summary,
+        processors: Summary,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Load {
+    pub ok: u64,
+    pub failed: u64,
+}
+
+pub struct Cluster {
+    pub addr: SocketAddr,
+    pub socket_dir: PathBuf,
+    pub default: processor_mock::Handle,
+    pub fallback: processor_mock::Handle,
+    config: Config,
+    options: Options,
+    workers: Mutex<Vec<Option<worker::Handle>>>,
+    balancer: JoinHandle<()>,
+    accepted: Mutex<Vec<Accepted>>,
+    ids: AtomicU64,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Cluster {
+    pub fn spawn(options: Options) -> std::io::Result<Cluster> {
+        let serial = CLUSTERS.lock().unwrap_or_else(|e| e.into_inner());
+        shutdown::reset();
+
+        let socket_dir = std::env::temp_dir().join(format!(
+            "rinha-harness-{}-{}",
+            std::process::id(),
+            DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&socket_dir)?;
+        let default =
+            processor_mock::spawn("127.0.0.1:0", processor_mock::Options::default_processor())?;
+        let fallback =
+            processor_mock::spawn("127.0.0.1:0", processor_mock::Options::fallback_processor())?;
+
+        let vars = [
+            ("SOCKET_DIR", socket_dir.display().to_string()),
+            ("PROCESSOR_DEFAULT_URL", format!("http://{}", default.addr)),
+            (
+                "PROCESSOR_FALLBACK_URL",
+                format!("http://{}", fallback.addr),
+            ),
+            ("DRAIN_TIMEOUT_MS", "500".to_string()),
+        ];
+        // as de options vêm antes e ganham
+        let pairs: Vec<(&str, &str)> = options
+            .vars
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .chain(vars.iter().map(|(key, value)| (*key, value.as_str())))
+            .collect();
+        let config = Config::from_pairs(&pairs)
+            .map_err(|errors| std::io::Error::other(errors.to_string()))?;
+
+        // Sockets prontos antes do load balancer listar a pasta
+        let workers = (0..options.workers)
+            .map(|n| start_worker(&config, n).map(Some))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let balancer = {
+            let config = config.clone();
+            std::thread::Builder::new()
+                .name("harness-lb".to_string())
+                .spawn(move || load_balance::start_on(&config.common, &config.lb, listener))?
+        };
+
+        Ok(Cluster {
+            addr,
+            socket_dir,
+            default,
+            fallback,
+            config,
+            options,
+            workers: Mutex::new(workers),
+            balancer,
+            accepted: Mutex::new(Vec::new()),
+            ids: AtomicU64::new(1),
+            _serial: serial,
+        })
+    }
+
+    // Conexão nova a cada requisição: o load balancer fecha depois de cada
+    // resposta e reusar corre contra o FIN, um reset ali não é falha do backend
+    fn client(&self) -> Client {
+        Client::new(&format!("http://{}", self.addr), self.options.timeout)
+    }
+
+    // POST /payments com um correlationId novo, 2xx entra nos aceitos
+    pub fn pay(&self, amount: u64) -> std::io::Result<u16> {
+        let mut state = self
+            .ids
+            .fetch_add(1, Ordering::Relaxed)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let correlation_id = bench::uuid(&mut state);
+        let body = format!(
+            r#"{{"correlationId":"{correlation_id}","amount":{}.{:02}}}"#,
+            amount / 100,
+            amount % 100
+        );
+
+        let (status, _) = self.client().request("POST", "/payments", None, &body)?;
+        if (200..300).contains(&status) {
+            self.accepted().push(Accepted {
+                correlation_id,
+                amount,
+            });
+        }
+        Ok(status)
+    }
+
+    // `clients` threads mandando `payments` pagamentos cada uma enquanto
+    // `during` roda nesta thread, que é onde entram as falhas
+    pub fn load(&self, clients: usize, payments: usize, during: impl FnOnce(&Cluster)) -> Load {
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..clients)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut load = Load::default();
+                        for n in 0..payments {
+                            // valores diferentes, pagamento trocado aparece no totalAmount
+                            match self.pay(1990 + n as u64 % 7) {
+                                Ok(status) if (200..300).contains(&status) => load.ok += 1,
+                                _ => load.failed += 1,
+                            }
+                            std::thread::sleep(self.options.pace);
+                        }
+                        load
+                    })
+                })
+                .collect();
+
+            during(self);
+            threads
+                .into_iter()
+                .map(|thread| thread.join().expect("load thread panicked"))
+                .fold(Load::default(), |total, load| Load {
+                    ok: total.ok + load.ok,
+                    failed: total.failed + load.failed,
+                })
+        })
+    }
+
+    // Para o worker n como num SIGTERM (drain e sobras no .pending), o load
+    // balancer descobre pelo link quebrado
+    pub fn kill_worker(&self, n: usize) {
+        let handle = self.workers().get_mut(n).and_then(Option::take);
+        if let Some(handle) = handle {
+            handle.stop();
+        }
+    }
+
+    // Mesmo nome, então o mesmo ledger e os pendentes que ele deixou. O load
+    // balancer só volta a usar no próximo renew.
+    pub fn restart_worker(&self, n: usize) -> std::io::Result<()> {
+        let mut workers = self.workers();
+        if workers.get(n).is_some_and(Option::is_none) {
+            workers[n] = Some(start_worker(&self.config, n)?);
+        }
+        Ok(())
+    }
+
+    // Cada pagamento demora `delay` para ser respondido, zero volta ao normal
+    pub fn stall(&self, processor: Processor, delay: Duration) {
+        self.mock(processor).set_delay(delay);
+    }
+
+    pub fn fail(&self, processor: Processor, failing: bool) {
+        self.mock(processor).set_failure(failing);
+    }
+
+    pub fn accepted(&self) -> MutexGuard<'_, Vec<Accepted>> {
+        self.accepted.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // GET /payments-summary do backend, None é sem from/to
+    pub fn summary(&self, window: Option<(u64, u64)>) -> std::io::Result<Summary> {
+        let path = match window {
+            Some((from, to)) => format!("/payments-summary?{}", bench::window(from, to)),
+            None => "/payments-summary".to_string(),
+        };
+        let (status, body) = self.client().request("GET", &path, None, "")?;
+        let totals = |processor: Processor| {
+            let field = |key| number(&body, Some(processor.name()), key);
+            Some(Totals {
+                requests: field("totalRequests")? as u64,
+                amount: (field("totalAmount")? * 100.0).round() as u64,
+            })
+        };
+        match (
+            status,
+            totals(Processor::Default),
+            totals(Processor::Fallback),
+        ) {
+            (200, Some(default), Some(fallback)) => Ok(Summary { default, fallback }),
+            _ => Err(std::io::Error::other(format!(
+                "invalid summary ({status}): {body}"
+            ))),
+        }
+    }
+
+    // O que os processadores registraram na mesma janela
+    pub fn processed(&self, window: Option<(u64, u64)>) -> Summary {
+        let (from, to) = window.unzip();
+        let totals = |processor: Processor| {
+            let summary = self.mock(processor).summary(from, to);
+            Totals {
+                requests: summary.requests,
+                amount: summary.amount,
+            }
+        };
+        Summary {
+            default: totals(Processor::Default),
+            fallback: totals(Processor::Fallback),
+        }
+    }
+
+    // Espera cada aceito chegar a um processador e o backend alcançar os
+    // processadores. false se o prazo acabou antes, o check diz o que faltou.
+    pub fn settle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let delivered = self.accepted().iter().all(|payment| {
+                Processor::ALL.iter().any(|processor| {
+                    self.mock(*processor)
+                        .payment(&payment.correlation_id)
+                        .is_some()
+                })
+            });
+            if delivered && self.summary(None).ok() == Some(self.processed(None)) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    // O que a rinha confere: todo 2xx em exatamente um processador, com o
+    // valor mandado, e o /payments-summary igual aos processadores em cada janela
+    pub fn check(&self, windows: &[Option<(u64, u64)>]) -> std::io::Result<Vec<Violation>> {
+        let mut violations = Vec::new();
+        for payment in self.accepted().iter() {
+            let found: Vec<u64> = Processor::ALL
+                .iter()
+                .filter_map(|processor| self.mock(*processor).payment(&payment.correlation_id))
+                .map(|processed| processed.amount)
+                .collect();
+            let id = || payment.correlation_id.clone();
+            match found[..] {
+                [] => violations.push(Violation::Missing(id())),
+                [amount] if amount != payment.amount => {
+                    violations.push(Violation::Amount(id(), payment.amount, amount))
+                }
+                [_] => {}
+                _ => violations.push(Violation::Duplicate(id())),
+            }
+        }
+
+        for &window in windows {
+            let backend = self.summary(window)?;
+            let processors = self.processed(window);
+            if backend != processors {
+                violations.push(Violation::Window {
+                    window,
+                    backend,
+                    processors,
+                });
+            }
+        }
+        Ok(violations)
+    }
+
+    // Shutdown do processo como no SIGTERM, tudo drena antes de voltar
+    pub fn stop(self) {
+        shutdown::request();
+        if self.balancer.join().is_err() {
+            logger::error!("Load balancer thread panicked");
+        }
+        let workers = self.workers.into_inner().unwrap_or_else(|e| e.into_inner());
+        workers.into_iter().flatten().for_each(worker::Handle::join);
+        self.default.stop();
+        self.fallback.stop();
+        let _ = std::fs::remove_dir_all(&self.socket_dir);
+    }
+
+    fn workers(&self) -> MutexGuard<'_, Vec<Option<worker::Handle>>> {
+        self.workers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn mock(&self, processor: Processor) -> &processor_mock::Processor {
+        match processor {
+            Processor::Default => &self.default.processor,
+            Processor::Fallback => &self.fallback.processor,
+        }
+    }
+}
+
+fn start_worker(config: &Config, n: usize) -> std::io::Result<worker::Handle> {
+    let mut worker = config.worker.clone();
+    worker.host = format!("{}-{}", config.worker.host, n + 1);
+    worker::spawn(config.common.clone(), worker)
+}
+
+#[cfg(test)]
+mod test {
+    use message::time::now_millis;
+
+    use super::*;
+
+    // Tudo, a janela do teste e pedaços com pontas em qualquer milissegundo
+    fn windows(from: u64, to: u64) -> Vec<Option<(u64, u64)>> {
+        let mut state = from | 1;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            from + state % (to - from + 1)
+        };
+        let mut windows = vec![None, Some((from, to))];
+        for _ in 0..16 {
+            let (a, b) = (next(), next());
+            windows.push(Some((a.min(b), a.max(b))));
+        }
+        windows
+    }
+
+    fn verify(cluster: &Cluster, started: u64) {
+        assert!(cluster.settle(Duration::from_secs(10)));
+        let violations = cluster.check(&windows(started, now_millis())).unwrap();
+        assert!(violations.is_empty(), "{violations:?}");
+    }
+
+    #[test]
+    fn test_steady_traffic() {
+        logger::init("warn");
+        let started = now_millis();
+        let cluster = Cluster::spawn(Options::default()).unwrap();
+
+        let load = cluster.load(4, 50, |_| {});
+        assert_eq!((load.ok, load.failed), (200, 0));
+        verify(&cluster, started);
+        assert_eq!(cluster.processed(None).default.requests, 200);
+        cluster.stop();
+    }
+
+    #[test]
+    fn test_worker_killed_mid_test() {
+        logger::init("warn");
+        let started = now_millis();
+        let options = Options {
+            workers: 3,
+            ..Options::default()
+        };
+        let cluster = Cluster::spawn(options).unwrap();
+
+        let load = cluster.load(4, 100, |cluster| {
+            std::thread::sleep(Duration::from_millis(100));
+            cluster.kill_worker(0);
+            cluster.restart_worker(0).unwrap();
+        });
+        assert_eq!(load.failed, 0, "{load:?}");
+        verify(&cluster, started);
+        cluster.stop();
+    }
+
+    #[test]
+    fn test_processor_stalled() {
+        logger::init("warn");
+        let started = now_millis();
+        let cluster = Cluster::spawn(Options::default()).unwrap();
+
+        let load = cluster.load(4, 100, |cluster| {
+            std::thread::sleep(Duration::from_millis(100));
+            cluster.stall(Processor::Default, Duration::from_secs(2));
+            std::thread::sleep(Duration::from_millis(300));
+            cluster.fail(Processor::Fallback, true);
+            std::thread::sleep(Duration::from_millis(200));
+            cluster.fail(Processor::Fallback, false);
+            cluster.stall(Processor::Default, Duration::ZERO);
+        });
+        assert_eq!(load.failed, 0, "{load:?}");
+        verify(&cluster, started);
+        cluster.stop();
+    }
+}
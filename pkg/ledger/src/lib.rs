@@ -0,0 +1,517 @@
+use std::{
+    fs::OpenOptions,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering, fence},
+};
+
+// Cada worker escreve o próprio arquivo em SOCKET_DIR/$HOST.ledger e o load-balance
+// lê todos direto da memória para responder o /payments-summary.
+//
+// Layout: Header + SLOTS buckets de BUCKET_MS cada, um ring indexado pelo tempo.
+// Cada bucket é protegido por um seqlock, escritores disputam o seq ímpar.
+
+pub const BUCKET_MS: u64 = 100;
+pub const EXTENSION: &str = "ledger";
+
+const SLOTS: usize = 1 << 15; // ~54 minutos de histórico
+const MAGIC: u64 = u64::from_be_bytes(*b"RINHALDG");
+const VERSION: u32 = 1;
+const SIZE: usize = std::mem::size_of::<Header>() + SLOTS * std::mem::size_of::<Bucket>();
+// Um escritor segura o seq ímpar por nanossegundos, tanto tempo assim é
+// escritor morto no meio do record
+const READ_SPINS: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Processor {
+    Default = 0,
+    Fallback = 1,
+}
+
+impl Processor {
+    pub const ALL: [Processor; 2] = [Processor::Default, Processor::Fallback];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Processor::Default => "default",
+            Processor::Fallback => "fallback",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Totals {
+    pub requests: u64,
+    pub amount: u64, // centavos
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub default: Totals,
+    pub fallback: Totals,
+}
+
+impl Summary {
+    pub fn get(&self, processor: Processor) -> &Totals {
+        match processor {
+            Processor::Default => &self.default,
+            Processor::Fallback => &self.fallback,
+        }
+    }
+
+    pub fn merge(&mut self, other: &Summary) {
+        self.default.requests += other.default.requests;
+        self.default.amount += other.default.amount;
+        self.fallback.requests += other.fallback.requests;
+        self.fallback.amount += other.fallback.amount;
+    }
+}
+
+#[repr(C, align(64))]
+struct Header {
+    magic: AtomicU64,
+    version: AtomicU32,
+    bucket_ms: AtomicU32,
+    slots: AtomicU64,
+}
+
+#[repr(C)]
+struct Bucket {
+    seq: AtomicU64,
+    tag: AtomicU64, // indice do bucket + 1, 0 é vazio
+    totals: [[AtomicU64; 2]; 2],
+}
+
+impl Bucket {
+    fn lock(&self) -> u64 {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & 1 == 1 {
+                std::hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+
+            match self
+                .seq
+                .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+
+        fence(Ordering::Release);
+        seq
+    }
+
+    fn unlock(&self, seq: u64) {
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    // Só com o lock
+    fn clear(&self) {
+        self.tag.store(0, Ordering::Relaxed);
+        for values in &self.totals {
+            values[0].store(0, Ordering::Relaxed);
+            values[1].store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Region {
+    ptr: *mut u8,
+}
+
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    fn map(fd: i32, writable: bool) -> std::io::Result<Self> {
+        let prot = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+
+        let flags = if fd < 0 {
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS
+        } else {
+            libc::MAP_SHARED
+        };
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), SIZE, prot, flags, fd, 0) };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Region {
+            ptr: ptr as *mut u8,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.ptr as *const Header) }
+    }
+
+    fn bucket(&self, slot: usize) -> &Bucket {
+        unsafe {
+            let base = self.ptr.add(std::mem::size_of::<Header>()) as *const Bucket;
+            &*base.add(slot)
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let header = self.header();
+        header.magic.load(Ordering::Acquire) == MAGIC
+            && header.version.load(Ordering::Relaxed) == VERSION
+            && header.bucket_ms.load(Ordering::Relaxed) as u64 == BUCKET_MS
+            && header.slots.load(Ordering::Relaxed) as usize == SLOTS
+    }
+
+    fn read_bucket(&self, slot: usize) -> std::io::Result<(u64, [[u64; 2]; 2])> {
+        let bucket = self.bucket(slot);
+        for _ in 0..READ_SPINS {
+            let before = bucket.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let tag = bucket.tag.load(Ordering::Relaxed);
+            let mut totals = [[0; 2]; 2];
+            for (processor, values) in bucket.totals.iter().enumerate() {
+                totals[processor][0] = values[0].load(Ordering::Relaxed);
+                totals[processor][1] = values[1].load(Ordering::Relaxed);
+            }
+
+            fence(Ordering::Acquire);
+            if bucket.seq.load(Ordering::Relaxed) == before {
+                return Ok((tag, totals));
+            }
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("ledger bucket {slot} stuck in a write"),
+        ))
+    }
+
+    fn summary(&self, from: u64, to: u64) -> std::io::Result<Summary> {
+        let mut summary = Summary::default();
+        if from > to {
+            return Ok(summary);
+        }
+
+        // Só os buckets que começam dentro da janela, o requestedAt é sempre o inicio do bucket
+        let first = from.div_ceil(BUCKET_MS);
+        let last = to / BUCKET_MS;
+        let span = last.saturating_sub(first).saturating_add(1);
+
+        let mut add = |slot: usize| {
+            let (tag, totals) = self.read_bucket(slot)?;
+            if tag == 0 || tag - 1 < first || tag - 1 > last {
+                return Ok(());
+            }
+
+            summary.default.requests += totals[0][0];
+            summary.default.amount += totals[0][1];
+            summary.fallback.requests += totals[1][0];
+            summary.fallback.amount += totals[1][1];
+            Ok::<_, std::io::Error>(())
+        };
+
+        if span >= SLOTS as u64 {
+            (0..SLOTS).try_for_each(&mut add)?;
+        } else {
+            (first..=last).try_for_each(|index| add(index as usize % SLOTS))?;
+        }
+
+        Ok(summary)
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, SIZE);
+        }
+    }
+}
+
+// Lado do worker, as threads de processamento escrevem aqui
+pub struct Ledger {
+    region: Region,
+}
+
+impl Ledger {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let reuse = file.metadata()?.len() == SIZE as u64;
+        if !reuse {
+            file.set_len(0)?;
+            file.set_len(SIZE as u64)?;
+        }
+
+        let region = Region::map(file.as_raw_fd(), true)?;
+        if !reuse || !region.is_valid() {
+            // arquivo novo ou de outra versão, começa zerado
+            unsafe { std::ptr::write_bytes(region.ptr, 0, SIZE) };
+            Ledger::init(&region);
+        } else {
+            Ledger::unlock_stale(&region);
+        }
+
+        Ok(Ledger { region })
+    }
+
+    // Sem arquivo, para quando load-balance e worker estão no mesmo processo.
+    // Memória anônima já vem zerada.
+    pub fn anonymous() -> std::io::Result<Self> {
+        let region = Region::map(-1, true)?;
+        Ledger::init(&region);
+        Ok(Ledger { region })
+    }
+
+    // Processo morto entre os dois incrementos deixa o seq ímpar e ninguém
+    // mais lê nem escreve o bucket. Ninguém mais escreve no arquivo antes do
+    // create voltar, então só devolve o seq par; o total fica como estava.
+    fn unlock_stale(region: &Region) {
+        for slot in 0..SLOTS {
+            let seq = &region.bucket(slot).seq;
+            let current = seq.load(Ordering::Acquire);
+            if current & 1 == 1 {
+                seq.store(current + 1, Ordering::Release);
+            }
+        }
+    }
+
+    fn init(region: &Region) {
+        let header = region.header();
+        header.version.store(VERSION, Ordering::Relaxed);
+        header.bucket_ms.store(BUCKET_MS as u32, Ordering::Relaxed);
+        header.slots.store(SLOTS as u64, Ordering::Relaxed);
+        header.magic.store(MAGIC, Ordering::Release);
+    }
+
+    // O requestedAt enviado ao processador deve passar por aqui, assim a janela
+    // do summary bate exatamente com a do processador
+    pub fn quantize(timestamp: u64) -> u64 {
+        timestamp - timestamp % BUCKET_MS
+    }
+
+    pub fn record(&self, processor: Processor, amount: u64, requested_at: u64) {
+        let index = requested_at / BUCKET_MS;
+        let bucket = self.region.bucket(index as usize % SLOTS);
+
+        let seq = bucket.lock();
+
+        if bucket.tag.load(Ordering::Relaxed) != index + 1 {
+            bucket.clear();
+            bucket.tag.store(index + 1, Ordering::Relaxed);
+        }
+
+        let totals = &bucket.totals[processor as usize];
+        totals[0].fetch_add(1, Ordering::Relaxed);
+        totals[1].fetch_add(amount, Ordering::Relaxed);
+
+        bucket.unlock(seq);
+    }
+
+    // Usado pelo purge, apaga todo o histórico
+    pub fn reset(&self) {
+        for slot in 0..SLOTS {
+            let bucket = self.region.bucket(slot);
+            let seq = bucket.lock();
+            bucket.clear();
+            bucket.unlock(seq);
+        }
+    }
+
+    pub fn summary(&self, from: u64, to: u64) -> std::io::Result<Summary> {
+        self.region.summary(from, to)
+    }
+}
+
+// Lado do load-balance, somente leitura
+pub struct LedgerView {
+    region: Region,
+}
+
+impl LedgerView {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        if file.metadata()?.len() != SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "ledger file has unexpected size",
+            ));
+        }
+
+        let region = Region::map(file.as_raw_fd(), false)?;
+        if !region.is_valid() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "ledger file has invalid header",
+            ));
+        }
+
+        Ok(LedgerView { region })
+    }
+
+    // Todos os *.ledger da pasta, arquivos inválidos são ignorados
+    pub fn open_dir(dir: impl AsRef<Path>) -> std::io::Result<Vec<LedgerView>> {
+        let mut views = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(std::ffi::OsStr::new(EXTENSION)) {
+                continue;
+            }
+
+            if let Ok(view) = LedgerView::open(&path) {
+                views.push(view);
+            }
+        }
+
+        Ok(views)
+    }
+
+    pub fn summary(&self, from: u64, to: u64) -> std::io::Result<Summary> {
+        self.region.summary(from, to)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ledger-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(format!("worker.{EXTENSION}"))
+    }
+
+    #[test]
+    fn test_record_and_summary() {
+        let path = temp_path("summary");
+        let ledger = Ledger::create(&path).unwrap();
+        let view = LedgerView::open(&path).unwrap();
+
+        let base = Ledger::quantize(1_594_384_496_123);
+        ledger.record(Processor::Default, 1990, base);
+        ledger.record(Processor::Default, 1990, base);
+        ledger.record(Processor::Fallback, 1000, base + BUCKET_MS);
+
+        let all = view.summary(0, u64::MAX).unwrap();
+        assert_eq!(
+            all.default,
+            Totals {
+                requests: 2,
+                amount: 3980
+            }
+        );
+        assert_eq!(
+            all.fallback,
+            Totals {
+                requests: 1,
+                amount: 1000
+            }
+        );
+
+        let window = view.summary(base + 1, base + BUCKET_MS).unwrap();
+        assert_eq!(window.default, Totals::default());
+        assert_eq!(window.fallback.requests, 1);
+
+        let window = view.summary(base, base + BUCKET_MS - 1).unwrap();
+        assert_eq!(window.default.requests, 2);
+        assert_eq!(window.fallback.requests, 0);
+
+        assert_eq!(view.summary(base + 10, base).unwrap().default.requests, 0);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_ring_overwrites_old_buckets() {
+        let path = temp_path("ring");
+        let ledger = Ledger::create(&path).unwrap();
+
+        let base = Ledger::quantize(1_594_384_496_000);
+        let wrapped = base + SLOTS as u64 * BUCKET_MS;
+        ledger.record(Processor::Default, 100, base);
+        ledger.record(Processor::Default, 200, wrapped);
+
+        let summary = ledger.summary(0, u64::MAX).unwrap();
+        assert_eq!(
+            summary.default,
+            Totals {
+                requests: 1,
+                amount: 200
+            }
+        );
+        assert_eq!(ledger.summary(base, base).unwrap().default.requests, 0);
+
+        // reabrir mantém o histórico
+        drop(ledger);
+        let ledger = Ledger::create(&path).unwrap();
+        assert_eq!(
+            ledger.summary(wrapped, wrapped).unwrap().default.amount,
+            200
+        );
+
+        ledger.reset();
+        assert_eq!(ledger.summary(0, u64::MAX).unwrap(), Summary::default());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_writer_died_mid_record() {
+        let path = temp_path("stuck");
+        let ledger = Ledger::create(&path).unwrap();
+        let base = Ledger::quantize(1_594_384_496_000);
+        ledger.record(Processor::Default, 1990, base);
+
+        // morreu depois do primeiro incremento do seq
+        let slot = (base / BUCKET_MS) as usize % SLOTS;
+        ledger.region.bucket(slot).lock();
+        let view = LedgerView::open(&path).unwrap();
+        assert!(view.summary(0, u64::MAX).is_err());
+        assert!(view.summary(base + BUCKET_MS, base + BUCKET_MS).is_ok());
+
+        // o worker que volta destrava sem perder o total
+        drop(ledger);
+        let ledger = Ledger::create(&path).unwrap();
+        assert_eq!(view.summary(0, u64::MAX).unwrap().default.amount, 1990);
+        ledger.record(Processor::Default, 10, base);
+        assert_eq!(ledger.summary(base, base).unwrap().default.requests, 2);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_open_dir_merges_workers() {
+        let path = temp_path("dir");
+        let dir = path.parent().unwrap();
+        let first = Ledger::create(dir.join("api1.ledger")).unwrap();
+        let second = Ledger::create(dir.join("api2.ledger")).unwrap();
+        std::fs::write(dir.join("api1.sock"), b"").unwrap();
+
+        first.record(Processor::Default, 1990, 0);
+        second.record(Processor::Fallback, 1990, BUCKET_MS);
+
+        let views = LedgerView::open_dir(dir).unwrap();
+        assert_eq!(views.len(), 2);
+
+        let mut summary = Summary::default();
+        views
+            .iter()
+            .for_each(|view| summary.merge(&view.summary(0, u64::MAX).unwrap()));
+        assert_eq!(summary.default.requests, 1);
+        assert_eq!(summary.fallback.amount, 1990);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
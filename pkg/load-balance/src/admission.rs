@@ -0,0 +1,242 @@
+use std::{net::IpAddr, time::Instant};
+
+use message::http::response;
+
+// Controle de admissão no accept, antes da conexão ocupar um slot: token
+// bucket e limite de conexões abertas por IP, numa tabela de tamanho fixo
+// (4 vias por conjunto, sai o IP parado há mais tempo). Cada event loop tem
+// a sua, então com lb.threads > 1 os limites valem por loop.
+
+const WAYS: usize = 4;
+const TOKEN: u64 = 1_000_000; // tokens em milionésimos, o refill é rate por microssegundo
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Admit,
+    RateLimited,
+    TooManyConnections,
+    Overloaded, // todos os slots ocupados
+}
+
+impl Verdict {
+    pub fn response(&self) -> &'static [u8] {
+        match self {
+            Verdict::Overloaded => response::OVERLOADED,
+            _ => response::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    ip: u128, // IPv4 mapeado em IPv6
+    used: bool,
+    tokens: u64,
+    last: u64, // micros desde o início da tabela
+    connections: u32,
+}
+
+pub struct Admission {
+    entries: Box<[Entry]>,
+    started: Instant,
+    rate: u64,
+    burst: u64,
+    max_connections: u32,
+}
+
+impl Admission {
+    // None com os dois limites desligados, aí o accept nem olha o IP
+    // now é do relógio do loop, o mesmo que vai para o admit
+    pub fn new(config: &config::LoadBalancer, now: Instant) -> Option<Admission> {
+        if config.rate_limit == 0 && config.max_conns_per_ip == 0 {
+            return None;
+        }
+
+        let size = config.limiter_entries.next_power_of_two().max(WAYS);
+        Some(Admission {
+            entries: vec![Entry::default(); size].into_boxed_slice(),
+            started: now,
+            rate: config.rate_limit as u64,
+            burst: config.rate_burst as u64 * TOKEN,
+            max_connections: config.max_conns_per_ip as u32,
+        })
+    }
+
+    pub fn admit(&mut self, ip: IpAddr, now: Instant) -> Verdict {
+        let now = self.micros(now);
+        let (rate, burst, max_connections) = (self.rate, self.burst, self.max_connections);
+        let entry = self.entry(ip, now);
+
+        let elapsed = now.saturating_sub(entry.last);
+        entry.tokens = entry
+            .tokens
+            .saturating_add(elapsed.saturating_mul(rate))
+            .min(burst);
+        entry.last = now;
+
+        if max_connections > 0 && entry.connections >= max_connections {
+            return Verdict::TooManyConnections;
+        }
+        if rate > 0 {
+            if entry.tokens < TOKEN {
+                return Verdict::RateLimited;
+            }
+            entry.tokens -= TOKEN;
+        }
+        entry.connections += 1;
+        Verdict::Admit
+    }
+
+    // IP que saiu da tabela enquanto tinha conexão aberta só perde a contagem
+    pub fn release(&mut self, ip: IpAddr) {
+        let key = key(ip);
+        let set = self.set(key);
+        if let Some(entry) = self.entries[set..set + WAYS]
+            .iter_mut()
+            .find(|entry| entry.used && entry.ip == key)
+        {
+            entry.connections = entry.connections.saturating_sub(1);
+        }
+    }
+
+    fn entry(&mut self, ip: IpAddr, now: u64) -> &mut Entry {
+        let key = key(ip);
+        let set = self.set(key);
+        let ways = &mut self.entries[set..set + WAYS];
+
+        let index = match ways.iter().position(|entry| entry.used && entry.ip == key) {
+            Some(index) => index,
+            None => {
+                // livre, senão o mais antigo sem conexão, senão o mais antigo
+                let victim = ways
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, entry)| (entry.used, entry.connections > 0, entry.last));
+                let index = victim.map(|(index, _)| index).unwrap_or_default();
+                ways[index] = Entry {
+                    ip: key,
+                    used: true,
+                    tokens: self.burst,
+                    last: now,
+                    connections: 0,
+                };
+                index
+            }
+        };
+        &mut ways[index]
+    }
+
+    fn set(&self, key: u128) -> usize {
+        let folded = (key as u64) ^ ((key >> 64) as u64);
+        let hash = folded.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (hash >> 32) as usize & (self.entries.len() - 1) & !(WAYS - 1)
+    }
+
+    fn micros(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.started).as_micros() as u64
+    }
+}
+
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+// Fim de uma conexão admitida, peer é None quando o limitador está desligado
+pub fn release(admission: &mut Option<Admission>, peer: Option<IpAddr>) {
+    if let (Some(admission), Some(ip)) = (admission.as_mut(), peer) {
+        admission.release(ip);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        time::Duration,
+    };
+
+    use super::*;
+
+    fn config(rate: u32, burst: u32, connections: usize, entries: usize) -> config::LoadBalancer {
+        let mut config = config::Config::from_pairs(&[("SOCKET_DIR", "/tmp")])
+            .unwrap()
+            .lb;
+        config.rate_limit = rate;
+        config.rate_burst = burst;
+        config.max_conns_per_ip = connections;
+        config.limiter_entries = entries;
+        config
+    }
+
+    fn ip(n: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, n))
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut admission = Admission::new(&config(10, 3, 0, 64), Instant::now()).unwrap();
+        let now = Instant::now();
+
+        // rajada até o burst, depois só no ritmo do refill
+        for _ in 0..3 {
+            assert_eq!(admission.admit(ip(1), now), Verdict::Admit);
+        }
+        assert_eq!(admission.admit(ip(1), now), Verdict::RateLimited);
+        assert_eq!(admission.admit(ip(2), now), Verdict::Admit);
+
+        let later = now + Duration::from_millis(100);
+        assert_eq!(admission.admit(ip(1), later), Verdict::Admit);
+        assert_eq!(admission.admit(ip(1), later), Verdict::RateLimited);
+
+        // parado muito tempo não acumula mais que o burst
+        let much_later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(admission.admit(ip(1), much_later), Verdict::Admit);
+        }
+        assert_eq!(admission.admit(ip(1), much_later), Verdict::RateLimited);
+    }
+
+    #[test]
+    fn test_connection_cap() {
+        let mut admission = Admission::new(&config(0, 1, 2, 64), Instant::now()).unwrap();
+        let now = Instant::now();
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+        assert_eq!(admission.admit(v6, now), Verdict::Admit);
+        assert_eq!(admission.admit(v6, now), Verdict::Admit);
+        assert_eq!(admission.admit(v6, now), Verdict::TooManyConnections);
+        admission.release(v6);
+        assert_eq!(admission.admit(v6, now), Verdict::Admit);
+
+        assert!(Admission::new(&config(0, 1, 0, 64), Instant::now()).is_none());
+    }
+
+    #[test]
+    fn test_bounded_table() {
+        let mut admission = Admission::new(&config(1, 1, 1, 16), Instant::now()).unwrap();
+        let now = Instant::now();
+
+        // muito mais IPs que entradas: a tabela não cresce e os novos entram
+        for n in 0..=255 {
+            assert_eq!(admission.admit(ip(n), now), Verdict::Admit);
+            admission.release(ip(n));
+        }
+        assert_eq!(admission.entries.len(), 16);
+
+        // quem tem conexão aberta é o último a sair
+        let mut admission = Admission::new(&config(0, 1, 1, 4), Instant::now()).unwrap();
+        assert_eq!(admission.admit(ip(1), now), Verdict::Admit);
+        for n in 2..=4 {
+            admission.admit(ip(n), now + Duration::from_secs(n as u64));
+            admission.release(ip(n));
+        }
+        admission.admit(ip(5), now + Duration::from_secs(5));
+        assert_eq!(
+            admission.admit(ip(1), now + Duration::from_secs(6)),
+            Verdict::TooManyConnections
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use connection::{
+    Connection, Status,
+    reactor::{Interest, Reactor, Ready},
+    tokens::Tokens,
+};
+use message::http::parse::Progress;
+
+use crate::{
+    admission::{self, Admission, Verdict},
+    handler::{Handler, Respond},
+    metrics::{self, Stamps},
+    timeouts::{Phase, Timeouts, Timer},
+};
+
+pub const SERVER: usize = 0;
+pub const SHUTDOWN: usize = usize::MAX;
+pub const WORKERS: usize = usize::MAX - 1; // resposta de pedido que esperava os workers
+
+// O loop do epoll, uma volta por vez: quem chama espera a prontidão (o mio,
+// ou o simulador) e passa o relógio. Slots, tokens, timeouts e admissão
+// ficam aqui, a resposta de cada requisição sai do Handler.
+pub struct Balancer<R: Reactor> {
+    reactor: R,
+    conns: Vec<Connection<R::Stream>>,
+    stamps: Vec<Stamps>,
+    admission: Option<Admission>,
+    peers: Vec<Option<IpAddr>>,
+    timeouts: Timeouts,
+    timers: Vec<Timer>,
+    // esperando os workers: os eventos da conexão ficam para depois da resposta
+    parked: Vec<bool>,
+    tokens: Tokens,
+    accept_per_iter: usize,
+    shed: bool,
+    drain_timeout: Duration,
+    listening: bool,
+    deadline: Option<Instant>,
+    finished: usize, // conexões concluídas durante o drain
+    // O listener é edge-triggered: se o accept parou pelo orçamento ou por
+    // falta de slot ainda tem conexão na fila e nenhum evento novo vem avisar
+    backlog: bool,
+}
+
+// Como o event loop terminou, para o drain e o log
+pub struct Outcome {
+    pub deadline: Option<Instant>,
+    pub finished: usize, // conexões concluídas durante o drain
+    pub forced: usize,   // ainda abertas no deadline
+}
+
+impl<R: Reactor> Balancer<R> {
+    pub fn new(
+        common: &config::Common,
+        config: &config::LoadBalancer,
+        reactor: R,
+        now: Instant,
+    ) -> Balancer<R> {
+        let max_slots = config.max_slots;
+        Balancer {
+            reactor,
+            conns: (0..max_slots)
+                .map(|_| Connection::new(None, config.buffer_size))
+                .collect(),
+            stamps: vec![Stamps::default(); max_slots],
+            admission: Admission::new(config, now),
+            peers: vec![None; max_slots],
+            timeouts: Timeouts::new(config),
+            timers: vec![Timer::new(Phase::Header, now); max_slots],
+            parked: vec![false; max_slots],
+            tokens: Tokens::new(max_slots),
+            accept_per_iter: config.accept_per_iter,
+            shed: config.shed,
+            drain_timeout: common.drain_timeout,
+            listening: true,
+            deadline: None,
+            finished: 0,
+            backlog: false,
+        }
+    }
+
+    pub fn reactor(&mut self) -> &mut R {
+        &mut self.reactor
+    }
+
+    pub fn tokens(&self) -> &Tokens {
+        &self.tokens
+    }
+
+    pub fn is_open(&self, slot: usize) -> bool {
+        self.conns[slot].stream.is_some()
+    }
+
+    // Drenando, sem conexão aberta ou passado o deadline
+    pub fn done(&self, now: Instant) -> bool {
+        self.deadline
+            .is_some_and(|deadline| self.tokens.is_empty() || now >= deadline)
+    }
+
+    // Quanto a espera pela prontidão pode durar
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        let capacity = !self.tokens.is_full() || self.shed;
+        // Com conexão aberta acorda a cada tick para a varredura dos timeouts
+        let tick = match (self.backlog && capacity, !self.tokens.is_empty()) {
+            (true, _) => Some(Duration::ZERO),
+            (false, true) => Some(self.timeouts.tick()),
+            (false, false) => None,
+        };
+        match self.deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(now);
+                Some(tick.map_or(left, |tick| tick.min(left)))
+            }
+            None => tick,
+        }
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        Outcome {
+            deadline: self.deadline,
+            finished: self.finished,
+            forced: self.tokens.len(),
+        }
+    }
+
+    pub fn turn(&mut self, ready: &[Ready], handler: &Handler, now: Instant) {
+        let mut accept = self.backlog;
+        let mut answered = false;
+        for event in ready {
+            match event.token {
+                SHUTDOWN => {
+                    if self.deadline.is_some() {
+                        continue;
+                    }
+
+                    // Para de aceitar, o que já está aberto tem até o deadline
+                    self.reactor.close();
+                    self.listening = false;
+                    self.backlog = false;
+                    self.deadline = Some(now + self.drain_timeout);
+                    logger::info!("Draining load balancer"; connections = self.tokens.len());
+                }
+                SERVER => accept = true,
+                WORKERS => answered = true,
+                token => self.ready(token, event.readable, handler, now),
+            }
+        }
+
+        // O token é da conexão que pediu, se ela ainda existe a resposta sai
+        if answered {
+            for (ticket, answer) in handler.workers.answers() {
+                let Some(slot_idx) = self.tokens.slot(ticket) else {
+                    continue;
+                };
+                if !std::mem::take(&mut self.parked[slot_idx]) {
+                    continue;
+                }
+                let conn = &mut self.conns[slot_idx];
+                handler.complete(answer, &mut conn.out_buffer, ticket);
+                self.reactor
+                    .reregister(conn.stream.as_mut().unwrap(), ticket, Interest::WRITABLE)
+                    .expect("unable to reregister stream with poll");
+            }
+        }
+
+        // Depois das conexões, que podem ter liberado slot
+        if accept && self.listening {
+            self.accept(now);
+        }
+
+        // Slowloris, corpo que não chega e cliente que não lê a resposta
+        for slot_idx in 0..self.conns.len() {
+            let timer = self.timers[slot_idx];
+            if self.conns[slot_idx].stream.is_none() || !self.timeouts.expired(&timer, now) {
+                continue;
+            }
+
+            logger::debug!("Connection timed out"; token = slot_idx, phase = timer.phase().name());
+            metrics::timed_out(timer.phase());
+            self.close(slot_idx);
+        }
+    }
+
+    fn ready(&mut self, token: usize, readable: bool, handler: &Handler, now: Instant) {
+        // evento de uma conexão que já fechou
+        let Some(slot_idx) = self.tokens.slot(token) else {
+            return;
+        };
+        if self.parked[slot_idx] {
+            return;
+        }
+        let conn = &mut self.conns[slot_idx];
+
+        match conn.http_ready(readable).cloned() {
+            Ok(Status::Done(true)) | Ok(Status::Close) => {
+                self.stamps[slot_idx].flushed();
+                self.finished += self.deadline.is_some() as usize;
+                self.close(slot_idx);
+            }
+            Ok(Status::Readable) => {
+                // requisição partida, o prazo depende de onde parou
+                let phase = match conn.progress() {
+                    Progress::Head => Phase::Header,
+                    _ => Phase::Body,
+                };
+                self.timers[slot_idx].enter(phase, now);
+            }
+            // resposta saindo aos poucos, o resto vai no próximo evento
+            Ok(Status::Writable) if !conn.out_buffer.is_empty() => {}
+            Ok(Status::Writable) => {
+                self.timers[slot_idx].enter(Phase::Write, now);
+                let (request, out) = conn.request_and_out();
+                let respond = handler.respond(request, out, &mut self.stamps[slot_idx], token);
+                if respond == Respond::Parked {
+                    self.parked[slot_idx] = true;
+                    return;
+                }
+
+                self.reactor
+                    .reregister(conn.stream.as_mut().unwrap(), token, Interest::WRITABLE)
+                    .expect("unable to reregister stream with poll");
+            }
+            Ok(status) => {
+                unreachable!("Unexpected status: {status:?}");
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                unreachable!("Should not happen, cause mio handles this internally");
+            }
+            Err(e) => {
+                logger::warn!("Error handling connection: {e}"; token = token);
+                self.close(slot_idx);
+            }
+        }
+    }
+
+    fn accept(&mut self, now: Instant) {
+        let mut max_conn_per_iter = self.accept_per_iter;
+        self.backlog = true;
+        // Com shed aceita mesmo cheio, só para responder 503
+        while max_conn_per_iter > 0 && (!self.tokens.is_full() || self.shed) {
+            max_conn_per_iter -= 1;
+            match self.reactor.accept() {
+                Ok((mut stream, peer)) => {
+                    let verdict = match (!self.tokens.is_full(), self.admission.as_mut(), peer) {
+                        (false, _, _) => Verdict::Overloaded,
+                        (true, Some(admission), Some(ip)) => admission.admit(ip, now),
+                        (true, _, _) => Verdict::Admit,
+                    };
+                    if verdict != Verdict::Admit {
+                        metrics::rejected(verdict);
+                        self.reactor.refuse(stream, verdict.response());
+                        continue;
+                    }
+
+                    let (token, slot_index) = self.tokens.open().expect("no free slot");
+                    self.reactor
+                        .register(&mut stream, token, Interest::READABLE)
+                        .expect("unable to register stream with poll");
+
+                    self.conns[slot_index].open(stream);
+                    self.stamps[slot_index] = Stamps::accepted();
+                    self.peers[slot_index] = self.admission.is_some().then_some(peer).flatten();
+                    self.timers[slot_index] = Timer::new(Phase::Header, now);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    // No more connections to accept
+                    self.backlog = false;
+                    break;
+                }
+                Err(e) => {
+                    logger::error!("Error accepting connection: {e}");
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, slot_idx: usize) {
+        let conn = &mut self.conns[slot_idx];
+        self.reactor
+            .deregister(conn.stream.as_mut().unwrap())
+            .expect("unable to deregister stream");
+        self.tokens.close(slot_idx);
+        conn.reset();
+        self.parked[slot_idx] = false;
+        admission::release(&mut self.admission, self.peers[slot_idx].take());
+    }
+}
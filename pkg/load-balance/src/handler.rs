@@ -0,0 +1,139 @@
+use message::http::{
+    Request,
+    response::{self, Connection, Response, Status},
+};
+
+use crate::{
+    metrics::{self, Route, Stamps},
+    summary::{self, Ledgers},
+    worker_poll::{Answer, Ask, Workers},
+};
+
+// Monta a resposta de uma requisição, igual para o loop do mio e o do io_uring
+pub struct Handler<'a> {
+    pub workers: &'a Workers,
+    pub ledgers: &'a Ledgers,
+    // Sem purge_token qualquer um pode apagar os pagamentos, com ele o header X-Purge-Token é exigido
+    pub purge_token: Option<&'a String>,
+}
+
+// Parked: a resposta depende dos workers e chega em Workers::answers com o
+// token da conexão, o loop chama complete nessa hora
+#[derive(Debug, PartialEq)]
+pub enum Respond {
+    Ready,
+    Parked,
+}
+
+impl Handler<'_> {
+    pub fn respond(
+        &self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        stamps: &mut Stamps,
+        token: usize,
+    ) -> Respond {
+        let workers = self.workers;
+        let request = Request::from_bytes(input);
+        stamps.parsed();
+
+        match request {
+            Request::Summary(from, to) => match self.ledgers.collect(workers, from, to) {
+                Some(summary) => self.complete(Answer::Summary(summary), out, token),
+                None => return self.park(Ask::Summary(from, to), out, token),
+            },
+            Request::Payment(amount, correlation_id) => {
+                let payment = message::socket::Message::Payment(amount, correlation_id);
+                match workers.send(payment) {
+                    Ok(()) => {
+                        stamps.enqueued();
+                        out.extend_from_slice(response::OK);
+                        metrics::request(Route::Payments, 200);
+                    }
+                    // a thread dos workers morreu, o cliente pode tentar de novo
+                    Err(refused) => {
+                        if let message::socket::Message::Payment(_, correlation_id) = refused.0 {
+                            logger::error!("Worker poll is gone, payment refused"; correlation_id = correlation_id, token = token);
+                        }
+                        out.extend_from_slice(response::SERVICE_UNAVAILABLE);
+                        metrics::request(Route::Payments, 503);
+                    }
+                }
+            }
+            Request::Purge => {
+                let authorized = self.purge_token.is_none_or(|token| {
+                    message::http::parse::header(input, b"x-purge-token") == Some(token.as_bytes())
+                });
+
+                if authorized {
+                    return self.park(Ask::Purge, out, token);
+                }
+                out.extend_from_slice(response::FORBIDDEN);
+                metrics::request(Route::Purge, 403);
+            }
+            Request::Metrics => {
+                // conta antes de renderizar para a própria requisição aparecer
+                metrics::request(Route::Metrics, 200);
+                return self.park(Ask::Metrics, out, token);
+            }
+            Request::NotFound => {
+                out.extend_from_slice(response::NOT_FOUND);
+                metrics::request(Route::Unknown, 404);
+            }
+            Request::BadRequest => {
+                out.extend_from_slice(response::BAD_REQUEST);
+                metrics::request(Route::Payments, 400);
+            }
+        }
+        Respond::Ready
+    }
+
+    fn park(&self, ask: Ask, out: &mut Vec<u8>, token: usize) -> Respond {
+        if let Err(e) = self.workers.ask(ask, token) {
+            let answer = match ask {
+                Ask::Purge => Answer::Purge(Err(e)),
+                Ask::Metrics => Answer::Metrics(Vec::new()),
+                Ask::Summary(..) => Answer::Summary(Err(e)),
+            };
+            self.complete(answer, out, token);
+            return Respond::Ready;
+        }
+        Respond::Parked
+    }
+
+    // Resposta de um pedido que esperou os workers
+    pub fn complete(&self, answer: Answer, out: &mut Vec<u8>, token: usize) {
+        match answer {
+            Answer::Summary(Ok(totals)) => {
+                summary::write_response(&totals, out);
+                metrics::request(Route::Summary, 200);
+            }
+            Answer::Summary(Err(e)) => {
+                logger::error!("Summary failed: {e}"; token = token);
+                out.extend_from_slice(response::SERVICE_UNAVAILABLE);
+                metrics::request(Route::Summary, 503);
+            }
+            Answer::Purge(Ok(_)) => {
+                out.extend_from_slice(response::OK);
+                metrics::request(Route::Purge, 200);
+            }
+            Answer::Purge(Err(e)) => {
+                logger::error!("Purge failed: {e}"; token = token);
+                out.extend_from_slice(response::SERVICE_UNAVAILABLE);
+                metrics::request(Route::Purge, 503);
+            }
+            Answer::Metrics(texts) => {
+                let mut exposition = ::metrics::Exposition::new();
+                exposition.add_text(&::metrics::render(None));
+                for text in texts {
+                    exposition.add_text(&text);
+                }
+
+                Response::new(out, Status::OK)
+                    .connection(Connection::Close)
+                    .content_type(b"text/plain; version=0.0.4")
+                    .render(|body| exposition.write(body));
+            }
+        }
+    }
+}
@@ -1,151 +1,392 @@
-use connection::{Connection, Status};
+use std::{net::IpAddr, os::fd::AsRawFd, sync::Arc, time::Instant};
+
+use connection::{
+    reactor::{Interest, Reactor, Ready},
+    shutdown,
+};
+use ledger::Ledger;
 use mio::{
-    Events, Poll, Token,
+    Events, Poll, Registry, Token,
     net::{TcpListener, TcpStream},
+    unix::SourceFd,
 };
 
-use crate::worker_poll::start_workers;
+use crate::{
+    balancer::{Balancer, Outcome, SERVER, SHUTDOWN, WORKERS},
+    timeouts::{Clock, SystemClock},
+    worker_poll::start_workers,
+};
+pub use crate::{
+    handler::Handler,
+    summary::Ledgers,
+    worker_poll::{Link, Pump, Remote, WorkerPoll, Workers, pump},
+};
 
+mod admission;
+pub mod balancer;
+mod handler;
+mod listener;
+mod metrics;
+pub mod proxy;
+mod summary;
+#[cfg(test)]
+mod testing;
+pub mod timeouts;
+#[cfg(feature = "io-uring")]
+mod uring;
 mod worker_poll;
 
-const SERVER: Token = Token(0);
-const MAX_SLOTS: usize = 50;
+// O shutdown é global no processo: teste que pede shutdown não roda junto
+// com outro que ainda usa servidor, e cada um começa sem pedido pendente
+#[cfg(test)]
+fn serialize_servers() -> std::sync::MutexGuard<'static, ()> {
+    static SERVERS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let guard = SERVERS.lock().unwrap_or_else(|e| e.into_inner());
+    shutdown::reset();
+    guard
+}
+
+// Com mais de uma thread cada uma tem listener, slots e links próprios, nada
+// é compartilhado no hot path. A última roda na thread de quem chamou.
+pub fn start(common: &config::Common, config: &config::LoadBalancer) {
+    let reuse_port = config.threads > 1;
+
+    std::thread::scope(|scope| {
+        for n in 0..config.threads {
+            let listener =
+                listener::bind(config.port, reuse_port).expect("unable to listen on TCP socket");
+            let run = move || {
+                let poll = WorkerPoll::new(common.socket_dir.clone(), config);
+                let ledgers = match config.workers.is_empty() {
+                    true => Ledgers::dir(common.socket_dir.clone()),
+                    false => Ledgers::Remote,
+                };
+                serve(
+                    common,
+                    config,
+                    TcpListener::from_std(listener),
+                    poll,
+                    ledgers,
+                );
+            };
+
+            if n + 1 == config.threads {
+                run();
+            } else {
+                std::thread::Builder::new()
+                    .name(format!("lb-{n}"))
+                    .spawn_scoped(scope, run)
+                    .expect("unable to spawn load balancer thread");
+            }
+        }
+    });
+}
+
+// Igual ao start com uma thread, num listener já aberto (porta efêmera do
+// harness de testes)
+pub fn start_on(
+    common: &config::Common,
+    config: &config::LoadBalancer,
+    listener: std::net::TcpListener,
+) {
+    listener
+        .set_nonblocking(true)
+        .expect("unable to set listener non-blocking");
+    let poll = WorkerPoll::new(common.socket_dir.clone(), config);
+    let ledgers = match config.workers.is_empty() {
+        true => Ledgers::dir(common.socket_dir.clone()),
+        false => Ledgers::Remote,
+    };
+    serve(
+        common,
+        config,
+        TcpListener::from_std(listener),
+        poll,
+        ledgers,
+    );
+}
+
+// Workers no mesmo processo: links em memória e ledgers compartilhados
+pub fn start_with(
+    common: &config::Common,
+    config: &config::LoadBalancer,
+    listener: std::net::TcpListener,
+    links: Vec<Link>,
+    ledgers: Vec<Arc<Ledger>>,
+) {
+    listener
+        .set_nonblocking(true)
+        .expect("unable to set listener non-blocking");
+    let poll = WorkerPoll::with_links(links, config);
+    serve(
+        common,
+        config,
+        TcpListener::from_std(listener),
+        poll,
+        Ledgers::Shared(ledgers),
+    );
+}
+
+fn serve(
+    common: &config::Common,
+    config: &config::LoadBalancer,
+    listener: TcpListener,
+    poll: WorkerPoll,
+    ledgers: Ledgers,
+) {
+    let workers = start_workers(poll, config);
+    let handler = Handler {
+        workers: &workers,
+        ledgers: &ledgers,
+        purge_token: config.purge_token.as_ref(),
+    };
+    metrics::register();
+    shutdown::install();
+
+    // Com a feature io-uring o loop troca de backend, se o kernel recusar fica no epoll
+    #[cfg(feature = "io-uring")]
+    let outcome = match connection::uring::Reactor::new(config.max_slots, config.buffer_size) {
+        Ok(reactor) => {
+            logger::info!("Using io_uring event loop");
+            uring::serve(
+                common,
+                config,
+                listener.into(),
+                reactor,
+                &handler,
+                &SystemClock,
+            )
+        }
+        Err(e) => {
+            logger::warn!("io_uring unavailable, falling back to epoll: {e}");
+            epoll(common, config, listener, &handler, &SystemClock)
+        }
+    };
+    #[cfg(not(feature = "io-uring"))]
+    let outcome = epoll(common, config, listener, &handler, &SystemClock);
+
+    // Conexões que não terminaram até o deadline são fechadas junto com o processo
+    let drained = workers.drain(outcome.deadline.unwrap_or_else(Instant::now));
+    logger::info!(
+        "Load balancer stopped";
+        finished = outcome.finished,
+        forced = outcome.forced,
+        forwarded = drained.forwarded,
+        dropped = drained.dropped
+    );
+}
 
-pub fn start(port: u16, socket_dir: String) {
-    let mut listener = TcpListener::bind(
-        format!("0.0.0.0:{port}")
-            .parse()
-            .expect("unable to parse socket address"),
-    )
-    .expect("unable to listen on TCP socket");
+// O Reactor de produção: o mio com o listener TCP e o registry do poll
+struct Mio {
+    registry: Registry,
+    listener: Option<TcpListener>,
+}
 
-    let workers = start_workers(socket_dir);
+impl Reactor for Mio {
+    type Stream = TcpStream;
 
-    // Performance
-    let mut conn_poll: [Connection<350, TcpStream>; MAX_SLOTS] =
-        std::array::from_fn(|_| Connection::new(None));
+    fn accept(&mut self) -> std::io::Result<(TcpStream, Option<IpAddr>)> {
+        let Some(listener) = self.listener.as_ref() else {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        };
+        listener
+            .accept()
+            .map(|(stream, peer)| (stream, Some(peer.ip())))
+    }
 
+    fn register(
+        &mut self,
+        stream: &mut TcpStream,
+        token: usize,
+        interest: Interest,
+    ) -> std::io::Result<()> {
+        self.registry.register(stream, Token(token), interest)
+    }
+
+    fn reregister(
+        &mut self,
+        stream: &mut TcpStream,
+        token: usize,
+        interest: Interest,
+    ) -> std::io::Result<()> {
+        self.registry.reregister(stream, Token(token), interest)
+    }
+
+    fn deregister(&mut self, stream: &mut TcpStream) -> std::io::Result<()> {
+        self.registry.deregister(stream)
+    }
+
+    fn close(&mut self) {
+        if let Some(mut listener) = self.listener.take() {
+            let _ = self.registry.deregister(&mut listener);
+        }
+    }
+
+    fn refuse(&mut self, stream: TcpStream, response: &[u8]) {
+        connection::reactor::refuse(stream.as_raw_fd(), response);
+    }
+}
+
+fn epoll(
+    common: &config::Common,
+    config: &config::LoadBalancer,
+    mut listener: TcpListener,
+    handler: &Handler,
+    clock: &dyn Clock,
+) -> Outcome {
     let mut io_poll = Poll::new().expect("unable to create poll instance");
     let mut events = Events::with_capacity(1024);
-    let mut next_token = 1;
-    let mut conn_count = 0;
 
     io_poll
         .registry()
-        .register(&mut listener, SERVER, mio::Interest::READABLE)
+        .register(&mut listener, Token(SERVER), Interest::READABLE)
         .expect("unable to register listener with poll");
+    shutdown::watch(io_poll.registry(), Token(SHUTDOWN)).expect("unable to watch for shutdown");
+    io_poll
+        .registry()
+        .register(
+            &mut SourceFd(&handler.workers.bell()),
+            Token(WORKERS),
+            Interest::READABLE,
+        )
+        .expect("unable to register worker answers with poll");
+
+    let reactor = Mio {
+        registry: io_poll
+            .registry()
+            .try_clone()
+            .expect("unable to clone poll registry"),
+        listener: Some(listener),
+    };
+    let mut balancer = Balancer::new(common, config, reactor, clock.now());
+    let mut ready: Vec<Ready> = Vec::with_capacity(1024);
 
-    loop {
-        let mut max_conn_per_iter = 10;
-        io_poll.poll(&mut events, None).expect("poll failed");
-
-        for event in &events {
-            match event.token() {
-                SERVER => {
-                    while max_conn_per_iter > 0 && conn_count < MAX_SLOTS {
-                        match listener.accept() {
-                            Ok((mut stream, _)) => {
-                                let token = Token(next_token);
-                                let slot_index = next_token % MAX_SLOTS;
-                                io_poll
-                                    .registry()
-                                    .register(&mut stream, token, mio::Interest::READABLE)
-                                    .expect("unable to register stream with poll");
-
-                                conn_poll[slot_index].stream = Some(stream);
-                                conn_poll[slot_index].status = Status::Readable;
-                                next_token += 1;
-                                conn_count += 1;
-                                max_conn_per_iter -= 1;
-                                // println!("Accepted connection");
-                            }
-                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                                // No more connections to accept
-                                break;
-                            }
-                            Err(e) => {
-                                eprintln!("Error accepting connection: {e}");
-                            }
-                        }
-                    }
-                }
-                token => {
-                    let slot_idx = token.0 % MAX_SLOTS;
-                    let conn = conn_poll
-                        .get_mut(slot_idx)
-                        .expect("no connection found for token");
-
-                    match conn.http_handle(event) {
-                        Ok(Status::Done(true)) | Ok(Status::Close) => {
-                            // println!("Connection closed or done");
-                            io_poll
-                                .registry()
-                                .deregister(conn.stream.as_mut().unwrap())
-                                .expect("unable to deregister stream");
-
-                            conn_count -= 1;
-                            conn.reset();
-                        }
-                        Ok(Status::Writable) => {
-                            match message::http::Request::from_bytes(&conn.in_buffer) {
-                                message::http::Request::Summary(from, to) => {
-                                    workers
-                                        .send(message::socket::Message::Summary(from, to))
-                                        .expect("Failed to send summary message");
-
-                                    conn.out_buffer
-                                        .extend_from_slice(message::http::response::SUMMARY);
-                                }
-                                message::http::Request::Payment(amount, correlation_id) => {
-                                    workers
-                                        .send(message::socket::Message::Payment(
-                                            amount,
-                                            correlation_id,
-                                        ))
-                                        .expect("Failed to send payment message");
-                                    // println!(
-                                    //     "Payment request for amount {amount} with correlation ID {correlation_id:?}"
-                                    // );
-                                    conn.out_buffer
-                                        .extend_from_slice(message::http::response::OK);
-                                }
-                                message::http::Request::NotFound => {
-                                    conn.out_buffer
-                                        .extend_from_slice(message::http::response::NOT_FOUND);
-                                }
-                                message::http::Request::BadRequest => {
-                                    conn.out_buffer
-                                        .extend_from_slice(message::http::response::BAD_REQUEST);
-                                }
-                            }
-
-                            // println!("Connection ready for writing");
-                            io_poll
-                                .registry()
-                                .reregister(
-                                    conn.stream.as_mut().unwrap(),
-                                    token,
-                                    mio::Interest::WRITABLE,
-                                )
-                                .expect("unable to reregister stream with poll");
-                        }
-                        Ok(_) => {
-                            unreachable!("Unexpected status: {:?}", conn.status);
-                        }
-                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            unreachable!("Should not happen, cause mio handles this internally");
-                        }
-                        Err(e) => {
-                            eprintln!("Error handling connection: {e}");
-                            io_poll
-                                .registry()
-                                .deregister(conn.stream.as_mut().unwrap())
-                                .expect("unable to deregister stream");
-                            conn.reset();
-                        }
-                    }
-                }
+    while !balancer.done(clock.now()) {
+        if let Err(e) = io_poll.poll(&mut events, balancer.timeout(clock.now())) {
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
             }
+            panic!("poll failed: {e}");
+        }
+
+        ready.clear();
+        ready.extend(events.iter().map(Ready::from));
+        balancer.turn(&ready, handler, clock.now());
+    }
+
+    balancer.outcome()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        sync::atomic::Ordering,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::testing::{BACKENDS, request, spawn, spawn_slow};
+
+    // Rajada bem maior que os slots e o orçamento de accept: tudo na fila do
+    // listener de uma vez, sem evento novo depois, e ninguém pode ficar parado
+    #[test]
+    fn test_accept_bursts() {
+        const CONNECTIONS: usize = 400;
+        let _servers = serialize_servers();
+        let config = config::Config::from_pairs(&[
+            ("MODE", "standalone"),
+            ("LB_MAX_SLOTS", "16"),
+            ("LB_ACCEPT_PER_ITER", "2"),
+        ])
+        .unwrap();
+        let body = r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90}"#;
+        let payment = format!(
+            "POST /payments HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        for &backend in BACKENDS {
+            let (addr, received, _) = spawn(backend, &config, &SystemClock);
+            let started = Instant::now();
+            let mut clients: Vec<TcpStream> = (0..CONNECTIONS)
+                .map(|_| TcpStream::connect(addr).unwrap())
+                .collect();
+            for client in clients.iter_mut() {
+                client
+                    .set_read_timeout(Some(Duration::from_secs(10)))
+                    .unwrap();
+                client.write_all(payment.as_bytes()).unwrap();
+            }
+            for client in clients.iter_mut() {
+                let mut response = String::new();
+                client.read_to_string(&mut response).unwrap();
+                assert!(
+                    response.starts_with("HTTP/1.1 200"),
+                    "{backend:?}: {response}"
+                );
+            }
+
+            while received.load(Ordering::Relaxed) < CONNECTIONS {
+                assert!(started.elapsed() < Duration::from_secs(10), "{backend:?}");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    // Worker que não responde as métricas não segura o event loop: o
+    // pagamento que chega no meio sai na hora e o /metrics responde no
+    // reply_timeout com as métricas locais
+    #[test]
+    fn test_parked_requests() {
+        let _servers = serialize_servers();
+        let config = config::Config::from_pairs(&[
+            ("MODE", "standalone"),
+            ("LB_REPLY_TIMEOUT_MS", "300"),
+            ("DRAIN_TIMEOUT_MS", "1000"),
+        ])
+        .unwrap();
+        let body = r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90}"#;
+        let payment = format!(
+            "POST /payments HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        crate::metrics::register();
+        let mut servers = Vec::new();
+        for &backend in BACKENDS {
+            let (addr, _, thread) =
+                spawn_slow(backend, &config, &SystemClock, Duration::from_secs(2));
+            let started = Instant::now();
+            let metrics =
+                std::thread::spawn(move || request(addr, b"GET /metrics HTTP/1.1\r\n\r\n"));
+            std::thread::sleep(Duration::from_millis(50));
+
+            let response = request(addr, payment.as_bytes());
+            assert!(
+                response.starts_with("HTTP/1.1 200"),
+                "{backend:?}: {response}"
+            );
+            assert!(
+                started.elapsed() < config.lb.reply_timeout,
+                "{backend:?}: payment waited {:?}",
+                started.elapsed()
+            );
+
+            let metrics = metrics.join().unwrap();
+            assert!(
+                metrics.starts_with("HTTP/1.1 200")
+                    && metrics.contains("rinha_http_requests_total"),
+                "{backend:?}: {metrics}"
+            );
+            assert!(started.elapsed() >= config.lb.reply_timeout, "{backend:?}");
+            servers.push(thread);
+        }
+
+        shutdown::request();
+        for thread in servers {
+            thread.join().unwrap();
         }
     }
 }
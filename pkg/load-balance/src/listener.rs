@@ -0,0 +1,77 @@
+use std::{
+    net::TcpListener,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+// Bind feito na unha porque a std não deixa ligar SO_REUSEPORT antes do bind.
+// Com ele cada event loop tem o seu listener e o kernel divide as conexões,
+// igual ao `listen 9999 reuseport` do nginx.
+pub fn bind(port: u16, reuse_port: bool) -> std::io::Result<TcpListener> {
+    let fd = cvt(unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    })?;
+    // fecha o socket se algo der errado no caminho
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let raw = fd.as_raw_fd();
+
+    set_option(raw, libc::SO_REUSEADDR)?;
+    if reuse_port {
+        set_option(raw, libc::SO_REUSEPORT)?;
+    }
+
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = port.to_be();
+    addr.sin_addr.s_addr = libc::INADDR_ANY;
+    cvt(unsafe {
+        libc::bind(
+            raw,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    })?;
+    cvt(unsafe { libc::listen(raw, 1024) })?;
+
+    Ok(TcpListener::from(fd))
+}
+
+fn set_option(fd: libc::c_int, option: libc::c_int) -> std::io::Result<()> {
+    let enabled: libc::c_int = 1;
+    cvt(unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })
+    .map(|_| ())
+}
+
+fn cvt(result: libc::c_int) -> std::io::Result<libc::c_int> {
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reuse_port() {
+        let first = bind(0, true).unwrap();
+        let port = first.local_addr().unwrap().port();
+
+        // mesma porta duas vezes só com SO_REUSEPORT
+        let second = bind(port, true).unwrap();
+        assert_eq!(second.local_addr().unwrap().port(), port);
+        assert!(bind(port, false).is_err());
+    }
+}
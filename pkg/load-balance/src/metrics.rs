@@ -0,0 +1,213 @@
+use std::time::Instant;
+
+use metrics::{Counter, Hdr, Metric};
+
+use crate::{admission::Verdict, timeouts::Phase};
+
+pub enum Route {
+    Payments,
+    Summary,
+    Purge,
+    Metrics,
+    Unknown,
+}
+
+// Um contador por rota/status que o load-balance consegue responder
+static HTTP_REQUESTS: [Counter; 10] = [
+    http_requests(&[("route", "payments"), ("status", "200")]),
+    http_requests(&[("route", "payments"), ("status", "400")]),
+    http_requests(&[("route", "payments"), ("status", "503")]),
+    http_requests(&[("route", "payments-summary"), ("status", "200")]),
+    http_requests(&[("route", "payments-summary"), ("status", "503")]),
+    http_requests(&[("route", "purge-payments"), ("status", "200")]),
+    http_requests(&[("route", "purge-payments"), ("status", "403")]),
+    http_requests(&[("route", "purge-payments"), ("status", "503")]),
+    http_requests(&[("route", "metrics"), ("status", "200")]),
+    http_requests(&[("route", "unknown"), ("status", "404")]),
+];
+
+const fn http_requests(labels: metrics::Labels) -> Counter {
+    Counter::new(
+        "rinha_http_requests_total",
+        "HTTP requests answered by the load balancer",
+        labels,
+    )
+}
+
+// Conexões recusadas no accept, por motivo
+static REJECTED: [Counter; 3] = [
+    rejected_connections(&[("reason", "rate_limited")]),
+    rejected_connections(&[("reason", "too_many_connections")]),
+    rejected_connections(&[("reason", "overloaded")]),
+];
+
+const fn rejected_connections(labels: metrics::Labels) -> Counter {
+    Counter::new(
+        "rinha_lb_rejected_connections_total",
+        "Connections answered with 429/503 by admission control",
+        labels,
+    )
+}
+
+// Conexões fechadas por timeout, pela fase em que estavam
+static TIMED_OUT: [Counter; 4] = [
+    timed_out_connections(&[("phase", "header")]),
+    timed_out_connections(&[("phase", "body")]),
+    timed_out_connections(&[("phase", "write")]),
+    timed_out_connections(&[("phase", "idle")]),
+];
+
+const fn timed_out_connections(labels: metrics::Labels) -> Counter {
+    Counter::new(
+        "rinha_lb_timed_out_connections_total",
+        "Connections closed for taking too long in a phase",
+        labels,
+    )
+}
+
+// Resultado de cada requisição no modo proxy
+pub enum Proxied {
+    Forwarded = 0,
+    BadGateway = 1,
+    Timeout = 2,
+    Rejected = 3, // requisição inválida ou grande demais, nem chegou no upstream
+}
+
+static PROXY_REQUESTS: [Counter; 4] = [
+    proxy_requests(&[("result", "forwarded")]),
+    proxy_requests(&[("result", "bad_gateway")]),
+    proxy_requests(&[("result", "timeout")]),
+    proxy_requests(&[("result", "rejected")]),
+];
+
+const fn proxy_requests(labels: metrics::Labels) -> Counter {
+    Counter::new(
+        "rinha_proxy_requests_total",
+        "HTTP requests handled by the reverse proxy",
+        labels,
+    )
+}
+
+pub enum Stage {
+    Read = 0,    // accept -> parse completo
+    Enqueue = 1, // parse -> entregue para a thread dos workers
+    Flush = 2,   // parse -> resposta escrita
+    Total = 3,   // accept -> resposta escrita
+}
+
+static STAGES: [Hdr; 4] = [
+    stage_latency(&[("stage", "read")]),
+    stage_latency(&[("stage", "enqueue")]),
+    stage_latency(&[("stage", "flush")]),
+    stage_latency(&[("stage", "total")]),
+];
+
+const fn stage_latency(labels: metrics::Labels) -> Hdr {
+    Hdr::new(
+        "rinha_lb_stage_latency_seconds",
+        "Time spent by a request in each load balancer stage",
+        labels,
+    )
+}
+
+// Marcas de tempo de cada slot, uma requisição por vez
+#[derive(Default, Clone, Copy)]
+pub struct Stamps {
+    pub accepted: Option<Instant>,
+    pub parsed: Option<Instant>,
+}
+
+impl Stamps {
+    pub fn accepted() -> Self {
+        Stamps {
+            accepted: Some(Instant::now()),
+            parsed: None,
+        }
+    }
+
+    pub fn parsed(&mut self) {
+        let now = Instant::now();
+        if let Some(accepted) = self.accepted {
+            STAGES[Stage::Read as usize].record((now - accepted).as_micros() as u64);
+        }
+        self.parsed = Some(now);
+    }
+
+    pub fn enqueued(&self) {
+        if let Some(parsed) = self.parsed {
+            STAGES[Stage::Enqueue as usize].record_since(parsed);
+        }
+    }
+
+    pub fn flushed(&mut self) {
+        let now = Instant::now();
+        if let Some(parsed) = self.parsed.take() {
+            STAGES[Stage::Flush as usize].record((now - parsed).as_micros() as u64);
+        }
+        if let Some(accepted) = self.accepted.take() {
+            STAGES[Stage::Total as usize].record((now - accepted).as_micros() as u64);
+        }
+    }
+}
+
+pub fn request(route: Route, status: u16) {
+    let index = match (route, status) {
+        (Route::Payments, 400) => 1,
+        (Route::Payments, 503) => 2,
+        (Route::Payments, _) => 0,
+        (Route::Summary, 503) => 4,
+        (Route::Summary, _) => 3,
+        (Route::Purge, 403) => 6,
+        (Route::Purge, 503) => 7,
+        (Route::Purge, _) => 5,
+        (Route::Metrics, _) => 8,
+        (Route::Unknown, _) => 9,
+    };
+
+    HTTP_REQUESTS[index].inc();
+}
+
+pub fn rejected(verdict: Verdict) {
+    let index = match verdict {
+        Verdict::Admit => return,
+        Verdict::RateLimited => 0,
+        Verdict::TooManyConnections => 1,
+        Verdict::Overloaded => 2,
+    };
+    REJECTED[index].inc();
+}
+
+pub fn timed_out(phase: Phase) {
+    TIMED_OUT[phase as usize].inc();
+}
+
+#[cfg(test)]
+pub fn timed_out_count(phase: Phase) -> u64 {
+    TIMED_OUT[phase as usize].get()
+}
+
+pub fn register() {
+    let all: Vec<&'static dyn Metric> = HTTP_REQUESTS
+        .iter()
+        .chain(REJECTED.iter())
+        .chain(TIMED_OUT.iter())
+        .map(|c| c as &dyn Metric)
+        .collect();
+    metrics::register(&all);
+    metrics::hdr::register(&[&STAGES[0], &STAGES[1], &STAGES[2], &STAGES[3]]);
+    connection::metrics::register();
+}
+
+pub fn proxied(result: Proxied) {
+    PROXY_REQUESTS[result as usize].inc();
+}
+
+pub fn register_proxy() {
+    let all: Vec<&'static dyn Metric> = PROXY_REQUESTS
+        .iter()
+        .chain(TIMED_OUT.iter())
+        .map(|c| c as &dyn Metric)
+        .collect();
+    metrics::register(&all);
+    connection::metrics::register();
+}
@@ -0,0 +1,1209 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+use connection::{
+    metrics::{ACTIVE, BYTES_READ, BYTES_WRITTEN},
+    shutdown,
+    transport::{Address, Stream, Target},
+};
+use message::http::{
+    framing::Framing,
+    response::{Connection, Response, Status},
+};
+use mio::{
+    Events, Interest, Poll, Token,
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    listener,
+    metrics::{self, Proxied},
+    timeouts::{Clock, Phase, SystemClock, Timeouts, Timer},
+};
+
+// Proxy L7 para colocar na frente de qualquer implementação, no lugar do
+// nginx. Cada requisição é lida inteira do cliente e vai para o upstream com
+// menos requisições em andamento, por uma conexão ociosa do pool dele ou uma
+// nova. A resposta volta em pedaços conforme chega, sem reescrever headers.
+
+const SERVER: Token = Token(0);
+const SHUTDOWN: Token = Token(usize::MAX);
+const UPSTREAM: usize = 1 << 32; // tokens daqui para cima são conexões com upstream
+
+const MAX_REQUEST: usize = 64 * 1024;
+const READ_CHUNK: usize = 4096;
+const PENDING_LIMIT: usize = 64 * 1024; // resposta parada no cliente antes de parar de ler do upstream
+const DOWN_FOR: Duration = Duration::from_secs(1); // upstream que recusou conexão fica de fora
+
+const CONTENT_TOO_LARGE: Status = Status::new(413, "Content Too Large");
+
+struct Client {
+    stream: TcpStream,
+    input: Vec<u8>,
+    scanned: usize, // bytes de input que já passaram pelo framing
+    request: Framing,
+    output: Vec<u8>,
+    written: usize,
+    conn: Option<usize>, // conexão com upstream atendendo a requisição atual
+    keep_alive: bool,
+    served: bool, // já respondeu alguma, esperar a próxima é keep-alive
+    timer: Timer,
+}
+
+struct Conn {
+    stream: Stream,
+    upstream: usize,
+    client: Option<usize>, // None no pool
+    request: Vec<u8>,      // guardada até o primeiro byte da resposta, para o retry
+    safe: bool,            // GET ou HEAD, pode ir de novo mesmo depois de escrita inteira
+    written: usize,
+    response: Framing,
+    received: bool,
+    reused: bool,     // veio do pool, pode ter sido fechada pelo upstream sem avisar
+    connecting: bool, // connect ainda em andamento, nada foi escrito
+    since: Instant,
+}
+
+struct Upstream {
+    address: Address,
+    target: Target, // resolvido no começo, o connect não consulta o DNS
+    outstanding: usize,
+    idle: Vec<usize>,
+    down_until: Option<Instant>,
+}
+
+struct Proxy<'a> {
+    config: &'a config::LoadBalancer,
+    poll: Poll,
+    listener: Option<TcpListener>,
+    clients: Vec<Option<Client>>,
+    free_clients: Vec<usize>,
+    conns: Vec<Option<Conn>>,
+    free_conns: Vec<usize>,
+    upstreams: Vec<Upstream>,
+    next: usize, // desempate entre upstreams com a mesma carga
+    backlogged: bool,
+    draining: bool,
+    scratch: Box<[u8]>,
+    timeouts: Timeouts,
+    clock: &'a dyn Clock,
+}
+
+// Mesmo esquema do start: uma thread por event loop, cada uma com listener e
+// pools próprios
+pub fn start(common: &config::Common, config: &config::LoadBalancer) {
+    let reuse_port = config.threads > 1;
+    metrics::register_proxy();
+    shutdown::install();
+
+    std::thread::scope(|scope| {
+        for n in 0..config.threads {
+            let listener =
+                listener::bind(config.port, reuse_port).expect("unable to listen on TCP socket");
+            let run = move || {
+                serve(
+                    common,
+                    config,
+                    TcpListener::from_std(listener),
+                    &SystemClock,
+                )
+            };
+
+            if n + 1 == config.threads {
+                run();
+            } else {
+                std::thread::Builder::new()
+                    .name(format!("proxy-{n}"))
+                    .spawn_scoped(scope, run)
+                    .expect("unable to spawn proxy thread");
+            }
+        }
+    });
+}
+
+// Os upstreams são nomes (api1:8080) e o getaddrinfo bloqueia, então o DNS
+// fica aqui, antes do loop. Nome que ainda não existe (o upstream subindo
+// depois) é tentado de novo até resolver ou chegar o shutdown.
+fn resolve(config: &config::LoadBalancer) -> Option<Vec<Upstream>> {
+    let mut upstreams = Vec::with_capacity(config.upstreams.len());
+    for address in config
+        .upstreams
+        .iter()
+        .map(|address| Address::parse(address))
+    {
+        let target = loop {
+            match address.resolve() {
+                Ok(target) => break target,
+                Err(e) if shutdown::requested() => {
+                    logger::error!("Unable to resolve upstream: {e}"; upstream = address);
+                    return None;
+                }
+                Err(e) => {
+                    logger::warn!("Unable to resolve upstream, retrying: {e}"; upstream = address);
+                    std::thread::sleep(DOWN_FOR);
+                }
+            }
+        };
+        upstreams.push(Upstream {
+            address,
+            target,
+            outstanding: 0,
+            idle: Vec::new(),
+            down_until: None,
+        });
+    }
+    Some(upstreams)
+}
+
+fn serve(
+    common: &config::Common,
+    config: &config::LoadBalancer,
+    mut listener: TcpListener,
+    clock: &dyn Clock,
+) {
+    let Some(upstreams) = resolve(config) else {
+        return;
+    };
+    let poll = Poll::new().expect("unable to create poll instance");
+    poll.registry()
+        .register(&mut listener, SERVER, Interest::READABLE)
+        .expect("unable to register listener with poll");
+    shutdown::watch(poll.registry(), SHUTDOWN).expect("unable to watch for shutdown");
+
+    let mut proxy = Proxy {
+        config,
+        poll,
+        listener: Some(listener),
+        clients: (0..config.max_slots).map(|_| None).collect(),
+        free_clients: (0..config.max_slots).rev().collect(),
+        conns: Vec::new(),
+        free_conns: Vec::new(),
+        upstreams,
+        next: 0,
+        backlogged: false,
+        draining: false,
+        scratch: vec![0; 16 * 1024].into_boxed_slice(),
+        timeouts: Timeouts::new(config),
+        clock,
+    };
+
+    let mut events = Events::with_capacity(1024);
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        let open = proxy.clients.len() - proxy.free_clients.len();
+        if let Some(deadline) = deadline
+            && (open == 0 || clock.now() >= deadline)
+        {
+            break;
+        }
+
+        // Com conexão aberta acorda de tempos em tempos para os timeouts e o 504
+        let waiting = proxy.upstreams.iter().any(|u| u.outstanding > 0);
+        let timeout = match (waiting, open > 0) {
+            (true, _) => Some(
+                (config.upstream_timeout / 4)
+                    .clamp(Duration::from_millis(10), proxy.timeouts.tick()),
+            ),
+            (false, true) => Some(proxy.timeouts.tick()),
+            (false, false) => None,
+        };
+        let timeout = match (timeout, deadline) {
+            (timeout, Some(deadline)) => {
+                let left = deadline.saturating_duration_since(clock.now());
+                Some(timeout.map_or(left, |timeout| timeout.min(left)))
+            }
+            (timeout, None) => timeout,
+        };
+        if let Err(e) = proxy.poll.poll(&mut events, timeout) {
+            if e.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            panic!("poll failed: {e}");
+        }
+
+        for event in &events {
+            match event.token() {
+                SHUTDOWN => {
+                    if deadline.is_some() {
+                        continue;
+                    }
+                    deadline = Some(clock.now() + common.drain_timeout);
+                    proxy.drain();
+                }
+                SERVER => proxy.accept(),
+                Token(token) if token >= UPSTREAM => {
+                    let index = token - UPSTREAM;
+                    if event.is_writable() {
+                        proxy.write_conn(index);
+                    }
+                    if event.is_readable() || event.is_read_closed() || event.is_error() {
+                        proxy.pump(index);
+                    }
+                }
+                Token(token) => {
+                    let index = token - 1;
+                    if event.is_writable() && proxy.pending(index) {
+                        proxy.client_ready(index);
+                    }
+                    if event.is_readable() || event.is_read_closed() {
+                        proxy.advance(index);
+                    }
+                }
+            }
+        }
+
+        proxy.sweep();
+    }
+
+    let open = proxy.clients.iter().flatten().count();
+    for _ in 0..open {
+        ACTIVE.dec();
+    }
+    logger::info!("Proxy stopped"; forced = open);
+}
+
+impl Proxy<'_> {
+    // Edge-triggered: aceita até esvaziar a fila ou acabar os slots
+    fn accept(&mut self) {
+        loop {
+            let Some(listener) = self.listener.as_ref() else {
+                return;
+            };
+            let Some(index) = self.free_clients.pop() else {
+                // fica no backlog do kernel até alguém fechar
+                self.backlogged = true;
+                return;
+            };
+
+            match listener.accept() {
+                Ok((mut stream, _)) => {
+                    let _ = stream.set_nodelay(true);
+                    self.poll
+                        .registry()
+                        .register(
+                            &mut stream,
+                            Token(index + 1),
+                            Interest::READABLE | Interest::WRITABLE,
+                        )
+                        .expect("unable to register stream with poll");
+                    self.clients[index] = Some(Client {
+                        stream,
+                        input: Vec::with_capacity(self.config.buffer_size),
+                        scanned: 0,
+                        request: Framing::request(),
+                        output: Vec::with_capacity(self.config.buffer_size),
+                        written: 0,
+                        conn: None,
+                        keep_alive: true,
+                        served: false,
+                        timer: Timer::new(Phase::Header, self.clock.now()),
+                    });
+                    ACTIVE.inc();
+                }
+                Err(e) => {
+                    self.free_clients.push(index);
+                    if e.kind() != ErrorKind::WouldBlock {
+                        logger::error!("Error accepting connection: {e}");
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    // Para de aceitar e fecha quem está parado em keep-alive, o resto termina
+    // a requisição atual e fecha
+    fn drain(&mut self) {
+        if let Some(mut listener) = self.listener.take() {
+            let _ = self.poll.registry().deregister(&mut listener);
+        }
+        self.draining = true;
+
+        for index in 0..self.clients.len() {
+            let idle = self.clients[index].as_ref().is_some_and(|client| {
+                client.conn.is_none() && client.input.is_empty() && client.output.is_empty()
+            });
+            if idle {
+                self.close_client(index);
+            }
+        }
+        for index in 0..self.conns.len() {
+            if self.conns[index]
+                .as_ref()
+                .is_some_and(|conn| conn.client.is_none())
+            {
+                self.drop_conn(index);
+            }
+        }
+        let open = self.clients.len() - self.free_clients.len();
+        logger::info!("Draining proxy"; connections = open);
+    }
+
+    fn pending(&self, index: usize) -> bool {
+        self.clients[index]
+            .as_ref()
+            .is_some_and(|client| client.written < client.output.len())
+    }
+
+    // Lê do cliente até ter uma requisição inteira e manda para um upstream.
+    // Enquanto a resposta anterior não termina o que chegar fica no socket.
+    fn advance(&mut self, index: usize) {
+        loop {
+            let Some(client) = self.clients[index].as_mut() else {
+                return;
+            };
+            if client.conn.is_some() || client.written < client.output.len() {
+                return;
+            }
+
+            if client.scanned < client.input.len() {
+                match client.request.feed(&client.input[client.scanned..]) {
+                    Ok(used) => client.scanned += used,
+                    Err(e) => {
+                        logger::debug!("Invalid request from client: {e}"; token = index);
+                        metrics::proxied(Proxied::Rejected);
+                        return self.respond_error(index, Status::BAD_REQUEST);
+                    }
+                }
+            }
+            if client.request.done() {
+                return self.forward(index);
+            }
+            // prazo de quem está mandando a requisição (ou de quem está parado)
+            let phase = match (client.request.is_idle(), client.request.in_head()) {
+                (true, _) if client.served => Phase::Idle,
+                (_, true) => Phase::Header,
+                _ => Phase::Body,
+            };
+            client.timer.enter(phase, self.clock.now());
+            if client.input.len() >= MAX_REQUEST {
+                metrics::proxied(Proxied::Rejected);
+                return self.respond_error(index, CONTENT_TOO_LARGE);
+            }
+
+            let len = client.input.len();
+            client.input.resize(len + READ_CHUNK, 0);
+            let read = client.stream.read(&mut client.input[len..]);
+            client.input.truncate(len + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => return self.close_client(index),
+                Ok(n) => BYTES_READ.add(n as u64),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    logger::debug!("Error reading from client: {e}"; token = index);
+                    return self.close_client(index);
+                }
+            }
+        }
+    }
+
+    fn forward(&mut self, index: usize) {
+        let Some(client) = self.clients[index].as_mut() else {
+            return;
+        };
+        client.keep_alive = client.request.keep_alive && !self.draining;
+        let head = client.request.is_head;
+        let safe = client.request.safe;
+        let end = client.scanned;
+
+        let Some(conn_index) = self.checkout(false) else {
+            metrics::proxied(Proxied::BadGateway);
+            return self.respond_error(index, Status::BAD_GATEWAY);
+        };
+
+        let client = self.clients[index].as_mut().unwrap();
+        let conn = self.conns[conn_index].as_mut().unwrap();
+        conn.request.clear();
+        conn.request.extend_from_slice(&client.input[..end]);
+        conn.safe = safe;
+        client.input.drain(..end);
+        client.scanned = 0;
+        client.request.reset(false);
+        client.conn = Some(conn_index);
+        self.attach(conn_index, index, head);
+    }
+
+    fn attach(&mut self, conn_index: usize, client: usize, head: bool) {
+        let conn = self.conns[conn_index].as_mut().unwrap();
+        conn.client = Some(client);
+        conn.written = 0;
+        conn.received = false;
+        conn.response.reset(head);
+        conn.since = self.clock.now();
+        self.upstreams[conn.upstream].outstanding += 1;
+        self.write_conn(conn_index);
+    }
+
+    // Upstream com menos requisições em andamento; quem recusou conexão há
+    // pouco só entra se todos recusaram, e nunca com skip_down. O connect não
+    // bloqueia, recusa que vier depois cai em connect_failed.
+    fn checkout(&mut self, skip_down: bool) -> Option<usize> {
+        let now = self.clock.now();
+        let count = self.upstreams.len();
+        let start = self.next % count;
+        self.next = self.next.wrapping_add(1);
+
+        let mut order: Vec<usize> = (0..count).map(|n| (start + n) % count).collect();
+        order.sort_by_key(|&n| {
+            let upstream = &self.upstreams[n];
+            let down = upstream.down_until.is_some_and(|until| until > now);
+            (down, upstream.outstanding)
+        });
+
+        for upstream in order {
+            if skip_down
+                && self.upstreams[upstream]
+                    .down_until
+                    .is_some_and(|until| until > now)
+            {
+                continue;
+            }
+            if let Some(conn) = self.upstreams[upstream].idle.pop() {
+                return Some(conn);
+            }
+
+            let Upstream {
+                address, target, ..
+            } = &self.upstreams[upstream];
+            match Stream::connect_nonblocking(target) {
+                Ok(stream) => return Some(self.open_conn(upstream, stream)),
+                Err(e) => {
+                    logger::warn!("Unable to connect to upstream: {e}"; upstream = address);
+                    self.upstreams[upstream].down_until = Some(now + DOWN_FOR);
+                }
+            }
+        }
+        None
+    }
+
+    fn open_conn(&mut self, upstream: usize, mut stream: Stream) -> usize {
+        let index = self.free_conns.pop().unwrap_or_else(|| {
+            self.conns.push(None);
+            self.conns.len() - 1
+        });
+        self.poll
+            .registry()
+            .register(
+                &mut stream,
+                Token(UPSTREAM + index),
+                Interest::READABLE | Interest::WRITABLE,
+            )
+            .expect("unable to register upstream with poll");
+        self.conns[index] = Some(Conn {
+            stream,
+            upstream,
+            client: None,
+            request: Vec::new(),
+            safe: false,
+            written: 0,
+            response: Framing::response(false),
+            received: false,
+            reused: false,
+            connecting: true,
+            since: self.clock.now(),
+        });
+        index
+    }
+
+    fn write_conn(&mut self, index: usize) {
+        let Some(conn) = self.conns[index].as_mut() else {
+            return;
+        };
+        if conn.connecting {
+            match conn.stream.connected() {
+                Ok(false) => return,
+                Ok(true) => {
+                    conn.connecting = false;
+                    self.upstreams[conn.upstream].down_until = None;
+                }
+                Err(e) => return self.connect_failed(index, e),
+            }
+        }
+        while conn.client.is_some() && conn.written < conn.request.len() {
+            match conn.stream.write(&conn.request[conn.written..]) {
+                Ok(n) => {
+                    BYTES_WRITTEN.add(n as u64);
+                    conn.written += n;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    logger::debug!("Error writing to upstream: {e}"; upstream = self.upstreams[conn.upstream].address);
+                    return self.conn_failed(index);
+                }
+            }
+        }
+    }
+
+    // Resposta do upstream para o cliente, até o fim da mensagem ou até o
+    // cliente acumular PENDING_LIMIT sem ler (retoma no writable dele)
+    fn pump(&mut self, index: usize) {
+        let Some(conn) = self.conns[index].as_mut() else {
+            return;
+        };
+        let Some(client_index) = conn.client else {
+            // no pool não chega nada de útil: fechou ou mandou lixo
+            return self.drop_conn(index);
+        };
+        // recusa do connect também chega como leitura
+        if conn.connecting {
+            return self.write_conn(index);
+        }
+
+        loop {
+            let conn = self.conns[index].as_mut().unwrap();
+            let client = self.clients[client_index].as_mut().unwrap();
+            if client.output.len() - client.written >= PENDING_LIMIT {
+                match write_client(client, self.clock.now()) {
+                    Ok(true) => continue,
+                    Ok(false) => return,
+                    Err(_) => return self.close_client(client_index),
+                }
+            }
+
+            match conn.stream.read(&mut self.scratch) {
+                Ok(0) if conn.response.ends_on_close() => {
+                    // o cliente também só sabe que acabou pelo close
+                    client.keep_alive = false;
+                    return self.finish(index, false);
+                }
+                Ok(0) => return self.conn_failed(index),
+                Ok(n) => {
+                    BYTES_READ.add(n as u64);
+                    conn.received = true;
+                    match conn.response.feed(&self.scratch[..n]) {
+                        Ok(used) => {
+                            client.output.extend_from_slice(&self.scratch[..used]);
+                            if conn.response.done() {
+                                // byte sobrando depois da resposta: upstream fora de sincronia
+                                let reusable = used == n && conn.response.keep_alive;
+                                return self.finish(index, reusable);
+                            }
+                        }
+                        Err(e) => {
+                            logger::warn!("Invalid response from upstream: {e}");
+                            return self.conn_failed(index);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    logger::debug!("Error reading from upstream: {e}");
+                    return self.conn_failed(index);
+                }
+            }
+        }
+
+        let client = self.clients[client_index].as_mut().unwrap();
+        if write_client(client, self.clock.now()).is_err() {
+            self.close_client(client_index);
+        }
+    }
+
+    // Resposta completa: a conexão volta para o pool se der
+    fn finish(&mut self, index: usize, reusable: bool) {
+        let conn = self.conns[index].as_mut().unwrap();
+        let client = conn.client.take().unwrap();
+        let upstream = &mut self.upstreams[conn.upstream];
+        upstream.outstanding -= 1;
+        metrics::proxied(Proxied::Forwarded);
+
+        if reusable && !self.draining && upstream.idle.len() < self.config.upstream_keepalive {
+            conn.reused = true;
+            conn.request.clear();
+            upstream.idle.push(index);
+        } else {
+            self.drop_conn(index);
+        }
+
+        self.clients[client].as_mut().unwrap().conn = None;
+        self.client_ready(client);
+    }
+
+    // Conexão do pool que o upstream já tinha fechado ganha uma nova tentativa,
+    // desde que nenhum byte da resposta tenha saído. Requisição escrita inteira
+    // pode ter sido processada antes do close, então só GET e HEAD vão de novo.
+    fn conn_failed(&mut self, index: usize) {
+        let conn = self.conns[index].as_mut().unwrap();
+        let (client, received, reused) = (conn.client.take(), conn.received, conn.reused);
+        let retry = reused && (conn.safe || conn.written < conn.request.len());
+        let safe = conn.safe;
+        let request = std::mem::take(&mut conn.request);
+        let head = conn.response.is_head_request();
+        self.upstreams[conn.upstream].outstanding -= 1;
+        self.drop_conn(index);
+
+        let Some(client) = client else {
+            return;
+        };
+        if received {
+            // metade da resposta já foi, o cliente só descobre pelo close
+            let client_conn = self.clients[client].as_mut().unwrap();
+            client_conn.conn = None;
+            client_conn.keep_alive = false;
+            return self.client_ready(client);
+        }
+        if retry && let Some(retry) = self.checkout(false) {
+            self.clients[client].as_mut().unwrap().conn = Some(retry);
+            let conn = self.conns[retry].as_mut().unwrap();
+            conn.request = request;
+            conn.safe = safe;
+            return self.attach(retry, client, head);
+        }
+
+        self.clients[client].as_mut().unwrap().conn = None;
+        metrics::proxied(Proxied::BadGateway);
+        self.respond_error(client, Status::BAD_GATEWAY);
+    }
+
+    // Nada saiu por uma conexão que nem abriu, então qualquer requisição vai
+    // para outro upstream que não esteja fora
+    fn connect_failed(&mut self, index: usize, e: std::io::Error) {
+        let conn = self.conns[index].as_mut().unwrap();
+        let (client, safe, upstream) = (conn.client.take(), conn.safe, conn.upstream);
+        let request = std::mem::take(&mut conn.request);
+        let head = conn.response.is_head_request();
+        logger::warn!("Unable to connect to upstream: {e}"; upstream = self.upstreams[upstream].address);
+        self.upstreams[upstream].down_until = Some(self.clock.now() + DOWN_FOR);
+        self.upstreams[upstream].outstanding -= 1;
+        self.drop_conn(index);
+
+        let Some(client) = client else {
+            return;
+        };
+        if let Some(retry) = self.checkout(true) {
+            self.clients[client].as_mut().unwrap().conn = Some(retry);
+            let conn = self.conns[retry].as_mut().unwrap();
+            conn.request = request;
+            conn.safe = safe;
+            return self.attach(retry, client, head);
+        }
+
+        self.clients[client].as_mut().unwrap().conn = None;
+        metrics::proxied(Proxied::BadGateway);
+        self.respond_error(client, Status::BAD_GATEWAY);
+    }
+
+    // Fim de escrita pendente no cliente: continua a resposta, passa para a
+    // próxima requisição ou fecha
+    fn client_ready(&mut self, index: usize) {
+        let Some(client) = self.clients[index].as_mut() else {
+            return;
+        };
+        match write_client(client, self.clock.now()) {
+            Err(_) => self.close_client(index),
+            Ok(false) => {}
+            Ok(true) => match client.conn {
+                Some(conn) => self.pump(conn),
+                None if client.keep_alive && !self.draining => self.advance(index),
+                None => self.close_client(index),
+            },
+        }
+    }
+
+    fn respond_error(&mut self, index: usize, status: Status) {
+        let client = self.clients[index].as_mut().unwrap();
+        client.keep_alive = false;
+        client.input.clear();
+        client.scanned = 0;
+        Response::new(&mut client.output, status)
+            .connection(Connection::Close)
+            .empty();
+        self.client_ready(index);
+    }
+
+    fn close_client(&mut self, index: usize) {
+        let Some(mut client) = self.clients[index].take() else {
+            return;
+        };
+        let _ = self.poll.registry().deregister(&mut client.stream);
+        ACTIVE.dec();
+        self.free_clients.push(index);
+
+        // resposta abandonada no meio, a conexão com o upstream não serve mais
+        if let Some(conn) = client.conn
+            && let Some(upstream) = self.conns[conn].as_ref().map(|conn| conn.upstream)
+        {
+            self.upstreams[upstream].outstanding -= 1;
+            self.drop_conn(conn);
+        }
+
+        if self.backlogged {
+            self.backlogged = false;
+            self.accept();
+        }
+    }
+
+    fn drop_conn(&mut self, index: usize) {
+        let Some(mut conn) = self.conns[index].take() else {
+            return;
+        };
+        let _ = self.poll.registry().deregister(&mut conn.stream);
+        self.upstreams[conn.upstream]
+            .idle
+            .retain(|&idle| idle != index);
+        self.free_conns.push(index);
+    }
+
+    // 504 para quem está esperando o primeiro byte há mais que o timeout e
+    // close para cliente lento demais na fase em que está
+    fn sweep(&mut self) {
+        let now = self.clock.now();
+        let timeout = self.config.upstream_timeout;
+        for index in 0..self.clients.len() {
+            let Some(client) = self.clients[index].as_ref() else {
+                continue;
+            };
+            // esperando o upstream, quem cuida é o timeout dele
+            let upstream = client.conn.is_some() && client.written == client.output.len();
+            if upstream || !self.timeouts.expired(&client.timer, now) {
+                continue;
+            }
+
+            let phase = client.timer.phase();
+            logger::debug!("Client timed out"; token = index, phase = phase.name());
+            metrics::timed_out(phase);
+            self.close_client(index);
+        }
+
+        for index in 0..self.conns.len() {
+            let expired = self.conns[index].as_ref().is_some_and(|conn| {
+                conn.client.is_some()
+                    && !conn.received
+                    && now.saturating_duration_since(conn.since) >= timeout
+            });
+            if !expired {
+                continue;
+            }
+
+            let conn = self.conns[index].as_mut().unwrap();
+            let client = conn.client.take().unwrap();
+            logger::warn!("Upstream timed out"; upstream = self.upstreams[conn.upstream].address);
+            // nem o connect terminou, os próximos tentam outro antes
+            if conn.connecting {
+                self.upstreams[conn.upstream].down_until = Some(now + DOWN_FOR);
+            }
+            self.upstreams[conn.upstream].outstanding -= 1;
+            self.drop_conn(index);
+
+            self.clients[client].as_mut().unwrap().conn = None;
+            metrics::proxied(Proxied::Timeout);
+            self.respond_error(client, Status::GATEWAY_TIMEOUT);
+        }
+    }
+}
+
+// Ok(true) quando não sobrou nada para escrever. O prazo de escrita conta
+// desde o último byte que o cliente aceitou.
+fn write_client(client: &mut Client, now: Instant) -> std::io::Result<bool> {
+    if client.written < client.output.len() {
+        client.timer.enter(Phase::Write, now);
+    }
+    while client.written < client.output.len() {
+        match client.stream.write(&client.output[client.written..]) {
+            Ok(n) => {
+                BYTES_WRITTEN.add(n as u64);
+                client.written += n;
+                client.timer.restart(now);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    client.output.clear();
+    client.written = 0;
+    // tudo entregue, daqui até o próximo byte conta como keep-alive
+    client.served = true;
+    client.timer = Timer::new(Phase::Idle, now);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use super::*;
+    use crate::timeouts::FakeClock;
+
+    // Upstream HTTP bloqueante, uma thread por conexão. respond escreve a
+    // resposta e diz se a conexão continua aberta.
+    type Respond = fn(&str, &mut dyn Write) -> bool;
+
+    fn serve_connection(mut stream: impl Read + Write, respond: Respond) {
+        let mut framing = Framing::request();
+        let mut head = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let n = match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            head.extend_from_slice(&buffer[..n]);
+            let used = framing.feed(&buffer[..n]).unwrap();
+            assert_eq!(used, n, "client pipelined to the upstream");
+            if !framing.done() {
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&head).into_owned();
+            let target = line.split(' ').nth(1).unwrap_or_default().to_string();
+            if !respond(&target, &mut stream) {
+                return;
+            }
+            framing.reset(false);
+            head.clear();
+        }
+    }
+
+    fn tcp_upstream(respond: Respond) -> (String, Arc<AtomicUsize>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::Relaxed);
+                std::thread::spawn(move || serve_connection(stream, respond));
+            }
+        });
+        (address, accepted)
+    }
+
+    fn unix_upstream(name: &str, respond: Respond) -> (String, Arc<AtomicUsize>) {
+        let path = std::env::temp_dir().join(format!("proxy-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::Relaxed);
+                std::thread::spawn(move || serve_connection(stream, respond));
+            }
+        });
+        (format!("unix:{}", path.display()), accepted)
+    }
+
+    // Proxy numa thread solta, fica rodando até o fim do processo
+    fn spawn_proxy(upstreams: &[&str], timeout_ms: &str, clock: &'static dyn Clock) -> SocketAddr {
+        let upstreams = upstreams.join(",");
+        let vars = [
+            ("MODE", "proxy"),
+            ("LB_UPSTREAMS", upstreams.as_str()),
+            ("LB_UPSTREAM_KEEPALIVE", "4"),
+            ("LB_UPSTREAM_TIMEOUT_MS", timeout_ms),
+        ];
+        let config = config::Config::from_pairs(&vars).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            serve(
+                &config.common,
+                &config.lb,
+                TcpListener::from_std(listener),
+                clock,
+            )
+        });
+        addr
+    }
+
+    fn connect(addr: SocketAddr) -> std::net::TcpStream {
+        let stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    // Lê exatamente uma resposta, devolve status e corpo
+    fn read_response(stream: &mut std::net::TcpStream, head: bool) -> (u16, Vec<u8>) {
+        let mut framing = Framing::response(head);
+        let mut raw = Vec::new();
+        let mut byte = [0u8; 1];
+        while !framing.done() {
+            let read = stream.read(&mut byte);
+            match read.unwrap_or_else(|e| panic!("{e} after {} bytes", raw.len())) {
+                0 if framing.ends_on_close() => break,
+                0 => panic!("connection closed mid response: {raw:?}"),
+                _ => {}
+            }
+            raw.push(byte[0]);
+            framing.feed(&byte).unwrap();
+        }
+        let end = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        (framing.status, raw.split_off(end))
+    }
+
+    fn respond_name(name: &'static str, target: &str, out: &mut dyn Write) -> bool {
+        let body = format!("{name} {target}");
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        out.write_all(response.as_bytes()).is_ok()
+    }
+
+    #[test]
+    fn test_keep_alive_and_balancing() {
+        let _servers = crate::serialize_servers();
+        let (tcp, tcp_accepted) = tcp_upstream(|target, out| respond_name("tcp", target, out));
+        let (unix, unix_accepted) =
+            unix_upstream("balance", |target, out| respond_name("unix", target, out));
+        let addr = spawn_proxy(&[&tcp, &unix], "2000", &SystemClock);
+
+        let mut client = connect(addr);
+        let mut seen = Vec::new();
+        for n in 0..20 {
+            let request = format!("GET /item/{n} HTTP/1.1\r\nHost: test\r\n\r\n");
+            client.write_all(request.as_bytes()).unwrap();
+            let (status, body) = read_response(&mut client, false);
+            assert_eq!(status, 200);
+            let body = String::from_utf8(body).unwrap();
+            assert!(body.ends_with(&format!(" /item/{n}")), "{body}");
+            seen.push(body.split(' ').next().unwrap().to_string());
+        }
+        // mesma carga nos dois, o desempate alterna
+        assert!(seen.iter().any(|name| name == "tcp"));
+        assert!(seen.iter().any(|name| name == "unix"));
+        // conexões reaproveitadas do pool
+        assert_eq!(tcp_accepted.load(Ordering::Relaxed), 1);
+        assert_eq!(unix_accepted.load(Ordering::Relaxed), 1);
+
+        // corpo e pipelining: duas requisições no mesmo write
+        let pipelined = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\n\r\n";
+        client.write_all(pipelined).unwrap();
+        let (_, first) = read_response(&mut client, false);
+        let (_, second) = read_response(&mut client, false);
+        assert!(first.ends_with(b" /a") && second.ends_with(b" /b"));
+
+        // HTTP/1.0 sem keep-alive fecha depois da resposta
+        let mut client = connect(addr);
+        client.write_all(b"GET /old HTTP/1.0\r\n\r\n").unwrap();
+        let (status, _) = read_response(&mut client, false);
+        assert_eq!(status, 200);
+        assert_eq!(client.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_response_framings() {
+        let _servers = crate::serialize_servers();
+        let (upstream, _) = tcp_upstream(|target, out| {
+            let response = match target {
+                "/chunked" => b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n".to_vec(),
+                "/head" => b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n".to_vec(),
+                "/large" => {
+                    let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 300000\r\n\r\n".to_vec();
+                    response.extend((0..300_000).map(|n| b'a' + (n % 26) as u8));
+                    response
+                }
+                _ => {
+                    // sem tamanho: termina no close
+                    let _ = out.write_all(b"HTTP/1.1 200 OK\r\n\r\nuntil close");
+                    return false;
+                }
+            };
+            out.write_all(&response).is_ok()
+        });
+        let addr = spawn_proxy(&[&upstream], "2000", &SystemClock);
+
+        let mut client = connect(addr);
+        client.write_all(b"GET /chunked HTTP/1.1\r\n\r\n").unwrap();
+        let (_, body) = read_response(&mut client, false);
+        assert_eq!(body, b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+
+        client.write_all(b"HEAD /head HTTP/1.1\r\n\r\n").unwrap();
+        let (status, body) = read_response(&mut client, true);
+        assert_eq!((status, body.len()), (200, 0));
+
+        // maior que PENDING_LIMIT, passa pelo backpressure com o cliente lento
+        client.write_all(b"GET /large HTTP/1.1\r\n\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let (_, body) = read_response(&mut client, false);
+        assert_eq!(body.len(), 300_000);
+        assert!(
+            body.iter()
+                .enumerate()
+                .all(|(n, b)| *b == b'a' + (n % 26) as u8)
+        );
+
+        client.write_all(b"GET /close HTTP/1.1\r\n\r\n").unwrap();
+        let (status, body) = read_response(&mut client, false);
+        assert_eq!((status, body.as_slice()), (200, b"until close".as_slice()));
+    }
+
+    #[test]
+    fn test_upstream_failures() {
+        let _servers = crate::serialize_servers();
+        // porta que ninguém escuta
+        let down = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let addr = spawn_proxy(&[&down], "2000", &SystemClock);
+        let mut client = connect(addr);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 502);
+
+        // fecha depois de cada resposta sem avisar: a conexão do pool está
+        // morta e a requisição vai de novo por uma nova
+        let (stale, accepted) = tcp_upstream(|target, out| {
+            respond_name("stale", target, out);
+            false
+        });
+        let addr = spawn_proxy(&[&stale], "2000", &SystemClock);
+        let mut client = connect(addr);
+        for n in 0..3 {
+            client
+                .write_all(format!("GET /{n} HTTP/1.1\r\n\r\n").as_bytes())
+                .unwrap();
+            // o upstream fecha logo depois da resposta, dá tempo do proxy não perceber
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(read_response(&mut client, false).0, 200);
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 3);
+
+        // aceita e nunca responde
+        let (silent, _) = tcp_upstream(|_, _| {
+            std::thread::sleep(Duration::from_secs(10));
+            false
+        });
+        let addr = spawn_proxy(&[&silent], "200", &SystemClock);
+        let mut client = connect(addr);
+        let started = Instant::now();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 504);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // requisição inválida nem chega no upstream
+        let mut client = connect(addr);
+        client.write_all(b"GARBAGE\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 400);
+    }
+
+    // O connect não bloqueia o loop: a recusa chega depois como evento e a
+    // requisição, que nem saiu, vai para o upstream que está de pé. O
+    // upstream recusado fica de fora pelo relógio do proxy, não pelo do sistema.
+    #[test]
+    fn test_refused_connect_moves_on() {
+        let _servers = crate::serialize_servers();
+        let clock: &'static FakeClock = Box::leak(Box::new(FakeClock::new()));
+        let down = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let (up, accepted) = tcp_upstream(|target, out| respond_name("up", target, out));
+        let addr = spawn_proxy(&[&down, &up], "2000", clock);
+
+        // HTTP/1.0 fecha a cada resposta, cada uma passa pelo checkout
+        let request = |n: usize| {
+            let mut client = connect(addr);
+            let request = format!("POST /{n} HTTP/1.0\r\nContent-Length: 1\r\n\r\nx");
+            client.write_all(request.as_bytes()).unwrap();
+            let (status, body) = read_response(&mut client, false);
+            assert_eq!(status, 200);
+            String::from_utf8(body).unwrap()
+        };
+        for n in 0..4 {
+            assert_eq!(request(n), format!("up /{n}"));
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+
+        // de pé de novo, mas o DOWN_FOR só passa no relógio do proxy
+        let revived = std::net::TcpListener::bind(&down).unwrap();
+        std::thread::spawn(move || {
+            for stream in revived.incoming().flatten() {
+                std::thread::spawn(move || {
+                    serve_connection(stream, |target, out| respond_name("revived", target, out))
+                });
+            }
+        });
+        std::thread::sleep(DOWN_FOR + Duration::from_millis(100));
+        for n in 4..8 {
+            assert_eq!(request(n), format!("up /{n}"));
+        }
+        clock.advance(DOWN_FOR);
+        let served: Vec<String> = (8..12).map(request).collect();
+        assert!(
+            served.iter().any(|body| body.starts_with("revived ")),
+            "{served:?}"
+        );
+    }
+
+    // Upstream que fecha a conexão do pool ao receber a requisição, sem
+    // responder: só o que pode ir duas vezes vai de novo
+    #[test]
+    fn test_retry_only_safe_requests() {
+        let _servers = crate::serialize_servers();
+        let (upstream, accepted) = tcp_upstream(|target, out| match target {
+            "/drop" => false,
+            _ => respond_name("up", target, out),
+        });
+        let addr = spawn_proxy(&[&upstream], "2000", &SystemClock);
+
+        let mut client = connect(addr);
+        client.write_all(b"GET /first HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 200);
+        // o POST já tinha saído inteiro, pode ter sido processado
+        client
+            .write_all(b"POST /drop HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc")
+            .unwrap();
+        assert_eq!(read_response(&mut client, false).0, 502);
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+
+        let mut client = connect(addr);
+        client.write_all(b"GET /second HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 200);
+        // o GET vai de novo por uma conexão nova, que também cai
+        client.write_all(b"GET /drop HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 502);
+        assert_eq!(accepted.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_client_timeouts() {
+        let _servers = crate::serialize_servers();
+        let clock: &'static FakeClock = Box::leak(Box::new(FakeClock::new()));
+        let (upstream, _) = tcp_upstream(|target, out| respond_name("up", target, out));
+        let addr = spawn_proxy(&[&upstream], "2000", clock);
+        let idle = metrics::timed_out_count(Phase::Idle);
+        let header = metrics::timed_out_count(Phase::Header);
+
+        // keep-alive parado menos que o idle continua servindo
+        let mut client = connect(addr);
+        client.write_all(b"GET /1 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 200);
+        clock.advance(Duration::from_secs(20));
+        std::thread::sleep(Duration::from_millis(300));
+        client.write_all(b"GET /2 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client, false).0, 200);
+
+        // e passando do idle é fechado
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(client.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(metrics::timed_out_count(Phase::Idle), idle + 1);
+
+        // headers pela metade ficam só até o header_timeout
+        let mut client = connect(addr);
+        client.write_all(b"GET /3 HTTP/1.1\r\nHost:").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(client.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(metrics::timed_out_count(Phase::Header), header + 1);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    cell::{Cell, Ref, RefCell},
+    sync::Arc,
+};
+
+use ledger::{Ledger, LedgerView, Processor, Summary};
+use message::http::response::{Connection, Response, Status};
+
+use crate::worker_poll::Workers;
+
+// Onde estão os ledgers dos workers: arquivos na pasta dos sockets, no modo
+// standalone a própria memória do processo, e com TCP só os workers sabem
+pub enum Ledgers {
+    Dir(Dir),
+    Shared(Vec<Arc<Ledger>>),
+    Remote,
+}
+
+// Os arquivos da pasta mapeados uma vez, de novo só quando os links mudam
+pub struct Dir {
+    path: String,
+    seen: Cell<Option<(usize, usize)>>, // Workers::attached da última abertura
+    views: RefCell<Vec<LedgerView>>,
+}
+
+impl Ledgers {
+    pub fn dir(path: String) -> Ledgers {
+        Ledgers::Dir(Dir {
+            path,
+            seen: Cell::new(None),
+            views: RefCell::new(Vec::new()),
+        })
+    }
+
+    // Soma os ledgers de todos os workers direto da memória compartilhada,
+    // sem passar pelo event loop deles. None com Remote: só os workers sabem
+    // e a resposta vem pelo link, sem segurar o event loop.
+    pub fn collect(
+        &self,
+        workers: &Workers,
+        from: u64,
+        to: u64,
+    ) -> Option<std::io::Result<Summary>> {
+        match self {
+            Ledgers::Remote => None,
+            local => Some(local.sum(workers, from, to)),
+        }
+    }
+
+    fn sum(&self, workers: &Workers, from: u64, to: u64) -> std::io::Result<Summary> {
+        let mut summary = Summary::default();
+        match self {
+            Ledgers::Dir(dir) => {
+                for view in dir.views(workers)?.iter() {
+                    summary.merge(&view.summary(from, to)?);
+                }
+            }
+            Ledgers::Shared(ledgers) => {
+                for ledger in ledgers {
+                    summary.merge(&ledger.summary(from, to)?);
+                }
+            }
+            Ledgers::Remote => {}
+        }
+
+        Ok(summary)
+    }
+}
+
+impl Dir {
+    // Worker novo (ou que voltou) aparece num renew. O socket existe antes do
+    // ledger, então menos arquivos que links também reabre.
+    fn views(&self, workers: &Workers) -> std::io::Result<Ref<'_, Vec<LedgerView>>> {
+        let attached = workers.attached();
+        let stale = self.seen.get() != Some(attached) || self.views.borrow().len() < attached.1;
+
+        if stale {
+            let views = LedgerView::open_dir(&self.path)?;
+            // sem o ledger de algum worker o total sairia menor
+            if views.len() < attached.1 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("fewer ledgers than workers in {}", self.path),
+                ));
+            }
+            self.views.replace(views);
+            self.seen.set(Some(attached));
+        }
+
+        Ok(self.views.borrow())
+    }
+}
+
+pub fn write_response(summary: &Summary, out: &mut Vec<u8>) {
+    Response::new(out, Status::OK)
+        .connection(Connection::Close)
+        .json(|json| {
+            json.begin_object();
+            for processor in Processor::ALL {
+                let totals = summary.get(processor);
+                json.key(processor.name())
+                    .begin_object()
+                    .key("totalRequests")
+                    .u64(totals.requests)
+                    .key("totalAmount")
+                    .cents(totals.amount)
+                    .end_object();
+            }
+            json.end_object();
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use ledger::{EXTENSION, Processor};
+
+    use super::*;
+    use crate::worker_poll::{WorkerPoll, start_workers};
+
+    #[test]
+    fn test_dir_views() {
+        let config = config::Config::from_pairs(&[("SOCKET_DIR", "/tmp")])
+            .unwrap()
+            .lb;
+        let poll: WorkerPoll = WorkerPoll::with_links(Vec::new(), &config);
+        let workers = start_workers(poll, &config);
+        let path = std::env::temp_dir().join(format!("summary-{}", std::process::id()));
+        let ledgers = Ledgers::dir(path.to_string_lossy().into_owned());
+
+        // pasta que não abre é erro, não total zerado
+        assert!(ledgers.collect(&workers, 0, u64::MAX).unwrap().is_err());
+
+        std::fs::create_dir_all(&path).unwrap();
+        let ledger = Ledger::create(path.join(format!("api1.{EXTENSION}"))).unwrap();
+        ledger.record(Processor::Default, 1990, 0);
+        let summary = ledgers.collect(&workers, 0, u64::MAX).unwrap().unwrap();
+        assert_eq!(summary.default.amount, 1990);
+
+        // o mapeamento fica para os próximos pedidos
+        ledger.record(Processor::Fallback, 1000, 0);
+        let Ledgers::Dir(dir) = &ledgers else {
+            unreachable!()
+        };
+        let mapped = dir.views.borrow().len();
+        let summary = ledgers.collect(&workers, 0, u64::MAX).unwrap().unwrap();
+        assert_eq!(summary.fallback.amount, 1000);
+        assert_eq!(dir.views.borrow().len(), mapped);
+        assert_eq!(dir.seen.get(), Some(workers.attached()));
+
+        workers.drain(std::time::Instant::now());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
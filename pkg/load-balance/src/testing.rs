@@ -0,0 +1,127 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use connection::memory;
+use ledger::Ledger;
+use message::socket::Message;
+
+use crate::{
+    Ledgers, Link, epoll,
+    handler::Handler,
+    timeouts::Clock,
+    worker_poll::{WorkerPoll, start_workers},
+};
+
+// Servidor de teste dos dois backends do load balancer
+
+#[derive(Clone, Copy, Debug)]
+pub enum Backend {
+    Epoll,
+    #[cfg(feature = "io-uring")]
+    Uring,
+}
+
+pub const BACKENDS: &[Backend] = &[
+    Backend::Epoll,
+    #[cfg(feature = "io-uring")]
+    Backend::Uring,
+];
+
+// Load balancer com um worker falso que só conta os pagamentos
+pub fn spawn(
+    backend: Backend,
+    config: &config::Config,
+    clock: &'static dyn Clock,
+) -> (SocketAddr, Arc<AtomicUsize>, std::thread::JoinHandle<()>) {
+    spawn_slow(backend, config, clock, Duration::ZERO)
+}
+
+// O mesmo com um worker que demora para responder as métricas
+pub fn spawn_slow(
+    backend: Backend,
+    config: &config::Config,
+    clock: &'static dyn Clock,
+    delay: Duration,
+) -> (SocketAddr, Arc<AtomicUsize>, std::thread::JoinHandle<()>) {
+    // mesmo bind da produção, com a fila de accept dela
+    let listener = crate::listener::bind(0, false).unwrap();
+    let addr = SocketAddr::from(([127, 0, 0, 1], listener.local_addr().unwrap().port()));
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let (lb_end, worker_end) = memory::pair();
+    let counter = received.clone();
+    std::thread::spawn(move || {
+        while let Ok(frame) = worker_end.rx.recv() {
+            match Message::from_bytes(&frame) {
+                Ok(Message::Payment(..)) => {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(Message::Metrics) => {
+                    std::thread::sleep(delay);
+                    for chunk in Message::chunks(b"") {
+                        let _ = worker_end.tx.send(chunk.to_bytes());
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+
+    let config = config.clone();
+    let thread = std::thread::spawn(move || {
+        let poll: WorkerPoll = WorkerPoll::with_links(vec![Link::Memory(lb_end)], &config.lb);
+        let workers = start_workers(poll, &config.lb);
+        let ledgers = Ledgers::Shared(vec![Arc::new(Ledger::anonymous().unwrap())]);
+        let handler = Handler {
+            workers: &workers,
+            ledgers: &ledgers,
+            purge_token: None,
+        };
+
+        let outcome = match backend {
+            Backend::Epoll => epoll(
+                &config.common,
+                &config.lb,
+                mio::net::TcpListener::from_std(listener),
+                &handler,
+                clock,
+            ),
+            #[cfg(feature = "io-uring")]
+            Backend::Uring => {
+                let reactor =
+                    connection::uring::Reactor::new(config.lb.max_slots, config.lb.buffer_size)
+                        .expect("io_uring unavailable");
+                crate::uring::serve(
+                    &config.common,
+                    &config.lb,
+                    listener,
+                    reactor,
+                    &handler,
+                    clock,
+                )
+            }
+        };
+        assert_eq!(outcome.forced, 0);
+        workers.drain(Instant::now() + Duration::from_secs(1));
+    });
+
+    (addr, received, thread)
+}
+
+pub fn request(addr: SocketAddr, raw: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(raw).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
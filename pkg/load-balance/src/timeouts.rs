@@ -0,0 +1,262 @@
+use std::time::{Duration, Instant};
+
+// Slowloris e conexão esquecida: cada slot guarda a fase em que está e desde
+// quando, o loop varre os slots de tempos em tempos e fecha quem passou do
+// limite da fase. O relógio é trocável para os testes andarem no tempo sem
+// esperar.
+
+pub trait Clock: Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Header = 0, // do accept (ou do primeiro byte) até o fim dos headers
+    Body = 1,
+    Write = 2, // resposta pronta esperando o cliente ler
+    Idle = 3,  // keep-alive entre requisições, só no proxy (o modo lb fecha depois da resposta)
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Header => "header",
+            Phase::Body => "body",
+            Phase::Write => "write",
+            Phase::Idle => "idle",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    phase: Phase,
+    since: Instant,
+}
+
+impl Timer {
+    pub fn new(phase: Phase, now: Instant) -> Timer {
+        Timer { phase, since: now }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    // Troca de fase recomeça a contagem, a mesma fase continua contando:
+    // quem manda um byte por vez não renova o prazo dos headers
+    pub fn enter(&mut self, phase: Phase, now: Instant) {
+        if self.phase != phase {
+            *self = Timer::new(phase, now);
+        }
+    }
+
+    // Progresso de verdade (escrita que andou) renova o prazo
+    pub fn restart(&mut self, now: Instant) {
+        self.since = now;
+    }
+}
+
+pub struct Timeouts {
+    limits: [Duration; 4],
+}
+
+impl Timeouts {
+    pub fn new(config: &config::LoadBalancer) -> Timeouts {
+        Timeouts {
+            limits: [
+                config.header_timeout,
+                config.body_timeout,
+                config.write_timeout,
+                config.idle_timeout,
+            ],
+        }
+    }
+
+    pub fn expired(&self, timer: &Timer, now: Instant) -> bool {
+        now.saturating_duration_since(timer.since) >= self.limits[timer.phase as usize]
+    }
+
+    // Intervalo da varredura: um quarto do menor limite, entre 10ms e 250ms
+    pub fn tick(&self) -> Duration {
+        let shortest = self.limits.iter().min().copied().unwrap_or_default();
+        (shortest / 4).clamp(Duration::from_millis(10), Duration::from_millis(250))
+    }
+}
+
+#[cfg(test)]
+pub(crate) use fake::FakeClock;
+
+#[cfg(test)]
+mod fake {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use super::Clock;
+
+    // Só anda quando o teste manda
+    pub struct FakeClock {
+        base: Instant,
+        offset: AtomicU64, // micros
+    }
+
+    impl FakeClock {
+        pub fn new() -> FakeClock {
+            FakeClock {
+                base: Instant::now(),
+                offset: AtomicU64::new(0),
+            }
+        }
+
+        pub fn advance(&self, by: Duration) {
+            self.offset
+                .fetch_add(by.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.base + Duration::from_micros(self.offset.load(Ordering::Relaxed))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::{
+        metrics,
+        testing::{BACKENDS, request, spawn},
+    };
+
+    #[test]
+    fn test_phases() {
+        let mut config = config::Config::from_pairs(&[("SOCKET_DIR", "/tmp")])
+            .unwrap()
+            .lb;
+        config.header_timeout = Duration::from_secs(2);
+        config.body_timeout = Duration::from_secs(4);
+        config.write_timeout = Duration::from_secs(1);
+        let timeouts = Timeouts::new(&config);
+        assert_eq!(timeouts.tick(), Duration::from_millis(250));
+
+        let clock = FakeClock::new();
+        let mut timer = Timer::new(Phase::Header, clock.now());
+        clock.advance(Duration::from_millis(1500));
+        // byte a byte nos headers não renova o prazo
+        timer.enter(Phase::Header, clock.now());
+        assert!(!timeouts.expired(&timer, clock.now()));
+        clock.advance(Duration::from_millis(500));
+        assert!(timeouts.expired(&timer, clock.now()));
+
+        // o corpo tem prazo próprio a partir do fim dos headers
+        timer.enter(Phase::Body, clock.now());
+        clock.advance(Duration::from_secs(3));
+        assert!(!timeouts.expired(&timer, clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(timeouts.expired(&timer, clock.now()));
+        assert_eq!(timer.phase(), Phase::Body);
+
+        timer.enter(Phase::Write, clock.now());
+        clock.advance(Duration::from_millis(900));
+        timer.restart(clock.now());
+        clock.advance(Duration::from_millis(900));
+        assert!(!timeouts.expired(&timer, clock.now()));
+    }
+
+    fn closed(stream: &mut std::net::TcpStream) -> bool {
+        match stream.read(&mut [0u8; 1]) {
+            Ok(0) => true,
+            Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+            Ok(_) => false,
+        }
+    }
+
+    // Cliente lento é fechado pela varredura do loop, o relógio falso faz o
+    // tempo passar sem esperar os prazos de verdade
+    #[test]
+    fn test_slow_clients() {
+        let _servers = crate::serialize_servers();
+        let config = config::Config::from_pairs(&[
+            ("MODE", "standalone"),
+            ("LB_HEADER_TIMEOUT_MS", "1000"),
+            ("LB_BODY_TIMEOUT_MS", "2000"),
+        ])
+        .unwrap();
+        let body = r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90}"#;
+        let head = format!(
+            "POST /payments HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        // dá tempo do loop ler o que foi escrito antes do relógio andar
+        let settle = || std::thread::sleep(Duration::from_millis(50));
+
+        for &backend in BACKENDS {
+            let clock: &'static FakeClock = Box::leak(Box::new(FakeClock::new()));
+            let (addr, _, _) = spawn(backend, &config, clock);
+            let connect = || {
+                let stream = std::net::TcpStream::connect(addr).unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                stream
+            };
+            let header = metrics::timed_out_count(Phase::Header);
+            let body_count = metrics::timed_out_count(Phase::Body);
+
+            // slowloris: um pedaço dos headers e mais nada
+            let mut slow = connect();
+            slow.write_all(&head.as_bytes()[..20]).unwrap();
+            settle();
+            clock.advance(Duration::from_millis(1000));
+            assert!(closed(&mut slow), "{backend:?}");
+            assert_eq!(metrics::timed_out_count(Phase::Header), header + 1);
+
+            // headers inteiros e metade do corpo
+            let mut slow = connect();
+            slow.write_all(head.as_bytes()).unwrap();
+            slow.write_all(&body.as_bytes()[..10]).unwrap();
+            settle();
+            clock.advance(Duration::from_millis(2000));
+            assert!(closed(&mut slow), "{backend:?}");
+            assert_eq!(metrics::timed_out_count(Phase::Body), body_count + 1);
+
+            // lenta mas dentro dos prazos de cada fase termina normalmente
+            let mut split = connect();
+            split.write_all(&head.as_bytes()[..20]).unwrap();
+            settle();
+            clock.advance(Duration::from_millis(800));
+            split.write_all(&head.as_bytes()[20..]).unwrap();
+            split.write_all(&body.as_bytes()[..10]).unwrap();
+            settle();
+            clock.advance(Duration::from_millis(1500));
+            settle();
+            split.write_all(&body.as_bytes()[10..]).unwrap();
+            let mut response = String::new();
+            split.read_to_string(&mut response).unwrap();
+            assert!(
+                response.starts_with("HTTP/1.1 200"),
+                "{backend:?}: {response}"
+            );
+
+            // e quem foi fechado não deixou slot preso
+            let summary = request(addr, b"GET /payments-summary HTTP/1.1\r\n\r\n");
+            assert!(
+                summary.starts_with("HTTP/1.1 200"),
+                "{backend:?}: {summary}"
+            );
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::VecDeque,
+    net::TcpListener,
+    os::fd::{AsRawFd, FromRawFd, RawFd},
+    time::Instant,
+};
+
+use message::http::parse::{self, Progress};
+
+use connection::{
+    metrics::{ACTIVE, BYTES_READ, BYTES_WRITTEN},
+    shutdown,
+    uring::{self, Completion, Reactor},
+};
+
+use crate::{
+    Outcome,
+    admission::{self, Admission, Verdict},
+    handler::{Handler, Respond},
+    metrics::{self, Stamps},
+    timeouts::{Clock, Phase, Timeouts, Timer},
+};
+
+// Mesmo ciclo do loop do mio (uma leitura, a resposta e close), com a leitura
+// e a escrita no buffer registrado do slot. Conexão aceita sem slot livre
+// espera no backlog até alguém fechar.
+
+struct Slot {
+    fd: RawFd, // -1 livre
+    out: Vec<u8>,
+    written: usize,
+    stamps: Stamps,
+    peer: Option<std::net::IpAddr>,
+    filled: usize, // requisição que chegou partida
+    timer: Timer,
+    expired: bool, // leitura ou escrita cancelada, fecha quando ela concluir
+    ticket: usize, // token da conexão para os workers, muda a cada accept
+    parked: bool,  // esperando os workers, sem operação pendente
+}
+
+struct Slots<'a> {
+    reactor: Reactor,
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    backlog: VecDeque<RawFd>,
+    buffer_size: usize,
+    count: usize,
+    accepted: usize, // conexões até agora, para o ticket de cada uma
+    admission: Option<Admission>,
+    shed: bool,
+    clock: &'a dyn Clock,
+}
+
+impl Slots<'_> {
+    fn open(&mut self, fd: RawFd) {
+        // o accept do io_uring não devolve o endereço
+        let peer = match self.admission.is_some() {
+            true => peer_ip(fd),
+            false => None,
+        };
+        let verdict = match (self.free.is_empty(), self.admission.as_mut(), peer) {
+            (true, _, _) if self.shed => Verdict::Overloaded,
+            (false, Some(admission), Some(ip)) => admission.admit(ip, self.clock.now()),
+            _ => Verdict::Admit,
+        };
+        if verdict != Verdict::Admit {
+            metrics::rejected(verdict);
+            connection::reactor::refuse(fd, verdict.response());
+            unsafe { libc::close(fd) };
+            return;
+        }
+
+        let Some(index) = self.free.pop() else {
+            self.backlog.push_back(fd);
+            return;
+        };
+
+        let ticket = self.accepted * self.slots.len() + index;
+        self.accepted += 1;
+        let slot = &mut self.slots[index];
+        slot.fd = fd;
+        slot.peer = peer;
+        slot.timer = Timer::new(Phase::Header, self.clock.now());
+        slot.stamps = Stamps::accepted();
+        // ticket % slots é o slot, resposta de quem já fechou não cai em quem reusou
+        slot.ticket = ticket;
+        ACTIVE.inc();
+        self.count += 1;
+        self.reactor.read(index, fd, 0);
+    }
+
+    fn close(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        self.reactor.close(index, slot.fd);
+        slot.fd = -1;
+        slot.out.clear();
+        slot.written = 0;
+        slot.filled = 0;
+        slot.expired = false;
+        slot.parked = false;
+        admission::release(&mut self.admission, slot.peer.take());
+        ACTIVE.dec();
+        self.count -= 1;
+
+        self.free.push(index);
+        if let Some(fd) = self.backlog.pop_front() {
+            self.open(fd);
+        }
+    }
+
+    // Resposta que cabe no slot vai pelo buffer registrado, o resto (metrics) por send
+    fn write(&mut self, index: usize) {
+        let slot = &self.slots[index];
+        let (fd, range) = (slot.fd, slot.written..slot.out.len());
+        if slot.out.len() <= self.buffer_size {
+            self.reactor.buffer(index)[range.clone()].copy_from_slice(&slot.out[range.clone()]);
+            self.reactor.write(index, fd, range);
+        } else {
+            // out não muda até a conclusão do WRITE, o slot só é limpo no close
+            unsafe { self.reactor.send(index, fd, &slot.out[range]) };
+        }
+    }
+}
+
+pub fn serve(
+    common: &config::Common,
+    config: &config::LoadBalancer,
+    listener: TcpListener,
+    reactor: Reactor,
+    handler: &Handler,
+    clock: &dyn Clock,
+) -> Outcome {
+    let timeouts = Timeouts::new(config);
+    uring::blocking(listener.as_raw_fd()).expect("unable to set listener blocking");
+    let mut listener = Some(listener);
+    let mut slots = Slots {
+        reactor,
+        slots: (0..config.max_slots)
+            .map(|_| Slot {
+                fd: -1,
+                out: Vec::with_capacity(config.buffer_size),
+                written: 0,
+                stamps: Stamps::default(),
+                peer: None,
+                filled: 0,
+                timer: Timer::new(Phase::Header, clock.now()),
+                expired: false,
+                ticket: 0,
+                parked: false,
+            })
+            .collect(),
+        free: (0..config.max_slots).rev().collect(),
+        backlog: VecDeque::new(),
+        buffer_size: config.buffer_size,
+        count: 0,
+        accepted: 0,
+        admission: Admission::new(config, clock.now()),
+        shed: config.shed,
+        clock,
+    };
+    let mut completions: Vec<Completion> = Vec::with_capacity(1024);
+
+    slots.reactor.accept(listener.as_ref().unwrap().as_raw_fd());
+    slots
+        .reactor
+        .watch(shutdown::eventfd().expect("unable to watch for shutdown"));
+    slots.reactor.notify(handler.workers.bell());
+
+    let mut deadline: Option<Instant> = None;
+    let mut finished = 0;
+
+    loop {
+        if let Some(deadline) = deadline
+            && (slots.count == 0 || Instant::now() >= deadline)
+        {
+            break;
+        }
+
+        let tick = (slots.count > 0).then(|| timeouts.tick());
+        let timeout = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                Some(tick.map_or(left, |tick| tick.min(left)))
+            }
+            None => tick,
+        };
+        if let Err(e) = slots.reactor.wait(timeout, &mut completions) {
+            panic!("io_uring wait failed: {e}");
+        }
+
+        for &Completion {
+            op,
+            slot: index,
+            result,
+            more,
+        } in completions.iter()
+        {
+            match op {
+                uring::SHUTDOWN => {
+                    if deadline.is_some() {
+                        continue;
+                    }
+
+                    // Para de aceitar, o que já está aberto tem até o deadline
+                    if listener.take().is_some() {
+                        slots.reactor.cancel_accept();
+                    }
+                    for fd in slots.backlog.drain(..) {
+                        unsafe { libc::close(fd) };
+                    }
+                    deadline = Some(Instant::now() + common.drain_timeout);
+                    logger::info!("Draining load balancer"; connections = slots.count);
+                }
+                uring::ACCEPT => {
+                    if result >= 0 {
+                        match listener.is_some() {
+                            true => slots.open(result),
+                            false => unsafe {
+                                libc::close(result);
+                            },
+                        }
+                    } else if result != -libc::ECANCELED {
+                        logger::error!(
+                            "Error accepting connection: {}",
+                            std::io::Error::from_raw_os_error(-result)
+                        );
+                    }
+
+                    if !more && let Some(listener) = listener.as_ref() {
+                        slots.reactor.accept(listener.as_raw_fd());
+                    }
+                }
+                // O ticket é da conexão que pediu, se ela ainda existe a resposta sai
+                uring::WAKE => {
+                    for (ticket, answer) in handler.workers.answers() {
+                        let index = ticket % slots.slots.len();
+                        let slot = &mut slots.slots[index];
+                        if slot.fd < 0 || slot.ticket != ticket || !slot.parked {
+                            continue;
+                        }
+                        slot.parked = false;
+                        handler.complete(answer, &mut slot.out, ticket);
+                        slots.write(index);
+                    }
+                    slots.reactor.notify(handler.workers.bell());
+                }
+                uring::READ | uring::WRITE if slots.slots[index].expired => slots.close(index),
+                uring::READ => {
+                    if result <= 0 {
+                        if result < 0 {
+                            let e = std::io::Error::from_raw_os_error(-result);
+                            logger::warn!("Error handling connection: {e}"; token = index);
+                        }
+                        slots.close(index);
+                        continue;
+                    }
+
+                    BYTES_READ.add(result as u64);
+                    let slot = &mut slots.slots[index];
+                    slot.filled += result as usize;
+                    let filled = slot.filled;
+
+                    // Requisição partida: lê o resto depois do que já chegou
+                    let progress = parse::progress(&slots.reactor.buffer(index)[..filled]);
+                    if progress != Progress::Complete && filled < slots.buffer_size {
+                        let phase = match progress {
+                            Progress::Head => Phase::Header,
+                            _ => Phase::Body,
+                        };
+                        slot.timer.enter(phase, clock.now());
+                        slots.reactor.read(index, slot.fd, filled);
+                        continue;
+                    }
+
+                    slot.timer.enter(Phase::Write, clock.now());
+                    let respond = handler.respond(
+                        &slots.reactor.buffer(index)[..filled],
+                        &mut slot.out,
+                        &mut slot.stamps,
+                        slot.ticket,
+                    );
+                    if respond == Respond::Parked {
+                        slot.parked = true;
+                        continue;
+                    }
+
+                    match slot.out.is_empty() {
+                        true => {
+                            finished += deadline.is_some() as usize;
+                            slots.close(index);
+                        }
+                        false => slots.write(index),
+                    }
+                }
+                uring::WRITE => {
+                    if result <= 0 {
+                        if result < 0 {
+                            let e = std::io::Error::from_raw_os_error(-result);
+                            logger::warn!("Error handling connection: {e}"; token = index);
+                        }
+                        slots.close(index);
+                        continue;
+                    }
+
+                    BYTES_WRITTEN.add(result as u64);
+                    let slot = &mut slots.slots[index];
+                    slot.written += result as usize;
+                    if slot.written < slot.out.len() {
+                        slots.write(index);
+                        continue;
+                    }
+
+                    slot.stamps.flushed();
+                    finished += deadline.is_some() as usize;
+                    slots.close(index);
+                }
+                _ => {} // CLOSE e CANCEL não mudam nada
+            }
+        }
+
+        // O fd só fecha quando a operação cancelada concluir, senão o slot
+        // voltaria para o pool com uma leitura pendente
+        let now = clock.now();
+        for index in 0..slots.slots.len() {
+            let slot = &mut slots.slots[index];
+            if slot.fd < 0 || slot.expired || !timeouts.expired(&slot.timer, now) {
+                continue;
+            }
+
+            let phase = slot.timer.phase();
+            logger::debug!("Connection timed out"; token = index, phase = phase.name());
+            metrics::timed_out(phase);
+            // parada esperando os workers não tem operação para cancelar
+            if slot.parked {
+                slots.close(index);
+                continue;
+            }
+            slot.expired = true;
+            let op = match phase {
+                Phase::Write => uring::WRITE,
+                _ => uring::READ,
+            };
+            slots.reactor.cancel(index, op);
+        }
+    }
+
+    // O drop do reactor cancela as leituras pendentes, só depois os fds fecham
+    let Slots {
+        reactor,
+        slots: open,
+        count,
+        ..
+    } = slots;
+    drop(reactor);
+    for slot in open.iter().filter(|slot| slot.fd >= 0) {
+        unsafe { libc::close(slot.fd) };
+        ACTIVE.dec();
+    }
+
+    Outcome {
+        deadline,
+        finished,
+        forced: count,
+    }
+}
+
+fn peer_ip(fd: RawFd) -> Option<std::net::IpAddr> {
+    // só empresta o fd para a std ler o getpeername
+    let stream = std::mem::ManuallyDrop::new(unsafe { std::net::TcpStream::from_raw_fd(fd) });
+    stream.peer_addr().ok().map(|addr| addr.ip())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use super::*;
+    use crate::{
+        testing::{BACKENDS, request, spawn},
+        timeouts::SystemClock,
+    };
+
+    // Mesma carga nos dois backends: respostas iguais e o tempo de cada um
+    // (cargo test --features io-uring -- --nocapture mostra a comparação)
+    #[test]
+    fn test_backends_match() {
+        const CLIENTS: usize = 8;
+        const REQUESTS: usize = 250;
+        let _servers = crate::serialize_servers();
+
+        let config =
+            config::Config::from_pairs(&[("MODE", "standalone"), ("DRAIN_TIMEOUT_MS", "1000")])
+                .unwrap();
+        let body = r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90}"#;
+        let payment = format!(
+            "POST /payments HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        // métricas maiores que o buffer do slot saem por send
+        crate::metrics::register();
+        let mut servers = Vec::new();
+        for &backend in BACKENDS {
+            let (addr, received, thread) = spawn(backend, &config, &SystemClock);
+
+            let started = Instant::now();
+            std::thread::scope(|scope| {
+                for _ in 0..CLIENTS {
+                    scope.spawn(|| {
+                        for _ in 0..REQUESTS {
+                            let response = request(addr, payment.as_bytes());
+                            assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+                        }
+                    });
+                }
+            });
+
+            let summary = request(addr, b"GET /payments-summary HTTP/1.1\r\n\r\n");
+            assert!(
+                summary.starts_with("HTTP/1.1 200"),
+                "{backend:?}: {summary}"
+            );
+            let missing = request(addr, b"GET /unknown HTTP/1.1\r\n\r\n");
+            assert!(
+                missing.starts_with("HTTP/1.1 404"),
+                "{backend:?}: {missing}"
+            );
+            let metrics = request(addr, b"GET /metrics HTTP/1.1\r\n\r\n");
+            assert!(
+                metrics.contains("rinha_http_requests_total"),
+                "{backend:?}: {metrics}"
+            );
+
+            let total = CLIENTS * REQUESTS;
+            while received.load(Ordering::Relaxed) < total {
+                assert!(started.elapsed() < Duration::from_secs(10), "{backend:?}");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            servers.push(thread);
+        }
+
+        shutdown::request();
+        for thread in servers {
+            thread.join().unwrap();
+        }
+    }
+}
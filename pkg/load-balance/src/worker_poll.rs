@@ -1,120 +1,1241 @@
 use std::{
-    io::Write,
-    sync::mpsc::{Sender, channel},
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, SendError, Sender, TryRecvError, channel},
+    },
+    time::{Duration, Instant},
 };
 
-use message::socket::Message;
-use mio::net::UnixStream;
+use connection::{
+    memory::Endpoint,
+    ring::Ring,
+    transport::{Address, Stream},
+};
+use ledger::{Processor, Summary, Totals};
+use message::socket::{HEALTH_INTERVAL, Message};
+
+// Frames por ring, 64 bytes cada
+const RING_FRAMES: usize = 4096;
+// De quanto em quanto tempo a thread lê o que os workers mandaram sem pedido
+const PUMP_INTERVAL: Duration = Duration::from_millis(50);
+// Com pagamento na fila ou pedido sem resposta a thread volta antes
+const BUSY_INTERVAL: Duration = Duration::from_millis(1);
+// Worker que mandava Health e parou por esse tempo está travado
+const SILENT_AFTER: Duration = HEALTH_INTERVAL.saturating_mul(20);
+// Quantos reply_timeout um pagamento espera na fila por um worker
+const FORWARD_PATIENCE: u32 = 16;
+
+// Ponta do load balancer no socket de um worker. O worker manda Health e
+// Credit quando quer, então toda leitura passa por aqui: isso fica no link
+// e só o resto (Ack, Chunk, Notice) volta para quem fez um pedido.
+pub struct Remote<S = Stream> {
+    name: String,
+    stream: S,
+    inbound: [u8; Message::SIZE],
+    filled: usize,
+    outbound: Vec<u8>, // frame que o socket não aceitou inteiro, sai antes do próximo
+    sent: u64,         // pagamentos mandados pelo socket
+    limit: Option<u64>, // do último Credit, None é worker sem controle de crédito
+    heard: Instant,
+    health: Option<(u32, u32)>, // (na fila, em retry) do último Health
+    attaching: bool,            // Attach mandado, o Ack ainda não chegou
+}
+
+impl<S: Read + Write> Remote<S> {
+    pub fn new(stream: S, name: String) -> Remote<S> {
+        Remote {
+            name,
+            stream,
+            inbound: [0; Message::SIZE],
+            filled: 0,
+            outbound: Vec::with_capacity(Message::SIZE),
+            sent: 0,
+            limit: None,
+            heard: Instant::now(),
+            health: None,
+            attaching: false,
+        }
+    }
+
+    // Link de ring com o Attach já escrito no stream e o Ack ainda por vir:
+    // o ring guarda os pagamentos desde já e o worker lê do começo quando
+    // abrir. O renew espera o Ack antes; quem não pode esperar (o simulador,
+    // com o worker na mesma thread) usa isto.
+    pub fn attached(mut self, ring: Ring) -> Link<S> {
+        self.attaching = true;
+        Link::Ring(ring, self)
+    }
+
+    // Um frame se já chegou inteiro, o pedaço fica guardado para a próxima
+    fn try_frame(&mut self, now: Instant) -> std::io::Result<Option<Message>> {
+        while self.filled < Message::SIZE {
+            match self.stream.read(&mut self.inbound[self.filled..]) {
+                Ok(0) => return Err(closed("eof")),
+                Ok(n) => self.filled += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.filled = 0;
+        self.heard = now;
+        Message::from_bytes(&self.inbound)
+            .map(Some)
+            .map_err(std::io::Error::other)
+    }
+
+    fn absorb(&mut self, message: Message) -> Option<Message> {
+        match message {
+            Message::Health(queued, retrying) => {
+                if self.health.is_none() {
+                    logger::debug!("Worker reports health"; worker = self.name);
+                }
+                self.health = Some((queued, retrying));
+                None
+            }
+            Message::Credit(limit) => {
+                // limite acumulado, um Credit atrasado não tira o que já foi dado
+                self.limit = Some(self.limit.map_or(limit, |old| old.max(limit)));
+                None
+            }
+            Message::Ack if self.attaching => {
+                self.attaching = false;
+                None
+            }
+            reply => Some(reply),
+        }
+    }
+
+    // Só no attach, que roda no renew antes do link entrar na lista
+    fn recv(&mut self, deadline: Instant) -> std::io::Result<Message> {
+        loop {
+            match self.try_frame(Instant::now())? {
+                Some(message) => {
+                    if let Some(reply) = self.absorb(message) {
+                        return Ok(reply);
+                    }
+                }
+                None if Instant::now() >= deadline => {
+                    return Err(std::io::Error::new(ErrorKind::TimedOut, "reply timed out"));
+                }
+                None => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+
+    // O resto de um frame que saiu pela metade, escrita parcial não desalinha o stream
+    fn flush(&mut self) -> std::io::Result<()> {
+        while !self.outbound.is_empty() {
+            match self.stream.write(&self.outbound) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outbound.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Ok(false) com o socket ainda cheio do frame anterior
+    fn queue(&mut self, frame: &[u8; Message::SIZE]) -> std::io::Result<bool> {
+        self.flush()?;
+        if !self.outbound.is_empty() {
+            return Ok(false);
+        }
+        self.outbound.extend_from_slice(frame);
+        self.flush()?;
+        Ok(true)
+    }
 
-pub struct WorkerPoll {
-    socket_dir: String,
-    poll: Vec<UnixStream>,
+    fn has_credit(&self) -> bool {
+        self.limit.is_none_or(|limit| self.sent < limit)
+    }
+
+    fn silent(&self, now: Instant) -> bool {
+        self.health.is_some() && now.saturating_duration_since(self.heard) >= SILENT_AFTER
+    }
+}
+
+// Caminho até um worker: socket (UNIX ou TCP) de outro processo, canal no
+// mesmo processo, ou ring compartilhado para os pagamentos com o socket para o resto
+pub enum Link<S = Stream> {
+    Socket(Remote<S>),
+    Memory(Endpoint),
+    Ring(Ring, Remote<S>),
+}
+
+impl<S: Read + Write> Link<S> {
+    // Nada aqui espera: Ok(false) é sem crédito, socket cheio ou ring cheio,
+    // o pagamento fica na fila para a próxima volta
+    fn offer(&mut self, frame: &[u8; Message::SIZE]) -> std::io::Result<bool> {
+        match self {
+            Link::Socket(remote) => {
+                if !remote.has_credit() {
+                    return Ok(false);
+                }
+                let accepted = remote.queue(frame)?;
+                remote.sent += accepted as u64;
+                Ok(accepted)
+            }
+            Link::Memory(endpoint) => endpoint.tx.send(*frame).map(|_| true).map_err(closed),
+            Link::Ring(ring, _) => Ok(ring.push(frame)),
+        }
+    }
+
+    // Mensagens de controle vão pelo socket, mas só depois do worker tratar
+    // os pagamentos que estão no ring, senão um purge passaria na frente deles
+    fn control(&mut self, frame: &[u8; Message::SIZE]) -> std::io::Result<bool> {
+        match self {
+            Link::Ring(ring, _) if !ring.is_empty() => Ok(false),
+            // controle não gasta crédito
+            Link::Socket(remote) | Link::Ring(_, remote) => remote.queue(frame),
+            Link::Memory(endpoint) => endpoint.tx.send(*frame).map(|_| true).map_err(closed),
+        }
+    }
+
+    // Tudo que já chegou, sem esperar. Health e Credit ficam no link.
+    fn replies(&mut self, now: Instant) -> std::io::Result<Vec<Message>> {
+        let mut replies = Vec::new();
+        match self {
+            Link::Socket(remote) | Link::Ring(_, remote) if remote.silent(now) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("no health for {SILENT_AFTER:?}"),
+                ));
+            }
+            Link::Socket(remote) | Link::Ring(_, remote) => {
+                remote.flush()?;
+                while let Some(message) = remote.try_frame(now)? {
+                    replies.extend(remote.absorb(message));
+                }
+            }
+            Link::Memory(endpoint) => loop {
+                match endpoint.rx.try_recv() {
+                    Ok(frame) => {
+                        replies.push(Message::from_bytes(&frame).map_err(std::io::Error::other)?)
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(e @ TryRecvError::Disconnected) => return Err(closed(e)),
+                }
+            },
+        }
+        Ok(replies)
+    }
+}
+
+fn closed(_: impl std::fmt::Debug) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "worker link closed")
+}
+
+// Pedidos que precisam de todos os workers. O event loop não espera: a
+// resposta volta depois em Workers::answers com o ticket de quem pediu.
+#[derive(Debug, Clone, Copy)]
+pub enum Ask {
+    Purge,
+    Metrics,
+    Summary(u64, u64),
+}
+
+pub enum Answer {
+    Purge(std::io::Result<usize>),
+    Metrics(Vec<String>), // texto de cada worker que respondeu
+    Summary(std::io::Result<Summary>),
+}
+
+enum Command {
+    Forward(Message),
+    Ask(Ask, usize),
+    Drain(Instant, Sender<Drained>),
+}
+
+// Resultado do flush da fila no shutdown
+pub struct Drained {
+    pub forwarded: usize,
+    pub dropped: usize,
+}
+
+// Quantas vezes os links foram refeitos e quantos estão vivos, para quem
+// lê os ledgers dos workers saber quando reabrir
+#[derive(Default)]
+pub struct Attached {
+    renewals: AtomicUsize,
+    links: AtomicUsize,
+}
+
+pub struct Workers {
+    tx: Sender<Command>,
+    answers: Receiver<(usize, Answer)>,
+    bell: Arc<OwnedFd>,
+    reply_timeout: Duration,
+    attached: Arc<Attached>,
+}
+
+impl Workers {
+    // (renews, links vivos)
+    pub fn attached(&self) -> (usize, usize) {
+        (
+            self.attached.renewals.load(Ordering::Acquire),
+            self.attached.links.load(Ordering::Acquire),
+        )
+    }
+
+    pub fn send(&self, msg: Message) -> Result<(), SendError<Message>> {
+        self.tx.send(Command::Forward(msg)).map_err(|e| match e.0 {
+            Command::Forward(msg) => SendError(msg),
+            _ => unreachable!(),
+        })
+    }
+
+    // Um pedido por vez chega aos workers, o resto espera na fila da thread
+    pub fn ask(&self, ask: Ask, ticket: usize) -> std::io::Result<()> {
+        self.tx
+            .send(Command::Ask(ask, ticket))
+            .map_err(|_| std::io::Error::other("worker poll is gone"))
+    }
+
+    // eventfd que fica legível quando chega resposta, o event loop observa
+    pub fn bell(&self) -> RawFd {
+        self.bell.as_raw_fd()
+    }
+
+    // Zera o eventfd antes de olhar o canal, resposta que chegar depois toca de novo
+    pub fn answers(&self) -> Vec<(usize, Answer)> {
+        let mut count = [0u8; 8];
+        unsafe { libc::read(self.bell(), count.as_mut_ptr() as *mut libc::c_void, 8) };
+        self.answers.try_iter().collect()
+    }
+
+    // Espera a fila de pagamentos ir para os workers, o que sobrar depois do
+    // deadline é descartado
+    pub fn drain(&self, deadline: Instant) -> Drained {
+        let (reply, drained) = channel();
+        if self.tx.send(Command::Drain(deadline, reply)).is_err() {
+            return Drained {
+                forwarded: 0,
+                dropped: 0,
+            };
+        }
+        drained
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()) + self.reply_timeout)
+            .unwrap_or(Drained {
+                forwarded: 0,
+                dropped: 0,
+            })
+    }
+}
+
+// De onde vêm os links num renew
+enum Source {
+    Dir(String), // *.sock na pasta compartilhada
+    Addresses(Vec<Address>),
+}
+
+// Pedido em andamento, com o que cada link respondeu até agora
+struct Asking {
+    ask: Ask,
+    ticket: usize,
+    frame: [u8; Message::SIZE],
+    deadline: Instant,
+    replies: Vec<Reply>, // mesma posição do link no poll
+    lost: usize,         // links que caíram no meio do pedido
+}
+
+enum Reply {
+    Unsent, // ring ainda com pagamentos ou socket cheio
+    Waiting(Vec<u8>),
+    Done(std::io::Result<Vec<u8>>),
+}
+
+// Links novos num renew, None com os links fixos (em memória ou do simulador)
+type Dial<S> = Box<dyn FnMut() -> Vec<Link<S>> + Send>;
+
+pub struct WorkerPoll<S = Stream> {
+    dial: Option<Dial<S>>,
+    poll: Vec<Link<S>>,
     conn_ptr: usize,
+    queue: VecDeque<(Message, Instant)>, // pagamentos esperando um worker aceitar
+    asks: VecDeque<(Ask, usize)>,
+    asking: Option<Asking>,
+    forwarded: usize,
+    dropped: usize,
+    failures: usize, // links perdidos desde o último renew
+    renewed: Option<Instant>,
+    renew_after: usize,
+    reply_timeout: Duration,
+    attached: Arc<Attached>,
 }
 
 impl WorkerPoll {
-    pub fn new(socket_dir: String) -> Self {
-        WorkerPoll {
-            poll: renew(&socket_dir),
-            socket_dir: socket_dir.to_owned(),
+    pub fn new(socket_dir: String, config: &config::LoadBalancer) -> Self {
+        let source = match config.workers.is_empty() {
+            true => Source::Dir(socket_dir),
+            false => Source::Addresses(config.workers.iter().map(|a| Address::parse(a)).collect()),
+        };
+        let (max_workers, reply_timeout, ring) =
+            (config.max_workers, config.reply_timeout, config.ring);
+        let dial = move || renew(&source, max_workers, reply_timeout, ring);
+        let poll = WorkerPoll {
+            renewed: Some(Instant::now()),
+            ..WorkerPoll::with_links(dial(), config)
+        };
+        let poll = WorkerPoll {
+            dial: Some(Box::new(dial)),
+            ..poll
+        };
+        poll.attached.renewals.store(1, Ordering::Release);
+        poll
+    }
+}
+
+impl<S: Read + Write> WorkerPoll<S> {
+    pub fn with_links(links: Vec<Link<S>>, config: &config::LoadBalancer) -> Self {
+        let poll = WorkerPoll {
+            poll: links,
+            dial: None,
             conn_ptr: 0,
+            queue: VecDeque::new(),
+            asks: VecDeque::new(),
+            asking: None,
+            forwarded: 0,
+            dropped: 0,
+            failures: 0,
+            renewed: None,
+            renew_after: config.renew_after,
+            reply_timeout: config.reply_timeout,
+            attached: Arc::default(),
+        };
+        poll.settle();
+        poll
+    }
+
+    // Refaz os links da pasta ou dos endereços, no máximo um por reply_timeout.
+    // Com pedido em andamento espera ele terminar, as respostas são por posição.
+    fn renew(&mut self, now: Instant) {
+        if self.dial.is_none()
+            || self.asking.is_some()
+            || self
+                .renewed
+                .is_some_and(|at| now.saturating_duration_since(at) < self.reply_timeout)
+        {
+            return;
         }
+
+        logger::warn!("Renewing worker sockets"; links = self.poll.len(), failures = self.failures);
+        if let Some(dial) = self.dial.as_mut() {
+            self.poll = dial();
+        }
+        self.attached.renewals.fetch_add(1, Ordering::AcqRel);
+        self.renewed = Some(now);
+        self.failures = 0;
+        self.conn_ptr = 0;
+        self.settle();
     }
 
-    pub fn send(&mut self, buf: &[u8]) -> std::io::Result<bool> {
-        loop {
-            if self.poll.is_empty() {
-                self.poll = renew(&self.socket_dir);
-                self.conn_ptr = 0;
+    // Link que saiu da lista também conta
+    fn settle(&self) {
+        self.attached
+            .links
+            .store(self.poll.len(), Ordering::Release);
+    }
 
-                if self.poll.is_empty() {
-                    panic!("No available worker sockets");
+    fn remove(&mut self, index: usize) {
+        self.poll.remove(index);
+        if let Some(asking) = self.asking.as_mut() {
+            asking.replies.remove(index);
+            asking.lost += 1;
+        }
+        self.failures += 1;
+        self.conn_ptr = 0;
+        self.settle();
+    }
+
+    pub fn forward(&mut self, message: Message, now: Instant) {
+        self.queue.push_back((message, now));
+    }
+
+    // O que está na fila quando o purge chega é da rodada anterior e não vai
+    // mais para worker nenhum. O que já está num ring chega antes do Purge
+    // (control espera o ring esvaziar) e o worker descarta pela geração.
+    pub fn ask(&mut self, ask: Ask, ticket: usize) {
+        if let Ask::Purge = ask {
+            for (message, _) in self.queue.drain(..) {
+                if let Message::Payment(_, correlation_id) = message {
+                    logger::debug!("Dropping payment from before purge"; correlation_id = correlation_id);
                 }
             }
+        }
+        self.asks.push_back((ask, ticket));
+    }
+
+    // Com trabalho pendente a thread volta logo, senão só para ler Health e Credit
+    fn busy(&self) -> bool {
+        !self.queue.is_empty() || !self.asks.is_empty() || self.asking.is_some()
+    }
+
+    // Uma volta sem bloquear: lê os links, manda o que der e devolve os
+    // pedidos que terminaram
+    pub fn turn(&mut self, now: Instant) -> Vec<(usize, Answer)> {
+        let pending = !self.queue.is_empty() || !self.asks.is_empty();
+        if (self.poll.is_empty() && pending) || self.failures >= self.renew_after {
+            self.renew(now);
+        }
+
+        self.read(now);
 
-            let mut stream = &self.poll[self.conn_ptr];
-            match stream.write(buf) {
-                Ok(n) if n == buf.len() => {
-                    self.conn_ptr += 1;
-                    self.conn_ptr %= self.poll.len();
-                    return Ok(true);
+        let mut answers = Vec::new();
+        while self.asking.is_none()
+            && let Some((ask, ticket)) = self.asks.pop_front()
+        {
+            match self.poll.is_empty() {
+                true => answers.push((ticket, unanswered(ask))),
+                false => {
+                    self.asking = Some(Asking {
+                        ask,
+                        ticket,
+                        frame: match ask {
+                            Ask::Purge => Message::Purge,
+                            Ask::Metrics => Message::Metrics,
+                            Ask::Summary(from, to) => Message::Summary(from, to),
+                        }
+                        .to_bytes(),
+                        deadline: now + self.reply_timeout,
+                        replies: self.poll.iter().map(|_| Reply::Unsent).collect(),
+                        lost: 0,
+                    })
                 }
-                Ok(_) => {
-                    self.conn_ptr += 1;
-                    return Ok(false);
+            }
+        }
+
+        self.control();
+        self.send(now);
+        answers.extend(self.finish(now));
+        answers
+    }
+
+    // Respostas vão para o pedido em andamento, Notice sem pedido vira log.
+    // Link quebrado ou worker que parou de dar notícia sai da lista.
+    fn read(&mut self, now: Instant) {
+        let mut index = 0;
+        while index < self.poll.len() {
+            match self.poll[index].replies(now) {
+                Ok(replies) => {
+                    for reply in replies {
+                        self.route(index, reply);
+                    }
+                    index += 1;
                 }
                 Err(e) => {
-                    eprintln!("Failed to write to stream: {e}");
-                    self.poll.remove(self.conn_ptr);
+                    logger::warn!("Dropping worker link: {e}"; worker = index);
+                    self.remove(index);
                 }
             }
         }
     }
+
+    fn route(&mut self, index: usize, message: Message) {
+        let Some(asking) = self
+            .asking
+            .as_mut()
+            .filter(|asking| matches!(asking.replies[index], Reply::Waiting(_)))
+        else {
+            match message {
+                Message::Notice(len, text) => logger::warn!(
+                    "Worker notice: {}",
+                    String::from_utf8_lossy(&text[..len as usize]);
+                    worker = index
+                ),
+                other => {
+                    logger::warn!("Unexpected reply from worker: {other:?}"; worker = index)
+                }
+            }
+            return;
+        };
+        let ask = asking.ask;
+        let Reply::Waiting(data) = &mut asking.replies[index] else {
+            unreachable!()
+        };
+
+        let done = match (ask, message) {
+            (Ask::Purge, Message::Ack) => Ok(Vec::new()),
+            (Ask::Metrics | Ask::Summary(..), Message::Chunk(last, len, payload)) => {
+                data.extend_from_slice(&payload[..len as usize]);
+                match last {
+                    true => Ok(std::mem::take(data)),
+                    false => return,
+                }
+            }
+            (_, Message::Notice(len, text)) => Err(std::io::Error::other(
+                String::from_utf8_lossy(&text[..len as usize]).into_owned(),
+            )),
+            (_, other) => Err(std::io::Error::other(format!(
+                "unexpected reply: {other:?}"
+            ))),
+        };
+        asking.replies[index] = Reply::Done(done);
+    }
+
+    // O frame do pedido para quem ainda não recebeu
+    fn control(&mut self) {
+        let Some(asking) = self.asking.as_ref() else {
+            return;
+        };
+        let frame = asking.frame;
+
+        let mut index = 0;
+        while let Some(asking) = self.asking.as_mut()
+            && index < asking.replies.len()
+        {
+            if !matches!(asking.replies[index], Reply::Unsent) {
+                index += 1;
+                continue;
+            }
+            match self.poll[index].control(&frame) {
+                Ok(sent) => {
+                    if sent {
+                        asking.replies[index] = Reply::Waiting(Vec::with_capacity(4096));
+                    }
+                    index += 1;
+                }
+                Err(e) => {
+                    logger::warn!("Failed to write to stream: {e}"; worker = index);
+                    self.remove(index);
+                }
+            }
+        }
+    }
+
+    // Enquanto um purge não foi confirmado o link não recebe pagamento, que
+    // seria apagado junto, nem com o purge ainda esperando outro pedido
+    // terminar. Ring com pedido na fila também espera esvaziar.
+    fn held(&self, index: usize) -> bool {
+        if self.asks.iter().any(|(ask, _)| matches!(ask, Ask::Purge)) {
+            return true;
+        }
+        self.asking.as_ref().is_some_and(|asking| {
+            match (&asking.replies[index], &self.poll[index]) {
+                (Reply::Done(_), _) => false,
+                (_, _) if matches!(asking.ask, Ask::Purge) => true,
+                (Reply::Unsent, Link::Ring(..)) => true,
+                _ => false,
+            }
+        })
+    }
+
+    // Pagamentos na ordem da fila, cada um para o próximo worker que aceitar
+    fn send(&mut self, now: Instant) {
+        while let Some((message, _)) = self.queue.front() {
+            let frame = message.to_bytes();
+            let Some(worker) = self.offer(&frame) else {
+                break;
+            };
+            if let Some((Message::Payment(_, correlation_id), _)) = self.queue.pop_front() {
+                logger::debug!("Forwarded payment"; correlation_id = correlation_id, worker = worker);
+            }
+            self.forwarded += 1;
+        }
+
+        // O cliente já recebeu 200, então o pagamento espera bastante antes de sumir
+        let patience = self.reply_timeout * FORWARD_PATIENCE;
+        while let Some(&(_, since)) = self.queue.front()
+            && now.saturating_duration_since(since) >= patience
+        {
+            if let Some((Message::Payment(_, correlation_id), _)) = self.queue.pop_front() {
+                logger::error!("Dropping payment, no worker took it"; correlation_id = correlation_id, waited_ms = patience.as_millis());
+            }
+            self.dropped += 1;
+        }
+    }
+
+    // Índice do worker que aceitou
+    fn offer(&mut self, frame: &[u8; Message::SIZE]) -> Option<usize> {
+        let mut tried = 0;
+        while tried < self.poll.len() {
+            let index = self.conn_ptr % self.poll.len();
+            self.conn_ptr = index + 1;
+            if self.held(index) {
+                tried += 1;
+                continue;
+            }
+
+            match self.poll[index].offer(frame) {
+                Ok(true) => return Some(index),
+                // worker sem crédito fica para depois se outro tiver
+                Ok(false) => tried += 1,
+                Err(e) => {
+                    logger::warn!("Failed to write to stream: {e}"; worker = index);
+                    self.remove(index);
+                }
+            }
+        }
+        None
+    }
+
+    // Pedido com todas as respostas, ou vencido. Link que não respondeu sai
+    // da lista: a resposta atrasada desalinharia o próximo pedido.
+    fn finish(&mut self, now: Instant) -> Option<(usize, Answer)> {
+        let asking = self.asking.as_ref()?;
+        let complete = asking
+            .replies
+            .iter()
+            .all(|reply| matches!(reply, Reply::Done(_)));
+        if !complete && now < asking.deadline {
+            return None;
+        }
+
+        let asking = self.asking.take()?;
+        let total = asking.replies.len() + asking.lost;
+        let mut results = Vec::with_capacity(asking.replies.len());
+        for (index, reply) in asking.replies.into_iter().enumerate().rev() {
+            let result = match reply {
+                Reply::Done(result) => result,
+                _ => Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    "worker reply timed out",
+                )),
+            };
+            if let Err(e) = &result {
+                match asking.ask {
+                    Ask::Purge => {
+                        logger::error!("Worker did not confirm purge: {e}"; worker = index)
+                    }
+                    Ask::Metrics => {
+                        logger::warn!("Worker did not send metrics: {e}"; worker = index)
+                    }
+                    Ask::Summary(..) => {
+                        logger::warn!("Worker did not send its summary: {e}"; worker = index)
+                    }
+                }
+                if e.kind() == ErrorKind::TimedOut || matches!(asking.ask, Ask::Purge) {
+                    self.remove(index);
+                }
+            }
+            results.push(result);
+        }
+        results.reverse();
+
+        let answer = match asking.ask {
+            Ask::Purge => {
+                let confirmed = results.iter().filter(|result| result.is_ok()).count();
+                match confirmed > 0 && confirmed == total {
+                    true => Answer::Purge(Ok(confirmed)),
+                    false => Answer::Purge(Err(std::io::Error::other(
+                        "not every worker confirmed the purge",
+                    ))),
+                }
+            }
+            Ask::Metrics => Answer::Metrics(
+                results
+                    .into_iter()
+                    .flatten()
+                    .map(|text| String::from_utf8_lossy(&text).into_owned())
+                    .collect(),
+            ),
+            // Sem resposta de todos o total estaria errado, então é tudo ou erro
+            Ask::Summary(..) => Answer::Summary(match asking.lost {
+                0 => results
+                    .into_iter()
+                    .try_fold(Summary::default(), |mut sum, result| {
+                        sum.merge(&totals(&result?)?);
+                        Ok(sum)
+                    }),
+                lost => Err(std::io::Error::other(format!(
+                    "{lost} worker links lost during the summary"
+                ))),
+            }),
+        };
+        Some((asking.ticket, answer))
+    }
+}
+
+// Sem nenhum worker: purge e summary falham, métricas saem só as locais
+fn unanswered(ask: Ask) -> Answer {
+    let none = || std::io::Error::other("No available worker sockets");
+    match ask {
+        Ask::Purge => Answer::Purge(Err(none())),
+        Ask::Metrics => Answer::Metrics(Vec::new()),
+        Ask::Summary(..) => Answer::Summary(Err(none())),
+    }
+}
+
+// Os 32 bytes do summary de um worker: (requests, amount) de cada processor
+fn totals(bytes: &[u8]) -> std::io::Result<Summary> {
+    if bytes.len() != 32 {
+        return Err(std::io::Error::other(format!(
+            "summary reply has {} bytes, expected 32",
+            bytes.len()
+        )));
+    }
+
+    let mut worker = Summary::default();
+    for (i, processor) in Processor::ALL.iter().enumerate() {
+        let field = |at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+        let value = Totals {
+            requests: field(i * 16),
+            amount: field(i * 16 + 8),
+        };
+        match processor {
+            Processor::Default => worker.default = value,
+            Processor::Fallback => worker.fallback = value,
+        }
+    }
+    Ok(worker)
 }
 
-pub fn start_workers(socket_dir: String) -> Sender<Message> {
-    let (tx, rx) = channel::<Message>();
+// A thread dos workers: comandos do event loop entram na poll, respostas
+// saem pelo canal e tocam o eventfd. O simulador não sobe a thread e chama
+// step quando decide.
+pub struct Pump<S = Stream> {
+    poll: WorkerPoll<S>,
+    rx: Receiver<Command>,
+    answers: Sender<(usize, Answer)>,
+    bell: Arc<OwnedFd>,
+    drain: Option<(Instant, Sender<Drained>)>,
+}
 
-    let mut poll = WorkerPoll::new(socket_dir);
-    let inner_tx = tx.clone();
-    std::thread::spawn(move || {
-        let mut retries = 0;
-        let mut shutdown = false;
+impl<S: Read + Write> Pump<S> {
+    fn run(mut self) {
         loop {
-            if retries >= 10 && shutdown {
-                eprintln!("Worker poll is empty, shutting down");
+            match self.rx.recv_timeout(self.interval()) {
+                Ok(command) => self.command(command, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if !self.step(Instant::now()) {
                 break;
             }
+        }
+    }
+
+    fn command(&mut self, command: Command, now: Instant) {
+        match command {
+            Command::Forward(message) => self.poll.forward(message, now),
+            Command::Ask(ask, ticket) => self.poll.ask(ask, ticket),
+            Command::Drain(deadline, reply) => self.drain = Some((deadline, reply)),
+        }
+    }
+
+    // Com pagamento na fila ou pedido sem resposta a próxima volta vem logo
+    pub fn interval(&self) -> Duration {
+        match self.poll.busy() {
+            true => BUSY_INTERVAL,
+            false => PUMP_INTERVAL,
+        }
+    }
+
+    pub fn busy(&self) -> bool {
+        self.poll.busy()
+    }
+
+    // false quando o drain terminou ou não sobrou quem mande comando
+    pub fn step(&mut self, now: Instant) -> bool {
+        loop {
+            match self.rx.try_recv() {
+                Ok(command) => self.command(command, now),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+
+        let answers = self.poll.turn(now);
+        if !answers.is_empty() {
+            for answer in answers {
+                let _ = self.answers.send(answer);
+            }
+            let one = 1u64.to_ne_bytes();
+            unsafe {
+                libc::write(
+                    self.bell.as_raw_fd(),
+                    one.as_ptr() as *const libc::c_void,
+                    one.len(),
+                )
+            };
+        }
 
-            if retries >= 10 {
-                println!("Renewing worker sockets after 10 retries");
-                poll.poll = renew(&poll.socket_dir);
-                retries = 0;
-                shutdown = true;
+        match &self.drain {
+            Some((deadline, _)) if self.poll.queue.is_empty() || now >= *deadline => {
+                let (_, reply) = self.drain.take().unwrap();
+                for (message, _) in &self.poll.queue {
+                    if let Message::Payment(_, correlation_id) = message {
+                        logger::error!("Dropping payment, drain deadline passed"; correlation_id = correlation_id);
+                    }
+                }
+                let _ = reply.send(Drained {
+                    forwarded: self.poll.forwarded,
+                    dropped: self.poll.dropped + self.poll.queue.len(),
+                });
+                // Ninguém manda mais nada depois do drain, fechar os links
+                // avisa os workers que podem encerrar
+                false
             }
+            _ => true,
+        }
+    }
+}
+
+pub fn start_workers<S>(poll: WorkerPoll<S>, config: &config::LoadBalancer) -> Workers
+where
+    S: Read + Write + Send + 'static,
+{
+    let (workers, pump) = pump(poll, config);
+    std::thread::spawn(move || pump.run());
+    workers
+}
+
+// Os dois lados sem a thread
+pub fn pump<S: Read + Write>(
+    poll: WorkerPoll<S>,
+    config: &config::LoadBalancer,
+) -> (Workers, Pump<S>) {
+    let (tx, rx) = channel::<Command>();
+    let (answers_tx, answers) = channel();
+    let attached = poll.attached.clone();
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    assert!(
+        fd >= 0,
+        "unable to create worker eventfd: {}",
+        std::io::Error::last_os_error()
+    );
+    let bell = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
 
-            if let Ok(msg) = rx.recv() {
-                if poll.send(&msg.to_bytes()).is_ok() {
-                    retries = 0;
-                } else {
-                    println!("Failed to send message, retrying...");
-                    inner_tx.send(msg).unwrap();
-                    retries += 1;
+    let pump = Pump {
+        poll,
+        rx,
+        answers: answers_tx,
+        bell: bell.clone(),
+        drain: None,
+    };
+    let workers = Workers {
+        tx,
+        answers,
+        bell,
+        reply_timeout: config.reply_timeout,
+        attached,
+    };
+    (workers, pump)
+}
+
+fn renew(source: &Source, max_workers: usize, timeout: Duration, ring: bool) -> Vec<Link> {
+    let addresses = match source {
+        Source::Dir(socket_dir) => {
+            logger::info!("Renewing worker sockets from folder: {socket_dir}");
+            // pasta sumida não derruba a thread, o próximo renew tenta de novo
+            let entries = match std::fs::read_dir(socket_dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    logger::error!("Unable to read socket folder {socket_dir}: {e}");
+                    return Vec::new();
                 }
+            };
+            entries
+                .filter_map(|file| file.ok())
+                .map(|file| file.path())
+                .filter(|path| path.extension() == Some(std::ffi::OsStr::new("sock")))
+                .map(Address::Unix)
+                .take(max_workers)
+                .collect()
+        }
+        Source::Addresses(addresses) => {
+            logger::info!("Renewing worker connections"; workers = addresses.len());
+            addresses.clone()
+        }
+    };
+
+    let mut poll = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let deadline = Instant::now() + timeout;
+        match Stream::connect(&address, timeout).and_then(|mut stream| {
+            stream
+                .write_frame(&Message::Ack.to_bytes(), deadline)
+                .map(|_| stream)
+        }) {
+            Ok(stream) => {
+                logger::info!("Connected to worker: {address}");
+                let mut remote = Remote::new(stream, address.to_string());
+                let link = match &address {
+                    Address::Unix(path) if ring => match attach(&mut remote, path, deadline) {
+                        Ok(ring) => Link::Ring(ring, remote),
+                        Err(e) => {
+                            logger::warn!("Shared ring unavailable, using the socket: {e}"; worker = address.to_string());
+                            Link::Socket(remote)
+                        }
+                    },
+                    _ => Link::Socket(remote),
+                };
+                poll.push(link);
             }
+            Err(e) => logger::warn!("Failed to connect to worker {address}: {e}"),
         }
-    });
+    }
 
-    tx
+    poll
 }
 
-fn renew(socket_dir: &str) -> Vec<UnixStream> {
-    println!("Renewing worker sockets from folder: {socket_dir}");
-    let mut poll = Vec::with_capacity(10);
-    for file in std::fs::read_dir(socket_dir).expect("unable to read socket folder") {
-        if poll.len() == 10 {
-            break;
+// Cria o ring ao lado do socket e espera o worker confirmar que abriu. Um
+// nome por link, várias threads e processos de load balancer podem coexistir.
+fn attach(remote: &mut Remote, socket: &Path, deadline: Instant) -> std::io::Result<Ring> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let stem = socket
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = format!(
+        "{stem}.{}-{}.ring",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    if name.len() > Message::CHUNK {
+        return Err(std::io::Error::other(format!(
+            "ring name {name} is too long"
+        )));
+    }
+
+    let ring = Ring::create(socket.with_file_name(&name), RING_FRAMES)?;
+    let mut payload = [0u8; Message::CHUNK];
+    payload[..name.len()].copy_from_slice(name.as_bytes());
+    if !remote.stream.write_frame(
+        &Message::Attach(name.len() as u8, payload).to_bytes(),
+        deadline,
+    )? {
+        return Err(std::io::Error::other("worker did not accept the attach"));
+    }
+
+    match remote.recv(deadline)? {
+        Message::Ack => Ok(ring),
+        Message::Notice(len, text) => Err(std::io::Error::other(
+            String::from_utf8_lossy(&text[..len as usize]).into_owned(),
+        )),
+        other => Err(std::io::Error::other(format!(
+            "unexpected reply to attach: {other:?}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, os::unix::net::UnixStream};
+
+    use message::CorrelationId;
+
+    use super::*;
+
+    fn write(worker: &mut UnixStream, message: Message) {
+        worker.write_all(&message.to_bytes()).unwrap();
+    }
+
+    fn read(worker: &mut UnixStream) -> Message {
+        let mut frame = [0u8; Message::SIZE];
+        worker.read_exact(&mut frame).unwrap();
+        Message::from_bytes(&frame).unwrap()
+    }
+
+    fn socket(config: &config::LoadBalancer) -> (WorkerPoll, UnixStream) {
+        let (lb, worker) = UnixStream::pair().unwrap();
+        lb.set_nonblocking(true).unwrap();
+        let remote = Remote::new(
+            Stream::Unix(mio::net::UnixStream::from_std(lb)),
+            "test".to_string(),
+        );
+        (
+            WorkerPoll::with_links(vec![Link::Socket(remote)], config),
+            worker,
+        )
+    }
+
+    fn config(reply_timeout: Duration) -> config::LoadBalancer {
+        let mut config = config::Config::from_pairs(&[("SOCKET_DIR", "/tmp")])
+            .unwrap()
+            .lb;
+        config.reply_timeout = reply_timeout;
+        config
+    }
+
+    // Voltas até o pedido terminar, o worker da outra ponta já escreveu tudo
+    fn answer(poll: &mut WorkerPoll) -> (usize, Answer) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            if let Some(answer) = poll.turn(Instant::now()).pop() {
+                return answer;
+            }
+            assert!(Instant::now() < deadline, "no answer");
+            std::thread::sleep(Duration::from_millis(1));
         }
+    }
 
-        let file = if let Ok(file) = file {
-            file
-        } else {
-            continue;
-        };
+    // Worker falso na outra ponta de um socketpair: Health e Credit chegam
+    // no meio das respostas e o crédito segura os pagamentos
+    #[test]
+    fn test_renew_missing_dir() {
+        let source = Source::Dir("/nonexistent/rinha-sockets".to_string());
+        assert!(renew(&source, 4, Duration::from_millis(100), false).is_empty());
+    }
+
+    #[test]
+    fn test_bidirectional_link() {
+        let config = config(Duration::from_millis(100));
+        let (mut poll, mut worker) = socket(&config);
 
-        if file.path().extension() != Some(std::ffi::OsStr::new("sock")) {
-            continue;
+        write(&mut worker, Message::Health(0, 0));
+        write(&mut worker, Message::Credit(2));
+        let payment = |id: u8| Message::Payment(1990, CorrelationId([id; 36]));
+        let now = Instant::now();
+        poll.turn(now);
+        for id in [b'a', b'b', b'c'] {
+            poll.forward(payment(id), now);
         }
+        // sem crédito o terceiro fica na fila
+        poll.turn(now);
+        assert_eq!(poll.queue.len(), 1);
+        assert!(matches!(read(&mut worker), Message::Payment(1990, id) if id.0[0] == b'a'));
+        assert!(matches!(read(&mut worker), Message::Payment(1990, id) if id.0[0] == b'b'));
 
-        if let Ok(mut stream) = UnixStream::connect(file.path())
-            && let Ok(n) = stream.write(&Message::Ack.to_bytes())
-        {
-            println!("Connected to socket: {:?}, sent {} bytes", file.path(), n);
-            poll.push(stream);
-        } else {
-            eprintln!("Failed to connect to socket: {:?}", file.path());
+        // o que ficou segurado sai quando o crédito chega
+        write(&mut worker, Message::Credit(3));
+        poll.turn(now);
+        assert!(poll.queue.is_empty());
+        assert!(matches!(read(&mut worker), Message::Payment(1990, id) if id.0[0] == b'c'));
+
+        // Health no meio da resposta não atrapalha o summary
+        poll.ask(Ask::Summary(0, 10), 7);
+        poll.turn(Instant::now());
+        assert!(matches!(read(&mut worker), Message::Summary(0, 10)));
+        write(&mut worker, Message::Health(3, 1));
+        let mut totals = [0u8; 32];
+        totals[..8].copy_from_slice(&2u64.to_be_bytes());
+        totals[8..16].copy_from_slice(&3980u64.to_be_bytes());
+        for chunk in Message::chunks(&totals) {
+            write(&mut worker, chunk);
         }
+        let (ticket, Answer::Summary(summary)) = answer(&mut poll) else {
+            unreachable!()
+        };
+        let summary = summary.unwrap();
+        assert_eq!(ticket, 7);
+        assert_eq!(summary.default.requests, 2);
+        assert_eq!(summary.default.amount, 3980);
+
+        let Link::Socket(remote) = &poll.poll[0] else {
+            unreachable!()
+        };
+        assert_eq!(remote.health, Some((3, 1)));
+
+        // Notice sem pedido só vira log, o link continua
+        write(&mut worker, Message::notice("unexpected message"));
+        poll.turn(Instant::now());
+        assert_eq!(poll.poll.len(), 1);
+
+        // Notice como resposta de pedido vira o erro do pedido
+        poll.ask(Ask::Purge, 8);
+        poll.turn(Instant::now());
+        assert!(matches!(read(&mut worker), Message::Purge));
+        write(&mut worker, Message::notice("purge failed"));
+        let (8, Answer::Purge(purged)) = answer(&mut poll) else {
+            unreachable!()
+        };
+        assert!(purged.is_err());
+        assert!(poll.poll.is_empty());
     }
 
-    poll
+    // Worker que não responde: o pedido vence no reply_timeout sem segurar
+    // os pagamentos, e o link sai porque a resposta atrasada desalinharia o próximo
+    #[test]
+    fn test_unanswered_ask() {
+        let config = config(Duration::from_millis(50));
+        let (mut poll, mut worker) = socket(&config);
+
+        let now = Instant::now();
+        poll.ask(Ask::Metrics, 1);
+        assert!(poll.turn(now).is_empty());
+        assert!(matches!(read(&mut worker), Message::Metrics));
+        poll.forward(Message::Payment(1990, CorrelationId([b'a'; 36])), now);
+        assert!(poll.turn(now).is_empty());
+        assert!(matches!(read(&mut worker), Message::Payment(1990, _)));
+
+        let (1, Answer::Metrics(texts)) = poll.turn(now + config.reply_timeout).pop().unwrap()
+        else {
+            unreachable!()
+        };
+        assert!(texts.is_empty());
+        assert!(poll.poll.is_empty());
+
+        // sem worker nenhum a resposta sai na hora
+        poll.ask(Ask::Summary(0, 10), 2);
+        let (2, Answer::Summary(summary)) = poll.turn(now).pop().unwrap() else {
+            unreachable!()
+        };
+        assert!(summary.is_err());
+    }
+
+    // Pagamento parado na fila quando o purge chega é da rodada anterior:
+    // não chega no worker nem depois do Ack, o que veio depois chega
+    #[test]
+    fn test_purge_drops_queued_payments() {
+        let config = config(Duration::from_millis(100));
+        let (mut poll, mut worker) = socket(&config);
+        let payment = |id: u8| Message::Payment(1990, CorrelationId([id; 36]));
+
+        // sem crédito os dois ficam na fila
+        write(&mut worker, Message::Credit(0));
+        let now = Instant::now();
+        poll.forward(payment(b'a'), now);
+        poll.forward(payment(b'b'), now);
+        poll.turn(now);
+        assert_eq!(poll.queue.len(), 2);
+
+        poll.ask(Ask::Purge, 3);
+        assert!(poll.queue.is_empty());
+        poll.forward(payment(b'c'), now);
+        write(&mut worker, Message::Credit(5));
+        poll.turn(now);
+        assert!(matches!(read(&mut worker), Message::Purge));
+        write(&mut worker, Message::Ack);
+        let (3, Answer::Purge(purged)) = answer(&mut poll) else {
+            unreachable!()
+        };
+        assert_eq!(purged.unwrap(), 1);
+
+        poll.turn(Instant::now());
+        assert!(poll.queue.is_empty());
+        assert!(matches!(read(&mut worker), Message::Payment(1990, id) if id.0[0] == b'c'));
+        worker.set_nonblocking(true).unwrap();
+        let mut rest = [0u8; Message::SIZE];
+        assert!(worker.read(&mut rest).is_err());
+    }
+
+    // Pela thread: o pagamento sem crédito espera na fila e chega no worker
+    // quando o crédito vem, bem depois do reply_timeout
+    #[test]
+    fn test_held_payment_is_retried() {
+        let config = config(Duration::from_millis(50));
+        let (poll, mut worker) = socket(&config);
+        write(&mut worker, Message::Credit(1));
+        let workers = start_workers(poll, &config);
+
+        for id in [b'a', b'b'] {
+            workers
+                .send(Message::Payment(1990, CorrelationId([id; 36])))
+                .unwrap();
+        }
+        assert!(matches!(read(&mut worker), Message::Payment(1990, id) if id.0[0] == b'a'));
+
+        // várias voltas passam antes do crédito
+        std::thread::sleep(config.reply_timeout * 4);
+        write(&mut worker, Message::Credit(2));
+        worker
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert!(matches!(read(&mut worker), Message::Payment(1990, id) if id.0[0] == b'b'));
+
+        let drained = workers.drain(Instant::now() + Duration::from_millis(100));
+        assert_eq!(drained.forwarded, 2);
+        assert_eq!(drained.dropped, 0);
+    }
 }
@@ -0,0 +1,373 @@
+use std::{
+    cell::RefCell,
+    fmt::{Arguments, Display},
+    io::Write,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, Sender, SyncSender, TrySendError, channel, sync_channel},
+    },
+    time::Duration,
+};
+
+use message::time;
+
+// Log fora do hot path: a linha é formatada num buffer da própria thread e vai
+// por uma fila limitada para a thread que escreve no stderr. Fila cheia = linha
+// descartada e contada, quem está atendendo requisição nunca bloqueia.
+//
+// info,worker=debug,load_balance::worker_poll=warn
+
+const QUEUE: usize = 1024;
+const RECORD_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Level> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Level::Off => "OFF  ",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN ",
+            Level::Info => "INFO ",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    default: Level,
+    targets: Vec<(String, Level)>, // prefixo mais longo primeiro
+}
+
+impl Filter {
+    // "info,worker=debug", diretivas inválidas são ignoradas
+    pub fn parse(spec: &str) -> Filter {
+        let mut filter = Filter {
+            default: Level::Info,
+            targets: Vec::new(),
+        };
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Some(level) = Level::parse(level) {
+                        filter
+                            .targets
+                            .push((target.trim().replace('-', "_"), level));
+                    }
+                }
+                None => {
+                    if let Some(level) = Level::parse(directive) {
+                        filter.default = level;
+                    }
+                }
+            }
+        }
+
+        filter
+            .targets
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        filter
+    }
+
+    pub fn level_for(&self, target: &str) -> Level {
+        for (prefix, level) in &self.targets {
+            if target == prefix
+                || (target.starts_with(prefix.as_str()) && target[prefix.len()..].starts_with("::"))
+            {
+                return *level;
+            }
+        }
+
+        self.default
+    }
+}
+
+struct Record {
+    len: usize,
+    bytes: [u8; RECORD_SIZE],
+}
+
+// O Record vai por valor de propósito, nada de alocação por linha
+#[allow(clippy::large_enum_variant)]
+enum Entry {
+    Line(Record),
+    Flush(Sender<()>),
+}
+
+struct Logger {
+    tx: SyncSender<Entry>,
+    filter: Filter,
+    dropped: AtomicU64,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+thread_local! {
+    static BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(RECORD_SIZE));
+}
+
+fn logger() -> &'static Logger {
+    // Sem init explícito fica no padrão
+    LOGGER.get_or_init(|| Logger::start(Filter::parse("info")))
+}
+
+impl Logger {
+    fn start(filter: Filter) -> Logger {
+        let (tx, rx) = sync_channel::<Entry>(QUEUE);
+        std::thread::Builder::new()
+            .name("logger".to_string())
+            .spawn(move || writer(rx))
+            .expect("unable to spawn logger thread");
+
+        Logger {
+            tx,
+            filter,
+            dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+fn writer(rx: Receiver<Entry>) {
+    let mut batch = Vec::with_capacity(QUEUE * 64);
+    let mut reported = 0;
+
+    while let Ok(entry) = rx.recv() {
+        let mut waiting = Vec::new();
+        let mut next = Some(entry);
+
+        while let Some(entry) = next {
+            match entry {
+                Entry::Line(record) => batch.extend_from_slice(&record.bytes[..record.len]),
+                Entry::Flush(reply) => waiting.push(reply),
+            }
+            next = rx.try_recv().ok();
+        }
+
+        if let Some(logger) = LOGGER.get() {
+            let dropped = logger.dropped.load(Ordering::Relaxed);
+            if dropped > reported {
+                let _ = writeln!(batch, "logger dropped {} records", dropped - reported);
+                reported = dropped;
+            }
+        }
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = stderr.write_all(&batch);
+        let _ = stderr.flush();
+        batch.clear();
+
+        waiting.into_iter().for_each(|reply| {
+            let _ = reply.send(());
+        });
+    }
+}
+
+// Configuração explícita, precisa vir antes do primeiro log. Devolve false se o
+// logger já estava rodando.
+pub fn init(spec: &str) -> bool {
+    let mut started = false;
+    LOGGER.get_or_init(|| {
+        started = true;
+        Logger::start(Filter::parse(spec))
+    });
+    started
+}
+
+#[inline(always)]
+pub fn enabled(level: Level, target: &str) -> bool {
+    level != Level::Off && level <= logger().filter.level_for(target)
+}
+
+pub fn dropped() -> u64 {
+    LOGGER
+        .get()
+        .map(|logger| logger.dropped.load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
+// Espera a thread de escrita esvaziar a fila, usado antes de sair do processo
+pub fn flush() {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+
+    let (reply, done) = channel();
+    if logger.tx.send(Entry::Flush(reply)).is_ok() {
+        let _ = done.recv_timeout(Duration::from_secs(1));
+    }
+}
+
+pub fn format_record(
+    out: &mut Vec<u8>,
+    now: u64,
+    level: Level,
+    target: &str,
+    args: Arguments,
+    fields: &[(&str, &dyn Display)],
+) {
+    let mut timestamp = [0u8; time::ISO_LEN];
+    time::format_iso8601(now, &mut timestamp);
+
+    out.extend_from_slice(&timestamp);
+    out.push(b' ');
+    out.extend_from_slice(level.label().as_bytes());
+    out.push(b' ');
+    out.extend_from_slice(target.as_bytes());
+    out.push(b' ');
+    let _ = out.write_fmt(args);
+    for (key, value) in fields {
+        let _ = write!(out, " {key}={value}");
+    }
+
+    if out.len() >= RECORD_SIZE {
+        out.truncate(RECORD_SIZE - 4);
+        out.extend_from_slice(b"...");
+    }
+    out.push(b'\n');
+}
+
+#[doc(hidden)]
+pub fn __log(level: Level, target: &str, args: Arguments, fields: &[(&str, &dyn Display)]) {
+    let logger = logger();
+    let mut record = Record {
+        len: 0,
+        bytes: [0; RECORD_SIZE],
+    };
+
+    BUFFER.with_borrow_mut(|buffer| {
+        buffer.clear();
+        format_record(buffer, time::now_millis(), level, target, args, fields);
+        record.len = buffer.len();
+        record.bytes[..buffer.len()].copy_from_slice(buffer);
+    });
+
+    match logger.tx.try_send(Entry::Line(record)) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+            logger.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// logger::info!("Connected to {path}"; worker = id, token = token.0)
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $fmt:literal $(, $arg:expr)* $(; $($key:ident = $value:expr),+)?) => {{
+        let level = $level;
+        if $crate::enabled(level, module_path!()) {
+            $crate::__log(
+                level,
+                module_path!(),
+                format_args!($fmt $(, $arg)*),
+                &[$($((stringify!($key), &$value as &dyn ::std::fmt::Display)),+)?],
+            );
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($tokens:tt)+) => { $crate::log!($crate::Level::Error, $($tokens)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($tokens:tt)+) => { $crate::log!($crate::Level::Warn, $($tokens)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($tokens:tt)+) => { $crate::log!($crate::Level::Info, $($tokens)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($tokens:tt)+) => { $crate::log!($crate::Level::Debug, $($tokens)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($tokens:tt)+) => { $crate::log!($crate::Level::Trace, $($tokens)+) };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("warn, worker=debug,load-balance::worker_poll=trace,bad=nope");
+        assert_eq!(filter.level_for("rinha_2025"), Level::Warn);
+        assert_eq!(filter.level_for("worker"), Level::Debug);
+        assert_eq!(filter.level_for("worker::processor"), Level::Debug);
+        assert_eq!(filter.level_for("workers"), Level::Warn);
+        assert_eq!(filter.level_for("load_balance"), Level::Warn);
+        assert_eq!(filter.level_for("load_balance::worker_poll"), Level::Trace);
+        assert_eq!(filter.level_for("bad"), Level::Warn);
+
+        assert_eq!(Filter::parse("").level_for("x"), Level::Info);
+        assert_eq!(Filter::parse("off").level_for("x"), Level::Off);
+    }
+
+    #[test]
+    fn test_format_record() {
+        let mut out = Vec::new();
+        let token = 7;
+        format_record(
+            &mut out,
+            1_594_384_496_123,
+            Level::Warn,
+            "worker",
+            format_args!("Failed to confirm purge: {}", "broken pipe"),
+            &[("token", &token), ("worker", &"api1")],
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2020-07-10T12:34:56.123Z WARN  worker Failed to confirm purge: broken pipe token=7 worker=api1\n"
+        );
+
+        let mut out = Vec::new();
+        let long = "x".repeat(RECORD_SIZE * 2);
+        format_record(&mut out, 0, Level::Info, "t", format_args!("{long}"), &[]);
+        assert_eq!(out.len(), RECORD_SIZE);
+        assert!(out.ends_with(b"...\n"));
+    }
+
+    #[test]
+    fn test_macros() {
+        // só garante que todas as formas compilam, sem sujar a saída dos testes
+        init("off");
+        let id = "api1";
+        crate::info!("plain");
+        crate::debug!("with args {} {id}", 1);
+        crate::trace!("with fields"; worker = id, token = 3);
+        crate::warn!("{} and fields", "args"; worker = id);
+        crate::error!("error");
+        flush();
+        assert_eq!(dropped(), 0);
+    }
+}
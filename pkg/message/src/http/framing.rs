@@ -0,0 +1,379 @@
+use super::parse;
+
+// Onde termina cada mensagem HTTP/1.1 num stream de bytes, para quem repassa
+// bytes sem entender o conteúdo (o proxy). Recebe os bytes em pedaços do
+// tamanho que vierem e diz quantos pertencem à mensagem atual.
+
+const MAX_HEAD: usize = 16 * 1024;
+const MAX_LINE: usize = 1024; // linha de tamanho do chunk ou trailer
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Body {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose, // resposta sem tamanho, termina quando o upstream fecha
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Invalid(pub &'static str);
+
+impl std::fmt::Display for Invalid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Head,
+    Length(u64),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkEnd, // \r\n depois dos dados do chunk
+    Trailers,
+    UntilClose,
+    Done,
+}
+
+pub struct Framing {
+    response: bool,
+    head_request: bool, // resposta de HEAD não tem corpo mesmo com Content-Length
+    state: State,
+    head: Vec<u8>,
+    line: Vec<u8>,
+    pub keep_alive: bool,
+    pub status: u16,   // só em respostas
+    pub is_head: bool, // só em requisições: método HEAD
+    pub safe: bool,    // só em requisições: GET ou HEAD, mandar de novo não muda nada
+    pub body: Body,
+}
+
+impl Framing {
+    pub fn request() -> Framing {
+        Framing::new(false, false)
+    }
+
+    pub fn response(head_request: bool) -> Framing {
+        Framing::new(true, head_request)
+    }
+
+    fn new(response: bool, head_request: bool) -> Framing {
+        Framing {
+            response,
+            head_request,
+            state: State::Head,
+            head: Vec::new(),
+            line: Vec::new(),
+            keep_alive: false,
+            status: 0,
+            is_head: false,
+            safe: false,
+            body: Body::Empty,
+        }
+    }
+
+    // Recomeça para a próxima mensagem da mesma conexão, sem soltar os buffers
+    pub fn reset(&mut self, head_request: bool) {
+        self.head_request = head_request;
+        self.state = State::Head;
+        self.head.clear();
+        self.line.clear();
+        self.keep_alive = false;
+        self.status = 0;
+        self.is_head = false;
+        self.safe = false;
+        self.body = Body::Empty;
+    }
+
+    pub fn is_head_request(&self) -> bool {
+        self.head_request
+    }
+
+    pub fn done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Nada da mensagem chegou ainda
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Head && self.head.is_empty()
+    }
+
+    // Ainda nos headers (ou nem começou)
+    pub fn in_head(&self) -> bool {
+        self.state == State::Head
+    }
+
+    // Fim do stream também é o fim da mensagem
+    pub fn ends_on_close(&self) -> bool {
+        self.state == State::UntilClose
+    }
+
+    // Quantos bytes do começo de `bytes` são da mensagem atual; o resto já
+    // é da próxima (pipelining) ou lixo do upstream
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<usize, Invalid> {
+        let mut used = 0;
+        while used < bytes.len() && self.state != State::Done {
+            let rest = &bytes[used..];
+            used += match self.state {
+                State::Head => self.feed_head(rest)?,
+                State::Length(remaining) => {
+                    let n = remaining.min(rest.len() as u64);
+                    self.state = match remaining - n {
+                        0 => State::Done,
+                        left => State::Length(left),
+                    };
+                    n as usize
+                }
+                State::ChunkData(remaining) => {
+                    let n = remaining.min(rest.len() as u64);
+                    self.state = match remaining - n {
+                        0 => State::ChunkEnd,
+                        left => State::ChunkData(left),
+                    };
+                    n as usize
+                }
+                State::ChunkSize | State::ChunkEnd | State::Trailers => self.feed_line(rest)?,
+                State::UntilClose => rest.len(),
+                State::Done => 0,
+            };
+        }
+        Ok(used)
+    }
+
+    fn feed_head(&mut self, bytes: &[u8]) -> Result<usize, Invalid> {
+        let searched = self.head.len().saturating_sub(3);
+        let take = bytes
+            .len()
+            .min(MAX_HEAD + 4 - self.head.len().min(MAX_HEAD));
+        self.head.extend_from_slice(&bytes[..take]);
+
+        let Some(end) = self.head[searched..]
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|at| searched + at + 4)
+        else {
+            if self.head.len() > MAX_HEAD {
+                return Err(Invalid("header section too large"));
+            }
+            return Ok(take);
+        };
+
+        // devolve o que passou do fim dos headers
+        let used = take - (self.head.len() - end);
+        self.head.truncate(end);
+        self.parse_head()?;
+        Ok(used)
+    }
+
+    fn parse_head(&mut self) -> Result<(), Invalid> {
+        let head = &self.head;
+        let first = head.split(|b| *b == b'\n').next().unwrap_or_default();
+        let first = first.strip_suffix(b"\r").unwrap_or(first);
+        let mut parts = first.split(|b| *b == b' ');
+
+        let (version, status) = match self.response {
+            true => {
+                let version = parts.next().unwrap_or_default();
+                let status = parts
+                    .next()
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse::<u16>().ok())
+                    .filter(|code| (100..600).contains(code))
+                    .ok_or(Invalid("invalid status line"))?;
+                (version, status)
+            }
+            false => {
+                let method = parts.next().unwrap_or_default();
+                let target = parts.next().unwrap_or_default();
+                if method.is_empty() || target.is_empty() {
+                    return Err(Invalid("invalid request line"));
+                }
+                self.is_head = method == b"HEAD";
+                self.safe = method == b"GET" || self.is_head;
+                (parts.next().unwrap_or_default(), 0)
+            }
+        };
+        if !version.starts_with(b"HTTP/1.") {
+            return Err(Invalid("unsupported HTTP version"));
+        }
+
+        let connection = parse::header(head, b"connection").unwrap_or_default();
+        self.keep_alive = match version == b"HTTP/1.0" {
+            true => contains_token(connection, b"keep-alive"),
+            false => !contains_token(connection, b"close"),
+        };
+
+        let chunked = parse::header(head, b"transfer-encoding")
+            .is_some_and(|encoding| contains_token(encoding, b"chunked"));
+        let length = match parse::header(head, b"content-length") {
+            Some(length) => Some(
+                std::str::from_utf8(length)
+                    .ok()
+                    .and_then(|length| length.parse::<u64>().ok())
+                    .ok_or(Invalid("invalid content-length"))?,
+            ),
+            None => None,
+        };
+
+        self.status = status;
+        self.body = match (chunked, length) {
+            // 1xx é resposta intermediária, a de verdade vem em seguida
+            _ if self.response && (100..200).contains(&status) && status != 101 => {
+                self.head.clear();
+                return Ok(());
+            }
+            _ if self.response && status == 101 => {
+                self.keep_alive = false;
+                Body::UntilClose
+            }
+            _ if self.response && (self.head_request || status == 204 || status == 304) => {
+                Body::Empty
+            }
+            (true, _) => Body::Chunked,
+            (false, Some(0)) => Body::Empty,
+            (false, Some(length)) => Body::Length(length),
+            (false, None) if self.response => {
+                self.keep_alive = false;
+                Body::UntilClose
+            }
+            (false, None) => Body::Empty,
+        };
+        self.state = match self.body {
+            Body::Empty => State::Done,
+            Body::Length(length) => State::Length(length),
+            Body::Chunked => State::ChunkSize,
+            Body::UntilClose => State::UntilClose,
+        };
+        Ok(())
+    }
+
+    // Linhas do chunked: tamanho em hex, \r\n depois dos dados e trailers
+    fn feed_line(&mut self, bytes: &[u8]) -> Result<usize, Invalid> {
+        let Some(end) = bytes.iter().position(|b| *b == b'\n') else {
+            self.line.extend_from_slice(bytes);
+            if self.line.len() > MAX_LINE {
+                return Err(Invalid("chunk line too long"));
+            }
+            return Ok(bytes.len());
+        };
+        self.line.extend_from_slice(&bytes[..end]);
+        let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+
+        self.state = match self.state {
+            State::ChunkSize => {
+                let size = line.split(|b| *b == b';').next().unwrap_or_default();
+                let size = std::str::from_utf8(size.trim_ascii())
+                    .ok()
+                    .and_then(|size| u64::from_str_radix(size, 16).ok())
+                    .ok_or(Invalid("invalid chunk size"))?;
+                match size {
+                    0 => State::Trailers,
+                    size => State::ChunkData(size),
+                }
+            }
+            State::ChunkEnd if line.is_empty() => State::ChunkSize,
+            State::ChunkEnd => return Err(Invalid("missing CRLF after chunk")),
+            State::Trailers if line.is_empty() => State::Done,
+            state => state,
+        };
+        self.line.clear();
+        Ok(end + 1)
+    }
+}
+
+// Lista separada por vírgula, sem diferenciar maiúsculas
+fn contains_token(value: &[u8], token: &[u8]) -> bool {
+    value
+        .split(|b| *b == b',')
+        .any(|item| item.trim_ascii().eq_ignore_ascii_case(token))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Alimenta de n em n bytes, devolve onde a mensagem terminou
+    fn feed_in_pieces(framing: &mut Framing, bytes: &[u8], piece: usize) -> Option<usize> {
+        let mut at = 0;
+        while at < bytes.len() {
+            let end = (at + piece).min(bytes.len());
+            at += framing.feed(&bytes[at..end]).unwrap();
+            if framing.done() {
+                return Some(at);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_request_framing() {
+        let raw =
+            b"POST /payments HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        for piece in [1, 3, 7, raw.len()] {
+            let mut framing = Framing::request();
+            assert_eq!(feed_in_pieces(&mut framing, raw, piece), Some(51));
+            assert!(framing.keep_alive && !framing.safe);
+            assert_eq!(framing.body, Body::Length(5));
+
+            framing.reset(false);
+            assert_eq!(feed_in_pieces(&mut framing, &raw[51..], piece), Some(18));
+            assert_eq!(framing.body, Body::Empty);
+            assert!(framing.safe);
+        }
+
+        let mut framing = Framing::request();
+        framing
+            .feed(b"HEAD / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n")
+            .unwrap();
+        assert!(framing.done() && framing.is_head && framing.safe && framing.keep_alive);
+
+        let mut framing = Framing::request();
+        assert_eq!(
+            framing.feed(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"),
+            Err(Invalid("invalid content-length"))
+        );
+        let mut framing = Framing::request();
+        assert!(framing.feed(&[b'a'; MAX_HEAD + 10]).is_err());
+    }
+
+    #[test]
+    fn test_response_framing() {
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4;x=y\r\nWiki\r\n5\r\npedia\r\n0\r\nTrailer: 1\r\n\r\nNEXT";
+        for piece in [1, 2, 5, chunked.len()] {
+            let mut framing = Framing::response(false);
+            assert_eq!(
+                feed_in_pieces(&mut framing, chunked, piece),
+                Some(chunked.len() - 4)
+            );
+            assert!(framing.keep_alive);
+        }
+
+        // 100 Continue antes da resposta de verdade
+        let mut framing = Framing::response(false);
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        assert_eq!(feed_in_pieces(&mut framing, raw, 3), Some(raw.len()));
+        assert_eq!(framing.status, 204);
+
+        // HEAD e 304 sem corpo mesmo com Content-Length
+        let mut framing = Framing::response(true);
+        framing
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n")
+            .unwrap();
+        assert!(framing.done());
+
+        // sem tamanho: até o upstream fechar, e a conexão não volta para o pool
+        let mut framing = Framing::response(false);
+        framing.feed(b"HTTP/1.1 200 OK\r\n\r\nsome body").unwrap();
+        assert!(framing.ends_on_close() && !framing.keep_alive);
+
+        let mut framing = Framing::response(false);
+        framing
+            .feed(b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nok")
+            .unwrap();
+        assert!(framing.done() && !framing.keep_alive);
+
+        let mut framing = Framing::response(false);
+        assert!(framing.feed(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+    }
+}
@@ -1,11 +1,14 @@
 use crate::CorrelationId;
 
+pub mod framing;
 pub mod parse;
 pub mod response;
 
 pub enum Request {
-    Summary(u32, u32),
+    Summary(u64, u64),
     Payment(u64, CorrelationId),
+    Purge,
+    Metrics,
     NotFound,
     BadRequest,
 }
@@ -24,46 +27,47 @@ const fn static_token(endpoint: &'static str) -> u32 {
     token
 }
 
-const SUMMARY: u32 = static_token("GET /payments-summary");
-const PAYMENTS: u32 = static_token("POST /payments");
-
-fn simple_timestamp(date_str: Option<&String>) -> u32 {
-    if date_str.is_none() {
-        return 0;
-    }
-
-    let date_str = date_str.unwrap();
-    let mut timestamp = 0u32;
-    let len = date_str.len();
-
-    for (i, byte) in date_str.chars().enumerate() {
-        let weight = len - i;
-        timestamp = timestamp.wrapping_add((byte as u32) * (weight as u32));
-    }
-
-    timestamp
+const SUMMARY_ROUTE: &str = "GET /payments-summary";
+const PAYMENTS_ROUTE: &str = "POST /payments";
+const PURGE_ROUTE: &str = "POST /purge-payments";
+const METRICS_ROUTE: &str = "GET /metrics";
+
+const SUMMARY: u32 = static_token(SUMMARY_ROUTE);
+const PAYMENTS: u32 = static_token(PAYMENTS_ROUTE);
+const PURGE: u32 = static_token(PURGE_ROUTE);
+const METRICS: u32 = static_token(METRICS_ROUTE);
+
+// Sem from/to a janela é aberta
+fn timestamp_or(date_str: Option<&String>, default: u64) -> u64 {
+    date_str
+        .and_then(|date| crate::time::parse_iso8601(date))
+        .unwrap_or(default)
 }
 
 // We should implement parse the type of message until the first \n character
 // The following messages will be valid:
 // GET /payments-summary?from=2020-07-10T12%3A34%3A56.000Z&to=2020-07-10T12%3A35%3A56.000Z HTTP/1.1
 // POST /payments HTTP/1.1
+// POST /purge-payments HTTP/1.1
+// GET /metrics HTTP/1.1
 impl Request {
     pub fn from_bytes(bytes: &[u8]) -> Self {
         match endpoint_token(bytes) {
-            (PAYMENTS, offset) => {
+            (PAYMENTS, offset) if is_route(bytes, offset, PAYMENTS_ROUTE) => {
                 if let Some((amount, correlation_id)) = parse::parse_body(&bytes[offset..]) {
                     Self::Payment(amount, CorrelationId(correlation_id))
                 } else {
                     Self::BadRequest
                 }
             } // Example correlation ID
-            (SUMMARY, offset) => {
+            (SUMMARY, offset) if is_route(bytes, offset, SUMMARY_ROUTE) => {
                 let params = parse::parse_params(&bytes[offset..]);
-                let from = simple_timestamp(params.0.get("from"));
-                let to = simple_timestamp(params.0.get("to"));
+                let from = timestamp_or(params.0.get("from"), 0);
+                let to = timestamp_or(params.0.get("to"), u64::MAX);
                 Self::Summary(from, to)
             }
+            (PURGE, offset) if is_route(bytes, offset, PURGE_ROUTE) => Self::Purge,
+            (METRICS, offset) if is_route(bytes, offset, METRICS_ROUTE) => Self::Metrics,
             _ => Self::NotFound,
         }
     }
@@ -91,6 +95,16 @@ fn endpoint_token(endpoint: &[u8]) -> (u32, usize) {
     (token, offset)
 }
 
+// O token é só a soma dos bytes: "GET /nothing" soma o mesmo que "GET /metrics"
+// e qualquer lixo pode acertar uma rota, então confere byte a byte
+fn is_route(bytes: &[u8], offset: usize, route: &str) -> bool {
+    let end = match offset.checked_sub(1).map(|last| bytes[last]) {
+        Some(b' ' | b'?') => offset - 1,
+        _ => offset,
+    };
+    &bytes[..end] == route.as_bytes()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -108,4 +122,39 @@ mod test {
         assert_eq!(two_match.0, TWO);
         assert_eq!(two_match.1, 10);
     }
+
+    #[test]
+    fn test_route_tokens_are_distinct() {
+        assert_ne!(SUMMARY, PAYMENTS);
+        assert_ne!(SUMMARY, PURGE);
+        assert_ne!(PAYMENTS, PURGE);
+        assert_ne!(METRICS, SUMMARY);
+        assert_ne!(METRICS, PAYMENTS);
+        assert_ne!(METRICS, PURGE);
+        assert!(matches!(
+            Request::from_bytes(b"POST /purge-payments HTTP/1.1\r\n\r\n"),
+            Request::Purge
+        ));
+    }
+
+    #[test]
+    fn test_route_collisions() {
+        assert_eq!(static_token("GET /nothing"), METRICS);
+        assert!(matches!(
+            Request::from_bytes(b"GET /nothing HTTP/1.1\r\n\r\n"),
+            Request::NotFound
+        ));
+        assert!(matches!(
+            Request::from_bytes(b"GET /metrics HTTP/1.1\r\n\r\n"),
+            Request::Metrics
+        ));
+        assert!(matches!(
+            Request::from_bytes(b"GET /metrics"),
+            Request::Metrics
+        ));
+        assert!(matches!(
+            Request::from_bytes(b"GET /payments-summary?to=x HTTP/1.1\r\n\r\n"),
+            Request::Summary(0, u64::MAX)
+        ));
+    }
 }
@@ -7,15 +7,15 @@ pub fn parse_body(bytes: &[u8]) -> Option<(u64, [u8; 36])> {
     let mut cursor = 0;
     let mut valid = (false, false);
 
-    // procurar pelo fim dos headers \n\n ou \r\n\r\n
+    // procurar pelo fim dos headers \n\n ou \r\n\r\n, que pode estar cortado no fim
     for b in bytes {
         cursor += 1;
-        if *b == b'\n' && bytes[cursor] == b'\n' {
+        if *b == b'\n' && bytes.get(cursor) == Some(&b'\n') {
             header_end_found = true;
             break;
         }
 
-        if *b == b'\r' && bytes[cursor..cursor + 3] == *b"\n\r\n" {
+        if *b == b'\r' && bytes.get(cursor..cursor + 3) == Some(b"\n\r\n") {
             header_end_found = true;
             break;
         }
@@ -29,14 +29,19 @@ pub fn parse_body(bytes: &[u8]) -> Option<(u64, [u8; 36])> {
         let b = &bytes[cursor];
         cursor += 1;
 
+        let key = |name: &[u8]| {
+            bytes
+                .get(cursor..cursor + name.len())
+                .is_some_and(|key| key.eq_ignore_ascii_case(name))
+        };
         if *b == b'"' {
-            if bytes[cursor..cursor + 6].eq_ignore_ascii_case(b"amount") {
+            if key(b"amount") {
                 cursor += 6;
                 if let Some(value) = parse_float_as_u32_as_bytes(bytes, &mut cursor) {
                     valid.0 = true;
                     amount = value;
                 }
-            } else if bytes[cursor..cursor + 13].eq_ignore_ascii_case(b"correlationId") {
+            } else if key(b"correlationId") {
                 cursor += 13;
                 if let Some(value) = parse_string(bytes, &mut cursor) {
                     correlation_id.copy_from_slice(&value);
@@ -50,7 +55,8 @@ pub fn parse_body(bytes: &[u8]) -> Option<(u64, [u8; 36])> {
         }
     }
 
-    Some((amount, correlation_id))
+    // campo faltando, cortado ou estourado é 400, não pagamento de 0
+    (valid.0 && valid.1).then_some((amount, correlation_id))
 }
 
 // We will only handle 2 cents
@@ -59,45 +65,44 @@ fn parse_float_as_u32_as_bytes(bytes: &[u8], cursor: &mut usize) -> Option<u64>
     let mut step = 0;
     let mut value = String::with_capacity(10);
     let mut cents = String::with_capacity(2);
+    // o número só vale se algo vier depois dele, senão pode estar cortado
+    let mut terminated = false;
 
     while *cursor < bytes.len() {
-        let mut b = &bytes[*cursor];
+        let b = bytes[*cursor];
         *cursor += 1;
 
-        if *b == b':' {
-            step = 1;
-            continue;
-        } else if step == 0 {
-            continue;
-        }
-
-        if *b == b'.' {
-            step = 2;
-            b = &bytes[*cursor];
-            *cursor += 1;
-        }
-
-        if step == 2 && cents.len() == 2 {
-            break;
-        }
-
-        if step == 1 && b.is_ascii_digit() {
-            value.push(*b as char);
-        } else if step == 2 && b.is_ascii_digit() {
-            cents.push(*b as char);
-        } else if step == 2 {
-            break;
+        match step {
+            0 if b == b':' => step = 1,
+            0 => continue,
+            1 if b.is_ascii_digit() => value.push(b as char),
+            1 if b == b'.' && !value.is_empty() => step = 2,
+            1 if value.is_empty() && b.is_ascii_whitespace() => continue,
+            // o resto dos centavos é descartado
+            2 if b.is_ascii_digit() => {
+                if cents.len() < 2 {
+                    cents.push(b as char);
+                }
+            }
+            _ => {
+                terminated = true;
+                *cursor -= 1;
+                break;
+            }
         }
     }
 
-    let value = if let Ok(value) = value.parse::<u64>() {
-        value
-    } else {
+    if !terminated || (step == 2 && cents.is_empty()) {
         return None;
-    };
+    }
+    let value = value.parse::<u64>().ok()?;
 
-    let cents = cents.parse::<u8>().unwrap_or(0);
-    Some(value * 100 + cents as u64)
+    // "19.9" são 90 centavos, não 9
+    let cents = match cents.len() {
+        1 => cents.parse::<u8>().unwrap_or(0) * 10,
+        _ => cents.parse::<u8>().unwrap_or(0),
+    };
+    value.checked_mul(100)?.checked_add(cents as u64)
 }
 
 #[inline(always)]
@@ -107,13 +112,17 @@ fn parse_string(bytes: &[u8], cursor: &mut usize) -> Option<[u8; 36]> {
     let chars = [b':', b'"'];
     let mut step = 0;
 
-    while *cursor < bytes.len() && index < value.len() {
+    while *cursor < bytes.len() {
         let b = &bytes[*cursor];
         *cursor += 1;
 
         if step == chars.len() {
             if *b == b'"' {
-                break;
+                // só o id inteiro, nem curto nem cortado
+                return (index == value.len()).then_some(value);
+            }
+            if index == value.len() {
+                return None;
             }
 
             value[index] = *b;
@@ -123,7 +132,50 @@ fn parse_string(bytes: &[u8], cursor: &mut usize) -> Option<[u8; 36]> {
         }
     }
 
-    if value.is_empty() { None } else { Some(value) }
+    None
+}
+
+// Valor de um header, procurando linha a linha até o fim dos headers
+pub fn header<'a>(bytes: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    for line in bytes.split(|b| *b == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+
+        if line.len() > name.len()
+            && line[name.len()] == b':'
+            && line[..name.len()].eq_ignore_ascii_case(name)
+        {
+            return Some(line[name.len() + 1..].trim_ascii());
+        }
+    }
+
+    None
+}
+
+// Até onde uma requisição chegou, para quem lê em pedaços
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+    Head,
+    Body,
+    Complete,
+}
+
+pub fn progress(bytes: &[u8]) -> Progress {
+    let Some(end) = bytes.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Progress::Head;
+    };
+
+    // Content-Length inválido fica para o parse responder 400
+    let length = header(bytes, b"content-length")
+        .and_then(|length| std::str::from_utf8(length).ok())
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    match bytes.len() - (end + 4) >= length {
+        true => Progress::Complete,
+        false => Progress::Body,
+    }
 }
 
 // Parse that string from=2020-07-10T12%3A34%3A56.000Z&to=2020-07-10T12%3A35%3A56.000Z HTTP/1.1
@@ -171,6 +223,18 @@ pub fn parse_params(bytes: &[u8]) -> (HashMap<String, String>, usize) {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_progress() {
+        let request = b"POST /payments HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        assert_eq!(progress(&request[..20]), Progress::Head);
+        assert_eq!(progress(&request[..request.len() - 1]), Progress::Body);
+        assert_eq!(progress(request), Progress::Complete);
+        assert_eq!(
+            progress(b"GET /payments-summary HTTP/1.1\r\n\r\n"),
+            Progress::Complete
+        );
+    }
+
     #[test]
     fn test_parse_summary() {
         let params = b"from=2020-07-10T12%3A34%3A56.000Z&to=2020-07-10T12%3A35%3A56.000Z HTTP/1.1";
@@ -189,4 +253,61 @@ mod test {
         assert_eq!(params.get("from"), None);
         assert_eq!(offset, 32);
     }
+
+    #[test]
+    fn test_parse_amount() {
+        let id = "4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3";
+        // o k6 manda Big(19.90).toNumber(), que vira 19.9 no JSON
+        for (amount, cents) in [("19.9", 1990), ("19.90", 1990), ("19", 1900), ("0.05", 5)] {
+            let request = format!(
+                "POST /payments HTTP/1.1\r\n\r\n{{\"correlationId\":\"{id}\",\"amount\":{amount}}}"
+            );
+            let (parsed, _) = parse_body(request.as_bytes()).unwrap();
+            assert_eq!(parsed, cents, "{amount}");
+        }
+    }
+
+    // Achados do fuzz: entrada cortada em qualquer ponto não pode passar do fim
+    #[test]
+    fn test_parse_body_truncated() {
+        let request = b"POST /payments HTTP/1.1\r\n\r\n{\"correlationId\":\"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3\",\"amount\":19.90}";
+        for end in 0..=request.len() {
+            let _ = parse_body(&request[..end]);
+        }
+        assert_eq!(parse_body(b"\n"), None);
+        assert_eq!(parse_body(b"\r\n\r"), None);
+        assert_eq!(parse_body(b"\n\n{\"amount\":1."), None);
+        assert_eq!(parse_body(b"\n\n{\"amount\":184467440737095517}"), None);
+        // só o corpo completo vira pagamento
+        let (head, body) = request.split_at(request.len() - 1);
+        assert!(parse_body(head).is_none());
+        assert_eq!(body, b"}");
+        assert_eq!(parse_body(request).unwrap().0, 1990);
+        assert_eq!(parse_body(b"\n\n{\"amount\":19.90}"), None);
+        assert_eq!(
+            parse_body(b"\n\n{\"correlationId\":\"short\",\"amount\":1}"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_body_amount_first() {
+        let id = "4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3";
+        let request = format!("\n\n{{\"amount\": 19.9, \"correlationId\":\"{id}\"}}");
+        let (amount, parsed) = parse_body(request.as_bytes()).unwrap();
+        assert_eq!(amount, 1990);
+        assert_eq!(&parsed, id.as_bytes());
+    }
+
+    #[test]
+    fn test_header() {
+        let request = b"POST /purge-payments HTTP/1.1\r\nHost: lb\r\nX-Purge-Token:  secret \r\n\r\nX-Body: no";
+        assert_eq!(header(request, b"x-purge-token"), Some(&b"secret"[..]));
+        assert_eq!(header(request, b"host"), Some(&b"lb"[..]));
+        assert_eq!(header(request, b"x-body"), None);
+        assert_eq!(
+            header(b"GET / HTTP/1.1\nHost: a\n\n", b"Host"),
+            Some(&b"a"[..])
+        );
+    }
 }
@@ -1,4 +1,361 @@
-pub static NOT_FOUND: &[u8] = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
-pub static BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
-pub static OK: &[u8] = b"HTTP/1.1 200 OK\r\nConnection: Keep-Alive\r\nKeep-Alive: timeout=30, max=500\r\nContent-Length: 0\r\n\r\n";
-pub static SUMMARY: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 98\r\n\r\n{\"default\":{\"totalRequests\": 0,\"totalAmount\": 0},\"fallback\":{\"totalRequests\": 0,\"totalAmount\": 0}}";
+use std::cell::Cell;
+
+use crate::time::{self, HTTP_DATE_LEN};
+
+// Respostas prontas para o caminho quente, sem Date nem nada calculado. O
+// load balancer fecha a conexão depois de cada resposta (keep-alive só no
+// modo proxy), então todas avisam o cliente.
+pub static NOT_FOUND: &[u8] =
+    b"HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+pub static BAD_REQUEST: &[u8] =
+    b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+pub static FORBIDDEN: &[u8] =
+    b"HTTP/1.1 403 Forbidden\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+pub static SERVICE_UNAVAILABLE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+// Admissão do load balancer: recusadas antes de ocupar um slot
+pub static TOO_MANY_REQUESTS: &[u8] = b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+pub static OVERLOADED: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+pub static OK: &[u8] = b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+// Summary zerado (depois do purge, ou antes do primeiro pagamento)
+pub static SUMMARY: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nConnection: close\r\nContent-Length: 100\r\n\r\n{\"default\":{\"totalRequests\":0,\"totalAmount\":0.00},\"fallback\":{\"totalRequests\":0,\"totalAmount\":0.00}}";
+
+pub const JSON: &[u8] = b"application/json";
+pub const TEXT: &[u8] = b"text/plain; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub code: u16,
+    pub reason: &'static str,
+}
+
+impl Status {
+    pub const OK: Status = Status::new(200, "OK");
+    pub const BAD_REQUEST: Status = Status::new(400, "Bad Request");
+    pub const FORBIDDEN: Status = Status::new(403, "Forbidden");
+    pub const NOT_FOUND: Status = Status::new(404, "Not Found");
+    pub const TOO_MANY_REQUESTS: Status = Status::new(429, "Too Many Requests");
+    pub const INTERNAL_SERVER_ERROR: Status = Status::new(500, "Internal Server Error");
+    pub const BAD_GATEWAY: Status = Status::new(502, "Bad Gateway");
+    pub const SERVICE_UNAVAILABLE: Status = Status::new(503, "Service Unavailable");
+    pub const GATEWAY_TIMEOUT: Status = Status::new(504, "Gateway Timeout");
+
+    pub const fn new(code: u16, reason: &'static str) -> Status {
+        Status { code, reason }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connection {
+    KeepAlive,
+    Close,
+}
+
+// Resposta escrita direto no out_buffer da conexão, sem String nem format!:
+// status, Date, headers e por último o corpo. O Content-Length é reservado
+// antes do corpo e ajustado depois, assim o corpo também é renderizado no
+// próprio buffer. Sem alocação enquanto couber na capacidade do out_buffer.
+pub struct Response<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> Response<'a> {
+    pub fn new(out: &'a mut Vec<u8>, status: Status) -> Self {
+        out.extend_from_slice(b"HTTP/1.1 ");
+        write_u64(out, status.code as u64);
+        out.push(b' ');
+        out.extend_from_slice(status.reason.as_bytes());
+        out.extend_from_slice(b"\r\nDate: ");
+        out.extend_from_slice(&date());
+        out.extend_from_slice(b"\r\n");
+        Response { out }
+    }
+
+    pub fn header(self, name: &[u8], value: &[u8]) -> Self {
+        self.out.extend_from_slice(name);
+        self.out.extend_from_slice(b": ");
+        self.out.extend_from_slice(value);
+        self.out.extend_from_slice(b"\r\n");
+        self
+    }
+
+    pub fn content_type(self, value: &[u8]) -> Self {
+        self.header(b"Content-Type", value)
+    }
+
+    pub fn connection(self, connection: Connection) -> Self {
+        match connection {
+            Connection::KeepAlive => self.header(b"Connection", b"keep-alive"),
+            Connection::Close => self.header(b"Connection", b"close"),
+        }
+    }
+
+    pub fn empty(self) {
+        self.out.extend_from_slice(b"Content-Length: 0\r\n\r\n");
+    }
+
+    pub fn body(self, body: &[u8]) {
+        self.render(|out| out.extend_from_slice(body));
+    }
+
+    // O corpo é escrito pelo chamador no fim do buffer
+    pub fn render(self, body: impl FnOnce(&mut Vec<u8>)) {
+        const RESERVED: usize = 20; // cabe qualquer u64
+
+        let out = self.out;
+        out.extend_from_slice(b"Content-Length: ");
+        let length_at = out.len();
+        out.extend_from_slice(&[b' '; RESERVED]);
+        out.extend_from_slice(b"\r\n\r\n");
+        let body_at = out.len();
+        body(out);
+
+        let mut digits = [0u8; RESERVED];
+        let n = format_u64((out.len() - body_at) as u64, &mut digits);
+        out[length_at..length_at + n].copy_from_slice(&digits[..n]);
+        out.copy_within(length_at + RESERVED.., length_at + n);
+        out.truncate(out.len() - (RESERVED - n));
+    }
+
+    pub fn json(self, body: impl FnOnce(&mut Json)) {
+        self.content_type(JSON).render(|out| {
+            let start = out.len();
+            body(&mut Json { out, start })
+        });
+    }
+}
+
+// Escritor de JSON sem alocação, a vírgula entre itens é decidida pelo último byte
+pub struct Json<'a> {
+    out: &'a mut Vec<u8>,
+    start: usize, // onde o JSON começa, antes disso são os headers
+}
+
+impl Json<'_> {
+    pub fn begin_object(&mut self) -> &mut Self {
+        self.separator();
+        self.out.push(b'{');
+        self
+    }
+
+    pub fn end_object(&mut self) -> &mut Self {
+        self.out.push(b'}');
+        self
+    }
+
+    pub fn begin_array(&mut self) -> &mut Self {
+        self.separator();
+        self.out.push(b'[');
+        self
+    }
+
+    pub fn end_array(&mut self) -> &mut Self {
+        self.out.push(b']');
+        self
+    }
+
+    pub fn key(&mut self, key: &str) -> &mut Self {
+        self.string(key);
+        self.out.push(b':');
+        self
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.separator();
+        self.out.push(b'"');
+        for &byte in value.as_bytes() {
+            match byte {
+                b'"' => self.out.extend_from_slice(b"\\\""),
+                b'\\' => self.out.extend_from_slice(b"\\\\"),
+                b'\n' => self.out.extend_from_slice(b"\\n"),
+                b'\r' => self.out.extend_from_slice(b"\\r"),
+                b'\t' => self.out.extend_from_slice(b"\\t"),
+                0..0x20 => {
+                    const HEX: &[u8; 16] = b"0123456789abcdef";
+                    self.out.extend_from_slice(b"\\u00");
+                    self.out.push(HEX[(byte >> 4) as usize]);
+                    self.out.push(HEX[(byte & 0xf) as usize]);
+                }
+                _ => self.out.push(byte),
+            }
+        }
+        self.out.push(b'"');
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.separator();
+        write_u64(self.out, value);
+        self
+    }
+
+    // Centavos como decimal de duas casas: 1990 -> 19.90
+    pub fn cents(&mut self, cents: u64) -> &mut Self {
+        self.separator();
+        write_u64(self.out, cents / 100);
+        self.out.push(b'.');
+        self.out.push(b'0' + (cents % 100 / 10) as u8);
+        self.out.push(b'0' + (cents % 10) as u8);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.separator();
+        self.out
+            .extend_from_slice(if value { b"true" } else { b"false" });
+        self
+    }
+
+    fn separator(&mut self) {
+        if self.out.len() > self.start && !matches!(self.out.last(), Some(b'{' | b'[' | b':')) {
+            self.out.push(b',');
+        }
+    }
+}
+
+fn format_u64(mut value: u64, digits: &mut [u8; 20]) -> usize {
+    let mut at = digits.len();
+    loop {
+        at -= 1;
+        digits[at] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    let n = digits.len() - at;
+    digits.copy_within(at.., 0);
+    n
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    let mut digits = [0u8; 20];
+    let n = format_u64(value, &mut digits);
+    out.extend_from_slice(&digits[..n]);
+}
+
+thread_local! {
+    // Date muda uma vez por segundo, cada thread formata só quando vira o segundo
+    static DATE: Cell<(u64, [u8; HTTP_DATE_LEN])> = const { Cell::new((u64::MAX, [0; HTTP_DATE_LEN])) };
+}
+
+fn date() -> [u8; HTTP_DATE_LEN] {
+    let seconds = time::now_millis() / 1000;
+    DATE.with(|cached| {
+        let (at, mut date) = cached.get();
+        if at != seconds {
+            time::format_http_date(seconds, &mut date);
+            cached.set((seconds, date));
+        }
+        date
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split(response: &[u8]) -> (String, &[u8]) {
+        let end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("no end of headers");
+        (
+            String::from_utf8(response[..end].to_vec()).unwrap(),
+            &response[end + 4..],
+        )
+    }
+
+    fn content_length(head: &str) -> usize {
+        head.lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .expect("no content-length")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_static_lengths() {
+        for response in [
+            NOT_FOUND,
+            BAD_REQUEST,
+            FORBIDDEN,
+            SERVICE_UNAVAILABLE,
+            TOO_MANY_REQUESTS,
+            OVERLOADED,
+            OK,
+            SUMMARY,
+        ] {
+            let (head, body) = split(response);
+            assert_eq!(content_length(&head), body.len(), "{head}");
+            // o load balancer fecha depois de responder, nenhuma promete keep-alive
+            assert!(head.contains("\r\nConnection: close\r\n"), "{head}");
+        }
+    }
+
+    #[test]
+    fn test_json_response() {
+        let mut out = Vec::with_capacity(512);
+        let buffer = out.as_ptr();
+        Response::new(&mut out, Status::OK)
+            .connection(Connection::Close)
+            .json(|json| {
+                json.begin_object();
+                for (name, requests, amount) in [("default", 1, 1990), ("fallback", 0, 5)] {
+                    json.key(name)
+                        .begin_object()
+                        .key("totalRequests")
+                        .u64(requests)
+                        .key("totalAmount")
+                        .cents(amount)
+                        .end_object();
+                }
+                json.key("note").string("a \"b\"\n").end_object();
+            });
+        // tudo no mesmo buffer, sem realocar
+        assert_eq!(out.as_ptr(), buffer);
+
+        let (head, body) = split(&out);
+        let mut lines = head.lines();
+        assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+        let date = lines.next().unwrap();
+        assert!(
+            date.starts_with("Date: ") && date.ends_with(" GMT"),
+            "{date}"
+        );
+        assert_eq!(date.len(), "Date: ".len() + HTTP_DATE_LEN);
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            [
+                "Connection: close",
+                "Content-Type: application/json",
+                "Content-Length: 120"
+            ]
+        );
+        assert_eq!(
+            body,
+            br#"{"default":{"totalRequests":1,"totalAmount":19.90},"fallback":{"totalRequests":0,"totalAmount":0.05},"note":"a \"b\"\n"}"#
+        );
+        assert_eq!(content_length(&head), body.len());
+    }
+
+    #[test]
+    fn test_custom_status_and_empty() {
+        let mut out = Vec::new();
+        Response::new(&mut out, Status::TOO_MANY_REQUESTS)
+            .header(b"Retry-After", b"1")
+            .empty();
+        let (head, body) = split(&out);
+        assert!(head.starts_with("HTTP/1.1 429 Too Many Requests\r\nDate: "));
+        assert!(head.ends_with("\r\nRetry-After: 1\r\nContent-Length: 0"));
+        assert!(body.is_empty());
+
+        let mut out = Vec::new();
+        Response::new(&mut out, Status::new(418, "I'm a teapot"))
+            .content_type(TEXT)
+            .body(b"");
+        let (head, _) = split(&out);
+        assert!(head.starts_with("HTTP/1.1 418 I'm a teapot"));
+        assert_eq!(content_length(&head), 0);
+    }
+}
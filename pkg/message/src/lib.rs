@@ -2,6 +2,7 @@ use std::fmt::Display;
 
 pub mod http;
 pub mod socket;
+pub mod time;
 
 pub struct CorrelationId(pub [u8; 36]);
 
@@ -1,9 +1,27 @@
+use std::time::Duration;
+
 use crate::CorrelationId;
 
+// De quanto em quanto tempo o worker manda Health (e renova os Credit) para
+// cada load balancer conectado pelo socket
+pub const HEALTH_INTERVAL: Duration = Duration::from_millis(250);
+
 pub enum Message {
-    Summary(u32, u32),
+    Summary(u64, u64),
     Payment(u64, CorrelationId),
+    Purge,
+    Metrics,
+    // Pedaço de uma resposta maior que um frame: (último, tamanho, dados)
+    Chunk(bool, u8, [u8; Message::CHUNK]),
+    // Nome do arquivo do ring ao lado do socket, os pagamentos passam a ir por ele: (tamanho, nome)
+    Attach(u8, [u8; Message::CHUNK]),
     Ack,
+    // Do worker para o load balancer sem pedido: (na fila, em retry)
+    Health(u32, u32),
+    // Total de pagamentos que o load balancer pode ter mandado pelo socket
+    Credit(u64),
+    // Erro do lado do worker, em texto: (tamanho, texto)
+    Notice(u8, [u8; Message::CHUNK]),
 }
 
 unsafe impl Send for Message {}
@@ -11,13 +29,38 @@ unsafe impl Sync for Message {}
 
 impl Message {
     pub const SIZE: usize = 54;
+    pub const CHUNK: usize = 50;
+
+    // Quebra um payload em frames Chunk, sempre termina com um frame marcado como último
+    pub fn chunks(data: &[u8]) -> impl Iterator<Item = Message> + '_ {
+        let count = data.len().div_ceil(Self::CHUNK).max(1);
+        (0..count).map(move |i| {
+            let part =
+                &data[(i * Self::CHUNK).min(data.len())..((i + 1) * Self::CHUNK).min(data.len())];
+            let mut payload = [0; Self::CHUNK];
+            payload[..part.len()].copy_from_slice(part);
+            Message::Chunk(i + 1 == count, part.len() as u8, payload)
+        })
+    }
+
+    // Texto cortado no tamanho de um frame
+    pub fn notice(text: &str) -> Message {
+        let mut end = text.len().min(Self::CHUNK);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = [0; Self::CHUNK];
+        payload[..end].copy_from_slice(&text.as_bytes()[..end]);
+        Message::Notice(end as u8, payload)
+    }
+
     pub fn to_bytes(&self) -> [u8; Self::SIZE] {
         match self {
             Message::Summary(from, to) => {
                 let mut bytes = [0; Self::SIZE];
                 bytes[0] = b'@'; // Marker for Summary
-                bytes[1..5].copy_from_slice(&from.to_be_bytes());
-                bytes[5..9].copy_from_slice(&to.to_be_bytes());
+                bytes[1..9].copy_from_slice(&from.to_be_bytes());
+                bytes[9..17].copy_from_slice(&to.to_be_bytes());
                 bytes[53] = 0x06; // ACK
                 bytes
             }
@@ -29,9 +72,61 @@ impl Message {
                 bytes[53] = 0x06; // ACK no último byte
                 bytes
             }
+            Message::Purge => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'!'; // Marker for Purge
+                bytes[53] = 0x06;
+                bytes
+            }
+            Message::Metrics => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'#'; // Marker for Metrics
+                bytes[53] = 0x06;
+                bytes
+            }
+            Message::Chunk(last, len, payload) => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'+'; // Marker for Chunk
+                bytes[1] = *len;
+                bytes[2] = *last as u8;
+                bytes[3..53].copy_from_slice(payload);
+                bytes[53] = 0x06;
+                bytes
+            }
+            Message::Attach(len, name) => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'%'; // Marker for Attach
+                bytes[1] = *len;
+                bytes[3..53].copy_from_slice(name);
+                bytes[53] = 0x06;
+                bytes
+            }
             Message::Ack => {
                 [0x06; Self::SIZE] // Just ACK
             }
+            Message::Health(queued, retrying) => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'^'; // Marker for Health
+                bytes[1..5].copy_from_slice(&queued.to_be_bytes());
+                bytes[5..9].copy_from_slice(&retrying.to_be_bytes());
+                bytes[53] = 0x06;
+                bytes
+            }
+            Message::Credit(limit) => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'='; // Marker for Credit
+                bytes[1..9].copy_from_slice(&limit.to_be_bytes());
+                bytes[53] = 0x06;
+                bytes
+            }
+            Message::Notice(len, text) => {
+                let mut bytes = [0; Self::SIZE];
+                bytes[0] = b'?'; // Marker for Notice
+                bytes[1] = *len;
+                bytes[3..53].copy_from_slice(text);
+                bytes[53] = 0x06;
+                bytes
+            }
         }
     }
 
@@ -52,13 +147,13 @@ impl Message {
                     ));
                 }
 
-                // Extrair from (bytes 1-4)
-                let from_bytes: [u8; 4] = bytes[1..5].try_into().unwrap_or([0; 4]);
-                let from = u32::from_be_bytes(from_bytes);
+                // Extrair from (bytes 1-8)
+                let from_bytes: [u8; 8] = bytes[1..9].try_into().unwrap_or([0; 8]);
+                let from = u64::from_be_bytes(from_bytes);
 
-                // Extrair to (bytes 5-8)
-                let to_bytes: [u8; 4] = bytes[5..9].try_into().unwrap_or([0; 4]);
-                let to = u32::from_be_bytes(to_bytes);
+                // Extrair to (bytes 9-16)
+                let to_bytes: [u8; 8] = bytes[9..17].try_into().unwrap_or([0; 8]);
+                let to = u64::from_be_bytes(to_bytes);
 
                 Ok(Message::Summary(from, to))
             }
@@ -81,6 +176,71 @@ impl Message {
 
                 Ok(Message::Payment(amount, correlation_id))
             }
+            // Purge
+            b'!' => {
+                if bytes.len() != Self::SIZE {
+                    return Err(format!(
+                        "Purge message doesn't have the correct size, got: {}, expected: {}",
+                        bytes.len(),
+                        Self::SIZE
+                    ));
+                }
+
+                Ok(Message::Purge)
+            }
+            // Metrics
+            b'#' if bytes.len() == Self::SIZE => Ok(Message::Metrics),
+            // Chunk
+            b'+' if bytes.len() == Self::SIZE => {
+                let len = bytes[1];
+                if len as usize > Self::CHUNK {
+                    return Err(format!("Chunk length {len} is bigger than {}", Self::CHUNK));
+                }
+
+                let payload = bytes[3..53].try_into().unwrap_or([0; Self::CHUNK]);
+                Ok(Message::Chunk(bytes[2] == 1, len, payload))
+            }
+            // Attach
+            b'%' if bytes.len() == Self::SIZE => {
+                let len = bytes[1];
+                if len as usize > Self::CHUNK {
+                    return Err(format!(
+                        "Attach name length {len} is bigger than {}",
+                        Self::CHUNK
+                    ));
+                }
+
+                let name = bytes[3..53].try_into().unwrap_or([0; Self::CHUNK]);
+                Ok(Message::Attach(len, name))
+            }
+            // Health
+            b'^' if bytes.len() == Self::SIZE => {
+                let queued = u32::from_be_bytes(bytes[1..5].try_into().unwrap_or([0; 4]));
+                let retrying = u32::from_be_bytes(bytes[5..9].try_into().unwrap_or([0; 4]));
+                Ok(Message::Health(queued, retrying))
+            }
+            // Credit
+            b'=' if bytes.len() == Self::SIZE => Ok(Message::Credit(u64::from_be_bytes(
+                bytes[1..9].try_into().unwrap_or([0; 8]),
+            ))),
+            // Notice
+            b'?' if bytes.len() == Self::SIZE => {
+                let len = bytes[1];
+                if len as usize > Self::CHUNK {
+                    return Err(format!(
+                        "Notice length {len} is bigger than {}",
+                        Self::CHUNK
+                    ));
+                }
+
+                let text = bytes[3..53].try_into().unwrap_or([0; Self::CHUNK]);
+                Ok(Message::Notice(len, text))
+            }
+            b'#' | b'+' | b'%' | b'^' | b'=' | b'?' => Err(format!(
+                "Message doesn't have the correct size, got: {}, expected: {}",
+                bytes.len(),
+                Self::SIZE
+            )),
             0x06 => {
                 // ACK message
                 Ok(Message::Ack)
@@ -97,7 +257,24 @@ impl std::fmt::Debug for Message {
             Message::Payment(amount, _) => {
                 write!(f, "Payment(amount: {amount}, correlation_id: [u8; 36])",)
             }
+            Message::Purge => write!(f, "Purge"),
+            Message::Metrics => write!(f, "Metrics"),
+            Message::Chunk(last, len, _) => write!(f, "Chunk(last: {last}, len: {len})"),
+            Message::Attach(len, name) => write!(
+                f,
+                "Attach({})",
+                String::from_utf8_lossy(&name[..(*len as usize).min(Self::CHUNK)])
+            ),
             Message::Ack => write!(f, "Ack"),
+            Message::Health(queued, retrying) => {
+                write!(f, "Health(queued: {queued}, retrying: {retrying})")
+            }
+            Message::Credit(limit) => write!(f, "Credit({limit})"),
+            Message::Notice(len, text) => write!(
+                f,
+                "Notice({})",
+                String::from_utf8_lossy(&text[..(*len as usize).min(Self::CHUNK)])
+            ),
         }
     }
 }
@@ -270,6 +447,80 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_roundtrip_purge() {
+        let bytes = Message::Purge.to_bytes();
+        assert_eq!(bytes[0], b'!');
+        assert_eq!(bytes[53], 0x06);
+
+        match Message::from_bytes(&bytes).unwrap() {
+            Message::Purge => {} // Sucesso
+            _ => panic!("Expected Purge message"),
+        }
+    }
+
+    #[test]
+    fn test_chunks_roundtrip() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(Message::CHUNK * 2 + 7).collect();
+        let mut rebuilt = Vec::new();
+        let mut frames = 0;
+
+        for chunk in Message::chunks(&data) {
+            frames += 1;
+            match Message::from_bytes(&chunk.to_bytes()).unwrap() {
+                Message::Chunk(last, len, payload) => {
+                    rebuilt.extend_from_slice(&payload[..len as usize]);
+                    assert_eq!(last, frames == 3);
+                }
+                _ => panic!("Expected Chunk message"),
+            }
+        }
+
+        assert_eq!(frames, 3);
+        assert_eq!(rebuilt, data);
+
+        let empty: Vec<Message> = Message::chunks(&[]).collect();
+        assert!(matches!(empty[..], [Message::Chunk(true, 0, _)]));
+    }
+
+    #[test]
+    fn test_roundtrip_attach() {
+        let mut name = [0u8; Message::CHUNK];
+        name[..10].copy_from_slice(b"api1.ring\0");
+        match Message::from_bytes(&Message::Attach(9, name).to_bytes()).unwrap() {
+            Message::Attach(len, decoded) => assert_eq!(&decoded[..len as usize], b"api1.ring"),
+            _ => panic!("Expected Attach message"),
+        }
+
+        let mut bytes = Message::Attach(9, name).to_bytes();
+        bytes[1] = 51;
+        assert!(Message::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_link_messages() {
+        match Message::from_bytes(&Message::Health(7, 3).to_bytes()).unwrap() {
+            Message::Health(queued, retrying) => assert_eq!((queued, retrying), (7, 3)),
+            _ => panic!("Expected Health message"),
+        }
+        match Message::from_bytes(&Message::Credit(u64::MAX - 1).to_bytes()).unwrap() {
+            Message::Credit(limit) => assert_eq!(limit, u64::MAX - 1),
+            _ => panic!("Expected Credit message"),
+        }
+
+        // texto comprido corta no frame sem quebrar caractere
+        let long = format!("ação {}", "x".repeat(60));
+        match Message::from_bytes(&Message::notice(&long).to_bytes()).unwrap() {
+            Message::Notice(len, text) => {
+                assert_eq!(len as usize, Message::CHUNK);
+                assert!(long.starts_with(std::str::from_utf8(&text[..len as usize]).unwrap()));
+            }
+            _ => panic!("Expected Notice message"),
+        }
+        let accented = format!("{}é", "x".repeat(Message::CHUNK - 1));
+        assert!(matches!(Message::notice(&accented), Message::Notice(49, _)));
+    }
+
     #[test]
     fn test_invalid_message_type() {
         let mut bytes = [0u8; Message::SIZE];
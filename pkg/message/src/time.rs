@@ -0,0 +1,227 @@
+// Datas no formato que o k6 manda: 2020-07-10T12:34:56.000Z (com ou sem %3A no lugar de ':')
+// Tudo é tratado como milissegundos desde a epoch em UTC.
+
+pub const ISO_LEN: usize = 24;
+
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn parse_iso8601(value: &str) -> Option<u64> {
+    let mut digits = [0u32; 7];
+    let mut field = 0;
+    let mut width = 0;
+    let bytes = value.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() && field < digits.len() {
+        let b = bytes[i];
+        i += 1;
+
+        if b.is_ascii_digit() {
+            // milissegundos podem vir com mais de 3 casas, o resto é ignorado
+            if field == 6 && width == 3 {
+                continue;
+            }
+            // número grande demais para o campo não é data
+            digits[field] = digits[field]
+                .checked_mul(10)?
+                .checked_add((b - b'0') as u32)?;
+            width += 1;
+            continue;
+        }
+
+        // %3A é ':' url encoded
+        if b == b'%'
+            && bytes
+                .get(i..i + 2)
+                .is_some_and(|s| s.eq_ignore_ascii_case(b"3A"))
+        {
+            i += 2;
+        } else if b == b'Z' || b == b'+' {
+            break;
+        } else if !matches!(b, b'-' | b'T' | b':' | b'.') {
+            return None;
+        }
+
+        if width == 0 {
+            return None;
+        }
+        field += 1;
+        width = 0;
+    }
+
+    if field < 5 {
+        return None;
+    }
+
+    let [year, month, day, hour, minute, second, mut millis] = digits;
+    if field == 6 {
+        // 1 ou 2 casas decimais: .5 == 500ms
+        for _ in width..3 {
+            millis *= 10;
+        }
+    }
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year as i64, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    let seconds = (days as u64)
+        .checked_mul(86_400)?
+        .checked_add(hour as u64 * 3600 + minute as u64 * 60 + second as u64)?;
+    seconds.checked_mul(1000)?.checked_add(millis as u64)
+}
+
+pub fn format_iso8601(millis: u64, out: &mut [u8; ISO_LEN]) {
+    let seconds = millis / 1000;
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let rem = seconds % 86_400;
+
+    write_digits(&mut out[0..4], year as u64);
+    out[4] = b'-';
+    write_digits(&mut out[5..7], month as u64);
+    out[7] = b'-';
+    write_digits(&mut out[8..10], day as u64);
+    out[10] = b'T';
+    write_digits(&mut out[11..13], rem / 3600);
+    out[13] = b':';
+    write_digits(&mut out[14..16], rem % 3600 / 60);
+    out[16] = b':';
+    write_digits(&mut out[17..19], rem % 60);
+    out[19] = b'.';
+    write_digits(&mut out[20..23], millis % 1000);
+    out[23] = b'Z';
+}
+
+// Date do HTTP (IMF-fixdate): Sun, 06 Nov 1994 08:49:37 GMT
+pub const HTTP_DATE_LEN: usize = 29;
+
+pub fn format_http_date(seconds: u64, out: &mut [u8; HTTP_DATE_LEN]) {
+    const WEEKDAYS: [&[u8; 3]; 7] = [b"Thu", b"Fri", b"Sat", b"Sun", b"Mon", b"Tue", b"Wed"];
+    const MONTHS: [&[u8; 3]; 12] = [
+        b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov",
+        b"Dec",
+    ];
+
+    let days = seconds / 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let rem = seconds % 86_400;
+
+    // 1970-01-01 foi uma quinta
+    out[0..3].copy_from_slice(WEEKDAYS[(days % 7) as usize]);
+    out[3..5].copy_from_slice(b", ");
+    write_digits(&mut out[5..7], day as u64);
+    out[7] = b' ';
+    out[8..11].copy_from_slice(MONTHS[month as usize - 1]);
+    out[11] = b' ';
+    write_digits(&mut out[12..16], year as u64);
+    out[16] = b' ';
+    write_digits(&mut out[17..19], rem / 3600);
+    out[19] = b':';
+    write_digits(&mut out[20..22], rem % 3600 / 60);
+    out[22] = b':';
+    write_digits(&mut out[23..25], rem % 60);
+    out[25..29].copy_from_slice(b" GMT");
+}
+
+#[inline(always)]
+fn write_digits(out: &mut [u8], mut value: u64) {
+    for b in out.iter_mut().rev() {
+        *b = b'0' + (value % 10) as u8;
+        value /= 10;
+    }
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_iso8601() {
+        assert_eq!(parse_iso8601("1970-01-01T00:00:00.000Z"), Some(0));
+        assert_eq!(
+            parse_iso8601("2020-07-10T12:34:56.000Z"),
+            Some(1_594_384_496_000)
+        );
+        assert_eq!(
+            parse_iso8601("2020-07-10T12%3A34%3A56.123Z"),
+            Some(1_594_384_496_123)
+        );
+        assert_eq!(
+            parse_iso8601("2020-07-10T12:34:56Z"),
+            Some(1_594_384_496_000)
+        );
+        assert_eq!(
+            parse_iso8601("2020-07-10T12:34:56.5Z"),
+            Some(1_594_384_496_500)
+        );
+        assert_eq!(parse_iso8601("2020-07-10"), None);
+        assert_eq!(parse_iso8601("not a date"), None);
+        assert_eq!(parse_iso8601("2020-13-10T12:34:56.000Z"), None);
+        // do fuzz: campo ou data que não cabe não pode estourar
+        assert_eq!(parse_iso8601("99999999999-07-10T12:34:56.000Z"), None);
+        assert_eq!(parse_iso8601("4294967295-12-31T23:59:59.999Z"), None);
+    }
+
+    #[test]
+    fn test_format_http_date() {
+        let mut out = [0u8; HTTP_DATE_LEN];
+        format_http_date(784_111_777, &mut out);
+        assert_eq!(&out, b"Sun, 06 Nov 1994 08:49:37 GMT");
+        format_http_date(0, &mut out);
+        assert_eq!(&out, b"Thu, 01 Jan 1970 00:00:00 GMT");
+        format_http_date(1_709_164_800, &mut out);
+        assert_eq!(&out, b"Thu, 29 Feb 2024 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_format_roundtrip() {
+        let mut out = [0u8; ISO_LEN];
+        format_iso8601(1_594_384_496_123, &mut out);
+        assert_eq!(&out, b"2020-07-10T12:34:56.123Z");
+
+        for millis in [0, 951_782_400_000, 1_709_164_800_999, 4_102_444_799_999] {
+            format_iso8601(millis, &mut out);
+            let text = std::str::from_utf8(&out).unwrap();
+            assert_eq!(parse_iso8601(text), Some(millis), "{text}");
+        }
+    }
+}
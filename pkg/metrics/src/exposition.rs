@@ -0,0 +1,127 @@
+// Junta o texto de vários processos (load-balance + workers) num único documento,
+// cada família aparece uma vez com as amostras de todos.
+
+struct Family {
+    name: String,
+    header: Vec<String>,
+    samples: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Exposition {
+    families: Vec<Family>,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_text(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                let (Some(keyword), Some(name)) = (parts.next(), parts.next()) else {
+                    continue;
+                };
+                if keyword != "HELP" && keyword != "TYPE" {
+                    continue;
+                }
+
+                let family = self.family(name);
+                if !family
+                    .header
+                    .iter()
+                    .any(|h| h.starts_with(&format!("# {keyword} ")))
+                {
+                    family.header.push(line.to_string());
+                }
+                continue;
+            }
+
+            let sample_name = line
+                .split(['{', ' '])
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            let family_name = self.family_of(&sample_name);
+            self.family(&family_name).samples.push(line.to_string());
+        }
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        for family in &self.families {
+            for line in family.header.iter().chain(family.samples.iter()) {
+                out.extend_from_slice(line.as_bytes());
+                out.push(b'\n');
+            }
+        }
+    }
+
+    fn family(&mut self, name: &str) -> &mut Family {
+        let index = match self.families.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => {
+                self.families.push(Family {
+                    name: name.to_string(),
+                    header: Vec::with_capacity(2),
+                    samples: Vec::new(),
+                });
+                self.families.len() - 1
+            }
+        };
+
+        &mut self.families[index]
+    }
+
+    // Amostras de histograma têm sufixo, a família é o nome sem ele
+    fn family_of(&self, sample_name: &str) -> String {
+        if self.families.iter().any(|f| f.name == sample_name) {
+            return sample_name.to_string();
+        }
+
+        for suffix in ["_bucket", "_sum", "_count"] {
+            if let Some(base) = sample_name.strip_suffix(suffix)
+                && self.families.iter().any(|f| f.name == base)
+            {
+                return base.to_string();
+            }
+        }
+
+        sample_name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge_families() {
+        let lb = "# HELP http_total Requests\n# TYPE http_total counter\nhttp_total{route=\"payments\"} 10\n";
+        let api1 = "# HELP queue_depth Depth\n# TYPE queue_depth gauge\nqueue_depth{worker=\"api1\"} 2\n# HELP lat Latency\n# TYPE lat histogram\nlat_bucket{worker=\"api1\",le=\"+Inf\"} 1\nlat_sum{worker=\"api1\"} 0.1\nlat_count{worker=\"api1\"} 1\n";
+        let api2 = "# HELP queue_depth Depth\n# TYPE queue_depth gauge\nqueue_depth{worker=\"api2\"} 5\n# HELP lat Latency\n# TYPE lat histogram\nlat_count{worker=\"api2\"} 0\n";
+
+        let mut exposition = Exposition::new();
+        exposition.add_text(lb);
+        exposition.add_text(api1);
+        exposition.add_text(api2);
+
+        let mut out = Vec::new();
+        exposition.write(&mut out);
+        let text = String::from_utf8(out).unwrap();
+
+        assert_eq!(text.matches("# TYPE queue_depth gauge").count(), 1);
+        assert_eq!(text.matches("# TYPE lat histogram").count(), 1);
+        let api1_depth = text.find("queue_depth{worker=\"api1\"} 2").unwrap();
+        let api2_depth = text.find("queue_depth{worker=\"api2\"} 5").unwrap();
+        let lat = text.find("# HELP lat ").unwrap();
+        assert!(api1_depth < api2_depth && api2_depth < lat);
+        assert!(text.find("lat_count{worker=\"api2\"} 0").unwrap() > lat);
+    }
+}
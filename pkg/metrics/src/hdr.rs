@@ -0,0 +1,279 @@
+use std::{
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{Labels, Metric, SHARDS, shard, write_labels};
+
+// Histograma log-linear no estilo HDR: 128 valores exatos e depois 64 sub-buckets
+// por potência de 2, erro relativo de no máximo 1/64 (~1.6%) em microssegundos.
+
+const SUB_BITS: u32 = 6;
+const SUB_COUNT: usize = 1 << SUB_BITS; // 64
+const LINEAR: usize = SUB_COUNT * 2; // 0..128 exatos
+const MAX_EXP: u32 = 36; // ~19 horas em µs, acima disso vai pro último bucket
+const LEN: usize = LINEAR + (MAX_EXP - SUB_BITS) as usize * SUB_COUNT;
+
+pub const QUANTILES: [f64; 5] = [0.5, 0.9, 0.99, 0.999, 1.0];
+
+static HDRS: Mutex<Vec<&'static Hdr>> = Mutex::new(Vec::new());
+
+pub struct Hdr {
+    name: &'static str,
+    help: &'static str,
+    labels: Labels,
+    shards: [Shard; SHARDS],
+}
+
+// Parte de uma thread, as leituras juntam todas
+#[repr(align(64))]
+struct Shard {
+    counts: [AtomicU64; LEN],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+#[inline(always)]
+fn index_of(value: u64) -> usize {
+    if value < LINEAR as u64 {
+        return value as usize;
+    }
+
+    let exp = 63 - value.leading_zeros(); // >= 7
+    let shift = exp - SUB_BITS;
+    let sub = (value >> shift) as usize - SUB_COUNT; // 0..64
+    (LINEAR + (shift - 1) as usize * SUB_COUNT + sub).min(LEN - 1)
+}
+
+// Maior valor que cai no mesmo bucket
+fn highest_of(index: usize) -> u64 {
+    if index < LINEAR {
+        return index as u64;
+    }
+
+    let shift = ((index - LINEAR) / SUB_COUNT) as u32 + 1;
+    let sub = ((index - LINEAR) % SUB_COUNT + SUB_COUNT) as u64;
+    ((sub + 1) << shift) - 1
+}
+
+impl Hdr {
+    pub const fn new(name: &'static str, help: &'static str, labels: Labels) -> Self {
+        Hdr {
+            name,
+            help,
+            labels,
+            shards: [const {
+                Shard {
+                    counts: [const { AtomicU64::new(0) }; LEN],
+                    count: AtomicU64::new(0),
+                    sum: AtomicU64::new(0),
+                    max: AtomicU64::new(0),
+                }
+            }; SHARDS],
+        }
+    }
+
+    #[inline(always)]
+    pub fn record(&self, micros: u64) {
+        let shard = &self.shards[shard()];
+        shard.counts[index_of(micros)].fetch_add(1, Ordering::Relaxed);
+        shard.count.fetch_add(1, Ordering::Relaxed);
+        shard.sum.fetch_add(micros, Ordering::Relaxed);
+        shard.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn record_since(&self, start: std::time::Instant) {
+        self.record(start.elapsed().as_micros() as u64);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.max.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn sum(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.sum.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    // Percentil em microssegundos, q entre 0 e 1
+    pub fn percentile(&self, q: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+
+        let target = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut cumulative = 0;
+        for index in 0..LEN {
+            cumulative += self
+                .shards
+                .iter()
+                .map(|shard| shard.counts[index].load(Ordering::Relaxed))
+                .sum::<u64>();
+            if cumulative >= target {
+                return highest_of(index).min(self.max());
+            }
+        }
+
+        self.max()
+    }
+
+    pub fn reset(&self) {
+        for shard in &self.shards {
+            shard
+                .counts
+                .iter()
+                .for_each(|count| count.store(0, Ordering::Relaxed));
+            shard.count.store(0, Ordering::Relaxed);
+            shard.sum.store(0, Ordering::Relaxed);
+            shard.max.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Metric for Hdr {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn kind(&self) -> &'static str {
+        "summary"
+    }
+
+    fn write_samples(&self, out: &mut String, extra: Option<(&str, &str)>) {
+        for q in QUANTILES {
+            out.push_str(self.name);
+            let quantile = q.to_string();
+            write_labels(out, self.labels, extra, Some(("quantile", &quantile)));
+            let _ = writeln!(out, " {}", self.percentile(q) as f64 / 1_000_000.0);
+        }
+
+        let _ = write!(out, "{}_sum", self.name);
+        write_labels(out, self.labels, extra, None);
+        let _ = writeln!(out, " {}", self.sum() as f64 / 1_000_000.0);
+
+        let _ = write!(out, "{}_count", self.name);
+        write_labels(out, self.labels, extra, None);
+        let _ = writeln!(out, " {}", self.count());
+    }
+}
+
+// Entra no /metrics e no dump de encerramento
+pub fn register(hdrs: &[&'static Hdr]) {
+    let all: Vec<&'static dyn Metric> = hdrs.iter().map(|h| *h as &dyn Metric).collect();
+    crate::register(&all);
+
+    let mut registry = HDRS.lock().expect("hdr registry poisoned");
+    for hdr in hdrs {
+        if !registry
+            .iter()
+            .any(|registered| std::ptr::eq(*registered, *hdr))
+        {
+            registry.push(hdr);
+        }
+    }
+}
+
+// Uma linha por histograma com os percentis em µs, para imprimir no shutdown
+pub fn dump() -> String {
+    let registry = HDRS.lock().expect("hdr registry poisoned");
+    let mut out = String::with_capacity(registry.len() * 128);
+
+    for hdr in registry.iter() {
+        out.push_str(hdr.name);
+        write_labels(&mut out, hdr.labels, None, None);
+        let _ = write!(out, " count={}", hdr.count());
+        for q in QUANTILES {
+            let _ = write!(out, " p{}={}us", q * 100.0, hdr.percentile(q));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_bounds() {
+        for value in [0, 1, 127, 128, 129, 255, 256, 1_000, 999_999, 60_000_000] {
+            let index = index_of(value);
+            assert!(highest_of(index) >= value, "{value}");
+            if index > 0 {
+                assert!(highest_of(index - 1) < value, "{value}");
+            }
+            // erro relativo de no máximo 1/64
+            assert!(highest_of(index) - value <= value / 64, "{value}");
+        }
+
+        assert_eq!(index_of(u64::MAX), LEN - 1);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        static LATENCY: Hdr = Hdr::new("test_hdr_seconds", "Latency", &[("stage", "total")]);
+        for micros in 1..=1000 {
+            LATENCY.record(micros);
+        }
+
+        assert_eq!(LATENCY.count(), 1000);
+        assert_eq!(LATENCY.percentile(0.0), 1);
+        assert_eq!(LATENCY.percentile(1.0), 1000);
+        let p50 = LATENCY.percentile(0.5);
+        assert!((500..=508).contains(&p50), "{p50}");
+        let p99 = LATENCY.percentile(0.99);
+        assert!((990..=1000).contains(&p99), "{p99}");
+
+        register(&[&LATENCY]);
+        let dump = dump();
+        assert!(dump.contains("test_hdr_seconds{stage=\"total\"} count=1000"));
+        assert!(dump.contains("p100=1000us"));
+
+        let text = crate::render(None);
+        assert!(text.contains("# TYPE test_hdr_seconds summary"));
+        assert!(text.contains("test_hdr_seconds{stage=\"total\",quantile=\"1\"} 0.001\n"));
+        assert!(text.contains("test_hdr_seconds_count{stage=\"total\"} 1000\n"));
+
+        LATENCY.reset();
+        assert_eq!(LATENCY.percentile(0.99), 0);
+    }
+
+    #[test]
+    fn test_merge_threads() {
+        static LATENCY: Hdr = Hdr::new("test_merge_seconds", "Latency", &[]);
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                std::thread::spawn(move || (1..=250).for_each(|v| LATENCY.record(t * 250 + v)))
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        assert_eq!(LATENCY.count(), 1000);
+        assert_eq!(LATENCY.max(), 1000);
+        assert!((500..=508).contains(&LATENCY.percentile(0.5)));
+    }
+}
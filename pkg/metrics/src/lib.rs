@@ -0,0 +1,375 @@
+use std::{
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+pub use crate::exposition::Exposition;
+pub use crate::hdr::Hdr;
+
+mod exposition;
+pub mod hdr;
+
+// Métricas são statics declaradas em cada crate, o hot path só faz fetch_add.
+// O registro acontece uma vez no start e só é lido para renderizar o /metrics.
+
+pub type Labels = &'static [(&'static str, &'static str)];
+
+// Counter e Hdr guardam uma cópia por thread e somam na leitura, assim as
+// threads do load balancer não disputam a mesma linha de cache. Acima de
+// SHARDS threads as cópias passam a ser divididas.
+pub const SHARDS: usize = 8;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+#[inline(always)]
+fn shard() -> usize {
+    SHARD.with(|shard| *shard)
+}
+
+#[repr(align(64))]
+struct Padded(AtomicU64);
+
+pub trait Metric: Sync {
+    fn name(&self) -> &'static str;
+    fn help(&self) -> &'static str;
+    fn kind(&self) -> &'static str;
+    fn write_samples(&self, out: &mut String, extra: Option<(&str, &str)>);
+}
+
+static REGISTRY: Mutex<Vec<&'static dyn Metric>> = Mutex::new(Vec::new());
+
+pub fn register(metrics: &[&'static dyn Metric]) {
+    let mut registry = REGISTRY.lock().expect("metrics registry poisoned");
+    for metric in metrics {
+        if !registry
+            .iter()
+            .any(|registered| std::ptr::addr_eq(*registered, *metric))
+        {
+            registry.push(*metric);
+        }
+    }
+}
+
+// Formato texto do prometheus, extra é um label adicionado em todas as amostras
+pub fn render(extra: Option<(&str, &str)>) -> String {
+    let registry = REGISTRY.lock().expect("metrics registry poisoned");
+    let mut out = String::with_capacity(4096);
+    let mut written: Vec<&str> = Vec::with_capacity(registry.len());
+
+    for metric in registry.iter() {
+        if written.contains(&metric.name()) {
+            continue;
+        }
+        written.push(metric.name());
+
+        let _ = writeln!(out, "# HELP {} {}", metric.name(), metric.help());
+        let _ = writeln!(out, "# TYPE {} {}", metric.name(), metric.kind());
+        registry
+            .iter()
+            .filter(|other| other.name() == metric.name())
+            .for_each(|other| other.write_samples(&mut out, extra));
+    }
+
+    out
+}
+
+// last fica sempre por último, é o le do histograma ou o quantile do summary
+fn write_labels(
+    out: &mut String,
+    labels: Labels,
+    extra: Option<(&str, &str)>,
+    last: Option<(&str, &str)>,
+) {
+    if labels.is_empty() && extra.is_none() && last.is_none() {
+        return;
+    }
+
+    out.push('{');
+    let mut first = true;
+    let pairs = labels.iter().copied().chain(extra).chain(last);
+    for (key, value) in pairs {
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        let _ = write!(out, "{key}=\"{value}\"");
+    }
+    out.push('}');
+}
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: Labels,
+    values: [Padded; SHARDS],
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str, labels: Labels) -> Self {
+        Counter {
+            name,
+            help,
+            labels,
+            values: [const { Padded(AtomicU64::new(0)) }; SHARDS],
+        }
+    }
+
+    #[inline(always)]
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    #[inline(always)]
+    pub fn add(&self, value: u64) {
+        self.values[shard()].0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.values
+            .iter()
+            .map(|value| value.0.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+impl Metric for Counter {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn kind(&self) -> &'static str {
+        "counter"
+    }
+
+    fn write_samples(&self, out: &mut String, extra: Option<(&str, &str)>) {
+        out.push_str(self.name);
+        write_labels(out, self.labels, extra, None);
+        let _ = writeln!(out, " {}", self.get());
+    }
+}
+
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    labels: Labels,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    pub const fn new(name: &'static str, help: &'static str, labels: Labels) -> Self {
+        Gauge {
+            name,
+            help,
+            labels,
+            value: AtomicI64::new(0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn add(&self, value: i64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn set(&self, value: i64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Gauge {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn kind(&self) -> &'static str {
+        "gauge"
+    }
+
+    fn write_samples(&self, out: &mut String, extra: Option<(&str, &str)>) {
+        out.push_str(self.name);
+        write_labels(out, self.labels, extra, None);
+        let _ = writeln!(out, " {}", self.get());
+    }
+}
+
+// Limites em microssegundos, renderizados em segundos como o prometheus espera
+pub const LATENCY_BOUNDS: &[u64] = &[
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000,
+];
+
+const MAX_BUCKETS: usize = 16;
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: Labels,
+    bounds: &'static [u64],
+    buckets: [AtomicU64; MAX_BUCKETS], // não cumulativo, o último é o +Inf
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: Labels,
+        bounds: &'static [u64],
+    ) -> Self {
+        assert!(bounds.len() < MAX_BUCKETS);
+        Histogram {
+            name,
+            help,
+            labels,
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; MAX_BUCKETS],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn observe(&self, micros: u64) {
+        let index = self.bounds.partition_point(|bound| *bound < micros);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Histogram {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn kind(&self) -> &'static str {
+        "histogram"
+    }
+
+    fn write_samples(&self, out: &mut String, extra: Option<(&str, &str)>) {
+        let mut cumulative = 0;
+        for i in 0..=self.bounds.len() {
+            cumulative += self.buckets[i].load(Ordering::Relaxed);
+            let le = match self.bounds.get(i) {
+                Some(bound) => format!("{}", *bound as f64 / 1_000_000.0),
+                None => "+Inf".to_string(),
+            };
+
+            let _ = write!(out, "{}_bucket", self.name);
+            write_labels(out, self.labels, extra, Some(("le", &le)));
+            let _ = writeln!(out, " {cumulative}");
+        }
+
+        let _ = write!(out, "{}_sum", self.name);
+        write_labels(out, self.labels, extra, None);
+        let _ = writeln!(
+            out,
+            " {}",
+            self.sum.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+
+        let _ = write!(out, "{}_count", self.name);
+        write_labels(out, self.labels, extra, None);
+        let _ = writeln!(out, " {}", self.count());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static REQUESTS_OK: Counter = Counter::new(
+        "test_requests_total",
+        "Requests handled",
+        &[("status", "200")],
+    );
+    static REQUESTS_BAD: Counter = Counter::new(
+        "test_requests_total",
+        "Requests handled",
+        &[("status", "400")],
+    );
+    static DEPTH: Gauge = Gauge::new("test_queue_depth", "Queue depth", &[]);
+    static LATENCY: Histogram =
+        Histogram::new("test_latency_seconds", "Latency", &[], &[100, 1_000]);
+
+    #[test]
+    fn test_render() {
+        register(&[&REQUESTS_OK, &DEPTH, &REQUESTS_BAD, &LATENCY, &REQUESTS_OK]);
+        REQUESTS_OK.add(3);
+        REQUESTS_BAD.inc();
+        DEPTH.inc();
+        DEPTH.inc();
+        DEPTH.dec();
+        LATENCY.observe(50);
+        LATENCY.observe(100);
+        LATENCY.observe(5_000);
+
+        let text = render(Some(("worker", "api1")));
+        assert_eq!(
+            text.matches("# TYPE test_requests_total counter").count(),
+            1
+        );
+        assert!(text.contains("test_requests_total{status=\"200\",worker=\"api1\"} 3\n"));
+        assert!(text.contains("test_requests_total{status=\"400\",worker=\"api1\"} 1\n"));
+        assert!(text.contains("test_queue_depth{worker=\"api1\"} 1\n"));
+        assert!(text.contains("test_latency_seconds_bucket{worker=\"api1\",le=\"0.0001\"} 2\n"));
+        assert!(text.contains("test_latency_seconds_bucket{worker=\"api1\",le=\"0.001\"} 2\n"));
+        assert!(text.contains("test_latency_seconds_bucket{worker=\"api1\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("test_latency_seconds_count{worker=\"api1\"} 3\n"));
+
+        // o segundo total_requests vem logo depois do primeiro
+        let ok = text.find("status=\"200\"").unwrap();
+        let bad = text.find("status=\"400\"").unwrap();
+        let depth = text.find("# HELP test_queue_depth").unwrap();
+        assert!(ok < bad && bad < depth);
+    }
+
+    #[test]
+    fn test_counter_sums_threads() {
+        static HITS: Counter = Counter::new("test_hits_total", "Hits", &[]);
+        let threads: Vec<_> = (0..SHARDS + 2)
+            .map(|_| std::thread::spawn(|| (0..1000).for_each(|_| HITS.inc())))
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        assert_eq!(HITS.get(), (SHARDS as u64 + 2) * 1000);
+    }
+}
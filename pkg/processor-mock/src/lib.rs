@@ -0,0 +1,636 @@
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use message::{
+    http::{
+        parse::{self, Progress},
+        response::{Response, Status},
+    },
+    time,
+};
+
+// Processador de pagamentos falso no lugar da imagem
+// zanfranceschi/payment-processor, que não roda no CI: a mesma API usada pelo
+// worker e pelo resources/requests.js, com tudo em memória. Roda como binário
+// ou dentro do processo do teste, uma thread por conexão.
+
+pub const DEFAULT_TOKEN: &str = "123";
+
+const NO_CONTENT: Status = Status::new(204, "No Content");
+const UNAUTHORIZED: Status = Status::new(401, "Unauthorized");
+const UNPROCESSABLE: Status = Status::new(422, "Unprocessable Entity");
+// Acorda as threads de conexão para verem o stop
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub fee: u64, // centésimos: 5 = 0.05 por transação
+    pub token: String,
+    pub health_limit: Duration, // uma chamada ao service-health por intervalo
+}
+
+impl Options {
+    pub fn default_processor() -> Options {
+        Options {
+            fee: 5,
+            token: DEFAULT_TOKEN.to_string(),
+            health_limit: Duration::from_secs(5),
+        }
+    }
+
+    pub fn fallback_processor() -> Options {
+        Options {
+            fee: 15,
+            ..Options::default_processor()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Payment {
+    pub amount: u64, // centavos
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Summary {
+    pub requests: u64,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+struct State {
+    token: String,
+    delay: Duration,
+    failing: bool,
+    payments: HashMap<String, Payment>,
+    health_at: Option<Instant>,
+}
+
+pub struct Processor {
+    fee: u64,
+    health_limit: Duration,
+    state: Mutex<State>,
+}
+
+impl Processor {
+    pub fn new(options: Options) -> Processor {
+        Processor {
+            fee: options.fee,
+            health_limit: options.health_limit,
+            state: Mutex::new(State {
+                token: options.token,
+                delay: Duration::ZERO,
+                failing: false,
+                payments: HashMap::new(),
+                health_at: None,
+            }),
+        }
+    }
+
+    // Os mesmos controles do /admin, para testes no mesmo processo
+    pub fn set_failure(&self, failing: bool) {
+        self.state().failing = failing;
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.state().delay = delay;
+    }
+
+    pub fn purge(&self) {
+        self.state().payments.clear();
+    }
+
+    pub fn payment(&self, correlation_id: &str) -> Option<Payment> {
+        self.state().payments.get(correlation_id).copied()
+    }
+
+    // Janela inclusiva nas duas pontas, None é sem limite
+    pub fn summary(&self, from: Option<u64>, to: Option<u64>) -> Summary {
+        let state = self.state();
+        let mut summary = Summary::default();
+        for payment in state.payments.values() {
+            if from.is_some_and(|from| payment.requested_at < from)
+                || to.is_some_and(|to| payment.requested_at > to)
+            {
+                continue;
+            }
+            summary.requests += 1;
+            summary.amount += payment.amount;
+        }
+        // arredonda para o centavo mais próximo
+        summary.fee = (summary.amount * self.fee + 50) / 100;
+        summary
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn handle(&self, request: &[u8], out: &mut Vec<u8>) {
+        let head_end = request
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap_or(request.len());
+        let body = std::str::from_utf8(&request[(head_end + 4).min(request.len())..]).unwrap_or("");
+        let line = request.split(|b| *b == b'\r').next().unwrap_or_default();
+        let mut parts = line.split(|b| *b == b' ');
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = match target.iter().position(|b| *b == b'?') {
+            Some(at) => (&target[..at], &target[at + 1..]),
+            None => (target, &b""[..]),
+        };
+
+        if path.starts_with(b"/admin/") {
+            let token = parse::header(request, b"x-rinha-token").unwrap_or_default();
+            if token != self.state().token.as_bytes() {
+                return Response::new(out, UNAUTHORIZED).empty();
+            }
+        }
+
+        match (method, path) {
+            (b"POST", b"/payments") => self.pay(body, out),
+            (b"GET", b"/payments/service-health") => self.health(out),
+            (b"GET", b"/admin/payments-summary") => {
+                let (params, _) = parse::parse_params(query);
+                let at = |name: &str| params.get(name).and_then(|v| time::parse_iso8601(v));
+                let summary = self.summary(at("from"), at("to"));
+                Response::new(out, Status::OK).json(|json| {
+                    json.begin_object()
+                        .key("totalRequests")
+                        .u64(summary.requests)
+                        .key("totalAmount")
+                        .cents(summary.amount)
+                        .key("totalFee")
+                        .cents(summary.fee)
+                        .key("feePerTransaction")
+                        .cents(self.fee)
+                        .end_object();
+                })
+            }
+            (b"PUT", b"/admin/configurations/token") => match field(body, "token") {
+                Some(token) => {
+                    self.state().token = token.to_string();
+                    // o requests.js espera 204 aqui e 200 nos outros
+                    Response::new(out, NO_CONTENT).empty()
+                }
+                None => Response::new(out, Status::BAD_REQUEST).empty(),
+            },
+            (b"PUT", b"/admin/configurations/delay") => {
+                match field(body, "delay").and_then(|ms| ms.parse::<u64>().ok()) {
+                    Some(ms) => {
+                        self.set_delay(Duration::from_millis(ms));
+                        Response::new(out, Status::OK).empty()
+                    }
+                    None => Response::new(out, Status::BAD_REQUEST).empty(),
+                }
+            }
+            (b"PUT", b"/admin/configurations/failure") => {
+                match field(body, "failure").and_then(|value| value.parse::<bool>().ok()) {
+                    Some(failing) => {
+                        self.set_failure(failing);
+                        Response::new(out, Status::OK).empty()
+                    }
+                    None => Response::new(out, Status::BAD_REQUEST).empty(),
+                }
+            }
+            (b"POST", b"/admin/purge-payments") => {
+                self.purge();
+                Response::new(out, Status::OK).json(|json| {
+                    json.begin_object()
+                        .key("message")
+                        .string("All payments purged.")
+                        .end_object();
+                })
+            }
+            (b"GET", _) if path.starts_with(b"/payments/") => {
+                let id = String::from_utf8_lossy(&path[b"/payments/".len()..]);
+                match self.payment(&id) {
+                    Some(payment) => {
+                        let mut requested_at = [0u8; time::ISO_LEN];
+                        time::format_iso8601(payment.requested_at, &mut requested_at);
+                        Response::new(out, Status::OK).json(|json| {
+                            json.begin_object()
+                                .key("correlationId")
+                                .string(&id)
+                                .key("amount")
+                                .cents(payment.amount)
+                                .key("requestedAt")
+                                .string(std::str::from_utf8(&requested_at).unwrap_or_default())
+                                .end_object();
+                        })
+                    }
+                    None => Response::new(out, Status::NOT_FOUND).empty(),
+                }
+            }
+            _ => Response::new(out, Status::NOT_FOUND).empty(),
+        }
+    }
+
+    fn pay(&self, body: &str, out: &mut Vec<u8>) {
+        let correlation_id = field(body, "correlationId").filter(|id| !id.is_empty());
+        let amount = field(body, "amount").and_then(cents);
+        let requested_at = field(body, "requestedAt").and_then(time::parse_iso8601);
+        let (Some(correlation_id), Some(amount), Some(requested_at)) =
+            (correlation_id, amount, requested_at)
+        else {
+            return Response::new(out, Status::BAD_REQUEST).empty();
+        };
+
+        // o delay vale para a resposta inteira, sem segurar o lock
+        let (delay, failing) = {
+            let state = self.state();
+            (state.delay, state.failing)
+        };
+        std::thread::sleep(delay);
+        if failing {
+            return Response::new(out, Status::INTERNAL_SERVER_ERROR).empty();
+        }
+
+        let mut state = self.state();
+        if state.payments.contains_key(correlation_id) {
+            drop(state);
+            return Response::new(out, UNPROCESSABLE).empty();
+        }
+        state.payments.insert(
+            correlation_id.to_string(),
+            Payment {
+                amount,
+                requested_at,
+            },
+        );
+        drop(state);
+
+        Response::new(out, Status::OK).json(|json| {
+            json.begin_object()
+                .key("message")
+                .string("payment processed successfully")
+                .end_object();
+        })
+    }
+
+    fn health(&self, out: &mut Vec<u8>) {
+        let mut state = self.state();
+        let now = Instant::now();
+        if state
+            .health_at
+            .is_some_and(|at| now.duration_since(at) < self.health_limit)
+        {
+            drop(state);
+            return Response::new(out, Status::TOO_MANY_REQUESTS).empty();
+        }
+        state.health_at = Some(now);
+        let (failing, delay) = (state.failing, state.delay);
+        drop(state);
+
+        Response::new(out, Status::OK).json(|json| {
+            json.begin_object()
+                .key("failing")
+                .bool(failing)
+                .key("minResponseTime")
+                .u64(delay.as_millis() as u64)
+                .end_object();
+        })
+    }
+}
+
+// Valor cru de um campo: string sem as aspas, número ou bool. Basta para os
+// corpos pequenos e planos que a API recebe.
+fn field<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    let at = body.find(&format!("\"{key}\""))? + key.len() + 2;
+    let value = body[at..].trim_start().strip_prefix(':')?.trim_start();
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next(),
+        None => value.split([',', '}']).next().map(str::trim),
+    }
+}
+
+// "19.9" -> 1990, casas depois da segunda são ignoradas
+fn cents(value: &str) -> Option<u64> {
+    let (units, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if units.is_empty()
+        || !units
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let mut cents = units.parse::<u64>().ok()?.checked_mul(100)?;
+    for (i, digit) in fraction.bytes().take(2).enumerate() {
+        cents += (digit - b'0') as u64 * if i == 0 { 10 } else { 1 };
+    }
+    Some(cents)
+}
+
+pub struct Handle {
+    pub addr: SocketAddr,
+    pub processor: Arc<Processor>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Handle {
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // o accept está bloqueado, uma conexão qualquer acorda
+        let _ = TcpStream::connect(self.addr);
+        self.join();
+    }
+
+    pub fn join(self) {
+        if self.thread.join().is_err() {
+            logger::error!("Processor mock thread panicked");
+        }
+    }
+}
+
+// Porta 0 escolhe uma livre, o endereço de verdade fica no Handle
+pub fn spawn(addr: &str, options: Options) -> std::io::Result<Handle> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    let processor = Arc::new(Processor::new(options));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let thread = {
+        let processor = processor.clone();
+        let stop = stop.clone();
+        std::thread::Builder::new()
+            .name("processor-mock".to_string())
+            .spawn(move || accept(listener, processor, stop))?
+    };
+
+    Ok(Handle {
+        addr,
+        processor,
+        stop,
+        thread,
+    })
+}
+
+fn accept(listener: TcpListener, processor: Arc<Processor>, stop: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                logger::warn!("Processor mock accept failed: {e}");
+                continue;
+            }
+        };
+
+        let processor = processor.clone();
+        let stop = stop.clone();
+        let spawned = std::thread::Builder::new()
+            .name("processor-mock-conn".to_string())
+            .spawn(move || {
+                if let Err(e) = serve(stream, &processor, &stop) {
+                    logger::debug!("Processor mock connection closed: {e}");
+                }
+            });
+        if let Err(e) = spawned {
+            logger::warn!("Processor mock could not spawn a connection thread: {e}");
+        }
+    }
+}
+
+// Keep-alive até o cliente fechar, pedir close ou o mock parar
+fn serve(mut stream: TcpStream, processor: &Processor, stop: &AtomicBool) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(POLL))?;
+    let mut buffer = Vec::with_capacity(1024);
+    let mut out = Vec::with_capacity(512);
+    let mut chunk = [0u8; 1024];
+
+    while !stop.load(Ordering::Relaxed) {
+        if parse::progress(&buffer) != Progress::Complete {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+            continue;
+        }
+
+        let head_end = buffer.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let length = parse::header(&buffer, b"content-length")
+            .and_then(|length| std::str::from_utf8(length).ok())
+            .and_then(|length| length.parse::<usize>().ok())
+            .unwrap_or(0);
+        let request: Vec<u8> = buffer.drain(..head_end + length).collect();
+        let close = parse::header(&request, b"connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case(b"close"));
+
+        out.clear();
+        processor.handle(&request, &mut out);
+        if close {
+            // Connection: close vai no fim dos headers, antes do corpo
+            let at = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(0);
+            out.splice(at..at, b"\r\nConnection: close".iter().copied());
+        }
+        stream.write_all(&out)?;
+        if close {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Uma requisição por conexão, devolve status e corpo
+    fn request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        token: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: x\r\nX-Rinha-Token: {token}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    fn payment(id: &str, amount: &str, at: &str) -> String {
+        format!(r#"{{"correlationId":"{id}","amount":{amount},"requestedAt":"{at}"}}"#)
+    }
+
+    #[test]
+    fn test_payments() {
+        let mock = spawn("127.0.0.1:0", Options::default_processor()).unwrap();
+        let addr = mock.addr;
+        let post = |body: &str| request(addr, "POST", "/payments", "", body).0;
+
+        assert_eq!(
+            post(&payment("a", "19.90", "2025-07-10T12:34:56.000Z")),
+            200
+        );
+        assert_eq!(post(&payment("b", "0.1", "2025-07-10T12:35:56.000Z")), 200);
+        // mesmo correlationId de novo é 422, como no processador de verdade
+        assert_eq!(
+            post(&payment("a", "19.90", "2025-07-10T12:34:56.000Z")),
+            422
+        );
+        assert_eq!(post(r#"{"correlationId":"c","amount":1}"#), 400);
+        assert_eq!(post(&payment("d", "-1", "2025-07-10T12:34:56.000Z")), 400);
+
+        let (status, body) = request(addr, "GET", "/payments/a", "", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"correlationId":"a","amount":19.90,"requestedAt":"2025-07-10T12:34:56.000Z"}"#
+        );
+        assert_eq!(request(addr, "GET", "/payments/zzz", "", "").0, 404);
+
+        // falha e delay valem para os pagamentos e aparecem no health
+        mock.processor.set_failure(true);
+        assert_eq!(post(&payment("e", "1", "2025-07-10T12:34:56.000Z")), 500);
+        assert!(mock.processor.payment("e").is_none());
+        mock.processor.set_failure(false);
+        mock.processor.set_delay(Duration::from_millis(50));
+        let started = Instant::now();
+        assert_eq!(post(&payment("e", "1", "2025-07-10T12:34:56.000Z")), 200);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let (status, body) = request(addr, "GET", "/payments/service-health", "", "");
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"failing":false,"minResponseTime":50}"#);
+        // uma chamada a cada 5s
+        assert_eq!(
+            request(addr, "GET", "/payments/service-health", "", "").0,
+            429
+        );
+        mock.stop();
+    }
+
+    #[test]
+    fn test_admin() {
+        let mock = spawn("127.0.0.1:0", Options::fallback_processor()).unwrap();
+        let addr = mock.addr;
+        let put = |path: &str, token: &str, body: &str| request(addr, "PUT", path, token, body).0;
+
+        for (id, at) in [
+            ("a", "2025-07-10T12:00:00.000Z"),
+            ("b", "2025-07-10T13:00:00.000Z"),
+        ] {
+            let body = payment(id, "19.90", at);
+            assert_eq!(request(addr, "POST", "/payments", "", &body).0, 200);
+        }
+
+        assert_eq!(
+            request(addr, "GET", "/admin/payments-summary", "", "").0,
+            401
+        );
+        let (status, body) = request(addr, "GET", "/admin/payments-summary", "123", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"totalRequests":2,"totalAmount":39.80,"totalFee":5.97,"feePerTransaction":0.15}"#
+        );
+        let (_, body) = request(
+            addr,
+            "GET",
+            "/admin/payments-summary?from=2025-07-10T12%3A30%3A00.000Z&to=2025-07-10T13%3A00%3A00.000Z",
+            "123",
+            "",
+        );
+        assert!(
+            body.starts_with(r#"{"totalRequests":1,"totalAmount":19.90"#),
+            "{body}"
+        );
+
+        assert_eq!(
+            put("/admin/configurations/delay", "123", r#"{"delay": 20}"#),
+            200
+        );
+        assert_eq!(
+            put(
+                "/admin/configurations/failure",
+                "123",
+                r#"{"failure":true}"#
+            ),
+            200
+        );
+        assert_eq!(
+            put("/admin/configurations/failure", "123", r#"{"failure":1}"#),
+            400
+        );
+        let (_, body) = request(addr, "GET", "/payments/service-health", "", "");
+        assert_eq!(body, r#"{"failing":true,"minResponseTime":20}"#);
+
+        // token novo vale a partir da próxima chamada
+        assert_eq!(
+            put("/admin/configurations/token", "123", r#"{"token":"456"}"#),
+            204
+        );
+        assert_eq!(
+            request(addr, "POST", "/admin/purge-payments", "123", "").0,
+            401
+        );
+        assert_eq!(
+            request(addr, "POST", "/admin/purge-payments", "456", "").0,
+            200
+        );
+        assert_eq!(mock.processor.summary(None, None), Summary::default());
+        mock.stop();
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let mock = spawn("127.0.0.1:0", Options::default_processor()).unwrap();
+        let mut stream = TcpStream::connect(mock.addr).unwrap();
+        // duas requisições no mesmo write, respostas na ordem
+        let first = payment("a", "1.00", "2025-07-10T12:00:00.000Z");
+        let second = payment("a", "1.00", "2025-07-10T12:00:00.000Z");
+        write!(
+            stream,
+            "POST /payments HTTP/1.1\r\nContent-Length: {}\r\n\r\n{first}POST /payments HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{second}",
+            first.len(),
+            second.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let statuses: Vec<_> = response
+            .match_indices("HTTP/1.1 ")
+            .map(|(at, _)| &response[at + 9..at + 12])
+            .collect();
+        assert_eq!(statuses, ["200", "422"]);
+        mock.stop();
+    }
+
+    #[test]
+    fn test_cents() {
+        assert_eq!(cents("19.9"), Some(1990));
+        assert_eq!(cents("19"), Some(1900));
+        assert_eq!(cents("0.015"), Some(1));
+        assert_eq!(cents(".5"), None);
+        assert_eq!(cents("1e3"), None);
+    }
+}
@@ -0,0 +1,41 @@
+use processor_mock::Options;
+
+const USAGE: &str = "usage: processor-mock [--port PORT] [--fallback] [--token TOKEN]
+
+  --port PORT     HTTP port, PORT in the environment (default 8080)
+  --fallback      Fallback processor fee (0.15 instead of 0.05)
+  --token TOKEN   X-Rinha-Token of the /admin endpoints (default 123)
+";
+
+fn main() {
+    let mut port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+    let mut options = Options::default_processor();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = args.next().unwrap_or_default(),
+            "--token" => options.token = args.next().unwrap_or_default(),
+            "--fallback" => options.fee = Options::fallback_processor().fee,
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return;
+            }
+            other => {
+                eprintln!("error: unknown argument {other}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    logger::init("info");
+    let handle = match processor_mock::spawn(&format!("0.0.0.0:{port}"), options) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("error: unable to listen on port {port}: {e}");
+            std::process::exit(1);
+        }
+    };
+    logger::info!("Processor mock listening on: {}", handle.addr);
+    handle.join();
+}
@@ -0,0 +1,256 @@
+use std::{cmp::Ordering, fmt};
+
+// Decimal exato no lugar do big.js: mantissa inteira e casas decimais.
+// Cobre o que o handleSummary faz (plus, minus, times, round(2)) com
+// qualquer valor que caiba em 38 dígitos, bem mais que um placar precisa.
+// Overflow vira None em vez de resultado errado.
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    units: i128,
+    scale: u32, // units / 10^scale
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { units: 0, scale: 0 };
+
+    pub fn new(units: i128, scale: u32) -> Decimal {
+        Decimal { units, scale }.normalized()
+    }
+
+    // Como Big(string): "19.90", "-0.5", "1e-7", "2.5E+3"
+    pub fn parse(value: &str) -> Option<Decimal> {
+        let value = value.trim();
+        let (mantissa, exponent) = match value.find(['e', 'E']) {
+            Some(at) => (&value[..at], value[at + 1..].parse::<i32>().ok()?),
+            None => (value, 0),
+        };
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        let mut units: i128 = 0;
+        for digit in whole.bytes().chain(fraction.bytes()) {
+            if !digit.is_ascii_digit() {
+                return None;
+            }
+            units = units.checked_mul(10)?.checked_add((digit - b'0') as i128)?;
+        }
+
+        let scale = fraction.len() as i32 - exponent;
+        let decimal = match scale {
+            ..0 => Decimal::new(units.checked_mul(pow10(scale.unsigned_abs())?)?, 0),
+            _ => Decimal::new(units, scale as u32),
+        };
+        Some(match negative {
+            true => decimal.negated(),
+            false => decimal,
+        })
+    }
+
+    // Como Big(number): os dígitos que o JS imprime para o number, não o
+    // valor binário exato. Big(0.1) é 0.1, não 0.1000000000000000055...
+    pub fn from_f64(value: f64) -> Option<Decimal> {
+        if !value.is_finite() {
+            return None;
+        }
+        Decimal::parse(&format!("{value:e}"))
+    }
+
+    // Como Big.toNumber(): o double mais próximo do decimal
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn plus(&self, other: &Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn minus(&self, other: &Decimal) -> Option<Decimal> {
+        self.plus(&other.negated())
+    }
+
+    pub fn times(&self, other: &Decimal) -> Option<Decimal> {
+        Some(Decimal::new(
+            self.units.checked_mul(other.units)?,
+            self.scale.checked_add(other.scale)?,
+        ))
+    }
+
+    // ROUND_HALF_UP do big.js: empate vai para longe do zero
+    pub fn round(&self, places: u32) -> Decimal {
+        if self.scale <= places {
+            return *self;
+        }
+        let Some(divisor) = pow10(self.scale - places) else {
+            return Decimal::ZERO;
+        };
+        let quotient = self.units / divisor;
+        let remainder = (self.units % divisor).abs();
+        let rounded = match remainder * 2 >= divisor {
+            true => quotient + self.units.signum(),
+            false => quotient,
+        };
+        Decimal::new(rounded, places)
+    }
+
+    pub fn max(self, other: Decimal) -> Decimal {
+        match self.cmp(&other) {
+            Ordering::Less => other,
+            _ => self,
+        }
+    }
+
+    fn negated(&self) -> Decimal {
+        Decimal {
+            units: -self.units,
+            scale: self.scale,
+        }
+    }
+
+    fn aligned(&self, other: &Decimal) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((
+            self.units.checked_mul(pow10(scale - self.scale)?)?,
+            other.units.checked_mul(pow10(scale - other.scale)?)?,
+            scale,
+        ))
+    }
+
+    // Sem zeros à direita, assim 19.90 e 19.9 são a mesma representação
+    fn normalized(mut self) -> Decimal {
+        if self.units == 0 {
+            return Decimal::ZERO;
+        }
+        while self.scale > 0 && self.units % 10 == 0 {
+            self.units /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        match self.aligned(other) {
+            Some((a, b, _)) => a.cmp(&b),
+            // não alinha sem overflow: a parte inteira decide
+            None => (self.units / pow10(self.scale).unwrap_or(i128::MAX))
+                .cmp(&(other.units / pow10(other.scale).unwrap_or(i128::MAX))),
+        }
+    }
+}
+
+// Notação normal do Big.toString(), sem zeros à direita
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.units.unsigned_abs().to_string();
+        let sign = if self.units < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{sign}{digits}");
+        }
+        match digits.len().checked_sub(scale) {
+            Some(0) | None => write!(f, "{sign}0.{digits:0>scale$}"),
+            Some(whole) => write!(f, "{sign}{}.{}", &digits[..whole], &digits[whole..]),
+        }
+    }
+}
+
+fn pow10(exponent: u32) -> Option<i128> {
+    10i128.checked_pow(exponent)
+}
+
+// Como o JS imprime um number: dígitos mínimos que voltam ao mesmo double,
+// notação científica só abaixo de 1e-6 ou a partir de 1e21
+pub fn js_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let magnitude = value.abs();
+    if (1e-6..1e21).contains(&magnitude) {
+        return format!("{value}");
+    }
+    let scientific = format!("{value:e}");
+    match scientific.split_once('e') {
+        Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+            format!("{mantissa}e+{exponent}")
+        }
+        _ => scientific,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::parse(value).unwrap()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // onde o f64 erra e o big.js não
+        assert_eq!(0.1 + 0.2, 0.30000000000000004);
+        assert_eq!(dec("0.1").plus(&dec("0.2")).unwrap(), dec("0.3"));
+        assert_eq!(dec("0.05").times(&dec("995")).unwrap().to_string(), "49.75");
+        assert_eq!(dec("10").minus(&dec("10.25")).unwrap().to_string(), "-0.25");
+        assert_eq!(dec("19.90").to_string(), "19.9");
+        assert_eq!(dec("0.007").to_string(), "0.007");
+        assert_eq!(dec("2.5e3").to_string(), "2500");
+        assert_eq!(dec("1e-7").to_string(), "0.0000001");
+        assert!(Decimal::parse("1.2.3").is_none());
+        assert!(Decimal::parse("").is_none());
+        assert!(
+            dec("99999999999999999999")
+                .times(&dec("99999999999999999999"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_round_half_up() {
+        assert_eq!(dec("0.125").round(2), dec("0.13"));
+        assert_eq!(dec("0.124999").round(2), dec("0.12"));
+        assert_eq!(dec("-0.125").round(2), dec("-0.13"));
+        assert_eq!(dec("7").round(2), dec("7"));
+        // (11 - 0.78) * 0.02 no JS é 0.20440000000000003
+        let bonus = Decimal::from_f64((11.0 - 0.78) * 0.02).unwrap();
+        assert_eq!(bonus.to_string(), "0.20440000000000003");
+        assert_eq!(bonus.round(2).to_f64(), 0.2);
+    }
+
+    #[test]
+    fn test_js_number() {
+        assert_eq!(js_number(13014.6), "13014.6");
+        assert_eq!(js_number(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(js_number(-0.0), "0");
+        assert_eq!(js_number(550.0), "550");
+        assert_eq!(js_number(1e21), "1e+21");
+        assert_eq!(js_number(1.5e-7), "1.5e-7");
+        assert_eq!(
+            Decimal::from_f64(1e21).unwrap().to_string(),
+            "1000000000000000000000"
+        );
+    }
+}
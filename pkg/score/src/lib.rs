@@ -0,0 +1,305 @@
+use std::fmt::Write;
+
+pub use crate::decimal::{Decimal, js_number};
+
+mod decimal;
+
+// O handleSummary do resources/rinha.js em Rust, para o bench, a simulação e
+// quem mais precisar do placar. As contas seguem o JS linha a linha: cada
+// Big(...) vira Decimal exato e cada toNumber() volta para f64, então os
+// arredondamentos intermediários são os mesmos e o partial-results.json sai
+// igual byte a byte.
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Totals {
+    pub requests: u64,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    pub participant: String,
+    pub max_requests: u64,
+    pub success: u64, // POST /payments com 200, 201, 202 ou 204
+    pub failure: u64,
+    pub p99_ms: f64, // só das respostas esperadas (2xx e 3xx), como o k6
+    pub inconsistencies: u64,
+    // o que o backend respondeu no teardown e o feePerTransaction de cada processador
+    pub default: Totals,
+    pub fallback: Totals,
+    pub default_fee: f64,
+    pub fallback_fee: f64,
+}
+
+// Os valores como number do JS, prontos para imprimir
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    pub liquid: f64,
+    pub gross: f64,
+    pub fees: f64,
+    pub default_fees: f64,
+    pub fallback_fees: f64,
+    pub p99: f64,
+    pub bonus: f64,
+    pub fine: f64,
+    pub fine_total: f64,
+    pub lag: i64,
+}
+
+impl Inputs {
+    // None só se algum valor não couber no Decimal
+    pub fn score(&self) -> Option<Score> {
+        let big = Decimal::from_f64;
+
+        let gross = big(self.default.amount)?
+            .plus(&big(self.fallback.amount)?)?
+            .to_f64();
+        let default_fees = big(self.default_fee)?
+            .times(&big(self.default.amount)?)?
+            .to_f64();
+        let fallback_fees = big(self.fallback_fee)?
+            .times(&big(self.fallback.amount)?)?
+            .to_f64();
+        let fees = big(default_fees)?.plus(&big(fallback_fees)?)?.to_f64();
+
+        // a subtração do bônus é em number no JS, só o round é Big
+        let p99 = big(self.p99_ms)?.round(2).to_f64();
+        let bonus = big((11.0 - p99) * 0.02)?.round(2).to_f64().max(0.0);
+        let fine = if self.inconsistencies > 0 { 0.35 } else { 0.0 };
+
+        let partial = big(gross)?.minus(&big(fees)?)?.to_f64();
+        let liquid = big(partial)?
+            .plus(&big(partial)?.times(&big(bonus)?)?)?
+            .minus(&big(partial)?.times(&big(fine)?)?)?
+            .to_f64();
+
+        Some(Score {
+            liquid,
+            gross,
+            fees,
+            default_fees,
+            fallback_fees,
+            p99,
+            bonus,
+            fine,
+            fine_total: big(partial)?.times(&big(fine)?)?.to_f64(),
+            lag: self.success as i64 - (self.default.requests + self.fallback.requests) as i64,
+        })
+    }
+
+    // Mesmo texto do JSON.stringify(custom_data, null, 2)
+    pub fn partial_results(&self) -> Option<String> {
+        let score = self.score()?;
+        let bonus = Decimal::from_f64(score.bonus)?.times(&Decimal::new(100, 0))?;
+        let processed = self.default.requests + self.fallback.requests;
+
+        let mut out = String::with_capacity(4096);
+        let _ = write!(
+            out,
+            r#"{{
+  "participante": {},
+  "total_liquido": {},
+  "total_bruto": {},
+  "total_taxas": {},
+  "descricao": "'total_liquido' é sua pontuação final. Equivale ao seu lucro. Fórmula: total_liquido + (total_liquido * p99.bonus) - (total_liquido * multa.porcentagem)",
+  "p99": {{
+    "valor": "{}ms",
+    "bonus": "{}%",
+    "max_requests": {},
+    "descricao": "Fórmula para o bônus: max((11 - p99.valor) * 0.02, 0)"
+  }},
+  "multa": {{
+    "porcentagem": {},
+    "total": {},
+    "composicao": {{
+      "num_inconsistencias": {},
+      "descricao": "Se 'num_inconsistencias' > 0, há multa de 35%."
+    }}
+  }},
+  "caixa_dois": {{
+    "detectado": {},
+    "descricao": "Se 'lag' for negativo, significa que seu backend registrou mais pagamentos do que solicitado, automaticamente desclassificando sua submissão!"
+  }},
+  "lag": {{
+    "num_pagamentos_total": {processed},
+    "num_pagamentos_solicitados": {},
+    "lag": {},
+    "descricao": "Lag é a diferença entre a quantidade de solicitações de pagamentos e o que foi realmente computado pelo backend. Mostra a perda de pagamentos possivelmente por estarem enfileirados."
+  }},
+  "pagamentos_solicitados": {{
+    "qtd_sucesso": {},
+    "qtd_falha": {},
+    "descricao": "'qtd_sucesso' foram requests bem sucedidos para 'POST /payments' e 'qtd_falha' os requests com erro."
+  }},
+  "pagamentos_realizados_default": {{
+    "total_bruto": {},
+    "num_pagamentos": {},
+    "total_taxas": {},
+    "descricao": "Informações do backend sobre solicitações de pagamento para o Payment Processor Default."
+  }},
+  "pagamentos_realizados_fallback": {{
+    "total_bruto": {},
+    "num_pagamentos": {},
+    "total_taxas": {},
+    "descricao": "Informações do backend sobre solicitações de pagamento para o Payment Processor Fallback."
+  }}
+}}"#,
+            string(&self.participant),
+            js_number(score.liquid),
+            js_number(score.gross),
+            js_number(score.fees),
+            js_number(score.p99),
+            bonus,
+            self.max_requests,
+            js_number(score.fine),
+            js_number(score.fine_total),
+            self.inconsistencies,
+            score.lag < 0,
+            self.success,
+            score.lag,
+            self.success,
+            self.failure,
+            js_number(self.default.amount),
+            self.default.requests,
+            js_number(score.default_fees),
+            js_number(self.fallback.amount),
+            self.fallback.requests,
+            js_number(score.fallback_fees),
+        );
+        Some(out)
+    }
+}
+
+fn string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Percentil como o k6 calcula nas trends: interpolado entre os vizinhos
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    match sorted.len() {
+        0 => 0.0,
+        1 => sorted[0],
+        len => {
+            let rank = p * (len - 1) as f64;
+            let low = rank.floor() as usize;
+            let high = (low + 1).min(len - 1);
+            sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inputs(participant: &str, max_requests: u64, success: u64, failure: u64) -> Inputs {
+        Inputs {
+            participant: participant.to_string(),
+            max_requests,
+            success,
+            failure,
+            default_fee: 0.05,
+            fallback_fee: 0.15,
+            ..Inputs::default()
+        }
+    }
+
+    fn totals(requests: u64, amount: f64) -> Totals {
+        Totals { requests, amount }
+    }
+
+    // O partial-results.json da raiz saiu do k6 de verdade (backend fora do
+    // ar), os de golden/ foram calculados com decimal exato seguindo o
+    // handleSummary linha a linha e conferidos contra esse snapshot
+    #[test]
+    fn test_golden() {
+        let root = Inputs {
+            p99_ms: 0.78,
+            default_fee: 0.0,
+            fallback_fee: 0.0,
+            ..inputs("anonymous", 550, 535, 0)
+        };
+        let clean = Inputs {
+            p99_ms: 2.6612,
+            default: totals(14000, 278600.0),
+            fallback: totals(2524, 50227.6),
+            ..inputs("rinha-rust", 550, 16524, 0)
+        };
+        // 2.655 arredonda para 2.66 no big.js, em f64 seria 2.65
+        let mixed = Inputs {
+            p99_ms: 2.655,
+            default: totals(420, 8358.0),
+            fallback: totals(234, 4656.6),
+            ..inputs("rinha-rust", 100, 654, 0)
+        };
+        let fined = Inputs {
+            p99_ms: 14.3,
+            inconsistencies: 3,
+            default: totals(98, 1950.2),
+            fallback: totals(5, 99.5),
+            ..inputs("caixa \"dois\"", 550, 100, 7)
+        };
+
+        for (inputs, golden) in [
+            (root, include_str!("../../../partial-results.json")),
+            (clean, include_str!("../golden/clean.json")),
+            (mixed, include_str!("../golden/mixed.json")),
+            (fined, include_str!("../golden/fined.json")),
+        ] {
+            assert_eq!(inputs.partial_results().unwrap(), golden, "{inputs:?}");
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let fined = Inputs {
+            p99_ms: 14.3,
+            inconsistencies: 3,
+            default: totals(98, 1950.2),
+            fallback: totals(5, 99.5),
+            ..inputs("x", 550, 100, 7)
+        };
+        let score = fined.score().unwrap();
+        assert_eq!(score.bonus, 0.0);
+        assert_eq!(score.fine, 0.35);
+        assert_eq!(score.lag, -3);
+        assert_eq!(score.fees, 112.435);
+        // f64 direto daria 1259.2222499999998
+        assert_eq!(score.liquid, 1259.22225);
+
+        let mixed = Inputs {
+            p99_ms: 2.655,
+            ..Inputs::default()
+        };
+        let score = mixed.score().unwrap();
+        assert_eq!((score.p99, score.bonus), (2.66, 0.17));
+
+        let huge = Inputs {
+            default: totals(1, 1e40),
+            default_fee: 0.05,
+            ..Inputs::default()
+        };
+        assert!(huge.score().is_none());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<f64> = (1..=100).map(|n| n as f64).collect();
+        assert!((percentile(&sorted, 0.99) - 99.01).abs() < 1e-9);
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&[], 0.99), 0.0);
+    }
+}
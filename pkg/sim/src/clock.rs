@@ -0,0 +1,49 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use load_balance::timeouts::Clock;
+
+// Julho de 2025, o requestedAt precisa parecer uma data de verdade
+pub const EPOCH_MS: u64 = 1_752_000_000_000;
+
+// Tempo virtual: só anda quando o simulador passa para o próximo evento
+pub struct VirtualClock {
+    base: Instant,
+    micros: AtomicU64,
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock {
+            base: Instant::now(),
+            micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn micros(&self) -> u64 {
+        self.micros.load(Ordering::Relaxed)
+    }
+
+    // Nunca volta
+    pub fn advance_to(&self, micros: u64) {
+        self.micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn millis(&self) -> u64 {
+        EPOCH_MS + self.micros() / 1000
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.base + Duration::from_micros(self.micros())
+    }
+}
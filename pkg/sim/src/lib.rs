@@ -0,0 +1,1027 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use connection::{reactor::Ready, ring::Ring, transport::Address};
+use ledger::{Ledger, Processor, Summary};
+use load_balance::{
+    Handler, Ledgers, Link, Pump, Remote, WorkerPoll, Workers,
+    balancer::{self, Balancer},
+    timeouts::Clock,
+};
+use message::socket::Message;
+use worker::{
+    Node,
+    processor::{Line, persist, restore},
+    server::{self, Server},
+};
+
+pub use crate::{
+    clock::{EPOCH_MS, VirtualClock},
+    rng::{Rng, Seeded},
+};
+use crate::{
+    net::{Net, Owner, Pipe, Wake},
+    reactor::{Side, SimReactor},
+    upstream::{SimProcessor, SimUpstream},
+};
+
+mod clock;
+mod net;
+mod reactor;
+mod rng;
+mod upstream;
+
+// Simulação determinística de load balancer, workers e processadores numa
+// thread só, em tempo virtual. Os loops são os de produção (Balancer, Handler,
+// WorkerPoll com a Pump, o Server e as filas do worker), só a prontidão, o
+// relógio e os sockets são daqui. A semente decide tudo: quando cada cliente
+// chega, em quantos pedaços cada byte é entregue, a ordem dos eventos que
+// vencem juntos, quem desiste no meio, qual resposta do processador se perde,
+// quando um processador cai e quando um worker reinicia. Os invariantes são
+// conferidos depois de cada passo, a semente que falhar repete a falha.
+
+const CLIENT_CAPACITY: usize = 4096;
+// Menor que dois frames e fora do múltiplo de 54: escrita parcial e frame
+// partido o tempo todo no link
+const LINK_CAPACITY: usize = 100;
+// Pouco, para o ring encher e segurar o load balancer
+const RING_FRAMES: usize = 8;
+// IPs dos clientes, poucos para a admissão recusar alguém
+const PEERS: u64 = 6;
+const TRACE: usize = 48;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub clients: usize,
+    pub summaries: usize, // GET /payments-summary no meio dos pagamentos
+    pub workers: usize,
+    pub slots: usize,       // do load balancer, pouco para os slots serem reusados
+    pub duration: Duration, // chegada dos clientes, em tempo virtual
+    pub latency: Duration,  // máximo de cada entrega na rede
+    pub stale: u64,         // % das prontidões repetidas mais tarde
+    pub abort: u64,         // % dos clientes que fecham no meio
+    pub slow: u64,          // % dos clientes que param até o timeout do header
+    pub lost: u64,          // % das respostas do processador que viram timeout
+    pub outage: u64,        // % de chance de cada processador cair uma vez
+    pub ring: u64,          // % dos workers ligados por ring
+    pub restarts: usize,    // quedas de worker
+    pub max_steps: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            clients: 60,
+            summaries: 3,
+            workers: 2,
+            slots: 4,
+            duration: Duration::from_secs(1),
+            latency: Duration::from_millis(2),
+            stale: 10,
+            abort: 5,
+            slow: 3,
+            lost: 10,
+            outage: 50,
+            ring: 50,
+            restarts: 1,
+            max_steps: 400_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub seed: u64,
+    pub steps: usize,
+    pub acked: usize,     // clientes que receberam 200
+    pub processed: usize, // pagamentos nos processadores
+    pub rejected: usize,  // 429 e 503 da admissão
+    pub fingerprint: u64, // hash do trace inteiro, igual para a mesma semente
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    pub seed: u64,
+    pub step: usize,
+    pub reason: String,
+    pub trace: Vec<String>, // últimos eventos até a falha
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "seed {} failed at step {}: {}",
+            self.seed, self.step, self.reason
+        )?;
+        for line in &self.trace {
+            writeln!(f, "  {line}")?;
+        }
+        write!(
+            f,
+            "reproduce with: cargo run -p sim -- --seed {}",
+            self.seed
+        )
+    }
+}
+
+pub fn run(seed: u64, options: &Options) -> Result<Report, Failure> {
+    Simulation::new(seed, options).run()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    Connect(usize),
+    Send(usize),
+    Abort(usize),
+    Deliver(usize),
+    Ready(Owner),
+    Wake(Side), // timeout da espera do loop
+    Pump,
+    Consume(usize),
+    Work(usize, usize), // worker, thread de processador
+    Restart(usize),
+    Outage(Processor, bool),
+    Shutdown(Side),
+}
+
+struct Client {
+    chunks: VecDeque<(u64, Vec<u8>)>, // espera antes de cada pedaço da requisição
+    payment: Option<([u8; 36], u64)>,
+    ip: IpAddr,
+    pipe: Option<Pipe>,
+    response: Vec<u8>,
+    status: Option<u16>,
+}
+
+impl Client {
+    fn new(request: Vec<u8>, slow: Option<u64>, ip: IpAddr, rng: &mut Seeded) -> Client {
+        // até quatro pedaços, o devagar espera o timeout antes do último
+        let mut cuts: Vec<usize> = (0..rng.below(4))
+            .map(|_| rng.between(1, request.len() as u64 - 1) as usize)
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        cuts.push(request.len());
+        let mut start = 0;
+        let count = cuts.len();
+        let chunks = cuts
+            .into_iter()
+            .enumerate()
+            .map(|(i, end)| {
+                let wait = match (i, slow) {
+                    (0, _) => 0,
+                    (i, Some(wait)) if i + 1 == count => wait,
+                    _ => rng.below(2_000),
+                };
+                let chunk = request[start..end].to_vec();
+                start = end;
+                (wait, chunk)
+            })
+            .collect();
+
+        Client {
+            chunks,
+            payment: None,
+            ip,
+            pipe: None,
+            response: Vec::new(),
+            status: None,
+        }
+    }
+
+    fn payment(
+        id: &[u8; 36],
+        amount: u64,
+        slow: Option<u64>,
+        ip: IpAddr,
+        rng: &mut Seeded,
+    ) -> Client {
+        let body = format!(
+            "{{\"correlationId\":\"{}\",\"amount\":{}.{:02}}}",
+            String::from_utf8_lossy(id),
+            amount / 100,
+            amount % 100
+        );
+        let request = format!(
+            "POST /payments HTTP/1.1\r\nHost: localhost:9999\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        Client {
+            payment: Some((*id, amount)),
+            ..Client::new(request.into_bytes(), slow, ip, rng)
+        }
+    }
+
+    fn summary(ip: IpAddr, rng: &mut Seeded) -> Client {
+        let request = b"GET /payments-summary HTTP/1.1\r\nHost: localhost:9999\r\n\r\n";
+        Client::new(request.to_vec(), None, ip, rng)
+    }
+
+    fn acked(&self) -> bool {
+        self.status == Some(200)
+    }
+}
+
+// O load balancer inteiro: o event loop e os dois lados da thread dos workers
+struct Lb {
+    balancer: Balancer<SimReactor>,
+    workers: Workers,
+    pump: Pump<Pipe>,
+    ledgers: Ledgers,
+    draining: bool,
+}
+
+// Um worker: o event loop (até o shutdown), o Node e as threads de processador
+struct Host {
+    server: Option<Server<SimReactor>>,
+    node: Arc<Node>,
+    ledger: Arc<Ledger>,
+    lines: Vec<Line>,
+    working: Vec<bool>,
+    ring: bool,
+    consuming: bool,
+    waking: Option<u64>,
+}
+
+struct Simulation {
+    seed: u64,
+    options: Options,
+    config: config::Config,
+    dir: PathBuf,
+    rng: Seeded,
+    clock: VirtualClock,
+    net: Net,
+    agenda: Vec<(u64, Event)>,
+    step: usize,
+    lb: Option<Lb>,
+    lb_waking: Option<u64>,
+    pumping: Option<u64>,
+    hosts: Vec<Host>,
+    processors: [SimProcessor; 2],
+    clients: Vec<Client>,
+    // Pagamentos que algum cliente mandou, e os que já tiveram resposta final
+    sent: BTreeMap<[u8; 36], u64>,
+    settled: BTreeSet<[u8; 36]>,
+    violations: Vec<String>,
+    trace: VecDeque<String>,
+    fingerprint: u64,
+}
+
+impl Simulation {
+    fn new(seed: u64, options: &Options) -> Simulation {
+        static RUNS: AtomicU64 = AtomicU64::new(0);
+        let run = RUNS.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("rinha-sim-{}-{run}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("unable to create simulation dir");
+
+        let mut rng = Seeded::new(seed);
+        let slots = options.slots.to_string();
+        let socket_dir = dir.to_str().expect("temp dir is not UTF-8").to_string();
+        let shed = rng.percent(50).to_string();
+        let config = config::Config::from_pairs(&[
+            ("MODE", "standalone"),
+            ("SOCKET_DIR", &socket_dir),
+            ("LB_MAX_SLOTS", &slots),
+            ("LB_SHED", &shed),
+            ("LB_MAX_CONNS_PER_IP", "2"),
+            ("LB_RATE_LIMIT", "10"),
+            ("LB_RATE_BURST", "3"),
+            // dois frames por leitura, o partial anda junto com o buffer
+            ("WORKER_BUFFER_SIZE", "108"),
+            ("WORKER_THREADS", "2"),
+            // janela pequena, o load balancer espera crédito o tempo todo
+            ("WORKER_CREDIT_WINDOW", "4"),
+        ])
+        .expect("invalid simulation config");
+
+        let clock = VirtualClock::new();
+        let net = Net::default();
+        let mut links = Vec::with_capacity(options.workers);
+        let mut hosts = Vec::with_capacity(options.workers);
+        for n in 0..options.workers {
+            let mut worker = config.worker.clone();
+            worker.host = format!("sim-{n}");
+            let ledger = Arc::new(Ledger::anonymous().expect("unable to create ledger"));
+            let node = Arc::new(Node::polled(&config.common, &worker, ledger.clone()));
+            let lines = (0..worker.threads)
+                .map(|_| node.processors().line())
+                .collect();
+            let address = Address::Unix(dir.join(format!("{}.sock", worker.host)));
+            let mut server = Server::new(
+                &config.common,
+                &worker,
+                address,
+                SimReactor::new(Side::Worker(n)),
+                node.clone(),
+                clock.now(),
+            );
+
+            // O link já conectado, na fila do listener do worker
+            let (mut lb_end, worker_end) =
+                net::pair(&net, Owner::Link(n), Owner::Nobody, LINK_CAPACITY);
+            server.reactor().backlog.push_back((worker_end, None));
+            net.borrow_mut().wake(Owner::Worker(n, server::SERVER));
+
+            let ring = rng.percent(options.ring);
+            let link = match ring {
+                false => Link::Socket(Remote::new(lb_end, worker.host.clone())),
+                // Attach já no socket: o ring guarda os pagamentos até o
+                // worker abrir
+                true => {
+                    let name = format!("{}.ring", worker.host);
+                    let mut payload = [0u8; Message::CHUNK];
+                    payload[..name.len()].copy_from_slice(name.as_bytes());
+                    let attach = Message::Attach(name.len() as u8, payload).to_bytes();
+                    lb_end
+                        .write_all(&attach)
+                        .expect("link is full before the attach");
+                    let ring =
+                        Ring::create(dir.join(&name), RING_FRAMES).expect("unable to create ring");
+                    Remote::new(lb_end, worker.host.clone()).attached(ring)
+                }
+            };
+            links.push(link);
+            hosts.push(Host {
+                server: Some(server),
+                node,
+                ledger,
+                lines,
+                working: vec![false; worker.threads],
+                ring,
+                consuming: false,
+                waking: None,
+            });
+        }
+
+        let poll = WorkerPoll::with_links(links, &config.lb);
+        let (workers, pump) = load_balance::pump(poll, &config.lb);
+        let balancer = Balancer::new(
+            &config.common,
+            &config.lb,
+            SimReactor::new(Side::Lb),
+            clock.now(),
+        );
+        let lb = Lb {
+            balancer,
+            workers,
+            pump,
+            ledgers: Ledgers::Remote,
+            draining: false,
+        };
+
+        let duration = options.duration.as_micros() as u64;
+        let header_timeout = config.lb.header_timeout.as_micros() as u64;
+        let mut agenda = Vec::new();
+        let mut clients = Vec::with_capacity(options.clients + options.summaries);
+        let mut sent = BTreeMap::new();
+        for i in 0..options.clients + options.summaries {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, rng.below(PEERS) as u8));
+            let client = match i < options.clients {
+                true => {
+                    let id = uuid(&mut rng);
+                    let amount = rng.between(1, 100_000);
+                    let slow = rng
+                        .percent(options.slow)
+                        .then(|| header_timeout + rng.below(header_timeout));
+                    sent.insert(id, amount);
+                    Client::payment(&id, amount, slow, ip, &mut rng)
+                }
+                false => Client::summary(ip, &mut rng),
+            };
+            clients.push(client);
+
+            let at = rng.below(duration);
+            agenda.push((at, Event::Connect(i)));
+            if rng.percent(options.abort) {
+                agenda.push((at + rng.below(20_000), Event::Abort(i)));
+            }
+        }
+        for processor in Processor::ALL {
+            if rng.percent(options.outage) {
+                let at = rng.below(duration);
+                agenda.push((at, Event::Outage(processor, true)));
+                agenda.push((
+                    at + rng.between(100_000, 2_000_000),
+                    Event::Outage(processor, false),
+                ));
+            }
+        }
+        for _ in 0..options.restarts {
+            let worker = rng.below(options.workers as u64) as usize;
+            agenda.push((rng.below(duration), Event::Restart(worker)));
+        }
+        // Depois das quedas dos processadores, com cliente devagar ainda aberto
+        agenda.push((
+            duration + 2_000_000 + rng.below(1_000_000),
+            Event::Shutdown(Side::Lb),
+        ));
+        agenda.push((0, Event::Pump));
+
+        Simulation {
+            seed,
+            options: options.clone(),
+            config,
+            dir,
+            rng,
+            clock,
+            net,
+            agenda,
+            step: 0,
+            lb: Some(lb),
+            lb_waking: None,
+            pumping: Some(0),
+            hosts,
+            processors: Default::default(),
+            clients,
+            sent,
+            settled: BTreeSet::new(),
+            violations: Vec::new(),
+            trace: VecDeque::with_capacity(TRACE),
+            fingerprint: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    fn run(mut self) -> Result<Report, Failure> {
+        while let Some(event) = self.next_event() {
+            self.step += 1;
+            if self.step > self.options.max_steps {
+                return Err(self.failure("did not settle".to_string()));
+            }
+            self.record(format!("t={}us {event:?}", self.clock.micros()));
+
+            let outcome = self.handle(event).and_then(|_| {
+                self.schedule();
+                self.check()
+            });
+            if let Err(reason) = outcome {
+                return Err(self.failure(reason));
+            }
+        }
+
+        self.finish().map_err(|reason| self.failure(reason))
+    }
+
+    // Entre os eventos que já venceram qualquer um pode ser o próximo, sem
+    // nenhum vencido o relógio pula para o mais cedo
+    fn next_event(&mut self) -> Option<Event> {
+        let earliest = self.agenda.iter().map(|(at, _)| *at).min()?;
+        self.clock.advance_to(earliest);
+        let now = self.clock.micros();
+        let due: Vec<usize> = (0..self.agenda.len())
+            .filter(|&i| self.agenda[i].0 <= now)
+            .collect();
+        let pick = due[self.rng.below(due.len() as u64) as usize];
+        Some(self.agenda.swap_remove(pick).1)
+    }
+
+    fn at(&mut self, delay: u64, event: Event) {
+        self.agenda.push((self.clock.micros() + delay, event));
+    }
+
+    fn handle(&mut self, event: Event) -> Result<(), String> {
+        let now = self.clock.micros();
+        match event {
+            Event::Connect(i) => {
+                let (client, lb_end) =
+                    net::pair(&self.net, Owner::Client(i), Owner::Nobody, CLIENT_CAPACITY);
+                self.clients[i].pipe = Some(client);
+                // sem listener o cliente leva EOF
+                if let Some(lb) = self.lb.as_mut()
+                    && lb.balancer.reactor().listening
+                {
+                    let ip = self.clients[i].ip;
+                    lb.balancer.reactor().backlog.push_back((lb_end, Some(ip)));
+                    self.net.borrow_mut().wake(Owner::Lb(balancer::SERVER));
+                }
+                self.at(0, Event::Send(i));
+            }
+            Event::Send(i) => {
+                let client = &mut self.clients[i];
+                let (Some(pipe), Some((_, chunk))) =
+                    (client.pipe.as_mut(), client.chunks.pop_front())
+                else {
+                    return Ok(());
+                };
+                // o load balancer já fechou (timeout ou recusa), o cliente desiste
+                if pipe.write_all(&chunk).is_err() {
+                    return Ok(());
+                }
+                if let Some((wait, _)) = client.chunks.front() {
+                    let wait = *wait;
+                    self.at(wait, Event::Send(i));
+                }
+            }
+            Event::Abort(i) => self.clients[i].pipe = None,
+            Event::Deliver(wire) => self.net.borrow_mut().deliver(wire, &mut self.rng),
+            Event::Ready(Owner::Client(i)) => self.read(i),
+            Event::Ready(Owner::Lb(token)) => self.lb_turn(&[Ready {
+                token,
+                readable: true,
+            }]),
+            // A thread dos workers não espera o socket, anda no intervalo dela
+            Event::Ready(Owner::Link(_)) | Event::Ready(Owner::Nobody) => {}
+            Event::Ready(Owner::Worker(n, token)) => self.serve(
+                n,
+                &[Ready {
+                    token,
+                    readable: true,
+                }],
+            ),
+            // Só vale a espera agendada por último, as antigas ficaram para
+            // trás quando o loop acordou antes
+            Event::Wake(Side::Lb) if self.lb_waking == Some(now) => {
+                self.lb_waking = None;
+                self.lb_turn(&[]);
+            }
+            Event::Wake(Side::Worker(n)) if self.hosts[n].waking == Some(now) => {
+                self.hosts[n].waking = None;
+                self.serve(n, &[]);
+            }
+            Event::Wake(_) => {}
+            Event::Pump if self.pumping == Some(now) => self.pump(),
+            Event::Pump => {}
+            Event::Consume(n) => {
+                let host = &mut self.hosts[n];
+                host.consuming = false;
+                if let Some(server) = host.server.as_ref() {
+                    server.consume();
+                }
+            }
+            Event::Work(n, k) => {
+                let host = &mut self.hosts[n];
+                host.working[k] = false;
+                let line = &mut host.lines[k];
+                let Some(payment) = line.try_next().or_else(|| line.retry()) else {
+                    return Ok(());
+                };
+                let mut upstream = SimUpstream {
+                    processors: &mut self.processors,
+                    rng: &mut self.rng,
+                    clock: &self.clock,
+                    lost: self.options.lost,
+                    sent: &self.sent,
+                    settled: &mut self.settled,
+                    violations: &mut self.violations,
+                };
+                line.run(payment, &mut upstream);
+                if let Some(violation) = self.violations.pop() {
+                    return Err(violation);
+                }
+            }
+            Event::Restart(n) => {
+                let restored = self.restart(n)?;
+                self.record(format!("worker {n} restored {restored}"));
+            }
+            Event::Outage(processor, down) => self.processors[processor as usize].down = down,
+            Event::Shutdown(side) => {
+                let shutdown = [Ready {
+                    token: balancer::SHUTDOWN,
+                    readable: true,
+                }];
+                match side {
+                    Side::Lb => {
+                        if let Some(lb) = self.lb.as_mut() {
+                            lb.draining = true;
+                        }
+                        self.lb_turn(&shutdown);
+                    }
+                    Side::Worker(n) => self.serve(n, &shutdown),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, i: usize) {
+        let client = &mut self.clients[i];
+        let Some(pipe) = client.pipe.as_mut() else {
+            return;
+        };
+        let mut buf = [0u8; 512];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) => {
+                    client.status = std::str::from_utf8(&client.response)
+                        .ok()
+                        .and_then(|response| response.strip_prefix("HTTP/1.1 "))
+                        .and_then(|rest| rest.get(..3))
+                        .and_then(|code| code.parse().ok());
+                    client.pipe = None;
+                    break;
+                }
+                Ok(n) => client.response.extend_from_slice(&buf[..n]),
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn lb_turn(&mut self, ready: &[Ready]) {
+        let now = self.clock.now();
+        let Some(lb) = self.lb.as_mut() else {
+            return;
+        };
+        let handler = Handler {
+            workers: &lb.workers,
+            ledgers: &lb.ledgers,
+            purge_token: None,
+        };
+        lb.balancer.turn(ready, &handler, now);
+
+        // Comando novo acorda a thread dos workers na hora
+        let delay = self.rng.below(100);
+        let at = self.clock.micros() + delay;
+        if self.pumping.is_none_or(|pumping| at < pumping) {
+            self.pumping = Some(at);
+            self.agenda.push((at, Event::Pump));
+        }
+    }
+
+    fn serve(&mut self, n: usize, ready: &[Ready]) {
+        let now = self.clock.now();
+        let host = &mut self.hosts[n];
+        let Some(server) = host.server.as_mut() else {
+            return;
+        };
+        server.turn(ready, now);
+        if server.done(now) {
+            let server = host.server.take().unwrap();
+            let deadline = server.stop();
+            let drained = deadline.is_none_or(|deadline| now < deadline);
+            self.record(format!("worker {n} stopped, drained {drained}"));
+        }
+    }
+
+    // Uma volta da thread dos workers, e o eventfd que acorda o event loop
+    fn pump(&mut self) {
+        let now = self.clock.now();
+        self.pumping = None;
+        let Some(lb) = self.lb.as_mut() else {
+            return;
+        };
+        lb.pump.step(now);
+        let handler = Handler {
+            workers: &lb.workers,
+            ledgers: &lb.ledgers,
+            purge_token: None,
+        };
+        lb.balancer.turn(
+            &[Ready {
+                token: balancer::WORKERS,
+                readable: true,
+            }],
+            &handler,
+            now,
+        );
+
+        // Drain terminado e nada na fila: fechar os links avisa os workers
+        if lb.draining && lb.balancer.done(now) && !lb.pump.busy() {
+            let outcome = lb.balancer.outcome();
+            self.record(format!(
+                "lb stopped, {} finished, {} forced",
+                outcome.finished, outcome.forced
+            ));
+            self.lb = None;
+            for n in 0..self.hosts.len() {
+                let delay = self.rng.below(10_000);
+                self.at(delay, Event::Shutdown(Side::Worker(n)));
+            }
+            return;
+        }
+
+        let interval = lb.pump.interval().as_micros() as u64;
+        self.pumping = Some(self.clock.micros() + interval);
+        self.at(interval, Event::Pump);
+        // O push no ring acorda o consumidor
+        for n in 0..self.hosts.len() {
+            if self.hosts[n].ring && !self.hosts[n].consuming {
+                self.hosts[n].consuming = true;
+                let delay = self.rng.below(500);
+                self.at(delay, Event::Consume(n));
+            }
+        }
+    }
+
+    // Queda e volta do processo: fila e retries passam pelo .pending como no
+    // shutdown, o ledger fica (é um arquivo). O link sobrevive, no real o
+    // load balancer reconecta e aqui só o processamento recomeça.
+    fn restart(&mut self, n: usize) -> Result<usize, String> {
+        let host = &mut self.hosts[n];
+        let pending: Vec<_> = host.lines.iter_mut().flat_map(Line::pending).collect();
+        let path = self.dir.join(format!("sim-{n}.restart"));
+        let path = path.to_str().expect("temp dir is not UTF-8");
+        persist(path, &pending).map_err(|e| format!("worker {n} persist: {e}"))?;
+        let restored = restore(path);
+        if restored.len() != pending.len() {
+            return Err(format!(
+                "worker {n} restored {} of {} pending payments",
+                restored.len(),
+                pending.len()
+            ));
+        }
+        let count = restored.len();
+        restored
+            .into_iter()
+            .for_each(|payment| host.node.processors().send(payment));
+        Ok(count)
+    }
+
+    // O que o último passo deixou pendente vira evento
+    fn schedule(&mut self) {
+        let latency = self.options.latency.as_micros() as u64;
+        let wakes = self.net.borrow_mut().take_wakes();
+        for wake in wakes {
+            match wake {
+                Wake::Deliver(wire) => {
+                    let delay = self.rng.below(latency);
+                    self.at(delay, Event::Deliver(wire));
+                }
+                Wake::Ready(owner) => {
+                    let delay = self.rng.below(100);
+                    self.at(delay, Event::Ready(owner));
+                    // a mesma prontidão de novo bem depois, quando o slot
+                    // pode ser de outra conexão
+                    if matches!(owner, Owner::Lb(_) | Owner::Worker(..))
+                        && self.rng.percent(self.options.stale)
+                    {
+                        let delay = self.rng.between(1_000, 50_000);
+                        self.at(delay, Event::Ready(owner));
+                    }
+                }
+            }
+        }
+
+        // O timeout de cada loop, como o do poll
+        let now = self.clock.now();
+        let micros = self.clock.micros();
+        let wake = |timeout: Option<Duration>, waking: Option<u64>| {
+            let at = micros + timeout?.as_micros() as u64;
+            waking.is_none_or(|waking| at < waking).then_some(at)
+        };
+        if let Some(lb) = self.lb.as_ref()
+            && let Some(at) = wake(lb.balancer.timeout(now), self.lb_waking)
+        {
+            self.lb_waking = Some(at);
+            self.agenda.push((at, Event::Wake(Side::Lb)));
+        }
+        for n in 0..self.hosts.len() {
+            let host = &mut self.hosts[n];
+            if let Some(server) = host.server.as_ref()
+                && let Some(at) = wake(server.timeout(now), host.waking)
+            {
+                host.waking = Some(at);
+                self.agenda.push((at, Event::Wake(Side::Worker(n))));
+            }
+
+            // Fila primeiro, retry só depois do intervalo, como a thread
+            let (queued, _) = host.node.processors().depth();
+            for k in 0..host.lines.len() {
+                if host.working[k] {
+                    continue;
+                }
+                let delay = match (queued > 0, host.lines[k].retries() > 0) {
+                    (true, _) => self.rng.between(100, 2_000),
+                    (false, true) => self.config.worker.retry_interval.as_micros() as u64,
+                    (false, false) => continue,
+                };
+                host.working[k] = true;
+                self.agenda.push((micros + delay, Event::Work(n, k)));
+            }
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(lb) = self.lb.as_ref() {
+            let (tokens, open) = (lb.balancer.tokens(), |slot| lb.balancer.is_open(slot));
+            check_slots("lb", tokens, open, self.config.lb.max_slots)?;
+        }
+        for (n, host) in self.hosts.iter().enumerate() {
+            if let Some(server) = host.server.as_ref() {
+                let open = |slot| server.is_open(slot);
+                check_slots(
+                    &format!("worker {n}"),
+                    server.tokens(),
+                    open,
+                    self.config.worker.max_slots,
+                )?;
+            }
+        }
+
+        // O ledger só anota depois do processador aceitar
+        let ledgers = self.ledgers(EPOCH_MS, self.clock.millis())?;
+        for processor in Processor::ALL {
+            let recorded = ledgers.get(processor);
+            let processed = self.processors[processor as usize].summary(0, u64::MAX);
+            if recorded.requests > processed.requests || recorded.amount > processed.amount {
+                return Err(format!(
+                    "{} ledger ahead of the processor: {recorded:?} > {processed:?}",
+                    processor.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Sem mais eventos: o load balancer e os workers pararam, todo pagamento
+    // com 200 foi processado uma vez e o ledger bate com os processadores
+    fn finish(&mut self) -> Result<Report, String> {
+        if self.lb.is_some() {
+            return Err("load balancer did not stop".to_string());
+        }
+        if let Some(n) = self.hosts.iter().position(|host| host.server.is_some()) {
+            return Err(format!("worker {n} did not stop"));
+        }
+        if !self.net.borrow().quiet() {
+            return Err("bytes left on the wire".to_string());
+        }
+        for (n, host) in self.hosts.iter().enumerate() {
+            let retries: usize = host.lines.iter().map(Line::retries).sum();
+            if host.node.processors().depth() != (0, 0) || retries > 0 {
+                return Err(format!("worker {n} still has pending payments"));
+            }
+        }
+
+        for client in self.clients.iter().filter(|client| client.acked()) {
+            let Some((id, amount)) = client.payment else {
+                continue;
+            };
+            let found: Vec<u64> = self
+                .processors
+                .iter()
+                .filter_map(|processor| processor.payments.get(&id))
+                .map(|(amount, _)| *amount)
+                .collect();
+            if found != [amount] {
+                let name = String::from_utf8_lossy(&id);
+                return Err(format!(
+                    "{name} acked with {amount}, processed as {found:?}"
+                ));
+            }
+        }
+        // Resumo pelo link: resposta dos workers, 503 no timeout ou a recusa
+        // da admissão
+        if let Some(client) = self.clients[self.options.clients..].iter().find(|client| {
+            client
+                .status
+                .is_some_and(|status| ![200, 429, 503].contains(&status))
+        }) {
+            return Err(format!("summary answered {:?}", client.status));
+        }
+
+        // janela inteira e pedaços sorteados, nas bordas dos buckets ou não
+        let end = self.clock.millis() + ledger::BUCKET_MS;
+        let mut windows = vec![(0, u64::MAX)];
+        for _ in 0..8 {
+            let from = self.rng.between(EPOCH_MS, end);
+            windows.push((from, self.rng.between(from, end)));
+        }
+        for (from, to) in windows {
+            let backend = self.ledgers(from, to)?;
+            let processors = Summary {
+                default: self.processors[0].summary(from, to),
+                fallback: self.processors[1].summary(from, to),
+            };
+            if backend != processors {
+                return Err(format!(
+                    "summary {from}..{to}: backend {backend:?}, processors {processors:?}"
+                ));
+            }
+        }
+
+        let acked = self.clients[..self.options.clients]
+            .iter()
+            .filter(|client| client.acked())
+            .count();
+        let rejected = self
+            .clients
+            .iter()
+            .filter(|client| matches!(client.status, Some(429) | Some(503)))
+            .count();
+        let processed = self.processors.iter().map(|p| p.payments.len()).sum();
+        Ok(Report {
+            seed: self.seed,
+            steps: self.step,
+            acked,
+            processed,
+            rejected,
+            fingerprint: self.fingerprint,
+        })
+    }
+
+    fn ledgers(&self, from: u64, to: u64) -> Result<Summary, String> {
+        // janela aberta do summary sem from/to varre o ledger inteiro
+        let (from, to) = (from.max(EPOCH_MS), to.min(self.clock.millis() + 1_000));
+        let mut total = Summary::default();
+        for (n, host) in self.hosts.iter().enumerate() {
+            let summary = host
+                .ledger
+                .summary(from, to)
+                .map_err(|e| format!("worker {n} ledger: {e}"))?;
+            total.merge(&summary);
+        }
+        Ok(total)
+    }
+
+    fn record(&mut self, line: String) {
+        let line = format!("{} {line}", self.step);
+        for byte in line.bytes() {
+            self.fingerprint = (self.fingerprint ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+        if self.trace.len() == TRACE {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(line);
+    }
+
+    fn failure(&self, reason: String) -> Failure {
+        Failure {
+            seed: self.seed,
+            step: self.step,
+            reason,
+            trace: self.trace.iter().cloned().collect(),
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Slot ocupado se e só se tem token, e cada token volta para o próprio slot
+fn check_slots(
+    name: &str,
+    tokens: &connection::tokens::Tokens,
+    open: impl Fn(usize) -> bool,
+    slots: usize,
+) -> Result<(), String> {
+    let count = (0..slots).filter(|&slot| open(slot)).count();
+    if count != tokens.len() {
+        return Err(format!(
+            "{name} has {count} connections and {} tokens",
+            tokens.len()
+        ));
+    }
+    for slot in 0..slots {
+        let token = tokens.token(slot);
+        if open(slot) != token.is_some() {
+            return Err(format!(
+                "{name} slot {slot} has token {token:?} out of sync"
+            ));
+        }
+        if let Some(token) = token
+            && tokens.slot(token) != Some(slot)
+        {
+            return Err(format!("{name} token {token} does not lead to slot {slot}"));
+        }
+    }
+    Ok(())
+}
+
+fn uuid(rng: &mut Seeded) -> [u8; 36] {
+    let hex = format!("{:016x}{:016x}", rng.next_u64(), rng.next_u64());
+    let mut id = [0u8; 36];
+    let mut digits = hex.bytes();
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = match i {
+            8 | 13 | 18 | 23 => b'-',
+            _ => digits.next().unwrap(),
+        };
+    }
+    id
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seeds() {
+        let options = Options::default();
+        for seed in 0..64 {
+            if let Err(failure) = run(seed, &options) {
+                panic!("{failure}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let options = Options::default();
+        let first = run(7, &options).unwrap();
+        assert_eq!(first, run(7, &options).unwrap());
+        assert_ne!(first.fingerprint, run(8, &options).unwrap().fingerprint);
+        assert!(first.acked > 0);
+    }
+}
@@ -0,0 +1,58 @@
+use sim::Options;
+
+const USAGE: &str = "usage: sim [OPTIONS]
+
+Runs the deterministic simulation of load balancer, workers and processors.
+
+  --seed N       First seed (default 0)
+  --seeds N      How many seeds from --seed on (default 1)
+  --clients N    Payments per seed (default 60)
+  --summaries N  GET /payments-summary clients per seed (default 3)
+  --workers N    Workers behind the load balancer (default 2)
+  --slots N      Load balancer slots (default 4)
+  --ring N       % of workers linked by shared ring (default 50)
+";
+
+fn main() {
+    let mut options = Options::default();
+    let mut seed = 0;
+    let mut seeds = 1;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            let value = args.next().unwrap_or_default();
+            value.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("error: {name} must be a number, got {value:?}\n\n{USAGE}");
+                std::process::exit(2);
+            })
+        };
+        match arg.as_str() {
+            "--seed" => seed = value("--seed"),
+            "--seeds" => seeds = value("--seeds"),
+            "--clients" => options.clients = value("--clients") as usize,
+            "--summaries" => options.summaries = value("--summaries") as usize,
+            "--workers" => options.workers = value("--workers").max(1) as usize,
+            "--slots" => options.slots = value("--slots").max(1) as usize,
+            "--ring" => options.ring = value("--ring").min(100),
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return;
+            }
+            other => {
+                eprintln!("error: unknown argument {other}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    for seed in seed..seed.saturating_add(seeds) {
+        match sim::run(seed, &options) {
+            Ok(report) => println!("{report:?}"),
+            Err(failure) => {
+                eprintln!("{failure}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+use crate::rng::Rng;
+
+// Rede simulada: cada direção de uma conexão é um fio com bytes em voo e
+// bytes que já chegaram. A entrega anda em pedaços de tamanho sorteado, então
+// frame de 54 bytes e requisição HTTP chegam partidos como no TCP. Ninguém é
+// avisado direto: leitura, escrita e close deixam pendências (Wake) que o
+// simulador transforma em eventos.
+
+pub type Net = Rc<RefCell<Wires>>;
+
+// Quem recebe a prontidão de uma ponta
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Owner {
+    Nobody,
+    Client(usize),
+    Lb(usize),            // token do slot no load balancer, 0 é o listener
+    Link(usize),          // ponta do load balancer no link com o worker n
+    Worker(usize, usize), // worker n, token do slot (0 é o listener)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wake {
+    Deliver(usize),
+    Ready(Owner),
+}
+
+struct Wire {
+    sent: VecDeque<u8>, // em voo
+    arrived: VecDeque<u8>,
+    capacity: usize, // buffer do socket, em voo mais chegado
+    reader: Owner,
+    writer: Owner,
+    closed: bool,  // quem escreve fechou
+    orphan: bool,  // quem lê fechou
+    blocked: bool, // a última escrita deu WouldBlock
+    delivering: bool,
+}
+
+#[derive(Default)]
+pub struct Wires {
+    wires: Vec<Wire>,
+    wakes: Vec<Wake>,
+}
+
+impl Wires {
+    pub fn wake(&mut self, owner: Owner) {
+        self.wakes.push(Wake::Ready(owner));
+    }
+
+    pub fn take_wakes(&mut self) -> Vec<Wake> {
+        std::mem::take(&mut self.wakes)
+    }
+
+    // Um pedaço do que está em voo chega do outro lado
+    pub fn deliver(&mut self, wire: usize, rng: &mut dyn Rng) {
+        let w = &mut self.wires[wire];
+        w.delivering = false;
+        if w.sent.is_empty() {
+            return;
+        }
+        let n = rng.between(1, w.sent.len() as u64) as usize;
+        let bytes: Vec<u8> = w.sent.drain(..n).collect();
+        if !w.orphan {
+            w.arrived.extend(bytes);
+        }
+        let reader = w.reader;
+        if !w.sent.is_empty() {
+            w.delivering = true;
+            self.wakes.push(Wake::Deliver(wire));
+        }
+        self.wakes.push(Wake::Ready(reader));
+    }
+
+    // Nada em voo nem esperando leitura
+    pub fn quiet(&self) -> bool {
+        self.wires
+            .iter()
+            .all(|w| w.orphan || (w.sent.is_empty() && w.arrived.is_empty()))
+    }
+}
+
+// Conexão entre `a` e `b`, devolve a ponta de cada um
+pub fn pair(net: &Net, a: Owner, b: Owner, capacity: usize) -> (Pipe, Pipe) {
+    let mut wires = net.borrow_mut();
+    let first = wires.wires.len();
+    for (reader, writer) in [(b, a), (a, b)] {
+        wires.wires.push(Wire {
+            sent: VecDeque::new(),
+            arrived: VecDeque::new(),
+            capacity,
+            reader,
+            writer,
+            closed: false,
+            orphan: false,
+            blocked: false,
+            delivering: false,
+        });
+    }
+    (
+        Pipe {
+            net: net.clone(),
+            rx: first + 1,
+            tx: first,
+        },
+        Pipe {
+            net: net.clone(),
+            rx: first,
+            tx: first + 1,
+        },
+    )
+}
+
+// Uma ponta, lê e escreve como um socket non-blocking
+pub struct Pipe {
+    net: Net,
+    rx: usize,
+    tx: usize,
+}
+
+impl Pipe {
+    // Como registrar no epoll: o dono novo é avisado do que já chegou
+    pub fn adopt(&self, owner: Owner) {
+        let mut wires = self.net.borrow_mut();
+        wires.wires[self.rx].reader = owner;
+        wires.wires[self.tx].writer = owner;
+        wires.wake(owner);
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut wires = self.net.borrow_mut();
+        let w = &mut wires.wires[self.rx];
+        if w.arrived.is_empty() {
+            return match w.closed && w.sent.is_empty() {
+                true => Ok(0),
+                false => Err(std::io::ErrorKind::WouldBlock.into()),
+            };
+        }
+
+        let n = buf.len().min(w.arrived.len());
+        for (slot, byte) in buf.iter_mut().zip(w.arrived.drain(..n)) {
+            *slot = byte;
+        }
+        // abriu espaço para quem estava esperando escrever
+        if w.blocked {
+            w.blocked = false;
+            let writer = w.writer;
+            wires.wake(writer);
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut wires = self.net.borrow_mut();
+        let w = &mut wires.wires[self.tx];
+        if w.orphan {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+        let space = w.capacity - w.sent.len() - w.arrived.len();
+        if space == 0 {
+            w.blocked = true;
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = buf.len().min(space);
+        w.sent.extend(&buf[..n]);
+        if !w.delivering {
+            w.delivering = true;
+            wires.wakes.push(Wake::Deliver(self.tx));
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut wires = self.net.borrow_mut();
+        // o outro lado lê o que já foi e depois o EOF
+        let w = &mut wires.wires[self.tx];
+        w.closed = true;
+        let reader = w.reader;
+        if w.sent.is_empty() {
+            wires.wake(reader);
+        }
+
+        // e quem escreve para cá passa a levar BrokenPipe
+        let w = &mut wires.wires[self.rx];
+        w.orphan = true;
+        w.sent.clear();
+        w.arrived.clear();
+        let writer = w.writer;
+        if std::mem::take(&mut w.blocked) {
+            wires.wake(writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rng::Seeded;
+
+    #[test]
+    fn test_pipe() {
+        let net = Net::default();
+        let (mut a, mut b) = pair(&net, Owner::Client(0), Owner::Lb(1), 8);
+        let mut buf = [0u8; 16];
+
+        // cabe só a capacidade, o resto é WouldBlock até o outro lado ler
+        assert_eq!(a.write(b"0123456789").unwrap(), 8);
+        assert!(a.write(b"89").is_err());
+        assert!(b.read(&mut buf).is_err());
+
+        let mut rng = Seeded::new(1);
+        while net.borrow().wires[0].delivering {
+            net.borrow_mut().deliver(0, &mut rng);
+        }
+        assert_eq!(b.read(&mut buf).unwrap(), 8);
+        assert_eq!(&buf[..8], b"01234567");
+        assert!(
+            net.borrow_mut()
+                .take_wakes()
+                .contains(&Wake::Ready(Owner::Client(0)))
+        );
+
+        // close: EOF de um lado, BrokenPipe do outro
+        drop(a);
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+        assert_eq!(
+            b.write(b"x").unwrap_err().kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use std::{collections::VecDeque, io::Write, net::IpAddr};
+
+use connection::reactor::{Interest, Reactor};
+
+use crate::net::{Owner, Pipe};
+
+// O epoll do simulador para os loops de verdade: registrar é adotar a ponta
+// do fio, então a prontidão do token vira evento, e o listener é uma fila
+// que o simulador enche
+pub struct SimReactor {
+    side: Side,
+    pub backlog: VecDeque<(Pipe, Option<IpAddr>)>,
+    pub listening: bool,
+}
+
+// De qual loop é o reactor, o token sozinho não diz
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Lb,
+    Worker(usize),
+}
+
+impl Side {
+    pub fn owner(self, token: usize) -> Owner {
+        match self {
+            Side::Lb => Owner::Lb(token),
+            Side::Worker(n) => Owner::Worker(n, token),
+        }
+    }
+}
+
+impl SimReactor {
+    pub fn new(side: Side) -> SimReactor {
+        SimReactor {
+            side,
+            backlog: VecDeque::new(),
+            listening: true,
+        }
+    }
+}
+
+impl Reactor for SimReactor {
+    type Stream = Pipe;
+
+    fn accept(&mut self) -> std::io::Result<(Pipe, Option<IpAddr>)> {
+        self.backlog
+            .pop_front()
+            .ok_or_else(|| std::io::ErrorKind::WouldBlock.into())
+    }
+
+    fn register(&mut self, stream: &mut Pipe, token: usize, _: Interest) -> std::io::Result<()> {
+        stream.adopt(self.side.owner(token));
+        Ok(())
+    }
+
+    // Como no mio, o reregister avisa de novo o que já está pronto
+    fn reregister(&mut self, stream: &mut Pipe, token: usize, _: Interest) -> std::io::Result<()> {
+        stream.adopt(self.side.owner(token));
+        Ok(())
+    }
+
+    fn deregister(&mut self, stream: &mut Pipe) -> std::io::Result<()> {
+        stream.adopt(Owner::Nobody);
+        Ok(())
+    }
+
+    // Quem ainda estava na fila do listener leva EOF
+    fn close(&mut self) {
+        self.listening = false;
+        self.backlog.clear();
+    }
+
+    fn refuse(&mut self, mut stream: Pipe, response: &[u8]) {
+        let _ = stream.write(response);
+    }
+}
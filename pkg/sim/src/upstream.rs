@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ledger::{Processor, Totals};
+use worker::processor::Payment;
+
+use crate::{
+    clock::VirtualClock,
+    rng::{Rng, Seeded},
+};
+
+// Processador de pagamentos com o comportamento que importa para a
+// contagem: 422 para correlationId repetido e, fora do ar, 500 ou conexão
+// recusada sem guardar nada
+#[derive(Default)]
+pub struct SimProcessor {
+    pub payments: BTreeMap<[u8; 36], (u64, u64)>, // amount, requestedAt
+    pub down: bool,
+}
+
+impl SimProcessor {
+    pub fn summary(&self, from: u64, to: u64) -> Totals {
+        self.payments
+            .values()
+            .filter(|(_, requested_at)| (from..=to).contains(requested_at))
+            .fold(Totals::default(), |totals, (amount, _)| Totals {
+                requests: totals.requests + 1,
+                amount: totals.amount + amount,
+            })
+    }
+}
+
+// O Upstream do worker::processor::process: cada chamada resolve na hora,
+// mas a resposta pode se perder (timeout) com o pagamento aceito ou não
+pub struct SimUpstream<'a> {
+    pub processors: &'a mut [SimProcessor; 2],
+    pub rng: &'a mut Seeded,
+    pub clock: &'a VirtualClock,
+    pub lost: u64,                         // % das respostas que viram timeout
+    pub sent: &'a BTreeMap<[u8; 36], u64>, // o que os clientes mandaram
+    // Pagamentos que já tiveram resposta final (2xx ou 422) no worker:
+    // chamada nova para um deles é o mesmo pagamento entregue duas vezes
+    pub settled: &'a mut BTreeSet<[u8; 36]>,
+    pub violations: &'a mut Vec<String>,
+}
+
+impl worker::processor::Upstream for SimUpstream<'_> {
+    fn pay(&mut self, processor: Processor, payment: &Payment) -> std::io::Result<u16> {
+        let id = payment.correlation_id.0;
+        let name = String::from_utf8_lossy(&id);
+        if self.sent.get(&id) != Some(&payment.amount) {
+            self.violations.push(format!(
+                "{name} of {} never sent by a client",
+                payment.amount
+            ));
+        }
+        if self.settled.contains(&id) {
+            self.violations
+                .push(format!("{name} reached a processor after it was settled"));
+        }
+        let other = &self.processors[1 - processor as usize];
+        let taken = other.payments.contains_key(&id);
+        let target = &mut self.processors[processor as usize];
+        if target.down {
+            return match self.rng.percent(50) {
+                true => Ok(500),
+                false => Err(std::io::ErrorKind::NotConnected.into()),
+            };
+        }
+
+        // timeout antes de chegar no processador
+        if self.rng.percent(self.lost / 2) {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        let status = match target.payments.contains_key(&id) {
+            true => 422,
+            false => {
+                if taken {
+                    self.violations
+                        .push(format!("{name} paid on both processors"));
+                }
+                target
+                    .payments
+                    .insert(id, (payment.amount, payment.requested_at()));
+                200
+            }
+        };
+        // aceito, mas a resposta não volta a tempo
+        if self.rng.percent(self.lost / 2) {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.settled.insert(id);
+        Ok(status)
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.clock.millis()
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread::JoinHandle,
+};
+
+use config::Config;
+use connection::{memory, shutdown};
+use ledger::Ledger;
+use load_balance::Link;
+
+// Load balancer e workers no mesmo processo: os workers rodam em threads,
+// falam com o load balancer por links em memória e os ledgers são memória
+// anônima. Roteamento, ledger e processadores são os mesmos do modo separado.
+
+pub fn start(config: &Config) {
+    let listener =
+        TcpListener::bind(("0.0.0.0", config.lb.port)).expect("unable to listen on TCP socket");
+    run(config, listener);
+}
+
+// Roda em background, pensado para testes de integração sem docker
+pub fn spawn(config: Config, addr: &str) -> std::io::Result<Handle> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    let thread = std::thread::Builder::new()
+        .name("standalone".to_string())
+        .spawn(move || run(&config, listener))?;
+
+    Ok(Handle { addr, thread })
+}
+
+pub struct Handle {
+    pub addr: SocketAddr,
+    thread: JoinHandle<()>,
+}
+
+impl Handle {
+    // Mesmo caminho do SIGTERM, o shutdown vale para o processo inteiro
+    pub fn stop(self) {
+        shutdown::request();
+        if self.thread.join().is_err() {
+            logger::error!("Standalone thread panicked");
+        }
+    }
+}
+
+fn run(config: &Config, listener: TcpListener) {
+    let count = config.standalone.workers;
+    let mut links = Vec::with_capacity(count);
+    let mut ledgers = Vec::with_capacity(count);
+    let mut threads = Vec::with_capacity(count);
+
+    for n in 1..=count {
+        let (lb_end, worker_end) = memory::pair();
+        let ledger = Arc::new(Ledger::anonymous().expect("unable to map ledger memory"));
+        let common = config.common.clone();
+        let mut worker = config.worker.clone();
+        worker.host = format!("{}-{n}", config.worker.host);
+
+        let shared = ledger.clone();
+        threads.push(
+            std::thread::Builder::new()
+                .name(worker.host.clone())
+                .spawn(move || worker::start_linked(&common, &worker, worker_end, shared))
+                .expect("unable to spawn worker thread"),
+        );
+        links.push(Link::Memory(lb_end));
+        ledgers.push(ledger);
+    }
+
+    // Cada worker tem um link só, então aqui é sempre um event loop
+    if config.lb.threads > 1 {
+        logger::warn!("lb.threads is ignored in standalone mode"; threads = config.lb.threads);
+    }
+    logger::info!("Starting standalone"; workers = count, addr = listener.local_addr().map(|a| a.to_string()).unwrap_or_default());
+    load_balance::start_with(&config.common, &config.lb, listener, links, ledgers);
+
+    // O load balancer fecha os links no fim do drain, os workers saem em seguida
+    for thread in threads {
+        if thread.join().is_err() {
+            logger::error!("Worker thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    // Processador falso: aceita qualquer pagamento
+    fn fake_processor() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                std::thread::spawn(move || {
+                    let mut buffer = Vec::new();
+                    let mut chunk = [0u8; 1024];
+                    while let Ok(n) = stream.read(&mut chunk) {
+                        if n == 0 {
+                            break;
+                        }
+                        buffer.extend_from_slice(&chunk[..n]);
+
+                        // um pagamento por vez, o cliente espera a resposta
+                        let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") else {
+                            continue;
+                        };
+                        let head = String::from_utf8_lossy(&buffer[..end]).to_lowercase();
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length:"))
+                            .and_then(|value| value.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if buffer.len() < end + 4 + length {
+                            continue;
+                        }
+
+                        buffer.drain(..end + 4 + length);
+                        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn request(addr: SocketAddr, raw: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    }
+
+    fn summary(addr: SocketAddr) -> String {
+        request(addr, "GET /payments-summary HTTP/1.1\r\nHost: x\r\n\r\n")
+    }
+
+    #[test]
+    fn test_payment_round_trip() {
+        logger::init("warn");
+        let processor = format!("http://{}", fake_processor());
+        let vars = [
+            ("MODE", "standalone"),
+            ("PROCESSOR_DEFAULT_URL", processor.as_str()),
+            ("PROCESSOR_FALLBACK_URL", processor.as_str()),
+            ("DRAIN_TIMEOUT_MS", "1000"),
+        ];
+        let config = Config::from_pairs(&vars).unwrap();
+        let handle = spawn(config, "127.0.0.1:0").unwrap();
+        let addr = handle.addr;
+
+        let body = r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90}"#;
+        let response = request(
+            addr,
+            &format!(
+                "POST /payments HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        let expected = r#""default":{"totalRequests":1,"totalAmount":19.90}"#;
+        let started = Instant::now();
+        while !summary(addr).contains(expected) {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "{}",
+                summary(addr)
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        let response = request(addr, "POST /purge-payments HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(summary(addr).contains(r#""default":{"totalRequests":0,"totalAmount":0.00}"#));
+
+        handle.stop();
+    }
+}
@@ -1,98 +1,540 @@
-use connection::{Connection, Status};
-use mio::{
-    Events, Poll, Token,
-    net::{UnixListener, UnixStream},
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
-const SERVER: Token = Token(0);
-const MAX_SLOTS: usize = 5;
+use connection::{
+    memory::Endpoint,
+    reactor::{Interest, Reactor, Ready},
+    ring::Ring,
+    shutdown::{self, Scope},
+    transport::{Address, Listener, Stream},
+};
+use ledger::{Ledger, Processor};
+use message::socket::Message;
+use mio::{Events, Poll, Registry, Token};
+
+use crate::{
+    processor::{Payment, Processors, persist, restore, start_processors},
+    server::{SERVER, SHUTDOWN, Server},
+};
+
+mod metrics;
+pub mod processor;
+pub mod server;
+#[cfg(feature = "io-uring")]
+mod uring;
+
+// O que o worker faz com cada mensagem, igual para socket e link em memória
+pub struct Node {
+    hostname: String,
+    ledger: Arc<Ledger>,
+    processors: Processors,
+    pending_path: Option<String>,
+    // No standalone o registry é o mesmo do load balancer, que já renderiza tudo
+    shared_metrics: bool,
+    credit_window: u64,
+    // Sem threads de processador nem de ring, quem criou faz andar (o simulador)
+    polled: bool,
+}
+
+// Por conexão do load balancer: pagamentos que chegaram pelo socket e até
+// onde ele já pode mandar. O limite é acumulado, então frame em voo não
+// confunde a conta de nenhum dos lados.
+#[derive(Default, Clone, Copy)]
+struct Credit {
+    received: u64,
+    limit: u64,
+}
 
-pub fn start(mut socket_dir: String) {
-    // get hostname from environment variable or use default
-    let hostname = std::env::var("HOST").unwrap_or_else(|_| "worker".to_string());
-    socket_dir.extend(format!("/{hostname}.sock").chars());
-    let _ = std::fs::remove_file(&socket_dir);
-    println!("Starting worker on: {socket_dir}");
+impl Node {
+    fn open(
+        common: &config::Common,
+        config: &config::Worker,
+        ledger: Arc<Ledger>,
+        shared_metrics: bool,
+    ) -> Node {
+        let processors = start_processors(ledger.clone(), config);
+        Node::with(common, config, ledger, processors, shared_metrics, false)
+    }
+
+    // A fila anda por Processors::line e os rings por Server::consume
+    pub fn polled(common: &config::Common, config: &config::Worker, ledger: Arc<Ledger>) -> Node {
+        let processors = Processors::new(ledger.clone());
+        Node::with(common, config, ledger, processors, true, true)
+    }
+
+    fn with(
+        common: &config::Common,
+        config: &config::Worker,
+        ledger: Arc<Ledger>,
+        processors: Processors,
+        shared_metrics: bool,
+        polled: bool,
+    ) -> Node {
+        let hostname = config.host.clone();
+        metrics::register();
 
-    let mut listener = UnixListener::bind(socket_dir).expect("unable to listen on UNIX socket");
+        // Sobras do último shutdown voltam para a fila com o mesmo requestedAt
+        let pending_path = (!common.socket_dir.is_empty())
+            .then(|| format!("{}/{hostname}.pending", common.socket_dir));
+        let restored = pending_path.as_deref().map(restore).unwrap_or_default();
+        if !restored.is_empty() {
+            logger::info!("Restoring pending payments"; worker = hostname, count = restored.len());
+        }
+        restored
+            .into_iter()
+            .for_each(|payment| processors.send(payment));
 
-    // Performance 10 * 54 max messages per read
-    let mut conn_poll: [Connection<540, UnixStream>; MAX_SLOTS] =
-        std::array::from_fn(|_| Connection::new(None));
+        Node {
+            hostname,
+            ledger,
+            processors,
+            pending_path,
+            shared_metrics,
+            credit_window: config.credit_window,
+            polled,
+        }
+    }
 
+    pub fn processors(&self) -> &Processors {
+        &self.processors
+    }
+
+    fn health(&self) -> Message {
+        let (queued, retrying) = self.processors.depth();
+        let depth = |depth: u64| depth.min(u32::MAX as u64) as u32;
+        Message::Health(depth(queued), depth(retrying))
+    }
+
+    // Só quando o limite anda. A folga é da fila inteira, com dois load
+    // balancers cada um pode ocupar a janela toda.
+    fn credit(&self, credit: &mut Credit) -> Option<Message> {
+        if self.credit_window == 0 {
+            return None;
+        }
+        let (queued, retrying) = self.processors.depth();
+        let backlog = queued + retrying;
+        let limit = credit.received + self.credit_window.saturating_sub(backlog);
+        (limit > credit.limit).then(|| {
+            credit.limit = limit;
+            Message::Credit(limit)
+        })
+    }
+
+    // O que vai a cada tick: Health sempre, Credit sem ring (o ring cheio já
+    // segura o load balancer)
+    fn announce(
+        &self,
+        credit: &mut Credit,
+        ring: bool,
+        reply: &mut dyn FnMut(&Message) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        reply(&self.health())?;
+        match self.credit(credit) {
+            Some(grant) if !ring => reply(&grant),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle(&self, message: Message, reply: &mut dyn FnMut(&Message) -> std::io::Result<()>) {
+        let hostname = &self.hostname;
+        match message {
+            Message::Payment(amount, correlation_id) => {
+                self.processors.send(Payment::new(amount, correlation_id));
+            }
+            // Load balancer sem acesso aos arquivos de ledger (TCP) pergunta pelo link
+            Message::Summary(from, to) => {
+                // sem resposta o load balancer responde 503 no timeout
+                let summary = match self.ledger.summary(from, to) {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        logger::error!("Failed to read ledger: {e}"; worker = hostname);
+                        return;
+                    }
+                };
+                let mut totals = [0u8; 32];
+                for (i, processor) in Processor::ALL.iter().enumerate() {
+                    let processor = summary.get(*processor);
+                    totals[i * 16..i * 16 + 8].copy_from_slice(&processor.requests.to_be_bytes());
+                    totals[i * 16 + 8..i * 16 + 16]
+                        .copy_from_slice(&processor.amount.to_be_bytes());
+                }
+                for chunk in Message::chunks(&totals) {
+                    if let Err(e) = reply(&chunk) {
+                        logger::error!("Failed to send summary: {e}"; worker = hostname);
+                        break;
+                    }
+                }
+            }
+            Message::Purge => {
+                self.processors.purge();
+                if let Err(e) = reply(&Message::Ack) {
+                    logger::error!("Failed to confirm purge: {e}"; worker = hostname);
+                }
+            }
+            Message::Metrics => {
+                let text = match self.shared_metrics {
+                    true => String::new(),
+                    false => ::metrics::render(Some(("worker", hostname))),
+                };
+                for chunk in Message::chunks(text.as_bytes()) {
+                    if let Err(e) = reply(&chunk) {
+                        logger::error!("Failed to send metrics: {e}"; worker = hostname);
+                        break;
+                    }
+                }
+            }
+            // handshake do load balancer
+            Message::Ack => {}
+            other => {
+                logger::warn!("Unexpected message: {other:?}"; worker = hostname);
+                let _ = reply(&Message::notice(&format!("unexpected {other:?}")));
+            }
+        }
+    }
+
+    fn stop(self, deadline: Instant) {
+        let hostname = self.hostname;
+        let drained = self.processors.drain(deadline);
+        if !drained.pending.is_empty() {
+            let persisted = match &self.pending_path {
+                Some(path) => persist(path, &drained.pending),
+                None => Err(std::io::Error::other("no socket_dir to write them")),
+            };
+            if let Err(e) = persisted {
+                logger::error!("Failed to persist pending payments: {e}"; worker = hostname, count = drained.pending.len());
+            }
+        }
+
+        logger::info!(
+            "Worker stopped";
+            worker = hostname,
+            finished = drained.finished,
+            persisted = drained.pending.len()
+        );
+    }
+}
+
+pub fn start(common: &config::Common, config: &config::Worker) {
+    let address = address(common, config);
+    let listener = Listener::bind(&address).expect("unable to listen for the load balancer");
+    serve(common, config, address, listener, &Scope::default());
+}
+
+// Worker numa thread com shutdown só dele, para o harness de testes. O
+// socket já existe quando volta, o load balancer pode conectar em seguida.
+pub fn spawn(common: config::Common, config: config::Worker) -> std::io::Result<Handle> {
+    let address = address(&common, &config);
+    let listener = Listener::bind(&address)?;
+    let scope = Arc::new(Scope::default());
+    let stop = scope.clone();
+    let thread = std::thread::Builder::new()
+        .name(config.host.clone())
+        .spawn(move || serve(&common, &config, address, listener, &stop))?;
+
+    Ok(Handle { scope, thread })
+}
+
+pub struct Handle {
+    scope: Arc<Scope>,
+    thread: JoinHandle<()>,
+}
+
+impl Handle {
+    // Como um SIGTERM só para este worker: drain e sobras no .pending
+    pub fn stop(self) {
+        self.scope.request();
+        self.join();
+    }
+
+    // Para quando o shutdown é do processo inteiro
+    pub fn join(self) {
+        if self.thread.join().is_err() {
+            logger::error!("Worker thread panicked");
+        }
+    }
+}
+
+fn address(common: &config::Common, config: &config::Worker) -> Address {
+    match &config.listen {
+        Some(listen) => Address::parse(listen),
+        None => Address::Unix(format!("{}/{}.sock", common.socket_dir, config.host).into()),
+    }
+}
+
+fn serve(
+    common: &config::Common,
+    config: &config::Worker,
+    address: Address,
+    listener: Listener,
+    scope: &Scope,
+) {
+    let hostname = &config.host;
+    let socket_dir = &common.socket_dir;
+
+    // Sem pasta (só TCP) o ledger fica em memória e o load balancer pergunta pelo link
+    let ledger = match socket_dir.is_empty() {
+        true => Ledger::anonymous(),
+        false => Ledger::create(format!("{socket_dir}/{hostname}.{}", ledger::EXTENSION)),
+    }
+    .expect("unable to create ledger");
+    let node = Arc::new(Node::open(common, config, Arc::new(ledger), false));
+    logger::info!("Starting worker on: {address}"; worker = hostname);
+    shutdown::install();
+
+    // Com a feature io-uring o loop troca de backend, se o kernel recusar fica no epoll
+    #[cfg(feature = "io-uring")]
+    let deadline = match connection::uring::Reactor::new(config.max_slots, config.buffer_size) {
+        Ok(mut reactor) => {
+            logger::info!("Using io_uring event loop"; worker = hostname);
+            // o shutdown do scope, que também acorda no global
+            reactor.watch(scope.eventfd().expect("unable to watch for shutdown"));
+            uring::serve(common, config, &address, listener, reactor, &node)
+        }
+        Err(e) => {
+            logger::warn!("io_uring unavailable, falling back to epoll: {e}"; worker = hostname);
+            epoll(common, config, address, listener, &node, scope)
+        }
+    };
+    #[cfg(not(feature = "io-uring"))]
+    let deadline = epoll(common, config, address, listener, &node, scope);
+
+    Arc::into_inner(node)
+        .expect("ring consumers still hold the node")
+        .stop(deadline.unwrap_or_else(Instant::now));
+}
+
+// O loop do mio em volta do Server: espera a prontidão e passa adiante
+fn epoll(
+    common: &config::Common,
+    config: &config::Worker,
+    address: Address,
+    mut listener: Listener,
+    node: &Arc<Node>,
+    scope: &Scope,
+) -> Option<Instant> {
     let mut io_poll = Poll::new().expect("unable to create poll instance");
     let mut events = Events::with_capacity(1024);
-    let mut next_token = 1;
-    let mut conn_count = 0;
 
     io_poll
         .registry()
-        .register(&mut listener, SERVER, mio::Interest::READABLE)
+        .register(&mut listener, Token(SERVER), Interest::READABLE)
         .expect("unable to register listener with poll");
+    scope
+        .watch(io_poll.registry(), Token(SHUTDOWN))
+        .expect("unable to watch for shutdown");
 
-    let mut req_count = 0;
-
-    loop {
-        let mut max_conn_per_iter = 10;
-        io_poll.poll(&mut events, None).expect("poll failed");
-
-        for event in &events {
-            match event.token() {
-                SERVER => {
-                    while max_conn_per_iter > 0 && conn_count < MAX_SLOTS {
-                        match listener.accept() {
-                            Ok((mut stream, _)) => {
-                                let token = Token(next_token);
-                                let slot_index = next_token % MAX_SLOTS;
-                                io_poll
-                                    .registry()
-                                    .register(
-                                        &mut stream,
-                                        token,
-                                        mio::Interest::READABLE | mio::Interest::WRITABLE,
-                                    )
-                                    .expect("unable to register stream with poll");
-
-                                println!("Accepted connection: {:?}", stream.peer_addr());
-                                conn_poll[slot_index].stream = Some(stream);
-                                conn_poll[slot_index].status = Status::Readable;
-                                next_token += 1;
-                                conn_count += 1;
-                                max_conn_per_iter -= 1;
-                            }
-                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                                // No more connections to accept
-                                break;
-                            }
-                            Err(e) => {
-                                eprintln!("Error accepting connection: {e}");
-                            }
-                        }
-                    }
+    let reactor = Mio {
+        registry: io_poll
+            .registry()
+            .try_clone()
+            .expect("unable to clone poll registry"),
+        listener: Some(listener),
+    };
+    let mut server = Server::new(
+        common,
+        config,
+        address,
+        reactor,
+        node.clone(),
+        Instant::now(),
+    );
+    let mut ready: Vec<Ready> = Vec::with_capacity(config.max_slots);
+
+    while !server.done(Instant::now()) {
+        if let Err(e) = io_poll.poll(&mut events, server.timeout(Instant::now())) {
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            panic!("poll failed: {e}");
+        }
+
+        ready.clear();
+        ready.extend(events.iter().map(Ready::from));
+        server.turn(&ready, Instant::now());
+    }
+
+    server.stop()
+}
+
+// O Reactor de produção: o mio com o listener do worker (UNIX ou TCP)
+struct Mio {
+    registry: Registry,
+    listener: Option<Listener>,
+}
+
+impl Reactor for Mio {
+    type Stream = Stream;
+
+    fn accept(&mut self) -> std::io::Result<(Stream, Option<IpAddr>)> {
+        let Some(listener) = self.listener.as_ref() else {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        };
+        listener.accept().map(|stream| (stream, None))
+    }
+
+    fn register(
+        &mut self,
+        stream: &mut Stream,
+        token: usize,
+        interest: Interest,
+    ) -> std::io::Result<()> {
+        self.registry.register(stream, Token(token), interest)
+    }
+
+    fn reregister(
+        &mut self,
+        stream: &mut Stream,
+        token: usize,
+        interest: Interest,
+    ) -> std::io::Result<()> {
+        self.registry.reregister(stream, Token(token), interest)
+    }
+
+    fn deregister(&mut self, stream: &mut Stream) -> std::io::Result<()> {
+        self.registry.deregister(stream)
+    }
+
+    fn close(&mut self) {
+        if let Some(mut listener) = self.listener.take() {
+            let _ = self.registry.deregister(&mut listener);
+            listener.close();
+        }
+    }
+
+    // O worker não recusa conexão, quem conecta é o load balancer
+    fn refuse(&mut self, stream: Stream, _: &[u8]) {
+        drop(stream);
+    }
+}
+
+// Attach é do transporte (cada conexão tem no máximo um ring), o resto vai para o Node
+fn dispatch(
+    message: Message,
+    address: &Address,
+    node: &Arc<Node>,
+    ring: &mut Option<Consumer>,
+    token: usize,
+    reply: &mut dyn FnMut(&Message) -> std::io::Result<()>,
+) {
+    let hostname = &node.hostname;
+    let Message::Attach(len, name) = message else {
+        node.handle(message, reply);
+        return;
+    };
+
+    let name = String::from_utf8_lossy(&name[..len as usize]).into_owned();
+    let answer = match attach(address, &name, node) {
+        Ok(consumer) => {
+            logger::info!("Attached shared ring: {name}"; worker = hostname, token = token);
+            if let Some(old) = ring.replace(consumer) {
+                old.stop(node);
+            }
+            Message::Ack
+        }
+        Err(e) => {
+            // Sem Ack o load balancer continua só com o socket
+            logger::warn!("Unable to attach ring {name}: {e}"; worker = hostname);
+            Message::notice(&format!("attach failed: {e}"))
+        }
+    };
+    if let Err(e) = reply(&answer) {
+        logger::error!("Failed to answer attach: {e}"; worker = hostname);
+    }
+}
+
+// Quem consome o ring de uma conexão do load balancer: uma thread, ou
+// ninguém com o Node sem threads e o ring esperando Server::consume
+enum Consumer {
+    Thread(Arc<AtomicBool>, JoinHandle<()>),
+    Polled(Ring),
+}
+
+impl Consumer {
+    fn stop(self, node: &Node) {
+        match self {
+            Consumer::Thread(stop, thread) => {
+                stop.store(true, Ordering::Release);
+                if thread.join().is_err() {
+                    logger::error!("Ring consumer panicked"; worker = node.hostname);
                 }
-                token => {
-                    let slot_idx = token.0 % MAX_SLOTS;
-                    let conn = conn_poll
-                        .get_mut(slot_idx)
-                        .expect("no connection found for token");
-
-                    let _message = match conn.read_messages() {
-                        Ok(e) => e,
-                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            continue;
-                        }
-                        Err(e) => {
-                            println!(
-                                "Failed to read message from connection: {e} is better to panic"
-                            );
-                            continue;
-                        }
-                    };
-
-                    req_count += 1;
-                    print!("Req {req_count}");
+            }
+            // o load balancer escreve tudo antes de fechar o socket
+            Consumer::Polled(ring) => {
+                consume(&ring, node);
+            }
+        }
+    }
+}
+
+fn consume(ring: &Ring, node: &Node) -> usize {
+    ring.consume(|frame| match Message::from_bytes(frame) {
+        // Só pagamentos chegam pelo ring, não tem por onde responder
+        Ok(message) => node.handle(message, &mut |_| {
+            Err(std::io::Error::other("ring is one way"))
+        }),
+        Err(e) => logger::warn!("Failed to parse ring frame: {e}"; worker = node.hostname),
+    })
+}
+
+fn attach(address: &Address, name: &str, node: &Arc<Node>) -> std::io::Result<Consumer> {
+    let Address::Unix(socket) = address else {
+        return Err(std::io::Error::other("shared ring needs a UNIX socket"));
+    };
+    // só o nome, o arquivo fica sempre ao lado do socket
+    if name.is_empty() || Path::new(name).file_name() != Some(name.as_ref()) {
+        return Err(std::io::Error::other("invalid ring name"));
+    }
+
+    let ring = Ring::open(socket.with_file_name(name))?;
+    if node.polled {
+        return Ok(Consumer::Polled(ring));
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let node = node.clone();
+    let stopped = stop.clone();
+    let thread = std::thread::Builder::new()
+        .name(format!("ring-{}", node.hostname))
+        .spawn(move || {
+            while !stopped.load(Ordering::Acquire) {
+                if consume(&ring, &node) == 0 {
+                    ring.wait(Duration::from_millis(100));
                 }
             }
+            consume(&ring, &node);
+        })?;
+
+    Ok(Consumer::Thread(stop, thread))
+}
+
+// Worker numa thread do mesmo processo que o load balancer (modo standalone),
+// termina quando o load balancer fecha o link depois do drain
+pub fn start_linked(
+    common: &config::Common,
+    config: &config::Worker,
+    link: Endpoint,
+    ledger: Arc<Ledger>,
+) {
+    let node = Node::open(common, config, ledger, true);
+    logger::info!("Starting linked worker"; worker = config.host);
+
+    while let Ok(frame) = link.rx.recv() {
+        match Message::from_bytes(&frame) {
+            Ok(message) => node.handle(message, &mut |reply| {
+                link.tx.send(reply.to_bytes()).map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "load balancer is gone")
+                })
+            }),
+            Err(e) => logger::warn!("Failed to parse message: {e}"; worker = config.host),
         }
     }
+
+    node.stop(Instant::now() + common.drain_timeout);
 }
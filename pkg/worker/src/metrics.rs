@@ -0,0 +1,96 @@
+use ledger::Processor;
+use metrics::{Counter, Gauge, Hdr, Metric};
+
+pub static QUEUE_DEPTH: Gauge = Gauge::new(
+    "rinha_worker_queue_depth",
+    "Payments waiting for a processor thread",
+    &[],
+);
+pub static RETRY_DEPTH: Gauge = Gauge::new(
+    "rinha_worker_retry_depth",
+    "Payments refused by both processors waiting for another attempt",
+    &[],
+);
+pub static PURGES: Counter = Counter::new("rinha_worker_purges_total", "Purges executed", &[]);
+
+static UPSTREAM_CALLS: [[Counter; 3]; 2] = [
+    [
+        upstream_calls(&[("processor", "default"), ("outcome", "success")]),
+        upstream_calls(&[("processor", "default"), ("outcome", "rejected")]),
+        upstream_calls(&[("processor", "default"), ("outcome", "error")]),
+    ],
+    [
+        upstream_calls(&[("processor", "fallback"), ("outcome", "success")]),
+        upstream_calls(&[("processor", "fallback"), ("outcome", "rejected")]),
+        upstream_calls(&[("processor", "fallback"), ("outcome", "error")]),
+    ],
+];
+
+static UPSTREAM_LATENCY: [Hdr; 2] = [
+    upstream_latency(&[("processor", "default")]),
+    upstream_latency(&[("processor", "fallback")]),
+];
+
+pub static QUEUE_WAIT: Hdr = Hdr::new(
+    "rinha_worker_queue_wait_seconds",
+    "Time a payment waited in the queue before a processor thread picked it",
+    &[],
+);
+
+static LEDGER_PAYMENTS: [Gauge; 2] = [
+    ledger_payments(&[("processor", "default")]),
+    ledger_payments(&[("processor", "fallback")]),
+];
+
+const fn upstream_calls(labels: metrics::Labels) -> Counter {
+    Counter::new(
+        "rinha_upstream_calls_total",
+        "Calls to the payment processors",
+        labels,
+    )
+}
+
+const fn upstream_latency(labels: metrics::Labels) -> Hdr {
+    Hdr::new(
+        "rinha_upstream_latency_seconds",
+        "Payment processor response time",
+        labels,
+    )
+}
+
+const fn ledger_payments(labels: metrics::Labels) -> Gauge {
+    Gauge::new(
+        "rinha_ledger_payments",
+        "Payments recorded in the ledger since the last purge",
+        labels,
+    )
+}
+
+pub enum Outcome {
+    Success = 0,
+    Rejected = 1,
+    Error = 2,
+}
+
+pub fn upstream(processor: Processor, outcome: Outcome, micros: u64) {
+    UPSTREAM_CALLS[processor as usize][outcome as usize].inc();
+    UPSTREAM_LATENCY[processor as usize].record(micros);
+}
+
+pub fn recorded(processor: Processor) {
+    LEDGER_PAYMENTS[processor as usize].inc();
+}
+
+pub fn purged() {
+    PURGES.inc();
+    LEDGER_PAYMENTS.iter().for_each(|gauge| gauge.set(0));
+}
+
+pub fn register() {
+    let mut all: Vec<&'static dyn Metric> = vec![&QUEUE_DEPTH, &RETRY_DEPTH, &PURGES];
+    all.extend(UPSTREAM_CALLS.iter().flatten().map(|c| c as &dyn Metric));
+    all.extend(LEDGER_PAYMENTS.iter().map(|g| g as &dyn Metric));
+    metrics::register(&all);
+    metrics::hdr::register(&[&QUEUE_WAIT, &UPSTREAM_LATENCY[0], &UPSTREAM_LATENCY[1]]);
+    connection::metrics::register();
+}
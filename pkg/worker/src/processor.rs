@@ -0,0 +1,664 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender, channel},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use ledger::{Ledger, Processor};
+use message::{CorrelationId, time};
+
+use crate::metrics::{self, Outcome};
+
+pub struct Payment {
+    pub amount: u64,
+    pub correlation_id: CorrelationId,
+    requested_at: u64, // 0 até a primeira tentativa
+    // Processador que pode ter aceitado sem responder, só ele tenta de novo
+    pinned: Option<Processor>,
+    generation: u64,
+    enqueued_at: Instant,
+}
+
+impl Payment {
+    pub fn new(amount: u64, correlation_id: CorrelationId) -> Self {
+        Payment {
+            amount,
+            correlation_id,
+            requested_at: 0,
+            pinned: None,
+            generation: 0,
+            enqueued_at: Instant::now(),
+        }
+    }
+
+    pub fn requested_at(&self) -> u64 {
+        self.requested_at
+    }
+
+    // "correlationId amount requestedAt [processador]", requestedAt e
+    // processador precisam sobreviver ao restart pelo mesmo motivo do retry
+    fn write_line(&self, out: &mut Vec<u8>) {
+        let id_len = self
+            .correlation_id
+            .0
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.correlation_id.0.len());
+        out.extend_from_slice(&self.correlation_id.0[..id_len]);
+        let _ = write!(out, " {} {}", self.amount, self.requested_at);
+        if let Some(pinned) = self.pinned {
+            let _ = write!(out, " {}", pinned.name());
+        }
+        out.push(b'\n');
+    }
+
+    fn parse_line(line: &str) -> Option<Payment> {
+        let mut parts = line.split(' ');
+        let (id, amount, requested_at) = (parts.next()?, parts.next()?, parts.next()?);
+        let pinned = match parts.next() {
+            None => None,
+            Some(name) => Some(*Processor::ALL.iter().find(|p| p.name() == name)?),
+        };
+        if id.is_empty() || id.len() > 36 || parts.next().is_some() {
+            return None;
+        }
+
+        let mut correlation_id = [0u8; 36];
+        correlation_id[..id.len()].copy_from_slice(id.as_bytes());
+        let mut payment = Payment::new(amount.parse().ok()?, CorrelationId(correlation_id));
+        payment.requested_at = requested_at.parse().ok()?;
+        payment.pinned = pinned;
+        Some(payment)
+    }
+}
+
+// Pagamentos que não terminaram até o deadline do shutdown
+pub fn persist(path: &str, payments: &[Payment]) -> std::io::Result<()> {
+    let mut out = Vec::with_capacity(payments.len() * 64);
+    payments
+        .iter()
+        .for_each(|payment| payment.write_line(&mut out));
+    std::fs::write(path, out)
+}
+
+// Lê e apaga o arquivo deixado pelo último shutdown
+pub fn restore(path: &str) -> Vec<Payment> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let _ = std::fs::remove_file(path);
+
+    text.lines()
+        .filter_map(|line| {
+            let payment = Payment::parse_line(line);
+            if payment.is_none() {
+                logger::warn!("Ignoring invalid pending payment: {line}");
+            }
+            payment
+        })
+        .collect()
+}
+
+pub struct Drained {
+    pub finished: usize,
+    pub pending: Vec<Payment>,
+}
+
+// Fila e retries deste worker. Os gauges são do processo, e no standalone
+// (ou no simulador) vários workers dividem o mesmo registry: o crédito
+// olha só o que é dele.
+#[derive(Default)]
+struct Depth {
+    queued: AtomicI64,
+    retrying: AtomicI64,
+}
+
+impl Depth {
+    fn queued(&self, delta: i64) {
+        self.queued.fetch_add(delta, Ordering::AcqRel);
+        metrics::QUEUE_DEPTH.add(delta);
+    }
+
+    fn retrying(&self, delta: i64) {
+        self.retrying.fetch_add(delta, Ordering::AcqRel);
+        metrics::RETRY_DEPTH.add(delta);
+    }
+}
+
+pub struct Processors {
+    tx: Sender<Payment>,
+    rx: Arc<Mutex<Receiver<Payment>>>,
+    ledger: Arc<Ledger>,
+    // Incrementado a cada purge, pagamentos de gerações antigas são descartados
+    // da fila e dos retries sem chamar o processador
+    generation: Arc<AtomicU64>,
+    depth: Arc<Depth>,
+    deadline: Arc<OnceLock<Instant>>,
+    threads: Vec<JoinHandle<Vec<Payment>>>,
+}
+
+impl Processors {
+    // Sem threads: quem criou consome a fila com line (o simulador)
+    pub fn new(ledger: Arc<Ledger>) -> Processors {
+        let (tx, rx) = channel::<Payment>();
+        Processors {
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+            ledger,
+            generation: Arc::new(AtomicU64::new(0)),
+            depth: Arc::default(),
+            deadline: Arc::new(OnceLock::new()),
+            threads: Vec::new(),
+        }
+    }
+
+    pub fn line(&self) -> Line {
+        Line {
+            rx: self.rx.clone(),
+            retries: VecDeque::new(),
+            ledger: self.ledger.clone(),
+            generation: self.generation.clone(),
+            depth: self.depth.clone(),
+        }
+    }
+
+    pub fn send(&self, mut payment: Payment) {
+        payment.generation = self.generation.load(Ordering::Acquire);
+        payment.enqueued_at = Instant::now();
+        self.tx.send(payment).expect("processor threads are gone");
+        self.depth.queued(1);
+    }
+
+    // (na fila, em retry)
+    pub fn depth(&self) -> (u64, u64) {
+        let get = |gauge: &AtomicI64| gauge.load(Ordering::Acquire).max(0) as u64;
+        (get(&self.depth.queued), get(&self.depth.retrying))
+    }
+
+    pub fn purge(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.ledger.reset();
+        metrics::purged();
+    }
+
+    // Fecha a fila e espera as threads esvaziarem fila e retries. Depois do
+    // deadline cada thread termina a chamada em andamento e devolve o resto.
+    pub fn drain(self, deadline: Instant) -> Drained {
+        let (queued, retrying) = self.depth();
+        let in_flight = (queued + retrying) as usize;
+        let _ = self.deadline.set(deadline);
+        let mut rest = self.line();
+        drop(self.tx);
+
+        let mut pending = Vec::new();
+        for thread in self.threads {
+            match thread.join() {
+                Ok(left) => pending.extend(left),
+                Err(_) => logger::error!("Processor thread panicked while draining"),
+            }
+        }
+        // sem thread nenhuma (ou todas em pânico) a fila ainda está cheia
+        pending.extend(rest.pending());
+
+        Drained {
+            finished: in_flight.saturating_sub(pending.len()),
+            pending,
+        }
+    }
+}
+
+// Uma thread de processador: a fila é de todas, os retries são só dela
+pub struct Line {
+    rx: Arc<Mutex<Receiver<Payment>>>,
+    retries: VecDeque<Payment>,
+    ledger: Arc<Ledger>,
+    generation: Arc<AtomicU64>,
+    depth: Arc<Depth>,
+}
+
+impl Line {
+    // Da fila sem esperar, para quem decide quando roda
+    pub fn try_next(&mut self) -> Option<Payment> {
+        let payment = self.rx.lock().expect("processor queue poisoned").try_recv();
+        payment.ok().map(|payment| self.taken(payment))
+    }
+
+    fn taken(&self, payment: Payment) -> Payment {
+        self.depth.queued(-1);
+        metrics::QUEUE_WAIT.record_since(payment.enqueued_at);
+        payment
+    }
+
+    // O retry mais antigo
+    pub fn retry(&mut self) -> Option<Payment> {
+        let payment = self.retries.pop_front()?;
+        self.depth.retrying(-1);
+        Some(payment)
+    }
+
+    pub fn retries(&self) -> usize {
+        self.retries.len()
+    }
+
+    // Geração antiga sai sem chamar ninguém, recusado volta para os retries
+    pub fn run(&mut self, payment: Payment, upstream: &mut impl Upstream) {
+        let current = self.generation.load(Ordering::Acquire);
+        if payment.generation != current {
+            logger::debug!("Dropping payment from before purge"; correlation_id = payment.correlation_id);
+            let before = self.retries.len();
+            self.retries.retain(|payment| payment.generation == current);
+            self.depth
+                .retrying(self.retries.len() as i64 - before as i64);
+            return;
+        }
+
+        if let Some(payment) = process(payment, upstream, &self.ledger, &self.generation) {
+            self.retries.push_back(payment);
+            self.depth.retrying(1);
+        }
+    }
+
+    // Fila e retries que sobraram, só da geração atual
+    pub fn pending(&mut self) -> Vec<Payment> {
+        while let Some(payment) = self.try_next() {
+            self.retries.push_back(payment);
+            self.depth.retrying(1);
+        }
+        let current = self.generation.load(Ordering::Acquire);
+        self.depth.retrying(-(self.retries.len() as i64));
+        self.retries
+            .drain(..)
+            .filter(|payment| payment.generation == current)
+            .collect()
+    }
+}
+
+pub fn start_processors(ledger: Arc<Ledger>, config: &config::Worker) -> Processors {
+    let retry_interval = config.retry_interval;
+    let mut processors = Processors::new(ledger);
+
+    for _ in 0..config.threads {
+        let mut line = processors.line();
+        let deadline = processors.deadline.clone();
+        let mut clients = Clients([
+            Client::new(&config.default_url, config.processor_timeout),
+            Client::new(&config.fallback_url, config.processor_timeout),
+        ]);
+
+        processors.threads.push(std::thread::spawn(move || {
+            loop {
+                if deadline
+                    .get()
+                    .is_some_and(|deadline| Instant::now() >= *deadline)
+                {
+                    break;
+                }
+
+                let payment = match next(&line.rx, line.retries.is_empty(), retry_interval) {
+                    Ok(payment) => line.taken(payment),
+                    Err(RecvTimeoutError::Timeout) => match line.retry() {
+                        Some(payment) => payment,
+                        None => continue,
+                    },
+                    // Fila fechada pelo drain, só sobraram os retries
+                    Err(RecvTimeoutError::Disconnected) => match line.retry() {
+                        Some(payment) => {
+                            std::thread::sleep(retry_interval);
+                            payment
+                        }
+                        None => break,
+                    },
+                };
+                line.run(payment, &mut clients);
+            }
+
+            line.pending()
+        }));
+    }
+
+    processors
+}
+
+fn next(
+    rx: &Mutex<Receiver<Payment>>,
+    block: bool,
+    retry_interval: Duration,
+) -> Result<Payment, RecvTimeoutError> {
+    if block {
+        let rx = rx.lock().expect("processor queue poisoned");
+        return rx.recv().map_err(|_| RecvTimeoutError::Disconnected);
+    }
+
+    // Com retries pendentes não dá pra ficar preso esperando a fila
+    match rx.try_lock() {
+        Ok(rx) => rx.recv_timeout(retry_interval),
+        Err(_) => {
+            std::thread::sleep(retry_interval);
+            Err(RecvTimeoutError::Timeout)
+        }
+    }
+}
+
+// O lado de fora do process: o POST em cada processador e a hora do
+// requestedAt. O simulador troca os dois.
+pub trait Upstream {
+    fn pay(&mut self, processor: Processor, payment: &Payment) -> std::io::Result<u16>;
+
+    fn now_millis(&self) -> u64 {
+        time::now_millis()
+    }
+}
+
+struct Clients([Client; 2]);
+
+impl Upstream for Clients {
+    fn pay(&mut self, processor: Processor, payment: &Payment) -> std::io::Result<u16> {
+        self.0[processor as usize].pay(payment)
+    }
+}
+
+// Devolve o pagamento quando nenhum processador aceitou
+pub fn process(
+    mut payment: Payment,
+    upstream: &mut impl Upstream,
+    ledger: &Ledger,
+    generation: &AtomicU64,
+) -> Option<Payment> {
+    // O requestedAt é fixo entre tentativas, um 422 no retry significa que o
+    // processador já aceitou esse pagamento com essa data
+    if payment.requested_at == 0 {
+        payment.requested_at = Ledger::quantize(upstream.now_millis());
+    }
+
+    for processor in Processor::ALL {
+        // O outro aceitaria também e o pagamento contaria nos dois
+        if payment.pinned.is_some_and(|pinned| pinned != processor) {
+            continue;
+        }
+        let started = Instant::now();
+        let result = upstream.pay(processor, &payment);
+        let elapsed = started.elapsed().as_micros() as u64;
+
+        match result {
+            // 422 só confirma quando a tentativa anterior pode ter chegado nele
+            Ok(status)
+                if (200..300).contains(&status)
+                    || (status == 422 && payment.pinned == Some(processor)) =>
+            {
+                metrics::upstream(processor, Outcome::Success, elapsed);
+                if generation.load(Ordering::Acquire) == payment.generation {
+                    ledger.record(processor, payment.amount, payment.requested_at);
+                    metrics::recorded(processor);
+                }
+                return None;
+            }
+            // correlationId que o processador já conhece sem ter vindo daqui:
+            // o outro aceitaria e contaria um pagamento que ninguém mandou
+            Ok(422) => {
+                metrics::upstream(processor, Outcome::Rejected, elapsed);
+                logger::warn!("Payment rejected by processor"; correlation_id = payment.correlation_id, processor = processor.name(), status = 422);
+                return None;
+            }
+            Ok(status) => {
+                metrics::upstream(processor, Outcome::Rejected, elapsed);
+                logger::warn!("Processor failed payment"; correlation_id = payment.correlation_id, processor = processor.name(), status = status);
+            }
+            // nem conectou, o pagamento não saiu daqui
+            Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {
+                metrics::upstream(processor, Outcome::Error, elapsed);
+                logger::warn!("Processor unreachable: {e}"; correlation_id = payment.correlation_id, processor = processor.name());
+            }
+            // timeout ou conexão caída depois do envio: o processador pode ter
+            // aceitado, o retry vai nele e um 422 confirma
+            Err(e) => {
+                metrics::upstream(processor, Outcome::Error, elapsed);
+                logger::warn!("Payment pinned after processor error: {e}"; correlation_id = payment.correlation_id, processor = processor.name());
+                payment.pinned = Some(processor);
+                return Some(payment);
+            }
+        }
+    }
+
+    logger::debug!("Payment going to retry"; correlation_id = payment.correlation_id);
+    Some(payment)
+}
+
+struct Client {
+    addr: String,
+    host: String,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Client {
+    fn new(url: &str, timeout: Duration) -> Self {
+        let addr = url
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+            .to_string();
+        let host = addr.split(':').next().unwrap_or_default().to_string();
+
+        Client {
+            addr,
+            host,
+            stream: None,
+            buffer: Vec::with_capacity(512),
+            timeout,
+        }
+    }
+
+    fn connect(&mut self) -> std::io::Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect(&self.addr)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            self.stream = Some(stream);
+        }
+
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    fn pay(&mut self, payment: &Payment) -> std::io::Result<u16> {
+        let result = self.request(payment);
+        if result.is_err() {
+            // keep-alive quebrado ou timeout, a próxima tentativa reconecta
+            self.stream = None;
+        }
+        result
+    }
+
+    fn request(&mut self, payment: &Payment) -> std::io::Result<u16> {
+        let mut requested_at = [0u8; time::ISO_LEN];
+        time::format_iso8601(payment.requested_at, &mut requested_at);
+
+        let id_len = payment
+            .correlation_id
+            .0
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(payment.correlation_id.0.len());
+
+        let mut body = Vec::with_capacity(128);
+        body.extend_from_slice(b"{\"correlationId\":\"");
+        body.extend_from_slice(&payment.correlation_id.0[..id_len]);
+        write!(
+            body,
+            "\",\"amount\":{}.{:02},\"requestedAt\":\"",
+            payment.amount / 100,
+            payment.amount % 100
+        )?;
+        body.extend_from_slice(&requested_at);
+        body.extend_from_slice(b"\"}");
+
+        self.buffer.clear();
+        write!(
+            self.buffer,
+            "POST /payments HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            self.host,
+            body.len()
+        )?;
+        self.buffer.extend_from_slice(&body);
+
+        let request = std::mem::take(&mut self.buffer);
+        let stream = self
+            .connect()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotConnected, e))?;
+        let written = stream.write_all(&request);
+        self.buffer = request;
+        written?;
+
+        self.read_response()
+    }
+
+    fn read_response(&mut self) -> std::io::Result<u16> {
+        let stream = self.stream.as_mut().unwrap();
+        self.buffer.clear();
+
+        let mut chunk = [0u8; 512];
+        let mut header_end = None;
+        while header_end.is_none() {
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "processor closed the connection",
+                ));
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+            header_end = self.buffer.windows(4).position(|w| w == b"\r\n\r\n");
+        }
+
+        let header_end = header_end.unwrap() + 4;
+        let head = String::from_utf8_lossy(&self.buffer[..header_end]).to_string();
+        let status = head
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| std::io::Error::other("invalid processor response"))?;
+
+        let content_length = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+
+        // descarta o corpo, só o status interessa
+        let mut remaining = (header_end + content_length).saturating_sub(self.buffer.len());
+        while remaining > 0 {
+            let n = stream.read(&mut chunk[..remaining.min(512)])?;
+            if n == 0 {
+                break;
+            }
+            remaining -= n;
+        }
+
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_persist_restore() {
+        let path = std::env::temp_dir().join(format!("rinha-pending-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut id = [0u8; 36];
+        id.copy_from_slice(b"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3");
+        let mut first = Payment::new(1990, CorrelationId(id));
+        first.requested_at = 1_594_384_496_100;
+        let mut short = [0u8; 36];
+        short[..3].copy_from_slice(b"abc");
+        let second = Payment::new(5, CorrelationId(short));
+
+        persist(path, &[first, second]).unwrap();
+        std::fs::write(
+            path,
+            std::fs::read_to_string(path).unwrap() + "broken line\n",
+        )
+        .unwrap();
+
+        let restored = restore(path);
+        assert_eq!(restored.len(), 2);
+        assert_eq!(&restored[0].correlation_id.0, &id);
+        assert_eq!(restored[0].amount, 1990);
+        assert_eq!(restored[0].requested_at, 1_594_384_496_100);
+        assert_eq!(&restored[1].correlation_id.0[..4], b"abc\0");
+        assert_eq!(restored[1].requested_at, 0);
+
+        // o arquivo é consumido
+        assert!(restore(path).is_empty());
+
+        let mut pinned = Payment::new(100, CorrelationId(id));
+        pinned.requested_at = 1_594_384_496_200;
+        pinned.pinned = Some(Processor::Fallback);
+        persist(path, &[pinned]).unwrap();
+        assert_eq!(restore(path)[0].pinned, Some(Processor::Fallback));
+    }
+
+    // Respostas na ordem em que o process pede, e quem foi chamado
+    struct Script {
+        results: VecDeque<std::io::Result<u16>>,
+        calls: Vec<Processor>,
+    }
+
+    impl Upstream for Script {
+        fn pay(&mut self, processor: Processor, _: &Payment) -> std::io::Result<u16> {
+            self.calls.push(processor);
+            self.results.pop_front().unwrap()
+        }
+
+        fn now_millis(&self) -> u64 {
+            1_594_384_496_123
+        }
+    }
+
+    #[test]
+    fn test_no_failover_after_timeout() {
+        let ledger = Ledger::anonymous().unwrap();
+        let generation = AtomicU64::new(0);
+        let timeout = || Err(std::io::ErrorKind::TimedOut.into());
+        let refused = || Err(std::io::ErrorKind::NotConnected.into());
+        let mut script = Script {
+            results: VecDeque::from([refused(), Ok(200), timeout(), Ok(500), Ok(422)]),
+            calls: Vec::new(),
+        };
+        let payment = || Payment::new(1990, CorrelationId([b'a'; 36]));
+
+        // sem conexão o pagamento não saiu, o fallback pode receber
+        assert!(process(payment(), &mut script, &ledger, &generation).is_none());
+        assert_eq!(script.calls, [Processor::Default, Processor::Fallback]);
+
+        // timeout no default: fica preso nele até uma resposta, mesmo um 500
+        script.calls.clear();
+        let retry = process(payment(), &mut script, &ledger, &generation).unwrap();
+        assert_eq!(retry.requested_at, 1_594_384_496_100);
+        let retry = process(retry, &mut script, &ledger, &generation).unwrap();
+        assert!(process(retry, &mut script, &ledger, &generation).is_none());
+        assert_eq!(script.calls, [Processor::Default; 3]);
+
+        // 422 de primeira é recusa, não entra no ledger nem vai no fallback
+        script.results.push_back(Ok(422));
+        script.calls.clear();
+        assert!(process(payment(), &mut script, &ledger, &generation).is_none());
+        assert_eq!(script.calls, [Processor::Default]);
+
+        let summary = ledger.summary(0, u64::MAX).unwrap();
+        assert_eq!(summary.get(Processor::Default).requests, 1);
+        assert_eq!(summary.get(Processor::Fallback).requests, 1);
+    }
+}
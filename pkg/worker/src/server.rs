@@ -0,0 +1,390 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use connection::{
+    Connection,
+    reactor::{Interest, Reactor, Ready},
+    tokens::Tokens,
+    transport::Address,
+};
+use message::socket::{HEALTH_INTERVAL, Message};
+
+use crate::{Consumer, Credit, Node, consume, dispatch};
+
+pub const SERVER: usize = 0;
+pub const SHUTDOWN: usize = usize::MAX;
+
+// O loop do epoll do worker, uma volta por vez: quem chama espera a
+// prontidão (o mio, ou o simulador) e passa o relógio. As conexões são dos
+// load balancers, as mensagens vão para o Node.
+pub struct Server<R: Reactor> {
+    reactor: R,
+    address: Address,
+    node: Arc<Node>,
+    // buffer_size / 54 mensagens por leitura
+    conns: Vec<Connection<R::Stream>>,
+    tokens: Tokens,
+    // Ring de cada conexão que pediu Attach, mesmo índice dos slots
+    rings: Vec<Option<Consumer>>,
+    credits: Vec<Credit>,
+    // Edge-triggered: listener e conexões que pararam pelo orçamento, com
+    // coisa ainda no socket, ganham outra passada sem esperar evento
+    requeued: Vec<usize>,
+    backlog: bool,
+    listening: bool,
+    deadline: Option<Instant>,
+    next_health: Instant,
+    accept_per_iter: usize,
+    drain_timeout: Duration,
+    req_count: usize,
+}
+
+impl<R: Reactor> Server<R> {
+    pub fn new(
+        common: &config::Common,
+        config: &config::Worker,
+        address: Address,
+        reactor: R,
+        node: Arc<Node>,
+        now: Instant,
+    ) -> Server<R> {
+        let max_slots = config.max_slots;
+        Server {
+            reactor,
+            address,
+            node,
+            conns: (0..max_slots)
+                .map(|_| Connection::new(None, config.buffer_size))
+                .collect(),
+            tokens: Tokens::new(max_slots),
+            rings: (0..max_slots).map(|_| None).collect(),
+            credits: vec![Credit::default(); max_slots],
+            requeued: Vec::with_capacity(max_slots),
+            backlog: false,
+            listening: true,
+            deadline: None,
+            next_health: now + HEALTH_INTERVAL,
+            accept_per_iter: config.accept_per_iter,
+            drain_timeout: common.drain_timeout,
+            req_count: 0,
+        }
+    }
+
+    pub fn reactor(&mut self) -> &mut R {
+        &mut self.reactor
+    }
+
+    pub fn tokens(&self) -> &Tokens {
+        &self.tokens
+    }
+
+    pub fn is_open(&self, slot: usize) -> bool {
+        self.conns[slot].stream.is_some()
+    }
+
+    // O load balancer fecha os sockets quando termina o próprio drain
+    pub fn done(&self, now: Instant) -> bool {
+        self.deadline
+            .is_some_and(|deadline| self.tokens.is_empty() || now >= deadline)
+    }
+
+    // Com load balancer conectado acorda para o Health
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        if !self.requeued.is_empty() || (self.backlog && !self.tokens.is_full()) {
+            return Some(Duration::ZERO);
+        }
+        let wake = match self.tokens.is_empty() {
+            true => self.deadline,
+            false => Some(
+                self.deadline
+                    .map_or(self.next_health, |deadline| deadline.min(self.next_health)),
+            ),
+        };
+        wake.map(|wake| wake.saturating_duration_since(now))
+    }
+
+    pub fn turn(&mut self, ready: &[Ready], now: Instant) {
+        let mut accept = self.backlog;
+        let mut tokens: Vec<usize> = ready.iter().map(|event| event.token).collect();
+        tokens.append(&mut self.requeued);
+        for token in tokens {
+            match token {
+                SHUTDOWN => {
+                    if self.deadline.is_some() {
+                        continue;
+                    }
+
+                    self.reactor.close();
+                    self.listening = false;
+                    self.backlog = false;
+                    self.deadline = Some(now + self.drain_timeout);
+                    logger::info!("Draining worker"; worker = self.node.hostname, connections = self.tokens.len());
+                }
+                SERVER => accept = true,
+                token => self.ready(token),
+            }
+        }
+
+        // Depois das conexões, que podem ter liberado slot
+        if accept && self.listening {
+            self.accept();
+        }
+
+        if now >= self.next_health {
+            self.next_health = now + HEALTH_INTERVAL;
+            for (slot_idx, conn) in self.conns.iter_mut().enumerate() {
+                if conn.stream.is_none() {
+                    continue;
+                }
+                let ring = self.rings[slot_idx].is_some();
+                if let Err(e) =
+                    self.node
+                        .announce(&mut self.credits[slot_idx], ring, &mut |message| {
+                            conn.queue_message(message)
+                        })
+                {
+                    logger::warn!("Failed to send health: {e}"; worker = self.node.hostname, token = slot_idx);
+                }
+            }
+        }
+    }
+
+    // Rings sem thread (Node::polled): os pagamentos entram na fila quando
+    // quem chama decide
+    pub fn consume(&self) -> usize {
+        self.rings
+            .iter()
+            .flatten()
+            .map(|consumer| match consumer {
+                Consumer::Polled(ring) => consume(ring, &self.node),
+                Consumer::Thread(..) => 0,
+            })
+            .sum()
+    }
+
+    // O que ainda estiver nos rings vai para a fila antes do drain
+    pub fn stop(self) -> Option<Instant> {
+        self.rings
+            .into_iter()
+            .flatten()
+            .for_each(|consumer| consumer.stop(&self.node));
+        self.deadline
+    }
+
+    fn ready(&mut self, token: usize) {
+        let hostname = &self.node.hostname;
+        // evento e requeue da mesma conexão na mesma volta, ou requeue de
+        // uma conexão cujo slot já foi reusado
+        let Some(slot_idx) = self.tokens.slot(token) else {
+            return;
+        };
+        let conn = &mut self.conns[slot_idx];
+        // resto de resposta que não coube no socket da outra vez
+        if let Err(e) = conn.flush_messages() {
+            logger::warn!("Failed to write to load balancer: {e}"; worker = hostname, token = token);
+        }
+
+        let messages = match conn.read_messages() {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                return;
+            }
+            // EOF ou reset (load balancer que morreu): o slot volta para o
+            // accept e o drain não espera por ele
+            Err(e) => {
+                match e.kind() {
+                    std::io::ErrorKind::UnexpectedEof => {
+                        logger::debug!("Connection closed"; worker = hostname, token = token)
+                    }
+                    _ => {
+                        logger::warn!("Failed to read message from connection: {e}"; worker = hostname, token = token)
+                    }
+                }
+                if let Some(stream) = conn.stream.as_mut() {
+                    let _ = self.reactor.deregister(stream);
+                }
+                if let Some(consumer) = self.rings[slot_idx].take() {
+                    consumer.stop(&self.node);
+                }
+                conn.reset();
+                self.tokens.close(slot_idx);
+                return;
+            }
+        };
+
+        for message in messages {
+            if let Message::Payment(..) = message {
+                self.credits[slot_idx].received += 1;
+            }
+            dispatch(
+                message,
+                &self.address,
+                &self.node,
+                &mut self.rings[slot_idx],
+                token,
+                &mut |reply| conn.queue_message(reply),
+            );
+        }
+        // devolve o crédito do que acabou de chegar
+        if self.rings[slot_idx].is_none()
+            && let Some(grant) = self.node.credit(&mut self.credits[slot_idx])
+            && let Err(e) = conn.queue_message(&grant)
+        {
+            logger::warn!("Failed to send credit: {e}"; worker = hostname, token = token);
+        }
+
+        if conn.backlog() && !self.requeued.contains(&token) {
+            self.requeued.push(token);
+        }
+        self.req_count += 1;
+        let req_count = self.req_count;
+        logger::trace!("Req {req_count}"; worker = hostname, token = token);
+    }
+
+    fn accept(&mut self) {
+        let hostname = &self.node.hostname;
+        let mut max_conn_per_iter = self.accept_per_iter;
+        self.backlog = true;
+        while max_conn_per_iter > 0 && !self.tokens.is_full() {
+            max_conn_per_iter -= 1;
+            match self.reactor.accept() {
+                Ok((mut stream, _)) => {
+                    let (token, slot_index) = self.tokens.open().expect("no free slot");
+                    self.reactor
+                        .register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)
+                        .expect("unable to register stream with poll");
+
+                    logger::debug!("Accepted connection"; worker = hostname, token = token);
+                    let conn = &mut self.conns[slot_index];
+                    conn.open(stream);
+                    self.credits[slot_index] = Credit::default();
+                    if let Err(e) =
+                        self.node
+                            .announce(&mut self.credits[slot_index], false, &mut |message| {
+                                conn.queue_message(message)
+                            })
+                    {
+                        logger::warn!("Failed to greet load balancer: {e}"; worker = hostname, token = token);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    // No more connections to accept
+                    self.backlog = false;
+                    break;
+                }
+                Err(e) => {
+                    logger::error!("Error accepting connection: {e}"; worker = hostname);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        io::{ErrorKind, Read, Write},
+        net::IpAddr,
+    };
+
+    use connection::reactor::{Interest, Reactor, Ready};
+    use ledger::Ledger;
+
+    use super::*;
+
+    // Load balancer do outro lado que só devolve o erro combinado
+    struct Peer(ErrorKind);
+
+    impl Read for Peer {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(self.0.into())
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Fake {
+        backlog: VecDeque<Peer>,
+        registered: usize,
+    }
+
+    impl Reactor for Fake {
+        type Stream = Peer;
+
+        fn accept(&mut self) -> std::io::Result<(Peer, Option<IpAddr>)> {
+            let peer = self.backlog.pop_front().ok_or(ErrorKind::WouldBlock)?;
+            Ok((peer, None))
+        }
+
+        fn register(&mut self, _: &mut Peer, _: usize, _: Interest) -> std::io::Result<()> {
+            self.registered += 1;
+            Ok(())
+        }
+
+        fn reregister(&mut self, _: &mut Peer, _: usize, _: Interest) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn deregister(&mut self, _: &mut Peer) -> std::io::Result<()> {
+            self.registered -= 1;
+            Ok(())
+        }
+
+        fn close(&mut self) {}
+
+        fn refuse(&mut self, _: Peer, _: &[u8]) {}
+    }
+
+    fn ready(token: usize) -> [Ready; 1] {
+        [Ready {
+            token,
+            readable: true,
+        }]
+    }
+
+    // Reset do load balancer libera o slot como o EOF, e o drain não espera
+    // a conexão morta
+    #[test]
+    fn test_reset_connection() {
+        let config = config::Config::from_pairs(&[("SOCKET_DIR", "/tmp")]).unwrap();
+        let ledger = Arc::new(Ledger::anonymous().unwrap());
+        let node = Arc::new(Node::polled(&config.common, &config.worker, ledger));
+        let mut reactor = Fake::default();
+        reactor.backlog.push_back(Peer(ErrorKind::ConnectionReset));
+        let now = Instant::now();
+        let mut server = Server::new(
+            &config.common,
+            &config.worker,
+            Address::Unix("/tmp/test-reset.sock".into()),
+            reactor,
+            node,
+            now,
+        );
+
+        server.turn(&ready(SERVER), now);
+        assert_eq!(server.tokens().len(), 1);
+        let slot = (0..config.worker.max_slots)
+            .find(|&slot| server.is_open(slot))
+            .unwrap();
+        let token = server.tokens().token(slot).unwrap();
+        server.turn(&ready(token), now);
+        assert!(server.tokens().is_empty());
+        assert!(!server.is_open(slot));
+        assert_eq!(server.reactor().registered, 0);
+
+        server.turn(&ready(SHUTDOWN), now);
+        assert!(server.done(now));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    os::fd::{AsRawFd, RawFd},
+    sync::Arc,
+    time::Instant,
+};
+
+use message::socket::{HEALTH_INTERVAL, Message};
+
+use connection::{
+    metrics::{ACTIVE, BYTES_READ, BYTES_WRITTEN},
+    parse_frames,
+    transport::{Address, Listener},
+    uring::{self, Completion, Reactor},
+};
+
+use crate::{Consumer, Credit, Node, dispatch};
+
+// Mesmo protocolo do loop do mio: uma leitura sempre armada no buffer
+// registrado de cada conexão do load balancer, com o pedaço de frame que
+// sobrou no começo. Respostas (ack, summary, metrics, health, credit) saem
+// por send de um Vec, uma de cada vez por conexão.
+
+struct Slot {
+    fd: RawFd, // -1 livre
+    partial: usize,
+    out: Vec<u8>,     // respostas esperando a escrita anterior
+    sending: Vec<u8>, // em voo no kernel, não mexe até o WRITE concluir
+    sent: usize,
+    credit: Credit,
+}
+
+pub fn serve(
+    common: &config::Common,
+    config: &config::Worker,
+    address: &Address,
+    listener: Listener,
+    mut reactor: Reactor,
+    node: &Arc<Node>,
+) -> Option<Instant> {
+    let hostname = &config.host;
+    uring::blocking(listener.as_raw_fd()).expect("unable to set listener blocking");
+    let mut listener = Some(listener);
+    let mut slots: Vec<Slot> = (0..config.max_slots)
+        .map(|_| Slot {
+            fd: -1,
+            partial: 0,
+            out: Vec::new(),
+            sending: Vec::new(),
+            sent: 0,
+            credit: Credit::default(),
+        })
+        .collect();
+    let mut completions: Vec<Completion> = Vec::with_capacity(1024);
+    // Ring de cada conexão que pediu Attach, mesmo índice dos slots
+    let mut rings: Vec<Option<Consumer>> = (0..config.max_slots).map(|_| None).collect();
+
+    reactor.accept(listener.as_ref().unwrap().as_raw_fd());
+
+    let mut conn_count = 0;
+    let mut deadline: Option<Instant> = None;
+    let mut next_health = Instant::now() + HEALTH_INTERVAL;
+
+    loop {
+        // O load balancer fecha os sockets quando termina o próprio drain
+        if let Some(deadline) = deadline
+            && (conn_count == 0 || Instant::now() >= deadline)
+        {
+            break;
+        }
+
+        // Com load balancer conectado acorda para o Health
+        let wake = match conn_count {
+            0 => deadline,
+            _ => Some(deadline.map_or(next_health, |deadline| deadline.min(next_health))),
+        };
+        let timeout = wake.map(|wake| wake.saturating_duration_since(Instant::now()));
+        if let Err(e) = reactor.wait(timeout, &mut completions) {
+            panic!("io_uring wait failed: {e}");
+        }
+
+        for &Completion {
+            op,
+            slot: index,
+            result,
+            more,
+        } in completions.iter()
+        {
+            match op {
+                uring::SHUTDOWN => {
+                    if deadline.is_some() {
+                        continue;
+                    }
+
+                    if let Some(listener) = listener.take() {
+                        reactor.cancel_accept();
+                        listener.close();
+                    }
+                    deadline = Some(Instant::now() + common.drain_timeout);
+                    logger::info!("Draining worker"; worker = hostname, connections = conn_count);
+                }
+                uring::ACCEPT => {
+                    if result >= 0 {
+                        // slot com send do dono anterior em voo ainda não serve
+                        let free = slots
+                            .iter()
+                            .position(|slot| slot.fd < 0 && slot.sending.is_empty());
+                        match (listener.is_some(), free) {
+                            (true, Some(index)) => {
+                                nodelay(result);
+                                logger::debug!("Accepted connection"; worker = hostname, token = index);
+                                let slot = &mut slots[index];
+                                slot.fd = result;
+                                slot.partial = 0;
+                                slot.credit = Credit::default();
+                                ACTIVE.inc();
+                                conn_count += 1;
+                                announce(node, slot, false);
+                                flush(&mut reactor, slot, index);
+                                reactor.read(index, result, 0);
+                            }
+                            (true, None) => {
+                                logger::warn!("No free slot for load balancer connection"; worker = hostname);
+                                unsafe { libc::close(result) };
+                            }
+                            (false, _) => unsafe {
+                                libc::close(result);
+                            },
+                        }
+                    } else if result != -libc::ECANCELED {
+                        logger::error!(
+                            "Error accepting connection: {}",
+                            std::io::Error::from_raw_os_error(-result);
+                            worker = hostname
+                        );
+                    }
+
+                    if !more && let Some(listener) = listener.as_ref() {
+                        reactor.accept(listener.as_raw_fd());
+                    }
+                }
+                uring::READ => {
+                    let slot = &mut slots[index];
+                    if result <= 0 {
+                        match result {
+                            0 => {
+                                logger::debug!("Connection closed"; worker = hostname, token = index)
+                            }
+                            _ => logger::warn!(
+                                "Failed to read message from connection: {}",
+                                std::io::Error::from_raw_os_error(-result);
+                                worker = hostname,
+                                token = index
+                            ),
+                        }
+                        if let Some(consumer) = rings[index].take() {
+                            consumer.stop(node);
+                        }
+                        reactor.close(index, slot.fd);
+                        slot.fd = -1;
+                        slot.out.clear();
+                        ACTIVE.dec();
+                        conn_count -= 1;
+                        continue;
+                    }
+
+                    BYTES_READ.add(result as u64);
+                    let (messages, partial) =
+                        parse_frames(reactor.buffer(index), slot.partial + result as usize);
+                    slot.partial = partial;
+                    for message in messages {
+                        if let Message::Payment(..) = message {
+                            slot.credit.received += 1;
+                        }
+                        dispatch(
+                            message,
+                            address,
+                            node,
+                            &mut rings[index],
+                            index,
+                            &mut |reply| {
+                                slot.out.extend_from_slice(&reply.to_bytes());
+                                Ok(())
+                            },
+                        );
+                    }
+
+                    // devolve o crédito do que acabou de chegar
+                    if rings[index].is_none()
+                        && let Some(grant) = node.credit(&mut slot.credit)
+                    {
+                        slot.out.extend_from_slice(&grant.to_bytes());
+                    }
+                    flush(&mut reactor, slot, index);
+                    reactor.read(index, slot.fd, partial);
+                }
+                uring::WRITE => {
+                    let slot = &mut slots[index];
+                    if result < 0 {
+                        let e = std::io::Error::from_raw_os_error(-result);
+                        logger::error!("Failed to send reply: {e}"; worker = hostname, token = index);
+                    } else {
+                        BYTES_WRITTEN.add(result as u64);
+                        slot.sent += result as usize;
+                        if slot.sent < slot.sending.len() && slot.fd >= 0 {
+                            // sending continua intacto até o resto sair
+                            unsafe { reactor.send(index, slot.fd, &slot.sending[slot.sent..]) };
+                            continue;
+                        }
+                    }
+
+                    slot.sending.clear();
+                    flush(&mut reactor, slot, index);
+                }
+                _ => {} // CLOSE e CANCEL não mudam nada
+            }
+        }
+
+        let now = Instant::now();
+        if now >= next_health {
+            next_health = now + HEALTH_INTERVAL;
+            for (index, slot) in slots.iter_mut().enumerate() {
+                if slot.fd < 0 {
+                    continue;
+                }
+                announce(node, slot, rings[index].is_some());
+                flush(&mut reactor, slot, index);
+            }
+        }
+    }
+
+    // O drop do reactor cancela as leituras pendentes, só depois os fds fecham
+    drop(reactor);
+    for slot in slots.iter().filter(|slot| slot.fd >= 0) {
+        unsafe { libc::close(slot.fd) };
+        ACTIVE.dec();
+    }
+    // O que ainda estiver nos rings vai para a fila antes do drain
+    rings
+        .into_iter()
+        .flatten()
+        .for_each(|consumer| consumer.stop(node));
+    deadline
+}
+
+// Load balancer que não lê não faz a fila crescer sem limite
+fn announce(node: &Node, slot: &mut Slot, ring: bool) {
+    if slot.out.len() + Message::SIZE * 2 > connection::OUTBOUND_LIMIT {
+        logger::warn!("Load balancer is not reading, skipping health"; worker = node.hostname);
+        return;
+    }
+    let _ = node.announce(&mut slot.credit, ring, &mut |message| {
+        slot.out.extend_from_slice(&message.to_bytes());
+        Ok(())
+    });
+}
+
+// Uma escrita por conexão de cada vez, o que chegar nesse meio tempo espera em out
+fn flush(reactor: &mut Reactor, slot: &mut Slot, index: usize) {
+    if slot.fd < 0 || !slot.sending.is_empty() || slot.out.is_empty() {
+        return;
+    }
+
+    std::mem::swap(&mut slot.out, &mut slot.sending);
+    slot.sent = 0;
+    unsafe { reactor.send(index, slot.fd, &slot.sending) };
+}
+
+// Mesmo TCP_NODELAY do accept do mio, no UNIX socket só falha
+fn nodelay(fd: RawFd) {
+    let enabled: libc::c_int = 1;
+    unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_NODELAY,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+}
@@ -0,0 +1,306 @@
+// Linha de comando feita na unha. Cada flag só sobrescreve a variável de
+// ambiente correspondente, então env continua valendo como padrão.
+
+pub struct Flag {
+    pub name: &'static str,
+    pub env: &'static str,
+    pub value: &'static str,
+    pub help: &'static str,
+}
+
+pub struct Subcommand {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub flags: &'static [Flag],
+}
+
+const CONFIG: Flag = Flag {
+    name: "--config",
+    env: config::FILE_ENV,
+    value: "PATH",
+    help: "TOML file with the configuration",
+};
+const SOCKET_DIR: Flag = Flag {
+    name: "--socket-dir",
+    env: "SOCKET_DIR",
+    value: "DIR",
+    help: "Folder with the worker sockets and ledgers",
+};
+const LOG: Flag = Flag {
+    name: "--log",
+    env: "RINHA_LOG",
+    value: "SPEC",
+    help: "Log filter, e.g. info,worker=debug",
+};
+const PORT: Flag = Flag {
+    name: "--port",
+    env: "PORT",
+    value: "PORT",
+    help: "HTTP port of the load balancer",
+};
+const HOST: Flag = Flag {
+    name: "--host",
+    env: "HOST",
+    value: "NAME",
+    help: "Worker name, used for the socket and ledger files",
+};
+const LISTEN: Flag = Flag {
+    name: "--listen",
+    env: "WORKER_LISTEN",
+    value: "ADDR",
+    help: "Listen on host:port (TCP) instead of a UNIX socket",
+};
+const WORKERS: Flag = Flag {
+    name: "--workers",
+    env: "LB_WORKERS",
+    value: "LIST",
+    help: "Worker addresses, e.g. api1:7000,api2:7000",
+};
+
+const UPSTREAMS: Flag = Flag {
+    name: "--upstreams",
+    env: "LB_UPSTREAMS",
+    value: "LIST",
+    help: "HTTP servers behind the proxy, e.g. api1:8080,unix:/tmp/api2.sock",
+};
+
+pub const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "lb",
+        about: "Run the HTTP load balancer",
+        flags: &[CONFIG, SOCKET_DIR, LOG, PORT, WORKERS],
+    },
+    Subcommand {
+        name: "worker",
+        about: "Run a worker that talks to the payment processors",
+        flags: &[CONFIG, SOCKET_DIR, LOG, HOST, LISTEN],
+    },
+    Subcommand {
+        name: "standalone",
+        about: "Run the load balancer and the workers in a single process",
+        flags: &[CONFIG, SOCKET_DIR, LOG, PORT],
+    },
+    Subcommand {
+        name: "proxy",
+        about: "Run as an HTTP reverse proxy in front of any backend",
+        flags: &[CONFIG, LOG, PORT, UPSTREAMS],
+    },
+    Subcommand {
+        name: "check-config",
+        about: "Validate the configuration and print it with secrets redacted",
+        flags: &[
+            CONFIG, SOCKET_DIR, LOG, PORT, HOST, WORKERS, LISTEN, UPSTREAMS,
+        ],
+    },
+    Subcommand {
+        name: "version",
+        about: "Print the version",
+        flags: &[],
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(config::Mode),
+    CheckConfig,
+    Version,
+    Help(Option<&'static str>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub overrides: Vec<(&'static str, String)>,
+}
+
+impl Cli {
+    // Sem subcomando o modo continua vindo do MODE
+    pub fn parse(args: &[String]) -> Result<Cli, String> {
+        let mut args = args.iter().map(String::as_str);
+        let Some(first) = args.next() else {
+            return Ok(Cli {
+                command: Command::Run(mode_from_env()?),
+                overrides: Vec::new(),
+            });
+        };
+
+        let (name, mut overrides) = match first {
+            "-h" | "--help" | "help" => {
+                return Ok(Cli {
+                    command: Command::Help(args.next().and_then(|name| find(name).map(|s| s.name))),
+                    overrides: Vec::new(),
+                });
+            }
+            "-V" | "--version" => ("version", Vec::new()),
+            name => (name, Vec::new()),
+        };
+
+        let subcommand = find(name).ok_or_else(|| format!("unknown command '{name}'"))?;
+        let command = match subcommand.name {
+            "lb" => Command::Run(config::Mode::LoadBalancer),
+            "worker" => Command::Run(config::Mode::Worker),
+            "standalone" => Command::Run(config::Mode::Standalone),
+            "proxy" => Command::Run(config::Mode::Proxy),
+            "check-config" => Command::CheckConfig,
+            _ => Command::Version,
+        };
+        if let Command::Run(mode) = command {
+            overrides.push(("MODE", mode.name().to_string()));
+        }
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(Cli {
+                    command: Command::Help(Some(subcommand.name)),
+                    overrides: Vec::new(),
+                });
+            }
+
+            let (flag_name, inline) = match arg.split_once('=') {
+                Some((flag_name, value)) => (flag_name, Some(value.to_string())),
+                None => (arg, None),
+            };
+            let flag = subcommand
+                .flags
+                .iter()
+                .find(|flag| flag.name == flag_name)
+                .ok_or_else(|| format!("unknown option '{arg}' for '{}'", subcommand.name))?;
+            let value = match inline {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("option '{}' needs a {}", flag.name, flag.value))?
+                    .to_string(),
+            };
+            overrides.push((flag.env, value));
+        }
+
+        Ok(Cli { command, overrides })
+    }
+
+    // Variáveis de ambiente com as flags por cima
+    pub fn env(&self, name: &str) -> Option<String> {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|(env, _)| *env == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var(name).ok())
+    }
+}
+
+fn find(name: &str) -> Option<&'static Subcommand> {
+    SUBCOMMANDS
+        .iter()
+        .find(|subcommand| subcommand.name == name)
+}
+
+fn mode_from_env() -> Result<config::Mode, String> {
+    match std::env::var("MODE") {
+        Ok(mode) => config::Mode::parse(&mode).ok_or_else(|| {
+            format!("unknown mode '{mode}' in MODE, expected lb, worker, standalone or proxy")
+        }),
+        Err(_) => Ok(config::Mode::LoadBalancer),
+    }
+}
+
+pub fn usage(subcommand: Option<&str>) -> String {
+    let bin = env!("CARGO_PKG_NAME");
+    let mut out = String::new();
+
+    match subcommand.and_then(find) {
+        Some(subcommand) => {
+            out.push_str(&format!(
+                "{}\n\nUsage: {bin} {}",
+                subcommand.about, subcommand.name
+            ));
+            if !subcommand.flags.is_empty() {
+                out.push_str(" [OPTIONS]\n\nOptions:\n");
+            } else {
+                out.push('\n');
+            }
+            for flag in subcommand.flags {
+                let left = format!("{} <{}>", flag.name, flag.value);
+                out.push_str(&format!("  {left:<22} {} [env: {}]\n", flag.help, flag.env));
+            }
+            out.push_str(&format!("  {:<22} Print this help\n", "-h, --help"));
+        }
+        None => {
+            out.push_str(&format!(
+                "Rinha de Backend 2025\n\nUsage: {bin} <COMMAND> [OPTIONS]\n\nCommands:\n"
+            ));
+            for subcommand in SUBCOMMANDS {
+                out.push_str(&format!("  {:<14} {}\n", subcommand.name, subcommand.about));
+            }
+            out.push_str(&format!(
+                "\nWithout a command the mode comes from MODE (default lb).\nRun '{bin} <COMMAND> --help' for the options of each command.\n"
+            ));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let cli = parse(&["worker", "--host", "api2", "--socket-dir=/tmp/s"]).unwrap();
+        assert_eq!(cli.command, Command::Run(config::Mode::Worker));
+        assert_eq!(
+            cli.overrides,
+            vec![
+                ("MODE", "worker".to_string()),
+                ("HOST", "api2".to_string()),
+                ("SOCKET_DIR", "/tmp/s".to_string()),
+            ]
+        );
+        assert_eq!(cli.env("HOST").as_deref(), Some("api2"));
+
+        let cli = parse(&["proxy", "--upstreams", "api1:8080"]).unwrap();
+        assert_eq!(cli.command, Command::Run(config::Mode::Proxy));
+        assert_eq!(cli.env("LB_UPSTREAMS").as_deref(), Some("api1:8080"));
+
+        assert_eq!(parse(&["version"]).unwrap().command, Command::Version);
+        assert_eq!(parse(&["--version"]).unwrap().command, Command::Version);
+        assert_eq!(
+            parse(&["check-config"]).unwrap().command,
+            Command::CheckConfig
+        );
+        assert_eq!(parse(&["--help"]).unwrap().command, Command::Help(None));
+        assert_eq!(
+            parse(&["help", "lb"]).unwrap().command,
+            Command::Help(Some("lb"))
+        );
+        assert_eq!(
+            parse(&["lb", "--port", "1", "-h"]).unwrap().command,
+            Command::Help(Some("lb"))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse(&["wroker"]).unwrap_err(), "unknown command 'wroker'");
+        assert_eq!(
+            parse(&["lb", "--host", "api1"]).unwrap_err(),
+            "unknown option '--host' for 'lb'"
+        );
+        assert_eq!(
+            parse(&["worker", "--host"]).unwrap_err(),
+            "option '--host' needs a NAME"
+        );
+    }
+
+    #[test]
+    fn test_usage() {
+        assert!(usage(None).contains("  standalone     Run the load balancer"));
+        assert!(usage(Some("worker")).contains("--host <NAME>"));
+        assert!(usage(Some("worker")).contains("[env: HOST]"));
+    }
+}
@@ -1,20 +1,77 @@
+use config::{Config, Mode};
+
+use crate::cli::{Cli, Command};
+
+mod cli;
+
 fn main() {
-    let mode = std::env::var("MODE").unwrap_or_else(|_| "api".to_string());
-    let lb_port = std::env::var("PORT")
-        .unwrap_or_else(|_| "9999".to_string())
-        .parse::<u16>()
-        .expect("Invalid port number");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = match Cli::parse(&args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{}", cli::usage(None));
+            std::process::exit(2);
+        }
+    };
 
-    let socket_dir =
-        std::env::var("SOCKET_DIR").expect("SOCKET_DIR environment variable is required");
+    let mode = match cli.command {
+        Command::Help(subcommand) => {
+            print!("{}", cli::usage(subcommand));
+            return;
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Command::CheckConfig => None,
+        Command::Run(mode) => Some(mode),
+    };
 
-    match mode.as_str() {
-        "worker" => {
-            worker::start(socket_dir);
+    let config = match Config::load(&|name| cli.env(name)) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprint!("{errors}");
+            std::process::exit(2);
         }
-        _ => {
-            println!("Starting in Load Balance mode on port: {lb_port}");
-            load_balance::start(lb_port, socket_dir);
+    };
+
+    let Some(mode) = mode else {
+        println!("{config}");
+        return;
+    };
+
+    logger::init(&config.common.log);
+    for line in config.to_string().lines() {
+        logger::info!("config {line}");
+    }
+
+    match mode {
+        Mode::Worker => {
+            worker::start(&config.common, &config.worker);
+        }
+        Mode::LoadBalancer => {
+            logger::info!("Starting in Load Balance mode on port: {}", config.lb.port);
+            load_balance::start(&config.common, &config.lb);
+        }
+        Mode::Standalone => {
+            logger::info!(
+                "Starting in standalone mode on port: {}", config.lb.port;
+                workers = config.standalone.workers
+            );
+            standalone::start(&config);
         }
+        Mode::Proxy => {
+            logger::info!(
+                "Starting in proxy mode on port: {}", config.lb.port;
+                upstreams = config.lb.upstreams.join(",")
+            );
+            load_balance::proxy::start(&config.common, &config.lb);
+        }
+    }
+
+    // Só chega aqui depois do drain
+    for line in metrics::hdr::dump().lines() {
+        logger::info!("{line}");
     }
+    logger::flush();
 }